@@ -0,0 +1,234 @@
+/// The Bilibili web API, as far as needed to download videos
+///
+/// Responses are wrapped in `{"code": 0, "message": "0", "data": ...}`,
+/// a non-zero code is turned into `Error::ApiError`.
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+
+use crate::{error, fetch};
+
+const API: &str = "https://api.bilibili.com";
+// The CDN refuses segment requests without a bilibili referer
+const REFERER: &str = "https://www.bilibili.com";
+const USER_AGENT: &str = "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/17.0 Safari/605.1.15";
+
+#[derive(Deserialize)]
+struct Response<T> {
+    code: i64,
+    #[serde(default)]
+    message: String,
+    data: Option<T>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Owner {
+    pub name: String,
+    #[serde(default)]
+    pub mid: u64,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Page {
+    pub cid: u64,
+    pub page: u32,
+    pub part: String,
+    #[serde(default)]
+    pub duration: u64, // seconds
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Video {
+    pub bvid: String,
+    #[serde(default)]
+    pub aid: u64,
+    pub title: String,
+    #[serde(default)]
+    pub pic: String, // cover URL
+    pub pubdate: i64,
+    pub owner: Owner,
+    #[serde(default)]
+    pub pages: Vec<Page>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Track {
+    pub id: u32, // quality id, e.g. 80 for 1080p or 30280 for 192k audio
+    #[serde(alias = "base_url", rename = "baseUrl")]
+    pub base_url: String,
+    #[serde(default)]
+    pub bandwidth: u64,
+    #[serde(default)]
+    pub width: Option<u32>,
+    #[serde(default)]
+    pub height: Option<u32>,
+    #[serde(default)]
+    pub codecs: String,
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct Dash {
+    #[serde(default)]
+    pub video: Vec<Track>,
+    #[serde(default)]
+    pub audio: Option<Vec<Track>>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Nav {
+    #[serde(rename = "isLogin")]
+    pub is_login: bool,
+    #[serde(default)]
+    pub uname: String,
+}
+
+#[derive(Deserialize)]
+struct PlayUrl {
+    dash: Option<Dash>,
+}
+
+/// Request headers, with the login cookie if there is one
+pub fn headers(sessdata: Option<&str>) -> Vec<String> {
+    let mut headers = vec![
+        format!("Referer: {}", REFERER),
+        format!("User-Agent: {}", USER_AGENT),
+    ];
+    if let Some(sessdata) = sessdata {
+        headers.push(format!("Cookie: SESSDATA={}", sessdata));
+    }
+    headers
+}
+
+pub fn get<T: DeserializeOwned>(url: &str, sessdata: Option<&str>) -> Result<T, error::Error> {
+    let body = fetch::get(url, &headers(sessdata))?;
+    let response: Response<T> = serde_json::from_slice(&body)?;
+    match response.data {
+        Some(data) if response.code == 0 => Ok(data),
+        _ => Err(error::Error::ApiError(response.code, response.message)),
+    }
+}
+
+/// Query parameter identifying a video by `BV1...`, `av123` or a bare aid
+pub fn id_param(id: &str) -> Result<String, error::Error> {
+    let id = id.trim();
+    if id.len() > 2 && id[..2].eq_ignore_ascii_case("bv") {
+        return Ok(format!("bvid={}", id));
+    }
+    let aid = if id.len() > 2 && id[..2].eq_ignore_ascii_case("av") {
+        &id[2..]
+    } else {
+        id
+    };
+    aid.parse::<u64>()
+        .map(|aid| format!("aid={}", aid))
+        .map_err(|_| error::Error::InvalidArgument)
+}
+
+/// Metadata of a video with its pages
+pub fn view(id: &str, sessdata: Option<&str>) -> Result<Video, error::Error> {
+    get(
+        &format!("{}/x/web-interface/view?{}", API, id_param(id)?),
+        sessdata,
+    )
+}
+
+/// DASH streams of one page, every quality the account may access
+pub fn dash(video: &Video, cid: u64, sessdata: Option<&str>) -> Result<Dash, error::Error> {
+    // fnval 4048 asks for DASH with HDR, 4K, Dolby and AV1 where available
+    let url = format!(
+        "{}/x/player/playurl?bvid={}&cid={}&fnval=4048&fourk=1",
+        API, video.bvid, cid
+    );
+    let play: PlayUrl = get(&url, sessdata)?;
+    play.dash
+        .ok_or_else(|| error::Error::ApiError(-1, "no DASH streams".to_string()))
+}
+
+/// The account behind a login cookie, an error if it is not logged in
+pub fn nav(sessdata: &str) -> Result<Nav, error::Error> {
+    get(&format!("{}/x/web-interface/nav", API), Some(sessdata))
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct FavoriteMedia {
+    pub bvid: String,
+    pub title: String,
+    #[serde(rename = "type")]
+    pub kind: i64, // 2 for videos
+    #[serde(default)]
+    pub attr: i64, // bit 0 set once the video became unavailable
+}
+
+#[derive(Deserialize)]
+struct FavoriteInfo {
+    title: String,
+}
+
+#[derive(Deserialize)]
+struct FavoriteList {
+    info: FavoriteInfo,
+    #[serde(default)]
+    medias: Option<Vec<FavoriteMedia>>,
+    #[serde(default)]
+    has_more: bool,
+}
+
+/// Title and entries of a favorites folder, private folders need the
+/// owner's login
+pub fn favorites(
+    fid: u64,
+    sessdata: Option<&str>,
+) -> Result<(String, Vec<FavoriteMedia>), error::Error> {
+    let mut title = String::new();
+    let mut medias = Vec::new();
+    for pn in 1.. {
+        let url = format!(
+            "{}/x/v3/fav/resource/list?media_id={}&pn={}&ps=20&platform=web",
+            API, fid, pn
+        );
+        let list: FavoriteList = get(&url, sessdata)?;
+        title = list.info.title;
+        medias.extend(list.medias.unwrap_or_default());
+        if !list.has_more {
+            break;
+        }
+    }
+    Ok((title, medias))
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Archive {
+    pub bvid: String,
+    pub title: String,
+    pub pubdate: i64,
+}
+
+#[derive(Deserialize)]
+struct ArchivePage {
+    total: usize,
+}
+
+#[derive(Deserialize)]
+struct ArchiveList {
+    #[serde(default)]
+    archives: Vec<Archive>,
+    page: ArchivePage,
+}
+
+/// Every video uploaded by `mid`, newest first
+pub fn archives(mid: u64, sessdata: Option<&str>) -> Result<Vec<Archive>, error::Error> {
+    // Unlike the space search this listing needs no WBI request signature
+    let mut archives = Vec::new();
+    for pn in 1.. {
+        let url = format!(
+            "{}/x/series/recArchivesByKeywords?mid={}&keywords=&pn={}&ps=50",
+            API, mid, pn
+        );
+        let list: ArchiveList = get(&url, sessdata)?;
+        let done = list.archives.is_empty();
+        archives.extend(list.archives);
+        if done || archives.len() >= list.page.total {
+            break;
+        }
+    }
+    Ok(archives)
+}
@@ -0,0 +1,70 @@
+/// Converted videos in the output directory
+///
+/// Outputs are found by the metadata copied next to each video, so every
+/// layout can be read back without the state database.
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use clap::ValueEnum;
+use log::*;
+
+use crate::container::Container;
+use crate::error::Context;
+use crate::{error, VideoInfo};
+
+const METADATA_NAME: &str = "videoInfo.json";
+
+pub struct Entry {
+    pub info: VideoInfo,
+    pub file: PathBuf,
+}
+
+// The video a metadata side file belongs to, see `layout::Output::side_file`
+fn video_file(metadata: &Path, info: &VideoInfo) -> Option<PathBuf> {
+    let name = metadata.file_name()?.to_string_lossy().to_string();
+    let stem = if name == METADATA_NAME {
+        info.item_id.to_string()
+    } else {
+        name.strip_suffix(&format!(".{}", METADATA_NAME))?
+            .to_string()
+    };
+    // Written in whichever container the run converting it used
+    Container::value_variants()
+        .iter()
+        .map(|c| metadata.with_file_name(format!("{}.{}", stem, c.extension())))
+        .find(|file| file.is_file())
+}
+
+fn scan_dir(dir: &Path, entries: &mut Vec<Entry>) -> Result<(), error::Error> {
+    for entry in dir.read_dir().context("read directory", dir)? {
+        let path = entry.context("read directory", dir)?.path();
+        let name = path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string();
+        // Work directories of running conversions and the state database
+        if name.starts_with('.') {
+            continue;
+        }
+        if path.is_dir() {
+            scan_dir(&path, entries)?;
+        } else if name.ends_with(METADATA_NAME) {
+            let info = fs::read_to_string(&path)
+                .ok()
+                .and_then(|content| VideoInfo::parse(&content).ok());
+            match info.and_then(|info| Some((video_file(&path, &info)?, info))) {
+                Some((file, info)) => entries.push(Entry { info, file }),
+                None => debug!("No video for {}", path.display()),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Every converted video below `target_path`
+pub fn scan(target_path: &Path) -> Result<Vec<Entry>, error::Error> {
+    let mut entries = Vec::new();
+    scan_dir(target_path, &mut entries)?;
+    Ok(entries)
+}
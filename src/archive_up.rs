@@ -0,0 +1,80 @@
+/// Archiving everything an uploader published
+///
+/// Videos of the uploader's space are filtered by date and title keywords and
+/// downloaded when missing from the archive. Finished pages are recorded in
+/// the state database, so an interrupted run carries on where it stopped.
+use std::path::Path;
+
+use chrono::{DateTime, NaiveDate};
+use log::*;
+
+use crate::i18n::tr;
+use crate::{api, download, error, ConvertOptions, Summary};
+
+pub struct Filter {
+    /// First and last publishing day to include
+    pub since: Option<NaiveDate>,
+    pub until: Option<NaiveDate>,
+    /// Words which must all appear in the title, ignoring case
+    pub keywords: Vec<String>,
+}
+
+impl Filter {
+    fn matches(&self, archive: &api::Archive) -> bool {
+        let day = DateTime::from_timestamp(archive.pubdate, 0).map(|dt| dt.date_naive());
+        if self
+            .since
+            .is_some_and(|since| day.is_some_and(|d| d < since))
+            || self
+                .until
+                .is_some_and(|until| day.is_some_and(|d| d > until))
+        {
+            return false;
+        }
+        let title = archive.title.to_lowercase();
+        self.keywords
+            .iter()
+            .all(|keyword| title.contains(&keyword.to_lowercase()))
+    }
+}
+
+/// Download the videos of uploader `mid` passing `filter`
+pub fn run(
+    target_path: &Path,
+    mid: u64,
+    filter: &Filter,
+    sessdata: Option<&str>,
+    dry_run: bool,
+    options: &ConvertOptions,
+) -> Result<Summary, error::Error> {
+    let archives = api::archives(mid, sessdata)?;
+    let videos: Vec<download::Listed> = archives
+        .iter()
+        .filter(|archive| filter.matches(archive))
+        .map(|archive| download::Listed {
+            bvid: archive.bvid.clone(),
+            title: archive.title.clone(),
+        })
+        .collect();
+    info!(
+        "Uploader {}: {} videos, {} match the filters",
+        mid,
+        archives.len(),
+        videos.len()
+    );
+
+    let summary = download::missing(target_path, &videos, sessdata, dry_run, options)?;
+    if !dry_run {
+        println!("{}", tr!("Uploader {} archived", mid));
+        println!(
+            "{}",
+            tr!(
+                "  downloaded {}, failed {}, skipped {}",
+                summary.converted,
+                summary.failed,
+                summary.skipped
+            )
+        );
+    }
+    Ok(summary)
+}
@@ -0,0 +1,274 @@
+/// Login credentials for authenticated downloads
+///
+/// The SESSDATA and bili_jct cookies are kept in the system keyring through
+/// `secret-tool` (libsecret) on Linux or `security` on macOS. Without a
+/// keyring they go to a file in the config directory readable only by the
+/// current user.
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::Duration;
+
+use log::*;
+use serde::{Deserialize, Serialize};
+
+use crate::error::Context;
+use crate::i18n::tr;
+use crate::{api, error, signal};
+
+const SERVICE: &str = "bilibili";
+const ACCOUNT: &str = "credentials";
+const FILE_NAME: &str = "credentials.json";
+
+const QR_GENERATE: &str = "https://passport.bilibili.com/x/passport-login/web/qrcode/generate";
+const QR_POLL: &str = "https://passport.bilibili.com/x/passport-login/web/qrcode/poll";
+const QR_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+// Poll codes of the QR login
+const QR_CONFIRMED: i64 = 0;
+const QR_EXPIRED: i64 = 86038;
+const QR_SCANNED: i64 = 86090;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Credentials {
+    #[serde(rename = "SESSDATA")]
+    pub sessdata: String,
+    #[serde(default)]
+    pub bili_jct: Option<String>,
+}
+
+/// Directory of the configuration files, e.g. `~/.config/bilibili`
+pub fn config_dir() -> Option<PathBuf> {
+    let config = match env::var("XDG_CONFIG_HOME") {
+        Ok(config) if !config.is_empty() => Some(PathBuf::from(config)),
+        _ if cfg!(windows) => env::var_os("APPDATA").map(PathBuf::from),
+        _ => env::home_dir().map(|home| home.join(".config")),
+    };
+    config.map(|config| config.join(SERVICE))
+}
+
+fn credentials_file() -> Result<PathBuf, error::Error> {
+    config_dir()
+        .map(|dir| dir.join(FILE_NAME))
+        .ok_or(error::Error::InvalidArgument)
+}
+
+// None if no keyring tool is installed, so the file is used instead
+fn keyring(args: &[&str], input: Option<&str>) -> Option<std::process::Output> {
+    let program = if cfg!(target_os = "macos") {
+        "security"
+    } else {
+        "secret-tool"
+    };
+    let mut cmd = Command::new(program);
+    cmd.args(args)
+        .stdin(if input.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    let mut child = cmd.spawn().ok()?;
+    if let (Some(input), Some(mut stdin)) = (input, child.stdin.take()) {
+        stdin.write_all(input.as_bytes()).ok()?;
+    }
+    child.wait_with_output().ok()
+}
+
+fn keyring_store(secret: &str) -> bool {
+    let output = if cfg!(target_os = "macos") {
+        keyring(
+            &[
+                "add-generic-password",
+                "-U",
+                "-s",
+                SERVICE,
+                "-a",
+                ACCOUNT,
+                "-w",
+                secret,
+            ],
+            None,
+        )
+    } else {
+        keyring(
+            &[
+                "store",
+                "--label=bilibili login",
+                "service",
+                SERVICE,
+                "account",
+                ACCOUNT,
+            ],
+            Some(secret),
+        )
+    };
+    output.is_some_and(|o| o.status.success())
+}
+
+fn keyring_lookup() -> Option<String> {
+    let output = if cfg!(target_os = "macos") {
+        keyring(
+            &["find-generic-password", "-s", SERVICE, "-a", ACCOUNT, "-w"],
+            None,
+        )
+    } else {
+        keyring(&["lookup", "service", SERVICE, "account", ACCOUNT], None)
+    }?;
+    if !output.status.success() {
+        return None;
+    }
+    let secret = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (!secret.is_empty()).then_some(secret)
+}
+
+fn keyring_clear() -> bool {
+    let output = if cfg!(target_os = "macos") {
+        keyring(
+            &["delete-generic-password", "-s", SERVICE, "-a", ACCOUNT],
+            None,
+        )
+    } else {
+        keyring(&["clear", "service", SERVICE, "account", ACCOUNT], None)
+    };
+    output.is_some_and(|o| o.status.success())
+}
+
+fn write_private(path: &PathBuf, content: &str) -> Result<(), error::Error> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).context("create directory", dir)?;
+    }
+    let mut file = fs::OpenOptions::new();
+    file.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        file.mode(0o600);
+    }
+    file.open(path)
+        .and_then(|mut f| f.write_all(content.as_bytes()))
+        .context("write", path)?;
+    Ok(())
+}
+
+/// Store credentials, replacing earlier ones
+pub fn store(credentials: &Credentials) -> Result<(), error::Error> {
+    let secret = serde_json::to_string(credentials)?;
+    if keyring_store(&secret) {
+        info!("Stored credentials in the system keyring");
+        // Do not leave older credentials behind in the fallback file
+        let _ = fs::remove_file(credentials_file()?);
+        return Ok(());
+    }
+    let path = credentials_file()?;
+    write_private(&path, &secret)?;
+    info!(
+        "No system keyring available, stored credentials in {}",
+        path.display()
+    );
+    Ok(())
+}
+
+/// Stored credentials, if logged in
+pub fn load() -> Option<Credentials> {
+    let secret = keyring_lookup().or_else(|| fs::read_to_string(credentials_file().ok()?).ok())?;
+    match serde_json::from_str(&secret) {
+        Ok(credentials) => Some(credentials),
+        Err(e) => {
+            warn!("Ignoring invalid stored credentials: {}", e);
+            None
+        }
+    }
+}
+
+/// Remove stored credentials, false if there were none
+pub fn remove() -> Result<bool, error::Error> {
+    let mut removed = keyring_clear() && keyring_lookup().is_none();
+    let path = credentials_file()?;
+    if path.exists() {
+        fs::remove_file(&path).context("remove", &path)?;
+        removed = true;
+    }
+    Ok(removed)
+}
+
+#[derive(Deserialize)]
+struct QrCode {
+    url: String,
+    qrcode_key: String,
+}
+
+#[derive(Deserialize)]
+struct QrPoll {
+    code: i64,
+    #[serde(default)]
+    message: String,
+    #[serde(default)]
+    url: String,
+}
+
+// The confirmed login redirects to a URL carrying the cookies as parameters
+fn query_param(url: &str, name: &str) -> Option<String> {
+    let (_, query) = url.split_once('?')?;
+    query.split('&').find_map(|pair| {
+        let (key, value) = pair.split_once('=')?;
+        (key == name).then(|| value.to_string())
+    })
+}
+
+// Show the QR code in the terminal if qrencode is installed
+fn show_qr(url: &str) {
+    let shown = Command::new("qrencode")
+        .args(["-t", "ANSIUTF8", url])
+        .status()
+        .is_ok_and(|s| s.success());
+    if !shown {
+        println!(
+            "{}",
+            tr!("Install qrencode to show the QR code here, or open this URL on the phone:")
+        );
+        println!("{}", url);
+    }
+}
+
+/// Log in by scanning a QR code with the Bilibili app
+pub fn qr_login() -> Result<Credentials, error::Error> {
+    let qr: QrCode = api::get(QR_GENERATE, None)?;
+    println!(
+        "{}",
+        tr!("Scan this QR code with the Bilibili app and confirm the login:")
+    );
+    show_qr(&qr.url);
+
+    signal::install();
+    let mut scanned = false;
+    loop {
+        if signal::interrupted() {
+            return Err(error::Error::Interrupted);
+        }
+        thread::sleep(QR_POLL_INTERVAL);
+        let poll: QrPoll = api::get(&format!("{}?qrcode_key={}", QR_POLL, qr.qrcode_key), None)?;
+        match poll.code {
+            QR_CONFIRMED => break credentials_from(&poll.url),
+            QR_EXPIRED => return Err(error::Error::LoginFailed(poll.message)),
+            QR_SCANNED if !scanned => {
+                println!("{}", tr!("Scanned, confirm the login in the app"));
+                scanned = true;
+            }
+            _ => {}
+        }
+    }
+}
+
+fn credentials_from(url: &str) -> Result<Credentials, error::Error> {
+    let sessdata = query_param(url, "SESSDATA")
+        .ok_or_else(|| error::Error::LoginFailed("no SESSDATA in the response".to_string()))?;
+    Ok(Credentials {
+        sessdata,
+        bili_jct: query_param(url, "bili_jct"),
+    })
+}
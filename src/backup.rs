@@ -0,0 +1,1397 @@
+/// Converting from a zip or tar backup of the cache, and writing backups
+///
+/// `--cache-dir` may name an archive of the cache directory instead of the
+/// directory itself. Only the headers are read to find the items, then each
+/// item is extracted into the work directory by itself, converted and
+/// removed again, so a 100 GB backup needs room for one item rather than
+/// for all of it.
+///
+/// Zip members may be stored or deflated, also in the zip64 format of large
+/// backups. Tar archives are read in the ustar, GNU and pax formats, but not
+/// compressed, as a compressed tar can not be read from the middle.
+///
+/// `export` writes the raw cache items as a GNU tar, compressed by piping it
+/// through `zstd` for `.tar.zst`. Its first member is an index of the items,
+/// so `restore` can list them without reading the whole archive, and pick
+/// the items to put back from a compressed tar in a single pass.
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdout, Command, Stdio};
+
+use chrono::Utc;
+use log::*;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{self, Context};
+use crate::select::item_name;
+use crate::{inflate, legacy, queue, runner, signal, CachedVideo, VideoInfo, VIDEO_METADATA_FILE};
+
+const EXTENSIONS: [&str; 2] = [".zip", ".tar"];
+
+// Directory the items of an exported backup are in, and its index
+const ROOT: &str = "bilibili";
+const INDEX_NAME: &str = "bilibili-backup.json";
+
+const BLOCK: u64 = 512;
+
+/// Whether `path` is a backup archive rather than a cache directory
+pub fn is_backup(path: &Path) -> bool {
+    let name = path.to_string_lossy().to_lowercase();
+    path.is_file() && EXTENSIONS.iter().any(|ext| name.ends_with(ext))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Method {
+    Stored,
+    Deflated,
+}
+
+#[derive(Debug)]
+struct Member {
+    // Path in the archive, with `/` separators
+    name: String,
+    offset: u64,
+    size: u64,
+    packed: u64,
+    method: Method,
+    crc: Option<u32>,
+}
+
+/// A cache backup, with where its files are stored
+#[derive(Debug)]
+pub struct Backup {
+    path: PathBuf,
+    members: Vec<Member>,
+}
+
+/// An item extracted from a backup, removed again when dropped
+pub struct Extracted {
+    root: PathBuf,
+    path: PathBuf,
+}
+
+impl Extracted {
+    // An empty directory for `item` in a new directory below `parent`
+    fn create(parent: &Path, item: &str) -> Result<Extracted, error::Error> {
+        let root = parent.join(format!(".backup-{}", item.replace('/', "-")));
+        let extracted = Extracted {
+            path: root.join(item),
+            root,
+        };
+        fs::create_dir_all(&extracted.path).context("create directory", &extracted.path)?;
+        Ok(extracted)
+    }
+
+    /// The item directory, named like the item
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for Extracted {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_dir_all(&self.root) {
+            warn!("Failed to remove {}: {}", self.root.display(), e);
+        }
+    }
+}
+
+// Items are named like their directory, pages of legacy caches `<avid>/<page>`
+// like `crate::item_name` does
+fn item_of(dir: &str, members: &[&Member]) -> String {
+    let entry = format!("{}/{}", dir, legacy::ENTRY_FILE);
+    let legacy = members.iter().any(|m| m.name == entry);
+    let mut parts = dir.rsplit('/');
+    match (parts.next(), parts.next()) {
+        (Some(page), Some(video)) if legacy => format!("{}/{}", video, page),
+        (name, _) => name.unwrap_or(dir).to_string(),
+    }
+}
+
+fn u16_at(data: &[u8], at: usize) -> u64 {
+    u16::from_le_bytes([data[at], data[at + 1]]) as u64
+}
+
+fn u32_at(data: &[u8], at: usize) -> u64 {
+    u32::from_le_bytes(data[at..at + 4].try_into().unwrap()) as u64
+}
+
+fn u64_at(data: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(data[at..at + 8].try_into().unwrap())
+}
+
+fn read_at(file: &mut File, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+    let mut data = vec![0; len];
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut data)?;
+    Ok(data)
+}
+
+// Members must stay inside the directory they are extracted to
+fn safe_name(name: &str) -> Option<String> {
+    let name = name.trim_start_matches("./").replace('\\', "/");
+    let unsafe_part = |part: &str| part == ".." || part.contains(':');
+    if name.starts_with('/') || name.split('/').any(unsafe_part) {
+        return None;
+    }
+    Some(name)
+}
+
+fn zip_members(file: &mut File) -> Result<Vec<Member>, String> {
+    let len = file.metadata().map_err(|e| e.to_string())?.len();
+    // The end of central directory record, followed by a comment of up to 64KiB
+    let tail_len = len.min(22 + 0xffff);
+    let tail = read_at(file, len - tail_len, tail_len as usize).map_err(|e| e.to_string())?;
+    // The comment may hold the signature too, the record must end the file
+    let end = (0..tail.len().saturating_sub(21))
+        .rev()
+        .find(|&i| {
+            tail[i..i + 4] == [0x50, 0x4b, 0x05, 0x06]
+                && i + 22 + u16_at(&tail, i + 20) as usize == tail.len()
+        })
+        .ok_or("no zip end of central directory")?;
+    let (mut count, mut dir_len, mut dir_offset) = (
+        u16_at(&tail, end + 10),
+        u32_at(&tail, end + 12),
+        u32_at(&tail, end + 16),
+    );
+    if count == 0xffff || dir_len == 0xffff_ffff || dir_offset == 0xffff_ffff {
+        let locator = end.checked_sub(20).ok_or("no zip64 locator")?;
+        if tail[locator..locator + 4] != [0x50, 0x4b, 0x06, 0x07] {
+            return Err("no zip64 locator".into());
+        }
+        let record = read_at(file, u64_at(&tail, locator + 8), 56).map_err(|e| e.to_string())?;
+        if record[..4] != [0x50, 0x4b, 0x06, 0x06] {
+            return Err("invalid zip64 end of central directory".into());
+        }
+        (count, dir_len, dir_offset) = (
+            u64_at(&record, 32),
+            u64_at(&record, 40),
+            u64_at(&record, 48),
+        );
+    }
+    // Checked before allocating what a corrupt record may claim
+    if dir_offset
+        .checked_add(dir_len)
+        .is_none_or(|dir_end| dir_end > len)
+    {
+        return Err("central directory beyond the end of the file".into());
+    }
+    let dir = read_at(file, dir_offset, dir_len as usize).map_err(|e| e.to_string())?;
+
+    let mut members = Vec::new();
+    let mut at = 0;
+    for _ in 0..count {
+        if dir.len() < at + 46 || dir[at..at + 4] != [0x50, 0x4b, 0x01, 0x02] {
+            return Err("corrupt central directory".into());
+        }
+        let flags = u16_at(&dir, at + 8);
+        let method = u16_at(&dir, at + 10);
+        let crc = u32_at(&dir, at + 16) as u32;
+        let mut packed = u32_at(&dir, at + 20);
+        let mut size = u32_at(&dir, at + 24);
+        let name_len = u16_at(&dir, at + 28) as usize;
+        let extra_len = u16_at(&dir, at + 30) as usize;
+        let comment_len = u16_at(&dir, at + 32) as usize;
+        let mut header = u32_at(&dir, at + 42);
+        let next = at + 46 + name_len + extra_len + comment_len;
+        if dir.len() < next {
+            return Err("corrupt central directory".into());
+        }
+        let name = String::from_utf8_lossy(&dir[at + 46..at + 46 + name_len]).to_string();
+
+        // The zip64 extra field holds the sizes that did not fit, in order
+        let mut extra = &dir[at + 46 + name_len..at + 46 + name_len + extra_len];
+        while extra.len() >= 4 {
+            let (id, len) = (u16_at(extra, 0), u16_at(extra, 2) as usize);
+            let data = &extra[4..(4 + len).min(extra.len())];
+            if id == 1 {
+                let mut values = data.chunks_exact(8).map(|v| u64_at(v, 0));
+                for field in [&mut size, &mut packed, &mut header] {
+                    if *field == 0xffff_ffff {
+                        *field = values.next().ok_or("truncated zip64 extra field")?;
+                    }
+                }
+            }
+            extra = &extra[(4 + len).min(extra.len())..];
+        }
+        at = next;
+
+        if name.ends_with('/') {
+            continue;
+        }
+        let name = safe_name(&name).ok_or_else(|| format!("unsafe member name {}", name))?;
+        if flags & 1 != 0 {
+            return Err(format!("{} is encrypted", name));
+        }
+        let method = match method {
+            0 => Method::Stored,
+            8 => Method::Deflated,
+            _ => return Err(format!("{} uses unsupported compression {}", name, method)),
+        };
+        let local = read_at(file, header, 30).map_err(|e| e.to_string())?;
+        if local[..4] != [0x50, 0x4b, 0x03, 0x04] {
+            return Err(format!("corrupt local header of {}", name));
+        }
+        let offset = header + 30 + u16_at(&local, 26) + u16_at(&local, 28);
+        members.push(Member {
+            name,
+            offset,
+            size,
+            packed,
+            method,
+            crc: Some(crc),
+        });
+    }
+    Ok(members)
+}
+
+// Octal, or big-endian binary with the high bit set for GNU sizes of 8GiB up
+fn tar_number(field: &[u8]) -> Option<u64> {
+    if field[0] & 0x80 != 0 {
+        let mut value = (field[0] & 0x7f) as u64;
+        for &byte in &field[1..] {
+            value = value.checked_mul(256)? | byte as u64;
+        }
+        return Some(value);
+    }
+    let text = String::from_utf8_lossy(field);
+    let text = text.trim_matches(|c: char| c == '\0' || c == ' ');
+    if text.is_empty() {
+        return Some(0);
+    }
+    u64::from_str_radix(text, 8).ok()
+}
+
+fn tar_string(field: &[u8]) -> String {
+    let end = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).to_string()
+}
+
+fn read_data(input: &mut impl Read, size: u64) -> Result<Vec<u8>, String> {
+    let mut data = Vec::new();
+    input
+        .take(size)
+        .read_to_end(&mut data)
+        .map_err(|e| e.to_string())?;
+    Ok(data)
+}
+
+// Walk the members of a tar from its start, calling `member` with the name,
+// data offset and size of each file while `input` is at its data. It
+// returns how much of the data it read, `skip` moves past the rest.
+fn walk_tar<R: Read>(
+    input: &mut R,
+    skip: fn(&mut R, u64) -> io::Result<()>,
+    mut member: impl FnMut(&mut R, String, u64, u64) -> Result<u64, String>,
+) -> Result<(), String> {
+    let (mut long_name, mut pax_size) = (None, None);
+    let mut header = [0u8; BLOCK as usize];
+    let mut offset = 0;
+    loop {
+        match input.read_exact(&mut header) {
+            Ok(()) => {}
+            // Some writers leave out the closing zero blocks
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e.to_string()),
+        }
+        if header.iter().all(|&b| b == 0) {
+            break;
+        }
+        let mut size = tar_number(&header[124..136]).ok_or("corrupt tar header")?;
+        let data = offset + BLOCK;
+        let read = match header[156] {
+            // GNU long name of the next member
+            b'L' => {
+                long_name = Some(tar_string(&read_data(input, size)?));
+                size
+            }
+            // pax extended header of the next member, `<len> <key>=<value>\n` records
+            b'x' => {
+                let records = read_data(input, size)?;
+                for record in String::from_utf8_lossy(&records).lines() {
+                    let Some((_, field)) = record.split_once(' ') else {
+                        continue;
+                    };
+                    match field.split_once('=') {
+                        Some(("path", path)) => long_name = Some(path.to_string()),
+                        Some(("size", size)) => pax_size = size.parse().ok(),
+                        _ => {}
+                    }
+                }
+                size
+            }
+            b'0' | b'\0' | b'7' => {
+                let name = long_name.take().unwrap_or_else(|| {
+                    let name = tar_string(&header[..100]);
+                    let prefix = tar_string(&header[345..500]);
+                    if header[257..262] == *b"ustar" && !prefix.is_empty() {
+                        format!("{}/{}", prefix, name)
+                    } else {
+                        name
+                    }
+                });
+                size = pax_size.take().unwrap_or(size);
+                let name =
+                    safe_name(&name).ok_or_else(|| format!("unsafe member name {}", name))?;
+                member(input, name, data, size)?
+            }
+            _ => {
+                long_name = None;
+                pax_size = None;
+                0
+            }
+        };
+        let padded = size.div_ceil(BLOCK) * BLOCK;
+        skip(input, padded - read).map_err(|e| e.to_string())?;
+        offset = data + padded;
+    }
+    Ok(())
+}
+
+fn tar_members(file: &mut File) -> Result<Vec<Member>, String> {
+    let mut members = Vec::new();
+    let mut input = io::BufReader::new(file);
+    let skip = |input: &mut io::BufReader<&mut File>, n| input.seek_relative(n as i64);
+    walk_tar(&mut input, skip, |_, name, offset, size| {
+        members.push(Member {
+            name,
+            offset,
+            size,
+            packed: size,
+            method: Method::Stored,
+            crc: None,
+        });
+        Ok(0)
+    })?;
+    Ok(members)
+}
+
+// CRC-32 of zip members, computed while they are written
+struct Crc<W> {
+    inner: W,
+    table: [u32; 256],
+    crc: u32,
+}
+
+impl<W> Crc<W> {
+    fn new(inner: W) -> Self {
+        let mut table = [0u32; 256];
+        for (i, entry) in table.iter_mut().enumerate() {
+            *entry = (0..8).fold(i as u32, |c, _| {
+                if c & 1 != 0 {
+                    0xedb8_8320 ^ (c >> 1)
+                } else {
+                    c >> 1
+                }
+            });
+        }
+        Crc {
+            inner,
+            table,
+            crc: !0,
+        }
+    }
+
+    fn value(&self) -> u32 {
+        !self.crc
+    }
+}
+
+impl<W: Write> Write for Crc<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(data)?;
+        for &byte in &data[..n] {
+            self.crc = self.table[((self.crc ^ byte as u32) & 0xff) as usize] ^ (self.crc >> 8);
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl Backup {
+    /// Read the list of files in the backup at `path`
+    pub fn open(path: &Path) -> Result<Backup, error::Error> {
+        let mut file = File::open(path).context("open", path)?;
+        let zip = path.to_string_lossy().to_lowercase().ends_with(".zip");
+        let members = if zip {
+            zip_members(&mut file)
+        } else {
+            tar_members(&mut file)
+        }
+        .map_err(|e| error::Error::BackupInvalid(path.to_path_buf(), e))?;
+        debug!("{} files in {}", members.len(), path.display());
+        Ok(Backup {
+            path: path.to_path_buf(),
+            members,
+        })
+    }
+
+    fn copy(&self, file: &mut File, member: &Member, output: impl Write) -> Result<(), String> {
+        file.seek(SeekFrom::Start(member.offset))
+            .map_err(|e| e.to_string())?;
+        let input = file.take(member.packed);
+        let mut output = Crc::new(output);
+        let written = match member.method {
+            Method::Stored => io::copy(&mut io::BufReader::new(input), &mut output),
+            Method::Deflated => inflate::inflate(input, &mut output),
+        }
+        .map_err(|e| format!("{}: {}", member.name, e))?;
+        if written != member.size || member.crc.is_some_and(|crc| crc != output.value()) {
+            return Err(format!("{} is corrupt", member.name));
+        }
+        Ok(())
+    }
+
+    // The directories of the items in the backup, with their files
+    fn items(&self) -> BTreeMap<&str, Vec<&Member>> {
+        let mut items: BTreeMap<&str, Vec<&Member>> = BTreeMap::new();
+        for member in &self.members {
+            let (dir, file) = member.name.rsplit_once('/').unwrap_or(("", &member.name));
+            if !dir.is_empty() && (file == VIDEO_METADATA_FILE || file == legacy::ENTRY_FILE) {
+                items.entry(dir).or_default();
+            }
+        }
+        for member in &self.members {
+            let mut dir = member.name.as_str();
+            while let Some((parent, _)) = dir.rsplit_once('/') {
+                if let Some(files) = items.get_mut(parent) {
+                    files.push(member);
+                    break;
+                }
+                dir = parent;
+            }
+        }
+        items
+    }
+
+    /// The items of the backup, only those named in `selected` unless it is
+    /// empty. A job's path is the item's directory in the backup.
+    pub fn jobs(&self, selected: &[String]) -> Vec<queue::Job> {
+        let mut file = File::open(&self.path).ok();
+        let mut jobs = Vec::new();
+        for (dir, members) in self.items() {
+            let item = item_of(dir, &members);
+            if !selected.is_empty() && !selected.contains(&item) {
+                continue;
+            }
+            let metadata = members
+                .iter()
+                .find(|m| m.name.ends_with(&format!("/{}", VIDEO_METADATA_FILE)));
+            let pubdate = metadata
+                .zip(file.as_mut())
+                .and_then(|(member, file)| {
+                    let mut content = Vec::new();
+                    self.copy(file, member, &mut content).ok()?;
+                    VideoInfo::parse(&String::from_utf8_lossy(&content)).ok()
+                })
+                .map(|info| info.pubdate)
+                .unwrap_or_default();
+            jobs.push(queue::Job {
+                item,
+                path: PathBuf::from(dir),
+                selected: false,
+                size: members.iter().map(|m| m.size).sum(),
+                pubdate,
+            });
+        }
+        for item in selected {
+            if !jobs.iter().any(|job| &job.item == item) {
+                warn!("No item {} in {}", item, self.path.display());
+            }
+        }
+        jobs
+    }
+
+    /// The files of the item in directory `dir` of the backup, relative to
+    /// it, with their sizes
+    pub fn files(&self, dir: &Path) -> Vec<(PathBuf, u64)> {
+        let dir = dir.to_string_lossy();
+        let items = self.items();
+        items
+            .get(&*dir)
+            .into_iter()
+            .flatten()
+            .map(|m| (PathBuf::from(&m.name[dir.len() + 1..]), m.size))
+            .collect()
+    }
+
+    /// Extract the item in directory `dir` of the backup below `work_dir`
+    pub fn extract(&self, dir: &Path, work_dir: &Path) -> Result<Extracted, error::Error> {
+        let dir = dir.to_string_lossy();
+        let items = self.items();
+        let item = item_of(&dir, items.get(&*dir).map_or(&[][..], |m| m));
+        let extracted = Extracted::create(work_dir, &item)?;
+        let mut file = File::open(&self.path).context("open", &self.path)?;
+        for member in items.get(&*dir).into_iter().flatten() {
+            let target = extracted.path.join(&member.name[dir.len() + 1..]);
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent).context("create directory", parent)?;
+            }
+            let mut output = io::BufWriter::new(File::create(&target).context("create", &target)?);
+            self.copy(&mut file, member, &mut output)
+                .map_err(|e| error::Error::BackupInvalid(self.path.clone(), e))?;
+            output.flush().context("write", &target)?;
+        }
+        debug!("Extracted {} from {}", dir, self.path.display());
+        Ok(extracted)
+    }
+}
+
+// GNU tar header of a regular file, or of the long name of the next one
+fn tar_header(name: &str, size: u64, mtime: u64, kind: u8) -> [u8; BLOCK as usize] {
+    let mut header = [0u8; BLOCK as usize];
+    let name = &name.as_bytes()[..name.len().min(100)];
+    header[..name.len()].copy_from_slice(name);
+    header[100..108].copy_from_slice(b"0000644\0");
+    header[108..116].copy_from_slice(b"0000000\0");
+    header[116..124].copy_from_slice(b"0000000\0");
+    if size < 0o77777777777 {
+        header[124..136].copy_from_slice(format!("{:011o}\0", size).as_bytes());
+    } else {
+        header[124] = 0x80;
+        header[128..136].copy_from_slice(&size.to_be_bytes());
+    }
+    header[136..148].copy_from_slice(format!("{:011o}\0", mtime.min(0o77777777777)).as_bytes());
+    header[156] = kind;
+    header[257..265].copy_from_slice(b"ustar  \0");
+    // Summed with the checksum field as spaces
+    header[148..156].fill(b' ');
+    let sum: u32 = header.iter().map(|&b| b as u32).sum();
+    header[148..156].copy_from_slice(format!("{:06o}\0 ", sum).as_bytes());
+    header
+}
+
+fn pad(output: &mut dyn Write, size: u64) -> io::Result<()> {
+    let padding = size.div_ceil(BLOCK) * BLOCK - size;
+    output.write_all(&vec![0; padding as usize])
+}
+
+fn write_member(output: &mut dyn Write, name: &str, data: &[u8]) -> io::Result<()> {
+    if name.len() > 100 {
+        write_long_name(output, name, 0)?;
+    }
+    output.write_all(&tar_header(name, data.len() as u64, 0, b'0'))?;
+    output.write_all(data)?;
+    pad(output, data.len() as u64)
+}
+
+// GNU long name of the next member, which has the first 100 bytes of it
+fn write_long_name(output: &mut dyn Write, name: &str, mtime: u64) -> io::Result<()> {
+    let long = format!("{}\0", name);
+    output.write_all(&tar_header("././@LongLink", long.len() as u64, mtime, b'L'))?;
+    output.write_all(long.as_bytes())?;
+    pad(output, long.len() as u64)
+}
+
+fn write_file(output: &mut dyn Write, name: &str, path: &Path) -> Result<(), error::Error> {
+    let file = File::open(path).context("open", path)?;
+    let metadata = file.metadata().context("read", path)?;
+    let mtime = metadata
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_secs());
+    if name.len() > 100 {
+        write_long_name(output, name, mtime).context("write", path)?;
+    }
+    let size = metadata.len();
+    output
+        .write_all(&tar_header(name, size, mtime, b'0'))
+        .context("write", path)?;
+    // The header has the size already, so the file must not change meanwhile
+    let copied = io::copy(&mut file.take(size), output).context("read", path)?;
+    if copied != size {
+        return Err(error::Error::FileError {
+            action: "read",
+            path: path.to_path_buf(),
+            source: io::ErrorKind::UnexpectedEof.into(),
+        });
+    }
+    pad(output, size).context("write", path)
+}
+
+/// What an exported backup holds, its first member
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Index {
+    pub created: i64,
+    pub items: Vec<IndexEntry>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct IndexEntry {
+    pub item: String,
+    /// Directory of the item in the backup
+    pub dir: String,
+    pub item_id: u64,
+    pub title: String,
+    pub uname: String,
+    pub size: u64,
+}
+
+// Files below `dir`, relative to it with `/` separators
+fn files(dir: &Path) -> Result<Vec<(String, PathBuf)>, error::Error> {
+    let mut files = Vec::new();
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(current) = dirs.pop() {
+        for entry in current.read_dir().context("read directory", &current)? {
+            let path = entry.context("read directory", &current)?.path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else if let Ok(relative) = path.strip_prefix(dir) {
+                files.push((relative.to_string_lossy().replace('\\', "/"), path));
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+// The archive being written, through zstd for `.zst`
+enum Writer {
+    File(io::BufWriter<File>),
+    Zstd(Child),
+}
+
+impl Writer {
+    fn create(part: &Path, zstd: bool) -> Result<Writer, error::Error> {
+        if !zstd {
+            let file = File::create(part).context("create", part)?;
+            return Ok(Writer::File(io::BufWriter::new(file)));
+        }
+        let mut cmd = Command::new("zstd");
+        cmd.args(["-q", "-f", "-T0", "-o"])
+            .arg(part)
+            .stdin(Stdio::piped());
+        runner::show(&cmd);
+        let child = cmd.spawn().map_err(|e| {
+            error!("zstd is needed to write {}: {}", part.display(), e);
+            error::Error::CommandNotFound
+        })?;
+        Ok(Writer::Zstd(child))
+    }
+
+    fn output(&mut self) -> &mut dyn Write {
+        match self {
+            Writer::File(file) => file,
+            Writer::Zstd(child) => child.stdin.as_mut().unwrap(),
+        }
+    }
+
+    fn finish(self, part: &Path) -> Result<(), error::Error> {
+        match self {
+            Writer::File(mut file) => {
+                file.flush().context("write", part)?;
+                file.get_ref().sync_all().context("write", part)
+            }
+            Writer::Zstd(mut child) => {
+                drop(child.stdin.take());
+                let status = child.wait().context("write", part)?;
+                if !status.success() {
+                    return Err(error::Error::FileError {
+                        action: "compress",
+                        path: part.to_path_buf(),
+                        source: io::Error::other(format!("zstd exited with {}", status)),
+                    });
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Pack the cached `videos` of `source_path` into the archive `to`, a
+/// `.tar` or a `.tar.zst` compressed with zstd, with an index first
+pub fn export(source_path: &Path, videos: &[CachedVideo], to: &Path) -> Result<(), error::Error> {
+    let name = to.to_string_lossy().to_lowercase();
+    let zstd = name.ends_with(".tar.zst");
+    if !zstd && !name.ends_with(".tar") {
+        error!(
+            "Backups are written as .tar or .tar.zst, not {}",
+            to.display()
+        );
+        return Err(error::Error::InvalidArgument);
+    }
+    let mut items = Vec::new();
+    for video in videos {
+        let relative = video.dir.strip_prefix(source_path).unwrap_or(&video.dir);
+        let dir = format!("{}/{}", ROOT, relative.to_string_lossy().replace('\\', "/"));
+        items.push((video, dir, files(&video.dir)?));
+    }
+    let index = Index {
+        created: Utc::now().timestamp(),
+        items: items
+            .iter()
+            .map(|(video, dir, files)| IndexEntry {
+                item: item_name(video),
+                dir: dir.clone(),
+                item_id: video.info.item_id,
+                title: video.info.title.clone(),
+                uname: video.info.uname.clone(),
+                size: files
+                    .iter()
+                    .filter_map(|(_, p)| p.metadata().ok())
+                    .map(|m| m.len())
+                    .sum(),
+            })
+            .collect(),
+    };
+
+    let part = to.with_file_name(format!(
+        "{}.part",
+        to.file_name().unwrap_or_default().to_string_lossy()
+    ));
+    signal::install();
+    let mut writer = Writer::create(&part, zstd)?;
+    let result = (|| {
+        let output = writer.output();
+        write_member(output, INDEX_NAME, &serde_json::to_vec_pretty(&index)?)
+            .context("write", &part)?;
+        for (video, dir, files) in &items {
+            for (relative, path) in files {
+                if signal::interrupted() {
+                    return Err(error::Error::Interrupted);
+                }
+                write_file(output, &format!("{}/{}", dir, relative), path)?;
+            }
+            info!("Packed {}", item_name(video));
+        }
+        output
+            .write_all(&[0; 2 * BLOCK as usize])
+            .context("write", &part)
+    })();
+    // zstd has to exit before its partial output can be removed
+    let finished = writer.finish(&part);
+    if let Err(e) = result.and(finished) {
+        let _ = fs::remove_file(&part);
+        return Err(e);
+    }
+    fs::rename(&part, to).context("rename", &part)?;
+    info!("Exported {} items to {}", index.items.len(), to.display());
+    Ok(())
+}
+
+impl Backup {
+    // The index of an exported backup, or one made from the metadata of
+    // the items otherwise
+    fn index(&self) -> Result<Index, error::Error> {
+        let mut file = File::open(&self.path).context("open", &self.path)?;
+        let mut read = |member: &Member| {
+            let mut content = Vec::new();
+            self.copy(&mut file, member, &mut content)
+                .map(|_| content)
+                .map_err(|e| error::Error::BackupInvalid(self.path.clone(), e))
+        };
+        if let Some(member) = self.members.iter().find(|m| m.name == INDEX_NAME) {
+            return Ok(serde_json::from_slice(&read(member)?)?);
+        }
+        let mut items = Vec::new();
+        for (dir, members) in self.items() {
+            let metadata = members
+                .iter()
+                .find(|m| m.name.ends_with(&format!("/{}", VIDEO_METADATA_FILE)));
+            let info = match metadata {
+                Some(member) => VideoInfo::parse(&String::from_utf8_lossy(&read(member)?)).ok(),
+                None => None,
+            };
+            items.push(IndexEntry {
+                item: item_of(dir, &members),
+                dir: dir.to_string(),
+                item_id: info.as_ref().map_or(0, |i| i.item_id),
+                title: info.as_ref().map(|i| i.title.clone()).unwrap_or_default(),
+                uname: info.map(|i| i.uname).unwrap_or_default(),
+                size: members.iter().map(|m| m.size).sum(),
+            });
+        }
+        Ok(Index { created: 0, items })
+    }
+}
+
+fn decompress(archive: &Path) -> Result<Child, error::Error> {
+    let mut cmd = Command::new("zstd");
+    cmd.arg("-dc")
+        .arg(archive)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped());
+    runner::show(&cmd);
+    cmd.spawn().map_err(|e| {
+        error!("zstd is needed to read {}: {}", archive.display(), e);
+        error::Error::CommandNotFound
+    })
+}
+
+fn skip_read(input: &mut ChildStdout, n: u64) -> io::Result<()> {
+    io::copy(&mut input.take(n), &mut io::sink()).map(|_| ())
+}
+
+// Stop zstd once reading is done, early or not
+fn finish_decompress(archive: &Path, mut child: Child, complete: bool) -> Result<(), error::Error> {
+    if !complete {
+        let _ = child.kill();
+    }
+    let status = child.wait().context("read", archive)?;
+    if complete && !status.success() {
+        return Err(error::Error::BackupInvalid(
+            archive.to_path_buf(),
+            format!("zstd exited with {}", status),
+        ));
+    }
+    Ok(())
+}
+
+fn is_compressed(archive: &Path) -> bool {
+    archive
+        .to_string_lossy()
+        .to_lowercase()
+        .ends_with(".tar.zst")
+}
+
+// The index a compressed tar starts with, without decompressing the rest
+fn compressed_index(archive: &Path) -> Result<Index, error::Error> {
+    let mut child = decompress(archive)?;
+    let mut stdout = child.stdout.take().unwrap();
+    let mut index = None;
+    let result = walk_tar(&mut stdout, skip_read, |input, name, _, size| {
+        if name != INDEX_NAME {
+            return Err(format!("no {} at the start", INDEX_NAME));
+        }
+        let data = read_data(input, size)?;
+        index = Some(serde_json::from_slice(&data).map_err(|e| e.to_string())?);
+        // Nothing else is needed
+        Err(String::new())
+    });
+    finish_decompress(archive, child, false)?;
+    match (index, result) {
+        (Some(index), _) => Ok(index),
+        (None, Err(e)) if !e.is_empty() => {
+            Err(error::Error::BackupInvalid(archive.to_path_buf(), e))
+        }
+        (None, _) => Err(error::Error::BackupInvalid(
+            archive.to_path_buf(),
+            format!("no {} at the start", INDEX_NAME),
+        )),
+    }
+}
+
+/// The items in the backup `archive`
+pub fn list(archive: &Path) -> Result<Vec<IndexEntry>, error::Error> {
+    let index = if is_compressed(archive) {
+        compressed_index(archive)?
+    } else {
+        Backup::open(archive)?.index()?
+    };
+    Ok(index.items)
+}
+
+// Where the item in directory `dir` of a backup goes in the cache: below the
+// last `bilibili` directory, as exported, or else directly in the cache
+fn cache_path(source_path: &Path, dir: &str) -> PathBuf {
+    let parts: Vec<&str> = dir.split('/').collect();
+    let start = match parts.iter().rposition(|part| *part == ROOT) {
+        Some(i) if i + 1 < parts.len() => i + 1,
+        _ => parts.len() - 1,
+    };
+    parts[start..]
+        .iter()
+        .fold(source_path.to_path_buf(), |path, part| path.join(part))
+}
+
+// The directories of `entries` to restore with their place in the cache,
+// leaving out items the cache has already
+fn restorable(
+    entries: &[IndexEntry],
+    source_path: &Path,
+    selected: &[String],
+) -> Vec<(String, PathBuf)> {
+    for item in selected {
+        if !entries.iter().any(|entry| &entry.item == item) {
+            warn!("No item {} in the backup", item);
+        }
+    }
+    let mut restorable = Vec::new();
+    for entry in entries {
+        if !selected.is_empty() && !selected.contains(&entry.item) {
+            continue;
+        }
+        let target = cache_path(source_path, &entry.dir);
+        if target.exists() {
+            warn!("Skip {}, {} exists already", entry.item, target.display());
+            continue;
+        }
+        restorable.push((entry.dir.clone(), target));
+    }
+    restorable
+}
+
+// Move an extracted item into its place in the cache
+fn put(extracted: Extracted, target: &Path) -> Result<(), error::Error> {
+    fs::rename(extracted.path(), target).context("rename", extracted.path())?;
+    info!("Restored {}", target.display());
+    Ok(())
+}
+
+/// Extract the items of `archive` named in `selected`, or all of them, back
+/// into the cache at `source_path`, returning how many were restored
+pub fn restore(
+    archive: &Path,
+    source_path: &Path,
+    selected: &[String],
+) -> Result<usize, error::Error> {
+    signal::install();
+    if is_compressed(archive) {
+        return restore_compressed(archive, source_path, selected);
+    }
+    let backup = Backup::open(archive)?;
+    let items = restorable(&backup.index()?.items, source_path, selected);
+    for (dir, target) in &items {
+        if signal::interrupted() {
+            return Err(error::Error::Interrupted);
+        }
+        // Extracted next to its place, so it only needs to be renamed
+        let parent = target.parent().unwrap_or(source_path);
+        fs::create_dir_all(parent).context("create directory", parent)?;
+        put(backup.extract(Path::new(dir), parent)?, target)?;
+    }
+    Ok(items.len())
+}
+
+// Restore from a compressed tar in one pass, which needs the index at its
+// start to know the items before their files come by
+fn restore_compressed(
+    archive: &Path,
+    source_path: &Path,
+    selected: &[String],
+) -> Result<usize, error::Error> {
+    let mut child = decompress(archive)?;
+    let mut stdout = child.stdout.take().unwrap();
+    let mut order: Vec<String> = Vec::new();
+    let mut staged: Vec<(String, Extracted, PathBuf)> = Vec::new();
+    let mut error = None;
+    let result = walk_tar(&mut stdout, skip_read, |input, name, _, size| {
+        if order.is_empty() {
+            if name != INDEX_NAME {
+                return Err(format!("no {} at the start", INDEX_NAME));
+            }
+            let index: Index =
+                serde_json::from_slice(&read_data(input, size)?).map_err(|e| e.to_string())?;
+            order = index.items.iter().map(|entry| entry.dir.clone()).collect();
+            for (dir, target) in restorable(&index.items, source_path, selected) {
+                let parent = target.parent().unwrap_or(source_path);
+                let item = index.items.iter().find(|entry| entry.dir == dir);
+                match Extracted::create(parent, item.map_or("", |entry| &entry.item)) {
+                    Ok(extracted) => staged.push((dir, extracted, target)),
+                    Err(e) => {
+                        error = Some(e);
+                        return Err(String::new());
+                    }
+                }
+            }
+            return Ok(size);
+        }
+        if signal::interrupted() {
+            error = Some(error::Error::Interrupted);
+            return Err(String::new());
+        }
+        let in_dir = |dir: &String| {
+            name.strip_prefix(dir.as_str())
+                .is_some_and(|r| r.starts_with('/'))
+        };
+        // Items are exported one after the other, in the order of the index
+        let position = order.iter().position(in_dir);
+        let last = staged
+            .last()
+            .and_then(|(dir, ..)| order.iter().position(|d| d == dir));
+        if last.is_none() || position > last {
+            return Err(String::new());
+        }
+        let Some((dir, extracted, _)) = staged.iter().find(|(dir, ..)| in_dir(dir)) else {
+            return Ok(0);
+        };
+        let target = extracted.path.join(&name[dir.len() + 1..]);
+        let mut write = || -> io::Result<u64> {
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            let mut output = io::BufWriter::new(File::create(&target)?);
+            let copied = io::copy(&mut input.take(size), &mut output)?;
+            output.flush()?;
+            Ok(copied)
+        };
+        match write() {
+            Ok(copied) if copied == size => Ok(copied),
+            Ok(_) => Err(format!("{} is truncated", name)),
+            Err(e) => {
+                error = Some(error::Error::FileError {
+                    action: "write",
+                    path: target.clone(),
+                    source: e,
+                });
+                Err(String::new())
+            }
+        }
+    });
+    // Stopped early once the last item to restore is complete
+    let complete = result.is_ok();
+    finish_decompress(archive, child, complete)?;
+    if let Some(e) = error {
+        return Err(e);
+    }
+    match result {
+        Err(e) if !e.is_empty() => {
+            return Err(error::Error::BackupInvalid(archive.to_path_buf(), e))
+        }
+        _ if order.is_empty() => {
+            return Err(error::Error::BackupInvalid(
+                archive.to_path_buf(),
+                format!("no {} at the start", INDEX_NAME),
+            ))
+        }
+        _ => {}
+    }
+    let restored = staged.len();
+    for (_, extracted, target) in staged {
+        put(extracted, &target)?;
+    }
+    Ok(restored)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::{self, Item, TempDir};
+
+    // A tar member with its header and data, padded to whole blocks
+    fn tar_entry(tar: &mut Vec<u8>, name: &str, kind: u8, data: &[u8]) {
+        let mut header = [0u8; BLOCK as usize];
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        header[124..135].copy_from_slice(format!("{:011o}", data.len()).as_bytes());
+        header[156] = kind;
+        header[257..263].copy_from_slice(b"ustar\0");
+        tar.extend_from_slice(&header);
+        tar.extend_from_slice(data);
+        tar.resize(tar.len().div_ceil(BLOCK as usize) * BLOCK as usize, 0);
+    }
+
+    fn put(buf: &mut Vec<u8>, value: u64, bytes: usize) {
+        buf.extend_from_slice(&value.to_le_bytes()[..bytes]);
+    }
+
+    // A zip of `(name, method, packed, data)` members, a comment after the
+    // end of central directory record, and in zip64 records if `zip64`
+    fn zip(members: &[(&str, u16, &[u8], &[u8])], zip64: bool) -> Vec<u8> {
+        let (mut zip, mut dir) = (Vec::new(), Vec::new());
+        for &(name, method, packed, data) in members {
+            let mut crc = Crc::new(io::sink());
+            crc.write_all(data).unwrap();
+            let offset = zip.len() as u64;
+            let sizes = [packed.len() as u64, data.len() as u64];
+            let small = |value: u64| if zip64 { 0xffff_ffff } else { value };
+
+            zip.extend_from_slice(b"PK\x03\x04\x14\0\0\0");
+            put(&mut zip, method as u64, 2);
+            put(&mut zip, 0, 4);
+            put(&mut zip, crc.value() as u64, 4);
+            put(&mut zip, small(sizes[0]), 4);
+            put(&mut zip, small(sizes[1]), 4);
+            put(&mut zip, name.len() as u64, 2);
+            // An extended timestamp, the data starts after it
+            put(&mut zip, 9, 2);
+            zip.extend_from_slice(name.as_bytes());
+            zip.extend_from_slice(b"UT\x05\0\x01\0\0\0\0");
+            zip.extend_from_slice(packed);
+
+            dir.extend_from_slice(b"PK\x01\x02\x14\0\x14\0\0\0");
+            put(&mut dir, method as u64, 2);
+            put(&mut dir, 0, 4);
+            put(&mut dir, crc.value() as u64, 4);
+            put(&mut dir, small(sizes[0]), 4);
+            put(&mut dir, small(sizes[1]), 4);
+            put(&mut dir, name.len() as u64, 2);
+            put(&mut dir, if zip64 { 28 } else { 0 }, 2);
+            // No comment, disk 0, no attributes
+            put(&mut dir, 0, 6);
+            put(&mut dir, 0, 4);
+            put(&mut dir, small(offset), 4);
+            dir.extend_from_slice(name.as_bytes());
+            if zip64 {
+                dir.extend_from_slice(b"\x01\0\x18\0");
+                for value in [sizes[1], sizes[0], offset] {
+                    put(&mut dir, value, 8);
+                }
+            }
+        }
+
+        let (dir_offset, dir_len) = (zip.len() as u64, dir.len() as u64);
+        let count = members.len() as u64;
+        zip.extend_from_slice(&dir);
+        if zip64 {
+            let record = zip.len() as u64;
+            zip.extend_from_slice(b"PK\x06\x06");
+            put(&mut zip, 44, 8);
+            put(&mut zip, 0x2d002d, 4);
+            put(&mut zip, 0, 8);
+            for value in [count, count, dir_len, dir_offset] {
+                put(&mut zip, value, 8);
+            }
+            zip.extend_from_slice(b"PK\x06\x07\0\0\0\0");
+            put(&mut zip, record, 8);
+            put(&mut zip, 1, 4);
+        }
+        let comment = b"backup of PK\x05\x06 the cache";
+        zip.extend_from_slice(b"PK\x05\x06\0\0\0\0");
+        for (value, bytes) in [(count, 2), (count, 2), (dir_len, 4), (dir_offset, 4)] {
+            put(&mut zip, if zip64 { u64::MAX } else { value }, bytes);
+        }
+        put(&mut zip, comment.len() as u64, 2);
+        zip.extend_from_slice(comment);
+        zip
+    }
+
+    #[test]
+    fn extracts_stored_deflated_and_zip64_members() {
+        let dir = TempDir::new();
+        let item = Item::single(222, "Single").write(&dir.path().join("cache"));
+        let files: Vec<(String, Vec<u8>)> = fixture::tree(&item)
+            .into_iter()
+            .map(|file| {
+                (
+                    format!("cache/222/{}", file),
+                    fs::read(item.join(&file)).unwrap(),
+                )
+            })
+            .collect();
+        // "hello hello hello bilibili", deflated with fixed codes
+        let deflated = [
+            0xcb, 0x48, 0xcd, 0xc9, 0xc9, 0x57, 0xc8, 0x40, 0x22, 0x93, 0x32, 0x73, 0x32, 0x41,
+            0x18, 0x00,
+        ];
+        let hello = b"hello hello hello bilibili";
+        let mut members: Vec<(&str, u16, &[u8], &[u8])> = vec![("cache/222/", 0, b"", b"")];
+        for (name, data) in &files {
+            members.push((name, 0, data, data));
+        }
+        members.push(("cache/222/notes.txt", 8, &deflated, hello));
+
+        for zip64 in [false, true] {
+            let archive = dir.path().join(format!("backup-{}.zip", zip64));
+            fs::write(&archive, zip(&members, zip64)).unwrap();
+            let backup = Backup::open(&archive).unwrap();
+            let jobs = backup.jobs(&[]);
+            assert_eq!(jobs.len(), 1);
+            assert_eq!(
+                (jobs[0].item.as_str(), jobs[0].pubdate),
+                ("222", fixture::NOW)
+            );
+
+            let extracted = backup
+                .extract(&jobs[0].path, &dir.path().join("work"))
+                .unwrap();
+            for file in fixture::tree(&item) {
+                assert_eq!(
+                    fs::read(extracted.path().join(&file)).unwrap(),
+                    fs::read(item.join(&file)).unwrap()
+                );
+            }
+            assert_eq!(fs::read(extracted.path().join("notes.txt")).unwrap(), hello);
+        }
+
+        // A flipped bit in the data fails the checksum
+        let mut corrupt = members.clone();
+        let mut data = files[0].1.clone();
+        data[0] ^= 1;
+        corrupt[1].2 = &data;
+        let archive = dir.path().join("corrupt.zip");
+        fs::write(&archive, zip(&corrupt, false)).unwrap();
+        let backup = Backup::open(&archive).unwrap();
+        let jobs = backup.jobs(&[]);
+        let Err(error) = backup.extract(&jobs[0].path, &dir.path().join("work")) else {
+            panic!("extracted a corrupt member");
+        };
+        assert!(error.to_string().contains("is corrupt"), "{}", error);
+
+        // A central directory claimed to be larger than the file
+        let mut zip = zip(&members, false);
+        let end = zip.len() - 22 - b"backup of PK\x05\x06 the cache".len();
+        // u32::MAX would ask for the zip64 record instead
+        zip[end + 12..end + 16].copy_from_slice(&(u32::MAX - 1).to_le_bytes());
+        let archive = dir.path().join("truncated.zip");
+        fs::write(&archive, zip).unwrap();
+        let Err(error::Error::BackupInvalid(_, error)) = Backup::open(&archive) else {
+            panic!("opened a zip with a central directory beyond its end");
+        };
+        assert_eq!(error, "central directory beyond the end of the file");
+    }
+
+    #[test]
+    fn rejects_zips_it_cannot_extract() {
+        let dir = TempDir::new();
+        let open = |name: &str, zip: &[u8]| {
+            let archive = dir.path().join(name);
+            fs::write(&archive, zip).unwrap();
+            match Backup::open(&archive) {
+                Err(error::Error::BackupInvalid(_, error)) => error,
+                _ => panic!("opened {}", name),
+            }
+        };
+        let member = |name, method| zip(&[(name, method, b"data", b"data")], false);
+
+        for truncated in [&b""[..], b"PK\x03\x04 and no directory"] {
+            assert_eq!(
+                open("truncated.zip", truncated),
+                "no zip end of central directory"
+            );
+        }
+        let mut zip64 = zip(&[("cache/222/a", 0, b"data", b"data")], true);
+        let locator = zip64.len() - 22 - b"backup of PK\x05\x06 the cache".len() - 20;
+        zip64[locator + 2] = 0;
+        assert_eq!(open("zip64.zip", &zip64), "no zip64 locator");
+        assert_eq!(
+            open("unsafe.zip", &member("cache/../../222/a", 0)),
+            "unsafe member name cache/../../222/a"
+        );
+        assert_eq!(
+            open("method.zip", &member("cache/222/a", 14)),
+            "cache/222/a uses unsupported compression 14"
+        );
+        let mut encrypted = member("cache/222/a", 0);
+        let entry = encrypted
+            .windows(4)
+            .position(|w| w == b"PK\x01\x02")
+            .unwrap();
+        encrypted[entry + 8] = 1;
+        assert_eq!(
+            open("encrypted.zip", &encrypted),
+            "cache/222/a is encrypted"
+        );
+    }
+
+    #[test]
+    fn extracts_items_of_a_tar() {
+        let dir = TempDir::new();
+        let item = Item::single(222, "Single").write(&dir.path().join("cache"));
+        // Deeper than fits the 100 bytes of a plain header
+        let root = format!("{}/222", "backup".repeat(20));
+        let mut tar = Vec::new();
+        tar_entry(&mut tar, "bilibili/notes.txt", b'0', b"not an item");
+        for file in fixture::tree(&item) {
+            let name = format!("{}/{}", root, file);
+            tar_entry(&mut tar, "././@LongLink", b'L', name.as_bytes());
+            tar_entry(
+                &mut tar,
+                &name[..100],
+                b'0',
+                &fs::read(item.join(&file)).unwrap(),
+            );
+        }
+        tar.resize(tar.len() + 2 * BLOCK as usize, 0);
+        let archive = dir.path().join("backup.tar");
+        fs::write(&archive, tar).unwrap();
+
+        assert!(is_backup(&archive));
+        let backup = Backup::open(&archive).unwrap();
+        let jobs = backup.jobs(&[]);
+        let items: Vec<_> = jobs
+            .iter()
+            .map(|job| (job.item.as_str(), job.pubdate))
+            .collect();
+        assert_eq!(items, [("222", fixture::NOW)]);
+        assert_eq!(jobs[0].path, Path::new(&root));
+        assert!(backup.jobs(&["111".to_string()]).is_empty());
+
+        let work = dir.path().join("work");
+        let extracted = backup.extract(&jobs[0].path, &work).unwrap();
+        assert_eq!(extracted.path().file_name().unwrap(), "222");
+        assert_eq!(fixture::tree(extracted.path()), fixture::tree(&item));
+        drop(extracted);
+        assert_eq!(fs::read_dir(&work).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn exports_items_readable_as_backup() {
+        let dir = TempDir::new();
+        // Deep enough for GNU long names
+        let cache = dir.path().join("cache".repeat(20));
+        let item = Item::single(222, "Single").write(&cache);
+        let video = crate::get_cached_video(&item).unwrap();
+        let archive = dir.path().join("backup.tar");
+        export(dir.path(), &[video], &archive).unwrap();
+
+        let backup = Backup::open(&archive).unwrap();
+        let jobs = backup.jobs(&[]);
+        assert_eq!(jobs.len(), 1);
+        let root = format!("{}/{}/222", ROOT, "cache".repeat(20));
+        assert_eq!(jobs[0].path, Path::new(&root));
+        let index = backup
+            .members
+            .iter()
+            .find(|m| m.name == INDEX_NAME)
+            .unwrap();
+        let mut content = Vec::new();
+        let mut file = File::open(&archive).unwrap();
+        backup.copy(&mut file, index, &mut content).unwrap();
+        let index: Index = serde_json::from_slice(&content).unwrap();
+        assert_eq!(index.items[0].dir, root);
+
+        let extracted = backup
+            .extract(&jobs[0].path, &dir.path().join("work"))
+            .unwrap();
+        assert_eq!(fixture::tree(extracted.path()), fixture::tree(&item));
+        for file in fixture::tree(&item) {
+            assert_eq!(
+                fs::read(extracted.path().join(&file)).unwrap(),
+                fs::read(item.join(&file)).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn restores_items_into_the_cache() {
+        let dir = TempDir::new();
+        let cache = dir.path().join("cache");
+        let items = [Item::single(111, "One"), Item::single(222, "Two")].map(|i| i.write(&cache));
+        let videos: Vec<_> = items
+            .iter()
+            .map(|item| crate::get_cached_video(item).unwrap())
+            .collect();
+        let archive = dir.path().join("backup.tar");
+        export(&cache, &videos, &archive).unwrap();
+
+        let restored = dir.path().join("restored");
+        assert_eq!(
+            restore(&archive, &restored, &["222".to_string()]).unwrap(),
+            1
+        );
+        assert_eq!(
+            fixture::tree(&restored.join("222")),
+            fixture::tree(&items[1])
+        );
+        // Items in the cache already are kept
+        fs::write(restored.join("222/cover.jpg"), b"new").unwrap();
+        assert_eq!(restore(&archive, &restored, &[]).unwrap(), 1);
+        assert_eq!(fs::read(restored.join("222/cover.jpg")).unwrap(), b"new");
+        assert_eq!(
+            fixture::tree(&restored).len(),
+            2 * fixture::tree(&items[0]).len()
+        );
+
+        let entries = list(&archive).unwrap();
+        let names: Vec<_> = entries
+            .iter()
+            .map(|e| (e.item.as_str(), e.item_id))
+            .collect();
+        assert_eq!(names, [("111", 111), ("222", 222)]);
+        assert_eq!(
+            cache_path(Path::new("/cache"), "Movies/bilibili/video/c_1"),
+            Path::new("/cache/video/c_1")
+        );
+        assert_eq!(
+            cache_path(Path::new("/cache"), "old/111"),
+            Path::new("/cache/111")
+        );
+    }
+}
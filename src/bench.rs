@@ -0,0 +1,489 @@
+/// Measuring why conversions are slow on a setup
+///
+/// `bench` writes incompressible synthetic data of `--size` bytes to the
+/// cache, work and output directories and reads it back, strips the prefix
+/// bytes off a synthetic cache file into the work directory and muxes a
+/// test clip generated by ffmpeg with the configured profile and container.
+/// Reads may be served from the page cache, a size above the memory of the
+/// machine gives the speed of the disk. The throughputs are printed with
+/// recommendations for the options that would help, and nothing is left
+/// behind in the directories.
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use log::*;
+
+use crate::error::{self, Context};
+use crate::ffmpeg::MuxJob;
+use crate::i18n::tr;
+use crate::list::print_table;
+use crate::profile::Profile;
+use crate::{backup, disk, eta, signal, ConvertOptions, SPECIAL_OFFSET};
+
+const FILE: &str = ".bilibili-bench";
+
+const CHUNK: usize = 1024 * 1024;
+
+/// Length of the test clip, about what a short video takes to mux
+const CLIP_SECS: u32 = 30;
+
+/// A directory writing this much slower than another is worth moving off
+const SLOWER: f64 = 2.0;
+
+/// Write speed of network shares and USB 2 sticks, in bytes per second
+const SLOW_DISK: f64 = 20.0 * 1024.0 * 1024.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    Cache,
+    Work,
+    Output,
+}
+
+impl Kind {
+    fn label(self) -> String {
+        match self {
+            Kind::Cache => tr!("cache"),
+            Kind::Work => tr!("work"),
+            Kind::Output => tr!("output"),
+        }
+        .to_string()
+    }
+}
+
+/// Speeds of a directory, in bytes per second
+#[derive(Debug, Clone, PartialEq)]
+pub struct Disk {
+    pub kind: Kind,
+    pub path: PathBuf,
+    pub write: f64,
+    pub read: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Report {
+    pub disks: Vec<Disk>,
+    /// Stripping throughput, None if ffmpeg reads the cache in place
+    pub strip: Option<f64>,
+    /// Muxing throughput of the profile, None if the clip could not be made
+    pub mux: Option<f64>,
+    pub profile: Profile,
+    /// Bytes in the cache, to estimate converting all of it
+    pub cache_bytes: u64,
+}
+
+fn rate(bytes: u64, elapsed: Duration) -> f64 {
+    bytes as f64 / elapsed.as_secs_f64().max(1e-6)
+}
+
+fn human_rate(rate: f64) -> String {
+    format!("{}/s", disk::human_size(rate as u64))
+}
+
+// Pseudo random bytes, so file systems compressing data do not look faster
+fn synthetic_chunk() -> Vec<u8> {
+    let mut state: u64 = 0x9e37_79b9_7f4a_7c15;
+    (0..CHUNK)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        })
+        .collect()
+}
+
+/// Write `prefix` bytes the client puts before cached media, then `size`
+/// bytes of synthetic data to `file`, synced to the disk
+fn write_synthetic(file: &Path, prefix: u64, size: u64) -> Result<(), error::Error> {
+    let chunk = synthetic_chunk();
+    let mut out = fs::File::create(file).context("create", file)?;
+    out.write_all(&vec![0xff; prefix as usize])
+        .context("write", file)?;
+    let mut left = size;
+    while left > 0 {
+        let n = left.min(CHUNK as u64) as usize;
+        out.write_all(&chunk[..n]).context("write", file)?;
+        left -= n as u64;
+        if signal::interrupted() {
+            return Err(error::Error::Interrupted);
+        }
+    }
+    out.sync_all().context("write", file)
+}
+
+fn write_read(file: &Path, size: u64) -> Result<(f64, f64), error::Error> {
+    let start = Instant::now();
+    write_synthetic(file, 0, size)?;
+    let write = rate(size, start.elapsed());
+    let start = Instant::now();
+    let mut f = fs::File::open(file).context("open", file)?;
+    let read = io::copy(&mut f, &mut io::sink()).context("read", file)?;
+    Ok((write, rate(read, start.elapsed())))
+}
+
+fn measure_disk(kind: Kind, dir: &Path, size: u64) -> Result<Disk, error::Error> {
+    let file = dir.join(FILE);
+    let result = write_read(&file, size);
+    let _ = fs::remove_file(&file);
+    let (write, read) = result?;
+    Ok(Disk {
+        kind,
+        path: dir.to_path_buf(),
+        write,
+        read,
+    })
+}
+
+// Strip a synthetic cache file in `source_dir` into `work_path`, like a
+// conversion reading the cache
+fn measure_strip(
+    source_dir: &Path,
+    work_path: &Path,
+    size: u64,
+    options: &ConvertOptions,
+) -> Result<f64, error::Error> {
+    let source = source_dir.join(format!("{}.m4s", FILE));
+    let result = write_synthetic(&source, SPECIAL_OFFSET, size).and_then(|_| {
+        let start = Instant::now();
+        crate::strip_media(&source, &work_path.join("stripped.m4s"), options.io_limit)?;
+        Ok(rate(size, start.elapsed()))
+    });
+    let _ = fs::remove_file(&source);
+    result
+}
+
+// Mux a test clip made by ffmpeg with the profile and container of a
+// conversion, the clip is about as dense as a 1080p video of the client
+fn measure_mux(work_path: &Path, options: &ConvertOptions) -> Result<f64, error::Error> {
+    let clip = work_path.join("clip.mp4");
+    // ffmpeg -f lavfi -i testsrc2=size=1920x1080:rate=30 -f lavfi -i sine
+    //        -t S -c:v mpeg4 -b:v 4M -c:a aac -b:a 192k -y -f mp4 clip
+    let mut cmd = options.ffmpeg.command();
+    cmd.args(["-f", "lavfi", "-i", "testsrc2=size=1920x1080:rate=30"]);
+    cmd.args(["-f", "lavfi", "-i", "sine"]);
+    cmd.arg("-t").arg(CLIP_SECS.to_string());
+    cmd.args(["-c:v", "mpeg4", "-b:v", "4M", "-c:a", "aac", "-b:a", "192k"]);
+    cmd.arg("-y").args(["-f", "mp4"]).arg(&clip);
+    options.ffmpeg.run(cmd)?;
+
+    let inputs = [clip];
+    let job = MuxJob {
+        inputs: &inputs,
+        concat: false,
+        read_rate: None,
+        skip_bytes: 0,
+        maps: &[],
+        profile: options.profile,
+        transcode_audio: false,
+        chapters: None,
+        tags: &[],
+        container: options.layout.container,
+        faststart: options.faststart,
+    };
+    let output = work_path.join(format!("muxed.{}", options.layout.container.extension()));
+    let bytes = inputs[0]
+        .metadata()
+        .context("read metadata of", &inputs[0])?
+        .len();
+    let start = Instant::now();
+    options.muxer().mux(&job, &output)?;
+    Ok(rate(bytes, start.elapsed()))
+}
+
+// Throughput of stripping, unless ffmpeg reads the cache in place, and of
+// muxing, unless ffmpeg could not make the clip
+fn measure_stages(
+    source_dir: &Path,
+    work_path: &Path,
+    size: u64,
+    options: &ConvertOptions,
+) -> Result<(Option<f64>, Option<f64>), error::Error> {
+    let strip = if options.ffmpeg.skips_initial_bytes() {
+        None
+    } else {
+        info!("Measuring stripping");
+        Some(measure_strip(source_dir, work_path, size, options)?)
+    };
+    info!(
+        "Measuring muxing with the {} profile",
+        options.profile.name()
+    );
+    let mux = match measure_mux(work_path, options) {
+        Ok(mux) => Some(mux),
+        Err(error::Error::Interrupted) => return Err(error::Error::Interrupted),
+        Err(e) => {
+            warn!("Failed to measure muxing: {}", e);
+            None
+        }
+    };
+    Ok((strip, mux))
+}
+
+/// Measure the directories and stages of a conversion with `size` bytes
+pub fn measure(
+    source_path: &Path,
+    target_path: &Path,
+    size: u64,
+    options: &ConvertOptions,
+) -> Result<Report, error::Error> {
+    // A backup is only read, and the work directory may be the output
+    let cache = Some(source_path).filter(|path| !backup::is_backup(path));
+    let work = Some(options.work_dir.as_path()).filter(|path| *path != target_path);
+    let mut disks = Vec::new();
+    for (kind, dir) in [
+        (Kind::Cache, cache),
+        (Kind::Work, work),
+        (Kind::Output, Some(target_path)),
+    ] {
+        let Some(dir) = dir else { continue };
+        info!("Measuring {}", dir.display());
+        match measure_disk(kind, dir, size) {
+            Ok(disk) => disks.push(disk),
+            Err(error::Error::Interrupted) => return Err(error::Error::Interrupted),
+            Err(e) => warn!("Failed to measure {}: {}", dir.display(), e),
+        }
+    }
+
+    // Without a writable cache the synthetic one is in the work directory
+    let cache_measured = disks.iter().any(|d| d.kind == Kind::Cache);
+    let work_path = crate::create_work_dir(&format!(".bench-{}", std::process::id()), options)?;
+    let source_dir = if cache_measured {
+        source_path
+    } else {
+        work_path.as_path()
+    };
+    let result = measure_stages(source_dir, &work_path, size, options);
+    crate::remove_work_dir(&work_path);
+    let (strip, mux) = result?;
+
+    let cache_bytes = match cache {
+        Some(dir) => disk::dir_size(dir)?,
+        None => fs::metadata(source_path)
+            .context("read metadata of", source_path)?
+            .len(),
+    };
+    Ok(Report {
+        disks,
+        strip,
+        mux,
+        profile: options.profile,
+        cache_bytes,
+    })
+}
+
+/// Options that would speed up conversions on the measured setup
+pub fn recommend(report: &Report, options: &ConvertOptions) -> Vec<String> {
+    let mut tips = Vec::new();
+    let disk = |kind| report.disks.iter().find(|d| d.kind == kind);
+    if let (Some(work), Some(output)) = (disk(Kind::Work), disk(Kind::Output)) {
+        if output.write > SLOWER * work.write {
+            tips.push(tr!(
+                "Put --work-dir on the disk of the output directory, it writes {} while the work directory writes {}",
+                human_rate(output.write),
+                human_rate(work.write)
+            ));
+        }
+    }
+    for d in report.disks.iter().filter(|d| d.write < SLOW_DISK) {
+        tips.push(tr!(
+            "{} only writes {}, like a network share or a USB 2 stick, large batches take hours there",
+            d.path.display(),
+            human_rate(d.write)
+        ));
+    }
+    if report.strip.is_some() {
+        tips.push(tr!(
+            "This ffmpeg has no -skip_initial_bytes, every item is copied to strip it first; a newer ffmpeg reads the cache in place"
+        ).to_string());
+    }
+    let slowest_disk = report
+        .disks
+        .iter()
+        .map(|d| d.write.min(d.read))
+        .reduce(f64::min);
+    if let (Some(limit), Some(slowest)) = (options.io_limit, slowest_disk) {
+        if (limit as f64) < slowest {
+            tips.push(tr!(
+                "--io-limit caps copies at {} though the disks manage {}",
+                human_rate(limit as f64),
+                human_rate(slowest)
+            ));
+        }
+    }
+    // Items are converted one at a time, re-encoding uses every core of the
+    // machine unless --threads says otherwise
+    let cpu_bound = report.profile != Profile::Copy
+        && report
+            .mux
+            .zip(slowest_disk)
+            .is_some_and(|(mux, disk)| mux < disk);
+    if cpu_bound {
+        let cores = std::thread::available_parallelism().map_or(1, |n| n.get() as u32);
+        match options.ffmpeg.threads.filter(|threads| *threads < cores) {
+            Some(threads) => tips.push(tr!(
+                "Re-encoding limits the speed and ffmpeg may only use {} of {} threads, raise --threads",
+                threads,
+                cores
+            )),
+            None => tips.push(tr!(
+                "Re-encoding with the {} profile limits the speed, --profile copy or --compat-target only re-encodes what has to be",
+                report.profile.name()
+            )),
+        }
+    }
+    tips
+}
+
+/// Seconds converting the whole cache takes at the measured throughput
+fn cache_eta(report: &Report) -> Option<f64> {
+    let bytes = report.cache_bytes as f64;
+    let mux = bytes / report.mux?;
+    Some(mux + report.strip.map_or(0.0, |strip| bytes / strip))
+}
+
+/// Measure the setup with `size` bytes per directory and print the speeds
+/// with recommendations
+pub fn run(
+    source_path: &Path,
+    target_path: &Path,
+    size: u64,
+    options: &ConvertOptions,
+) -> Result<(), error::Error> {
+    let report = measure(source_path, target_path, size, options)?;
+    let rows: Vec<Vec<String>> = report
+        .disks
+        .iter()
+        .map(|d| {
+            vec![
+                d.kind.label(),
+                d.path.display().to_string(),
+                human_rate(d.write),
+                human_rate(d.read),
+            ]
+        })
+        .collect();
+    print_table(
+        &["DIRECTORY", "PATH", "WRITE", "READ"],
+        &[false, false, true, true],
+        &rows,
+    );
+    println!();
+
+    let strip = match report.strip {
+        Some(strip) => human_rate(strip),
+        None => tr!("not needed, ffmpeg reads the cache in place").to_string(),
+    };
+    let mux = report.mux.map_or("-".to_string(), human_rate);
+    let rows = vec![
+        vec![tr!("strip").to_string(), strip],
+        vec![tr!("mux ({})", report.profile.name()), mux],
+    ];
+    print_table(&["STAGE", "THROUGHPUT"], &[false, true], &rows);
+    println!();
+
+    if let Some(secs) = cache_eta(&report) {
+        println!(
+            "{}",
+            tr!(
+                "Converting the whole cache of {} takes about {}",
+                disk::human_size(report.cache_bytes),
+                eta::format(secs)
+            )
+        );
+    }
+    let tips = recommend(&report, options);
+    if tips.is_empty() {
+        println!(
+            "{}",
+            tr!("No recommendations, nothing on this setup slows conversions down")
+        );
+    }
+    for tip in tips {
+        println!("- {}", tip);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::{self, ScriptedRunner, StubMuxer, TempDir};
+
+    // ffmpeg writes the test clip, has no -skip_initial_bytes
+    fn answer(args: &[String]) -> Option<String> {
+        if args.iter().any(|a| a == "lavfi") {
+            fs::write(args.last()?, [0; 1000]).unwrap();
+        }
+        args.iter().any(|a| a == "-i").then(String::new)
+    }
+
+    #[test]
+    fn measures_the_setup_and_recommends_options() {
+        let dir = TempDir::new();
+        let (cache, work, output) = (
+            dir.path().join("cache"),
+            dir.path().join("work"),
+            dir.path().join("output"),
+        );
+        for path in [&cache, &work, &output] {
+            fs::create_dir_all(path).unwrap();
+        }
+        fs::write(cache.join("111.m4s"), [0; 100]).unwrap();
+        let muxer = StubMuxer::default();
+        let mut options = fixture::options(&["--profile", "h264", "--threads", "1"], &work, &muxer);
+        let runner = ScriptedRunner::new(answer);
+        options.ffmpeg.runner = runner.clone();
+
+        let report = measure(&cache, &output, 3 * CHUNK as u64, &options).unwrap();
+        assert_eq!(
+            report.disks.iter().map(|d| d.kind).collect::<Vec<_>>(),
+            [Kind::Cache, Kind::Work, Kind::Output]
+        );
+        assert!(report.strip.is_some() && report.mux.is_some());
+        assert_eq!(report.cache_bytes, 100);
+        assert!(muxer.jobs()[0].contains("profile=H264"));
+        // Nothing is left behind
+        for path in [&cache, &work, &output] {
+            assert_eq!(fixture::tree(path).len(), usize::from(path == &cache));
+        }
+
+        let mib = 1024.0 * 1024.0;
+        let disk = |kind, write| Disk {
+            kind,
+            path: dir.path().join(format!("{:?}", kind)),
+            write,
+            read: 1000.0 * mib,
+        };
+        let report = Report {
+            disks: vec![
+                disk(Kind::Cache, 500.0 * mib),
+                disk(Kind::Work, 10.0 * mib),
+                disk(Kind::Output, 400.0 * mib),
+            ],
+            strip: None,
+            mux: Some(5.0 * mib),
+            profile: Profile::H264,
+            cache_bytes: 3600 * 5 * 1024 * 1024,
+        };
+        let tips = recommend(&report, &options);
+        assert_eq!(tips.len(), 3, "{:?}", tips);
+        assert!(tips[0].starts_with("Put --work-dir on the disk of the output directory"));
+        assert!(tips[1].contains("only writes 10.0 MiB/s"));
+        assert!(
+            tips[2].contains("raise --threads")
+                || std::thread::available_parallelism().unwrap().get() == 1
+        );
+        assert_eq!(cache_eta(&report), Some(3600.0));
+
+        let fast = Report {
+            disks: vec![disk(Kind::Output, 400.0 * mib)],
+            profile: Profile::Copy,
+            mux: Some(400.0 * mib),
+            ..report
+        };
+        assert!(recommend(&fast, &options).is_empty());
+    }
+}
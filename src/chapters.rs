@@ -0,0 +1,46 @@
+/// Chapter markers from video metadata
+///
+/// Newer clients store the segment markers shown on the progress bar
+/// ("view points") in the metadata. They are written as an ffmetadata file
+/// which ffmpeg muxes into the output as chapters.
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{self, Context};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Chapter {
+    #[serde(alias = "content")]
+    pub title: String,
+    pub from: f64, // seconds
+    pub to: f64,
+}
+
+// ffmetadata escapes `=`, `;`, `#`, `\` and newlines with a backslash
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '=' | ';' | '#' | '\\' | '\n') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Write chapters in ffmpeg's metadata format, skipping empty or inverted ranges
+pub fn write_ffmetadata(chapters: &[Chapter], path: &Path) -> Result<(), error::Error> {
+    let mut content = String::from(";FFMETADATA1\n");
+    for chapter in chapters.iter().filter(|c| c.to > c.from) {
+        let _ = writeln!(content, "[CHAPTER]");
+        let _ = writeln!(content, "TIMEBASE=1/1000");
+        let _ = writeln!(content, "START={}", (chapter.from * 1000.0) as u64);
+        let _ = writeln!(content, "END={}", (chapter.to * 1000.0) as u64);
+        let _ = writeln!(content, "title={}", escape(&chapter.title));
+    }
+    fs::write(path, content).context("write", path)?;
+    Ok(())
+}
@@ -0,0 +1,125 @@
+/// Re-encoding only what a playback device can not play
+///
+/// With `--compat-target` the codecs of each item are read from its cached
+/// streams and compared with what the device plays. A video stream it
+/// lacks is re-encoded to H.264 and an audio stream to AAC, which every
+/// target plays; streams it plays are copied. Items whose codecs can not be
+/// read, e.g. old FLV caches, are converted as `--profile` says.
+use clap::ValueEnum;
+
+use crate::mp4::SampleEntry;
+use crate::profile::Profile;
+
+/// Device the outputs have to play on
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Target {
+    /// Chromecast and Google TV: H.264 or VP9 with AAC, Opus or FLAC
+    Chromecast,
+    /// iPhone, iPad and Apple TV: H.264 or HEVC with AAC, AC-3, E-AC-3 or FLAC
+    Ios,
+    /// TVs only decoding H.264, with AAC, AC-3 or E-AC-3
+    TvH264,
+}
+
+impl Target {
+    /// Video codecs the device plays, as `SampleEntry::codec` names them
+    fn video_codecs(self) -> &'static [&'static str] {
+        match self {
+            Target::Chromecast => &["H.264", "VP9"],
+            Target::Ios => &["H.264", "HEVC"],
+            Target::TvH264 => &["H.264"],
+        }
+    }
+
+    fn audio_codecs(self) -> &'static [&'static str] {
+        match self {
+            Target::Chromecast => &["AAC", "Opus", "FLAC"],
+            Target::Ios => &["AAC", "AC-3", "E-AC-3", "FLAC"],
+            Target::TvH264 => &["AAC", "AC-3", "E-AC-3"],
+        }
+    }
+
+    /// How the streams described by `formats` are converted for the device,
+    /// None if none of them could be read
+    pub fn plan(self, formats: &[SampleEntry]) -> Option<Plan> {
+        if formats.is_empty() {
+            return None;
+        }
+        let plays = |entry: &SampleEntry| {
+            let codecs = if entry.video {
+                self.video_codecs()
+            } else {
+                self.audio_codecs()
+            };
+            codecs.contains(&entry.codec().as_str())
+        };
+        let unplayable = |video: bool| formats.iter().any(|f| f.video == video && !plays(f));
+        Some(Plan {
+            profile: if unplayable(true) {
+                Profile::H264
+            } else {
+                Profile::Copy
+            },
+            audio: unplayable(false),
+        })
+    }
+}
+
+/// What is re-encoded for a target
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Plan {
+    /// Profile of the video, copied if the device plays it
+    pub profile: Profile,
+    /// Re-encode the audio to AAC
+    pub audio: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(format: &[u8; 4], video: bool) -> SampleEntry {
+        SampleEntry {
+            format: *format,
+            video,
+            width: if video { 1920 } else { 0 },
+            height: if video { 1080 } else { 0 },
+        }
+    }
+
+    #[test]
+    fn re_encodes_only_unplayable_streams() {
+        let hevc = [entry(b"hvc1", true), entry(b"mp4a", false)];
+        assert_eq!(
+            Target::Ios.plan(&hevc),
+            Some(Plan {
+                profile: Profile::Copy,
+                audio: false
+            })
+        );
+        assert_eq!(
+            Target::TvH264.plan(&hevc),
+            Some(Plan {
+                profile: Profile::H264,
+                audio: false
+            })
+        );
+
+        let av1 = [entry(b"av01", true), entry(b"ec-3", false)];
+        assert_eq!(
+            Target::Chromecast.plan(&av1),
+            Some(Plan {
+                profile: Profile::H264,
+                audio: true
+            })
+        );
+        assert_eq!(
+            Target::Ios.plan(&[entry(b"avc1", true), entry(b"fLaC", false)]),
+            Some(Plan {
+                profile: Profile::Copy,
+                audio: false
+            })
+        );
+        assert_eq!(Target::TvH264.plan(&[]), None);
+    }
+}
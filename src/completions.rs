@@ -0,0 +1,333 @@
+/// Shell completion scripts
+///
+/// Scripts are generated from the clap command definition, item ids for
+/// `convert`, `clean` and `open` are completed dynamically by calling back into
+/// the hidden `__complete-items` subcommand which scans the cache.
+use std::fmt::Write;
+use std::path::Path;
+
+use clap::{Arg, Command, ValueEnum};
+
+use crate::select::item_name;
+use crate::{error, get_video_list};
+
+pub const ITEMS_COMMAND: &str = "__complete-items";
+
+// Subcommands whose positional argument is a cache item
+const ITEM_COMMANDS: &[&str] = &["convert", "clean", "open"];
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+fn visible_args(cmd: &Command) -> Vec<&Arg> {
+    cmd.get_arguments()
+        .filter(|a| !a.is_hide_set() && !a.is_positional())
+        .collect()
+}
+
+fn visible_subcommands(cmd: &Command) -> Vec<&Command> {
+    cmd.get_subcommands()
+        .filter(|c| !c.is_hide_set() && c.get_name() != "help")
+        .collect()
+}
+
+fn flags(args: &[&Arg]) -> Vec<String> {
+    let mut flags = Vec::new();
+    for arg in args {
+        if let Some(short) = arg.get_short() {
+            flags.push(format!("-{}", short));
+        }
+        if let Some(long) = arg.get_long() {
+            flags.push(format!("--{}", long));
+        }
+    }
+    flags
+}
+
+fn takes_value(arg: &Arg) -> bool {
+    arg.get_action().takes_values()
+}
+
+fn possible_values(arg: &Arg) -> Vec<String> {
+    if !takes_value(arg) {
+        return Vec::new();
+    }
+    arg.get_possible_values()
+        .iter()
+        .map(|v| v.get_name().to_string())
+        .collect()
+}
+
+fn bash(cmd: &Command) -> String {
+    let name = cmd.get_name();
+    let global = visible_args(cmd);
+    let mut script = String::new();
+
+    // Options taking a value, so their value is not mistaken for a subcommand
+    let value_options: Vec<String> = flags(
+        &global
+            .iter()
+            .copied()
+            .filter(|a| takes_value(a))
+            .collect::<Vec<_>>(),
+    );
+
+    let _ = writeln!(script, "_{}() {{", name);
+    let _ = writeln!(script, "    local cur prev sub i");
+    let _ = writeln!(script, "    cur=\"${{COMP_WORDS[COMP_CWORD]}}\"");
+    let _ = writeln!(script, "    prev=\"${{COMP_WORDS[COMP_CWORD-1]}}\"");
+    let _ = writeln!(script, "    sub=\"\"");
+    let _ = writeln!(script, "    for ((i=1; i<COMP_CWORD; i++)); do");
+    let _ = writeln!(script, "        case \"${{COMP_WORDS[i]}}\" in");
+    if !value_options.is_empty() {
+        let _ = writeln!(
+            script,
+            "            {}) ((i++)) ;;",
+            value_options.join("|")
+        );
+    }
+    let _ = writeln!(script, "            -*) ;;");
+    let _ = writeln!(
+        script,
+        "            *) sub=\"${{COMP_WORDS[i]}}\"; break ;;"
+    );
+    let _ = writeln!(script, "        esac");
+    let _ = writeln!(script, "    done");
+
+    // Values of enumerated options, wherever they appear
+    let _ = writeln!(script, "    case \"$prev\" in");
+    let all_args = global
+        .iter()
+        .copied()
+        .chain(visible_subcommands(cmd).into_iter().flat_map(visible_args));
+    for arg in all_args {
+        let values = possible_values(arg);
+        if values.is_empty() {
+            continue;
+        }
+        let names = flags(&[arg]).join("|");
+        let _ = writeln!(
+            script,
+            "        {}) COMPREPLY=($(compgen -W \"{}\" -- \"$cur\")); return ;;",
+            names,
+            values.join(" ")
+        );
+    }
+    let _ = writeln!(script, "    esac");
+
+    let subcommands: Vec<&str> = visible_subcommands(cmd)
+        .iter()
+        .map(|c| c.get_name())
+        .collect();
+    let _ = writeln!(script, "    case \"$sub\" in");
+    let _ = writeln!(
+        script,
+        "        \"\") COMPREPLY=($(compgen -W \"{} {}\" -- \"$cur\")) ;;",
+        subcommands.join(" "),
+        flags(&global).join(" ")
+    );
+    for sub in visible_subcommands(cmd) {
+        let mut words = flags(&visible_args(sub));
+        // Enumerated positionals, e.g. the shell of `completions`
+        for arg in sub.get_positionals() {
+            words.extend(possible_values(arg));
+        }
+        // Actions, e.g. of `index`, with the flags of any of them
+        for action in visible_subcommands(sub) {
+            words.push(action.get_name().to_string());
+            words.extend(flags(&visible_args(action)));
+        }
+        let sub_flags = words.join(" ");
+        if ITEM_COMMANDS.contains(&sub.get_name()) {
+            let _ = writeln!(script, "        {})", sub.get_name());
+            let _ = writeln!(script, "            if [[ \"$cur\" == -* ]]; then");
+            let _ = writeln!(
+                script,
+                "                COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))",
+                sub_flags
+            );
+            let _ = writeln!(script, "            else");
+            let _ = writeln!(
+                script,
+                "                COMPREPLY=($(compgen -W \"$({} {} 2>/dev/null | cut -f1)\" -- \"$cur\"))",
+                name, ITEMS_COMMAND
+            );
+            let _ = writeln!(script, "            fi ;;");
+        } else {
+            let _ = writeln!(
+                script,
+                "        {}) COMPREPLY=($(compgen -W \"{}\" -- \"$cur\")) ;;",
+                sub.get_name(),
+                sub_flags
+            );
+        }
+    }
+    let _ = writeln!(script, "    esac");
+    let _ = writeln!(script, "}}");
+    let _ = writeln!(script, "complete -F _{} {}", name, name);
+    script
+}
+
+fn zsh(cmd: &Command) -> String {
+    // zsh understands bash completion functions through bashcompinit
+    format!("autoload -U +X bashcompinit && bashcompinit\n{}", bash(cmd))
+}
+
+fn fish_escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('\'', "\\'")
+}
+
+fn fish_arg(script: &mut String, name: &str, condition: &str, arg: &Arg) {
+    let mut line = format!("complete -c {} -n '{}'", name, condition);
+    if let Some(short) = arg.get_short() {
+        let _ = write!(line, " -s {}", short);
+    }
+    if let Some(long) = arg.get_long() {
+        let _ = write!(line, " -l {}", long);
+    }
+    let values = possible_values(arg);
+    if !values.is_empty() {
+        let _ = write!(line, " -x -a '{}'", values.join(" "));
+    } else if takes_value(arg) {
+        line.push_str(" -r");
+    }
+    if let Some(help) = arg.get_help() {
+        let _ = write!(line, " -d '{}'", fish_escape(&help.to_string()));
+    }
+    let _ = writeln!(script, "{}", line);
+}
+
+fn fish(cmd: &Command) -> String {
+    let name = cmd.get_name();
+    let mut script = String::new();
+    let _ = writeln!(script, "complete -c {} -f", name);
+    for arg in visible_args(cmd) {
+        fish_arg(&mut script, name, "true", arg);
+    }
+    for sub in visible_subcommands(cmd) {
+        let about = sub.get_about().map(|a| a.to_string()).unwrap_or_default();
+        let _ = writeln!(
+            script,
+            "complete -c {} -n '__fish_use_subcommand' -a {} -d '{}'",
+            name,
+            sub.get_name(),
+            fish_escape(&about)
+        );
+        let condition = format!("__fish_seen_subcommand_from {}", sub.get_name());
+        for arg in visible_args(sub) {
+            fish_arg(&mut script, name, &condition, arg);
+        }
+        for arg in sub.get_positionals() {
+            let values = possible_values(arg);
+            if !values.is_empty() {
+                let _ = writeln!(
+                    script,
+                    "complete -c {} -n '{}' -a '{}'",
+                    name,
+                    condition,
+                    values.join(" ")
+                );
+            }
+        }
+        for action in visible_subcommands(sub) {
+            let about = action
+                .get_about()
+                .map(|a| a.to_string())
+                .unwrap_or_default();
+            let _ = writeln!(
+                script,
+                "complete -c {} -n '{}' -a {} -d '{}'",
+                name,
+                condition,
+                action.get_name(),
+                fish_escape(&about)
+            );
+            let condition = format!(
+                "{}; and __fish_seen_subcommand_from {}",
+                condition,
+                action.get_name()
+            );
+            for arg in visible_args(action) {
+                fish_arg(&mut script, name, &condition, arg);
+            }
+        }
+        if ITEM_COMMANDS.contains(&sub.get_name()) {
+            // Lines are `item<TAB>title`, fish shows the title as description
+            let _ = writeln!(
+                script,
+                "complete -c {} -n '{}' -a '({} {} 2>/dev/null)'",
+                name, condition, name, ITEMS_COMMAND
+            );
+        }
+    }
+    script
+}
+
+/// Print the completion script for `shell`
+pub fn print(cmd: &Command, shell: Shell) {
+    let script = match shell {
+        Shell::Bash => bash(cmd),
+        Shell::Zsh => zsh(cmd),
+        Shell::Fish => fish(cmd),
+    };
+    print!("{}", script);
+}
+
+/// Print `item<TAB>title` for every cache entry, used by the scripts above
+pub fn print_items(source_path: &Path) -> Result<(), error::Error> {
+    for video in get_video_list(source_path)? {
+        println!("{}\t{}", item_name(&video), video.info.title);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    // Names of the visible subcommands of `cmd` and below, and their flags
+    fn words(cmd: &Command) -> Vec<String> {
+        let mut found = flags(&visible_args(cmd));
+        for sub in visible_subcommands(cmd) {
+            found.push(sub.get_name().to_string());
+            found.extend(words(sub));
+        }
+        found
+    }
+
+    #[test]
+    fn scripts_complete_every_subcommand_and_flag() {
+        let cmd = crate::Args::command();
+        let words = words(&cmd);
+        assert!(words.iter().any(|w| w == "--cache-dir"));
+        assert!(words.iter().any(|w| w == "rebuild"));
+        for shell in [Shell::Bash, Shell::Fish] {
+            let script = match shell {
+                Shell::Fish => fish(&cmd),
+                _ => bash(&cmd),
+            };
+            let tokens: Vec<&str> = script
+                .split(|c: char| !(c.is_alphanumeric() || c == '-' || c == '_'))
+                .collect();
+            // fish names flags by `-l <long>` and `-s <short>`
+            let missing: Vec<&String> = words
+                .iter()
+                .filter(|word| match word.strip_prefix("--") {
+                    Some(long) if matches!(shell, Shell::Fish) => {
+                        !script.contains(&format!(" -l {} ", long))
+                    }
+                    None if word.starts_with('-') && matches!(shell, Shell::Fish) => {
+                        !script.contains(&format!(" -s {} ", &word[1..]))
+                    }
+                    _ => !tokens.contains(&word.as_str()),
+                })
+                .collect();
+            assert!(missing.is_empty(), "{:?} misses {:?}", shell, missing);
+        }
+    }
+}
@@ -0,0 +1,175 @@
+/// Joining the parts of a multi-part video into a single file
+///
+/// Every part is remuxed on its own first, then ffmpeg's concat demuxer
+/// joins them without re-encoding. Chapters mark where each part starts.
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use log::*;
+
+use crate::chapters::{self, Chapter};
+use crate::error::Context;
+use crate::layout::Output;
+use crate::profile::Profile;
+use crate::{
+    check_free_space, check_output, create_work_dir, error, ffmpeg, finish_output, get_video_list,
+    item_name, journal, metadata_tags, output_valid, part_path, playlists, probe, read_rate,
+    remove_work_dir, remux, rename_synced, signal, CachedVideo, ConvertOptions, VideoInfo,
+};
+
+/// Concat demuxer list, quotes are escaped by closing and reopening the quote
+pub fn write_list(files: &[PathBuf], path: &Path) -> Result<(), error::Error> {
+    let mut content = String::new();
+    for file in files {
+        let name = file.to_string_lossy().replace('\'', "'\\''");
+        let _ = writeln!(content, "file '{}'", name);
+    }
+    fs::write(path, content).context("write", path)?;
+    Ok(())
+}
+
+// One chapter per part, `None` if a duration is unknown
+fn part_chapters(ffmpeg: &ffmpeg::Ffmpeg, parts: &[(String, PathBuf)]) -> Option<Vec<Chapter>> {
+    let mut chapters = Vec::new();
+    let mut start = 0.0;
+    for (title, file) in parts {
+        match probe::duration(ffmpeg, file) {
+            Ok(duration) => {
+                chapters.push(Chapter {
+                    title: title.clone(),
+                    from: start,
+                    to: start + duration,
+                });
+                start += duration;
+            }
+            Err(e) => {
+                warn!(
+                    "Skip chapters, unable to get duration of {}: {}",
+                    file.display(),
+                    e
+                );
+                return None;
+            }
+        }
+    }
+    Some(chapters)
+}
+
+/// Convert every cached part of `group` into one video, returning its path
+pub fn convert(
+    source_path: &Path,
+    target_path: &Path,
+    group: &str,
+    options: &ConvertOptions,
+) -> Result<PathBuf, error::Error> {
+    let mut parts: Vec<_> = get_video_list(source_path)?
+        .into_iter()
+        .filter(|video| video.info.group_title == group)
+        .collect();
+    if parts.is_empty() {
+        return Err(error::Error::GroupNotFound(group.to_string()));
+    }
+    parts.sort_by_key(|video| video.info.p);
+    info!("Concat {} parts of {}", parts.len(), group);
+
+    // Remuxed parts and the joined video, plus the stripped temp files of one part
+    let total: u64 = parts.iter().map(|video| video.info.total_size).sum();
+    let largest = parts
+        .iter()
+        .map(|video| video.info.total_size)
+        .max()
+        .unwrap_or_default();
+    check_free_space(total + largest, total, target_path, options)?;
+
+    // The joined video is laid out like a single video titled after the group
+    let mut video_info = parts[0].info.clone();
+    video_info.title = group.to_string();
+
+    let work_path = create_work_dir(&format!(".concat-{}", video_info.item_id), options)?;
+    let result = join(&parts, &video_info, &work_path, target_path, options);
+    remove_work_dir(&work_path);
+    let output = result?;
+    finish_output(&parts[0].dir, &video_info, &output, options)?;
+    playlists::update(target_path, &video_info, options);
+
+    if options.autoremove && output_valid(&output.file) {
+        let journal = journal::Journal::new(target_path);
+        for part in &parts {
+            info!("Removing directory {}", part.dir.display());
+            let item = item_name(&part.dir);
+            journal.remove(&item, &part.dir, &output.file, options.permanent)?;
+        }
+    }
+    Ok(output.file)
+}
+
+fn join(
+    parts: &[CachedVideo],
+    video_info: &VideoInfo,
+    work_path: &Path,
+    target_path: &Path,
+    options: &ConvertOptions,
+) -> Result<Output, error::Error> {
+    let mut files = Vec::new();
+    for part in parts {
+        if signal::interrupted() {
+            return Err(error::Error::Interrupted);
+        }
+        info!("Part: {}", part.info);
+        let file = work_path.join(format!(
+            "{}.{}",
+            part.info.item_id,
+            options.layout.container.extension()
+        ));
+        remux(&part.dir, &part.info, work_path, &[], options, &file)?;
+        files.push((format!("{} {}", part.info.p, part.info.title), file));
+    }
+
+    let list_file = work_path.join("concat.txt");
+    let media: Vec<PathBuf> = files.iter().map(|(_, file)| file.clone()).collect();
+    write_list(&media, &list_file)?;
+
+    let chapters_file = work_path.join("chapters.ffmeta");
+    let chapters = match part_chapters(&options.ffmpeg, &files) {
+        Some(chapters) => {
+            chapters::write_ffmetadata(&chapters, &chapters_file)?;
+            Some(chapters_file.as_path())
+        }
+        None => None,
+    };
+
+    let output = options.layout.output(video_info, target_path);
+    fs::create_dir_all(&output.dir).context("create directory", &output.dir)?;
+    let part_file = part_path(&output.file);
+    let tags = metadata_tags(video_info);
+    let job = ffmpeg::MuxJob {
+        inputs: &[list_file],
+        concat: true,
+        read_rate: options
+            .io_limit
+            .and_then(|limit| read_rate(&options.ffmpeg, &media, 0, limit)),
+        skip_bytes: 0,
+        maps: &[],
+        // The parts are encoded already
+        profile: Profile::Copy,
+        transcode_audio: false,
+        chapters,
+        tags: &tags,
+        container: options.layout.container,
+        faststart: options.faststart,
+    };
+    let muxed = options
+        .muxer()
+        .mux(&job, &part_file)
+        .and_then(|_| check_output(&options.ffmpeg, &part_file))
+        .and_then(|_| rename_synced(&part_file, &output.file));
+    if let Err(e) = muxed {
+        let _ = fs::remove_file(&part_file);
+        if output.own_dir {
+            let _ = fs::remove_dir(&output.dir);
+        }
+        return Err(e);
+    }
+    Ok(output)
+}
@@ -0,0 +1,106 @@
+/// Container formats the outputs are written in
+///
+/// mp4 plays nearly everywhere, mkv holds any codec, subtitles and
+/// chapters, mov suits Apple editing tools and MPEG-TS streams to old set
+/// top boxes. The streams are the same in every container, only the
+/// arguments ffmpeg needs for the format differ.
+use clap::ValueEnum;
+
+use crate::profile::Profile;
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Default)]
+pub enum Container {
+    #[default]
+    Mp4,
+    /// Matroska
+    Mkv,
+    /// QuickTime
+    Mov,
+    /// MPEG transport stream
+    Ts,
+}
+
+impl Container {
+    /// Extension of the output files
+    pub fn extension(self) -> &'static str {
+        match self {
+            Container::Mp4 => "mp4",
+            Container::Mkv => "mkv",
+            Container::Mov => "mov",
+            Container::Ts => "ts",
+        }
+    }
+
+    /// Name of the ffmpeg muxer writing the container
+    pub fn format(self) -> &'static str {
+        match self {
+            Container::Mp4 => "mp4",
+            Container::Mkv => "matroska",
+            Container::Mov => "mov",
+            Container::Ts => "mpegts",
+        }
+    }
+
+    /// Arguments the container needs for streams of `profile`, after the
+    /// codec arguments of the profile
+    pub fn muxer_args(self, profile: Profile) -> Vec<&'static str> {
+        match self {
+            // hvc1 is the tag Apple players require for HEVC in mp4 and mov
+            Container::Mp4 | Container::Mov if profile == Profile::H265 => vec!["-tag:v", "hvc1"],
+            _ => Vec::new(),
+        }
+    }
+
+    /// Arguments moving the index to the front, so playback over a network
+    /// starts before the whole file is read. Other containers need none.
+    pub fn faststart_args(self) -> &'static [&'static str] {
+        match self {
+            Container::Mp4 | Container::Mov => &["-movflags", "+faststart"],
+            Container::Mkv | Container::Ts => &[],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ffmpeg::{Ffmpeg, MuxJob};
+    use crate::fixture::ScriptedRunner;
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn muxes_with_the_arguments_of_the_container() {
+        let runner = ScriptedRunner::new(|_| Some(String::new()));
+        let mut ffmpeg = Ffmpeg::new(PathBuf::from("ffmpeg"), Vec::new());
+        ffmpeg.runner = runner.clone();
+        let inputs = [PathBuf::from("video.m4s")];
+        for container in [Container::Mp4, Container::Mkv] {
+            let job = MuxJob {
+                inputs: &inputs,
+                concat: false,
+                read_rate: None,
+                skip_bytes: 0,
+                maps: &[],
+                profile: Profile::H265,
+                transcode_audio: false,
+                chapters: None,
+                tags: &[],
+                container,
+                faststart: true,
+            };
+            let output = format!("111.{}", container.extension());
+            ffmpeg.mux(&job, Path::new(&output)).unwrap();
+        }
+        let encode = "-c:v libx265 -crf 28 -preset medium -c:a copy";
+        assert_eq!(
+            runner.commands(),
+            [
+                format!(
+                    "ffmpeg -i video.m4s {} -tag:v hvc1 -movflags +faststart -y -f mp4 111.mp4",
+                    encode
+                ),
+                format!("ffmpeg -i video.m4s {} -y -f matroska 111.mkv", encode),
+            ]
+        );
+    }
+}
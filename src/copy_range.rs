@@ -0,0 +1,99 @@
+/// Copying part of a file inside the kernel
+///
+/// On Linux `copy_file_range` copies without passing the data through user
+/// space, and btrfs, XFS and NFS even share the blocks or copy on the
+/// server when the filesystem allows it. Other systems have no way to clone a
+/// range starting at the unaligned offset of the client's prefix bytes, so
+/// callers fall back to a streaming copy there.
+use std::fs::File;
+use std::io;
+
+#[cfg(target_os = "linux")]
+mod ffi {
+    use std::os::raw::{c_int, c_uint};
+
+    extern "C" {
+        pub fn copy_file_range(
+            fd_in: c_int,
+            off_in: *mut i64,
+            fd_out: c_int,
+            off_out: *mut i64,
+            len: usize,
+            flags: c_uint,
+        ) -> isize;
+    }
+}
+
+/// Copy up to `len` bytes at `offset` of `source` to `target_offset` of
+/// `target`, returning the bytes copied, 0 at the end of `source`. Neither
+/// file position moves.
+#[cfg(target_os = "linux")]
+pub fn copy(
+    source: &File,
+    offset: u64,
+    target: &File,
+    target_offset: u64,
+    len: u64,
+) -> io::Result<u64> {
+    use std::os::unix::io::AsRawFd;
+
+    let mut off_in = offset as i64;
+    let mut off_out = target_offset as i64;
+    let copied = unsafe {
+        ffi::copy_file_range(
+            source.as_raw_fd(),
+            &mut off_in,
+            target.as_raw_fd(),
+            &mut off_out,
+            len as usize,
+            0,
+        )
+    };
+    if copied < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(copied as u64)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn copy(_: &File, _: u64, _: &File, _: u64, _: u64) -> io::Result<u64> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+/// Whether `e` means the files can not be copied this way, rather than a
+/// failure a streaming copy would run into as well
+pub fn unsupported(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        // ENOSYS, EOPNOTSUPP, EXDEV before Linux 5.3, EINVAL on some
+        // filesystems and EPERM from seccomp filters of containers
+        io::ErrorKind::Unsupported
+            | io::ErrorKind::CrossesDevices
+            | io::ErrorKind::InvalidInput
+            | io::ErrorKind::PermissionDenied
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::fixture::TempDir;
+
+    #[test]
+    fn copies_range_at_unaligned_offset() {
+        let dir = TempDir::new();
+        let source = dir.path().join("source");
+        let target = dir.path().join("target");
+        fs::write(&source, b"000000000payload").unwrap();
+        let (from, to) = (File::open(&source).unwrap(), File::create(&target).unwrap());
+        match copy(&from, 9, &to, 0, 64) {
+            Ok(copied) => {
+                assert_eq!(copied, 7);
+                assert_eq!(fs::read(&target).unwrap(), b"payload");
+            }
+            Err(e) => assert!(unsupported(&e), "{}", e),
+        }
+    }
+}
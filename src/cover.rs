@@ -0,0 +1,170 @@
+/// Naming and converting cover art for TVs and media servers
+///
+/// The cover of an item is written as `poster.<ext>` and its group cover as
+/// `fanart.<ext>`, or as `--group-cover-name` says, the names Kodi and
+/// Jellyfin look for, unless `--original-cover-names` keeps the names of the
+/// cached files. Covers are often cached as WebP or AVIF, which many TVs can
+/// not show. With `--cover-format` or `--cover-max` they are converted with
+/// ffmpeg and shrunk to fit within the given size if larger; without either
+/// option covers are copied as they are.
+use std::path::Path;
+
+use clap::ValueEnum;
+
+use crate::error;
+use crate::ffmpeg::Ffmpeg;
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    /// JPEG, shown by nearly every TV and media server
+    Jpg,
+    /// Lossless PNG
+    Png,
+}
+
+impl Format {
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Jpg => "jpg",
+            Format::Png => "png",
+        }
+    }
+}
+
+/// How covers are converted
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Conversion {
+    pub format: Format,
+    /// Longest side in pixels, smaller covers are not enlarged
+    pub max: Option<u32>,
+}
+
+impl Conversion {
+    /// Conversion asked for by `--cover-format` and `--cover-max`, if any
+    pub fn new(format: Option<Format>, max: Option<u32>) -> Option<Conversion> {
+        if format.is_none() && max.is_none() {
+            return None;
+        }
+        Some(Conversion {
+            format: format.unwrap_or(Format::Jpg),
+            max,
+        })
+    }
+}
+
+/// Name the group cover is written under
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum GroupCover {
+    /// `fanart.<ext>`, the backdrop behind the item
+    Fanart,
+    /// `season-poster.<ext>`, the poster of the season holding the item
+    SeasonPoster,
+    /// `folder.<ext>`, the picture of the directory
+    Folder,
+}
+
+impl GroupCover {
+    pub fn name(self) -> &'static str {
+        match self {
+            GroupCover::Fanart => "fanart",
+            GroupCover::SeasonPoster => "season-poster",
+            GroupCover::Folder => "folder",
+        }
+    }
+}
+
+/// Name a cover cached as `original` is written under: `name`, or the
+/// original one if None, with the extension of `conversion` if converted
+pub fn file_name(original: &str, name: Option<&str>, conversion: Option<Conversion>) -> String {
+    let path = Path::new(original);
+    let extension = match conversion {
+        Some(conversion) => conversion.format.extension().to_string(),
+        None => match path.extension() {
+            Some(extension) => extension.to_string_lossy().to_string(),
+            None if name.is_none() => return original.to_string(),
+            None => "jpg".to_string(),
+        },
+    };
+    let stem = match name {
+        Some(name) => name.to_string(),
+        None => path
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string(),
+    };
+    format!("{}.{}", stem, extension)
+}
+
+/// Convert the image `source` into `target` as `conversion` says
+pub fn convert(
+    ffmpeg: &Ffmpeg,
+    source: &Path,
+    target: &Path,
+    conversion: Conversion,
+) -> Result<(), error::Error> {
+    // ffmpeg -i source [-vf scale=...] -frames:v 1 -update 1 [-q:v 2] -y target
+    let mut cmd = ffmpeg.command();
+    cmd.arg("-i").arg(source);
+    if let Some(max) = conversion.max {
+        cmd.arg("-vf").arg(format!(
+            "scale='min(iw,{0})':'min(ih,{0})':force_original_aspect_ratio=decrease",
+            max
+        ));
+    }
+    cmd.args(["-frames:v", "1", "-update", "1"]);
+    if conversion.format == Format::Jpg {
+        cmd.args(["-q:v", "2"]);
+    }
+    cmd.args(ffmpeg.thread_args());
+    cmd.arg("-y").arg(target);
+    ffmpeg.run(cmd)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::ScriptedRunner;
+    use std::path::PathBuf;
+
+    #[test]
+    fn converts_and_shrinks_covers() {
+        assert_eq!(Conversion::new(None, None), None);
+        let conversion = Conversion::new(None, Some(1920)).unwrap();
+        assert_eq!(file_name("d81c.webp", Some("poster"), None), "poster.webp");
+        assert_eq!(file_name("d81c.webp", None, Some(conversion)), "d81c.jpg");
+        assert_eq!(
+            file_name("d81c", Some(GroupCover::SeasonPoster.name()), None),
+            "season-poster.jpg"
+        );
+        assert_eq!(file_name("d81c", None, None), "d81c");
+
+        let runner = ScriptedRunner::new(|_| Some(String::new()));
+        let mut ffmpeg = Ffmpeg::new(PathBuf::from("ffmpeg"), Vec::new());
+        ffmpeg.runner = runner.clone();
+        convert(
+            &ffmpeg,
+            Path::new("cover.webp"),
+            Path::new("poster.jpg"),
+            conversion,
+        )
+        .unwrap();
+        let png = Conversion::new(Some(Format::Png), None).unwrap();
+        convert(
+            &ffmpeg,
+            Path::new("cover.avif"),
+            Path::new("folder.png"),
+            png,
+        )
+        .unwrap();
+        assert_eq!(
+            runner.commands(),
+            [
+                "ffmpeg -i cover.webp -vf \
+                 'scale='\\''min(iw,1920)'\\'':'\\''min(ih,1920)'\\'':force_original_aspect_ratio=decrease' \
+                 -frames:v 1 -update 1 -q:v 2 -y poster.jpg",
+                "ffmpeg -i cover.avif -frames:v 1 -update 1 -y folder.png"
+            ]
+        );
+    }
+}
@@ -0,0 +1,344 @@
+/// Danmaku next to converted videos, and burned into a variant for players
+/// that cannot overlay ASS subtitles
+///
+/// Caches hold danmaku as the XML of the Bilibili API or, converted by the
+/// client, as ASS. XML is laid out here: scrolling comments move across the
+/// screen right to left in rows, top and bottom ones stay centered for a few
+/// seconds. Advanced and scripted comments are left out.
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use log::*;
+
+use crate::container::Container;
+use crate::error::{self, Context};
+use crate::ffmpeg::Ffmpeg;
+use crate::layout::Output;
+use crate::profile::Profile;
+use crate::ConvertOptions;
+
+const WIDTH: f64 = 1920.0;
+const HEIGHT: f64 = 1080.0;
+/// Font size of comments in the default size 25
+const FONT_SIZE: f64 = 48.0;
+const SCROLL_SECS: f64 = 8.0;
+const FIXED_SECS: f64 = 4.0;
+/// Space kept between scrolling comments in a row
+const GAP: f64 = 32.0;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Mode {
+    Scroll,
+    Top,
+    Bottom,
+}
+
+struct Comment {
+    time: f64,
+    mode: Mode,
+    size: f64,
+    color: u32,
+    text: String,
+}
+
+impl Comment {
+    fn width(&self) -> f64 {
+        // Full width characters take about twice the space of ASCII
+        let units: usize = self
+            .text
+            .chars()
+            .map(|c| if c.is_ascii() { 1 } else { 2 })
+            .sum();
+        units as f64 * self.size / 2.0
+    }
+}
+
+fn unescape(text: &str) -> String {
+    let mut result = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        result.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let Some(end) = rest.find(';') else {
+            break;
+        };
+        let entity = &rest[1..end];
+        let decoded = match entity {
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "amp" => Some('&'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => entity
+                .strip_prefix("#x")
+                .map(|hex| u32::from_str_radix(hex, 16).ok())
+                .unwrap_or_else(|| entity.strip_prefix('#').and_then(|n| n.parse().ok()))
+                .and_then(char::from_u32),
+        };
+        match decoded {
+            Some(c) => {
+                result.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                result.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+// Comments look like `<d p="12.5,1,25,16777215,...">text</d>`
+fn parse(xml: &str) -> Vec<Comment> {
+    let mut comments = Vec::new();
+    for element in xml.split("<d p=\"").skip(1) {
+        let Some((attributes, rest)) = element.split_once("\">") else {
+            continue;
+        };
+        let Some((text, _)) = rest.split_once("</d>") else {
+            continue;
+        };
+        let fields: Vec<&str> = attributes.split(',').collect();
+        let number = |i: usize| fields.get(i).and_then(|f| f.parse::<f64>().ok());
+        let mode = match number(1).unwrap_or(1.0) as u32 {
+            1..=3 => Mode::Scroll,
+            4 => Mode::Bottom,
+            5 => Mode::Top,
+            _ => continue,
+        };
+        let text = unescape(text);
+        if text.trim().is_empty() {
+            continue;
+        }
+        comments.push(Comment {
+            time: number(0).unwrap_or_default(),
+            mode,
+            size: number(2).unwrap_or(25.0) * FONT_SIZE / 25.0,
+            color: number(3).unwrap_or(16777215.0) as u32,
+            text,
+        });
+    }
+    comments.sort_by(|a, b| a.time.total_cmp(&b.time));
+    comments
+}
+
+fn timestamp(secs: f64) -> String {
+    let centis = (secs.max(0.0) * 100.0).round() as u64;
+    format!(
+        "{}:{:02}:{:02}.{:02}",
+        centis / 360_000,
+        centis / 6000 % 60,
+        centis / 100 % 60,
+        centis % 100
+    )
+}
+
+// Override tags cannot be escaped in ASS, so braces become full width
+fn escape(text: &str) -> String {
+    text.replace('\\', "＼")
+        .replace('{', "｛")
+        .replace('}', "｝")
+        .replace('\n', "\\N")
+}
+
+/// Lay out danmaku XML as ASS subtitles
+fn to_ass(xml: &str) -> String {
+    let mut ass = format!(
+        "[Script Info]\nScriptType: v4.00+\nPlayResX: {}\nPlayResY: {}\nWrapStyle: 2\n\n\
+         [V4+ Styles]\nFormat: Name, Fontname, Fontsize, PrimaryColour, SecondaryColour, OutlineColour, \
+         BackColour, Bold, Italic, Underline, StrikeOut, ScaleX, ScaleY, Spacing, Angle, BorderStyle, \
+         Outline, Shadow, Alignment, MarginL, MarginR, MarginV, Encoding\n\
+         Style: Danmaku,sans-serif,{},&H33FFFFFF,&H33FFFFFF,&H33000000,&H00000000,1,0,0,0,100,100,0,0,1,1,0,7,0,0,0,1\n\n\
+         [Events]\nFormat: Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text\n",
+        WIDTH, HEIGHT, FONT_SIZE
+    );
+    let rows = (HEIGHT / FONT_SIZE) as usize;
+    // Time from which the next comment fits into each row
+    let mut scroll_rows = vec![0.0; rows];
+    let mut top_rows = vec![0.0; rows];
+    let mut bottom_rows = vec![0.0; rows];
+
+    for comment in parse(xml) {
+        let duration = if comment.mode == Mode::Scroll {
+            SCROLL_SECS
+        } else {
+            FIXED_SECS
+        };
+        let width = comment.width();
+        let speed = (WIDTH + width) / SCROLL_SECS;
+        let free = match comment.mode {
+            Mode::Scroll => &mut scroll_rows,
+            Mode::Top => &mut top_rows,
+            Mode::Bottom => &mut bottom_rows,
+        };
+        // The first free row, or the one freed the soonest
+        let row = free
+            .iter()
+            .position(|t| *t <= comment.time)
+            .unwrap_or_else(|| {
+                (0..rows)
+                    .min_by(|a, b| free[*a].total_cmp(&free[*b]))
+                    .unwrap_or_default()
+            });
+        free[row] = comment.time
+            + match comment.mode {
+                Mode::Scroll => (width + GAP) / speed,
+                _ => duration,
+            };
+
+        let y = row as f64 * FONT_SIZE;
+        let position = match comment.mode {
+            Mode::Scroll => format!("\\move({},{:.0},{:.0},{:.0})", WIDTH, y, -width, y),
+            Mode::Top => format!("\\an8\\pos({},{:.0})", WIDTH / 2.0, y),
+            Mode::Bottom => format!("\\an2\\pos({},{:.0})", WIDTH / 2.0, HEIGHT - y),
+        };
+        // ASS colors are BGR
+        let color = comment.color & 0xFFFFFF;
+        let color = if color == 0xFFFFFF {
+            String::new()
+        } else {
+            let bgr = (color & 0xFF) << 16 | (color & 0xFF00) | color >> 16;
+            format!("\\c&H{:06X}&", bgr)
+        };
+        let size = if comment.size == FONT_SIZE {
+            String::new()
+        } else {
+            format!("\\fs{:.0}", comment.size)
+        };
+        let _ = writeln!(
+            ass,
+            "Dialogue: 0,{},{},Danmaku,,0,0,0,,{{{}{}{}}}{}",
+            timestamp(comment.time),
+            timestamp(comment.time + duration),
+            position,
+            color,
+            size,
+            escape(&comment.text)
+        );
+    }
+    ass
+}
+
+fn has_extension(path: &Path, extension: &str) -> bool {
+    path.extension().is_some_and(|e| e == extension)
+}
+
+// Converted ASS of the client is preferred over the XML
+fn find(dir: &Path) -> Option<PathBuf> {
+    let mut files: Vec<PathBuf> = dir
+        .read_dir()
+        .ok()?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .collect();
+    files.sort();
+    let ass = files.iter().find(|f| has_extension(f, "ass"));
+    ass.or_else(|| files.iter().find(|f| has_extension(f, "xml")))
+        .cloned()
+}
+
+/// The burned variant of `output`, `<name>.danmaku.<ext>`
+pub fn variant_path(output: &Output) -> PathBuf {
+    let stem = output
+        .file
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy();
+    let extension = output
+        .file
+        .extension()
+        .unwrap_or_default()
+        .to_string_lossy();
+    output
+        .file
+        .with_file_name(format!("{}.danmaku.{}", stem, extension))
+}
+
+fn render(
+    ffmpeg: &Ffmpeg,
+    video: &Path,
+    work_path: &Path,
+    profile: Profile,
+    container: Container,
+    faststart: bool,
+    target: &Path,
+) -> Result<(), error::Error> {
+    // ffmpeg -i video -vf subtitles=danmaku.ass <profile codecs> [container args]
+    //        [-movflags +faststart] [-threads N] -f format -y target
+    // Run in the work directory, so the subtitles path needs no filter escaping
+    let video = std::path::absolute(video).context("resolve", video)?;
+    let target = std::path::absolute(target).context("resolve", target)?;
+    let mut cmd = ffmpeg.command();
+    cmd.current_dir(work_path);
+    cmd.arg("-i").arg(&video);
+    cmd.args(["-vf", "subtitles=danmaku.ass"]);
+    cmd.args(profile.codec_args())
+        .args(container.muxer_args(profile));
+    if faststart {
+        cmd.args(container.faststart_args());
+    }
+    cmd.args(ffmpeg.thread_args());
+    cmd.args(["-f", container.format(), "-y"]).arg(&target);
+    ffmpeg.run(cmd)
+}
+
+/// Profile used to burn danmaku, which always re-encodes
+pub fn profile(options: &ConvertOptions) -> Profile {
+    match options.profile {
+        Profile::Copy => Profile::H264,
+        profile => profile,
+    }
+}
+
+/// With `--burn-danmaku`, keep the danmaku of the cache item in `dir` next
+/// to its output as ASS and burn it into a variant of the video. The video
+/// itself is fine without, so failures are only warned about.
+pub fn burn(dir: &Path, output: &Output, work_path: &Path, options: &ConvertOptions) {
+    if !options.burn_danmaku {
+        return;
+    }
+    let Some(source) = find(dir) else {
+        debug!("No danmaku in {}", dir.display());
+        return;
+    };
+    let ass = work_path.join("danmaku.ass");
+    let written = fs::read_to_string(&source)
+        .context("read", &source)
+        .and_then(|content| {
+            let content = if has_extension(&source, "xml") {
+                to_ass(&content)
+            } else {
+                content
+            };
+            fs::write(&ass, content).context("write", &ass)
+        })
+        .and_then(|_| {
+            let kept = output.side_file("danmaku.ass");
+            fs::copy(&ass, &kept).context("copy", &ass).map(|_| ())
+        });
+    if let Err(e) = written {
+        warn!("Skip danmaku of {}: {}", dir.display(), e);
+        return;
+    }
+
+    let target = variant_path(output);
+    let part = crate::part_path(&target);
+    info!("Burning danmaku into {}", target.display());
+    let rendered = render(
+        &options.ffmpeg,
+        &output.file,
+        work_path,
+        profile(options),
+        options.layout.container,
+        options.faststart,
+        &part,
+    )
+    .and_then(|_| fs::rename(&part, &target).context("rename", &part));
+    if let Err(e) = rendered {
+        let _ = fs::remove_file(&part);
+        warn!("Failed to burn danmaku into {}: {}", target.display(), e);
+    }
+    let _ = fs::remove_file(&ass);
+}
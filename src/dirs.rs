@@ -0,0 +1,91 @@
+/// Default locations of the cache and the output directory
+///
+/// On macOS and Windows they are where the desktop client keeps its cache.
+/// Linux has no Movies folder, so there the videos directory of the XDG
+/// user dirs is used, unless the old `~/Movies` defaults exist already.
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use log::*;
+
+#[cfg(not(windows))]
+const LEGACY_SOURCE_DIR: &str = "Movies/bilibili";
+#[cfg(not(windows))]
+const LEGACY_TARGET_DIR: &str = "Movies/output";
+#[cfg(windows)]
+const LEGACY_SOURCE_DIR: &str = r"Videos\bilibili";
+#[cfg(windows)]
+const LEGACY_TARGET_DIR: &str = r"Videos\output";
+
+const SOURCE_NAME: &str = "bilibili";
+const TARGET_NAME: &str = "output";
+
+/// The cache directory to convert from and the directory outputs go to
+#[derive(Debug, Clone)]
+pub struct Dirs {
+    pub source: PathBuf,
+    pub target: PathBuf,
+}
+
+impl Dirs {
+    /// Resolve the directories below `home`, `source` overrides the cache
+    pub fn resolve(home: &Path, source: Option<PathBuf>) -> Dirs {
+        let (default_source, target) = if cfg!(target_os = "linux") {
+            let videos = videos_dir(home);
+            (
+                existing_or(home.join(LEGACY_SOURCE_DIR), videos.join(SOURCE_NAME)),
+                existing_or(home.join(LEGACY_TARGET_DIR), videos.join(TARGET_NAME)),
+            )
+        } else {
+            (home.join(LEGACY_SOURCE_DIR), home.join(LEGACY_TARGET_DIR))
+        };
+        Dirs {
+            source: source.unwrap_or(default_source),
+            target,
+        }
+    }
+}
+
+// Directories used by older versions keep being used once they exist
+fn existing_or(legacy: PathBuf, path: PathBuf) -> PathBuf {
+    if legacy.is_dir() {
+        debug!("Using existing directory {}", legacy.display());
+        legacy
+    } else {
+        path
+    }
+}
+
+/// XDG videos directory from `XDG_VIDEOS_DIR` or `user-dirs.dirs`,
+/// `~/Videos` if neither sets it
+fn videos_dir(home: &Path) -> PathBuf {
+    if let Some(dir) = env::var_os("XDG_VIDEOS_DIR").filter(|d| !d.is_empty()) {
+        return PathBuf::from(dir);
+    }
+    let config = match env::var_os("XDG_CONFIG_HOME") {
+        Some(config) if !config.is_empty() => PathBuf::from(config),
+        _ => home.join(".config"),
+    };
+    fs::read_to_string(config.join("user-dirs.dirs"))
+        .ok()
+        .and_then(|content| user_dir(&content, "XDG_VIDEOS_DIR", home))
+        .unwrap_or_else(|| home.join("Videos"))
+}
+
+// A line like `XDG_VIDEOS_DIR="$HOME/Videos"` of `user-dirs.dirs`, where
+// paths are either absolute or relative to `$HOME`
+fn user_dir(content: &str, key: &str, home: &Path) -> Option<PathBuf> {
+    let value = content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.starts_with('#'))
+        .find_map(|line| line.strip_prefix(key)?.trim_start().strip_prefix('='))?
+        .trim()
+        .trim_matches('"');
+    match value.strip_prefix("$HOME") {
+        Some(rest) => Some(home.join(rest.trim_start_matches('/'))),
+        None if value.starts_with('/') => Some(PathBuf::from(value)),
+        None => None,
+    }
+}
@@ -0,0 +1,129 @@
+use std::path::Path;
+use std::process::Command;
+
+use crate::error::{self, Context};
+
+// The `df` line of the filesystem containing `path`, split into columns.
+// A path that does not exist yet is resolved to its closest existing ancestor.
+fn df(path: &Path) -> Result<Vec<String>, error::Error> {
+    let path = path
+        .ancestors()
+        .find(|p| p.exists())
+        .ok_or(error::Error::DiskSpaceUnavailable)?;
+    // df -P -k <path>
+    // Filesystem 1024-blocks Used Available Capacity Mounted on
+    // /dev/disk1s1 488245288 123456 364788832 26% /
+    let output = Command::new("df").arg("-P").arg("-k").arg(path).output()?;
+    if !output.status.success() {
+        return Err(error::Error::DiskSpaceUnavailable);
+    }
+    let stdout = String::from_utf8(output.stdout)?;
+    let line = stdout
+        .lines()
+        .nth(1)
+        .ok_or(error::Error::DiskSpaceUnavailable)?;
+    Ok(line.split_whitespace().map(String::from).collect())
+}
+
+/// Return the number of bytes available to unprivileged users on the
+/// filesystem containing `path`.
+///
+/// The value is read from POSIX `df` output, so it works the same on macOS
+/// and Linux without linking against platform specific APIs.
+pub fn available_space(path: &Path) -> Result<u64, error::Error> {
+    let available = df(path)?
+        .get(3)
+        .and_then(|blocks| blocks.parse::<u64>().ok())
+        .ok_or(error::Error::DiskSpaceUnavailable)?;
+    Ok(available * 1024)
+}
+
+/// Whether two paths are on the same filesystem, false if unknown
+pub fn same_filesystem(a: &Path, b: &Path) -> bool {
+    match (df(a), df(b)) {
+        (Ok(a), Ok(b)) => !a.is_empty() && a.first() == b.first(),
+        _ => false,
+    }
+}
+
+/// Total size in bytes of all files below `path`
+pub fn dir_size(path: &Path) -> Result<u64, error::Error> {
+    let mut size = 0;
+    for entry in path.read_dir().context("read directory", path)? {
+        let entry = entry.context("read directory", path)?;
+        let metadata = entry
+            .metadata()
+            .context("read metadata of", &entry.path())?;
+        if metadata.is_dir() {
+            size += dir_size(&entry.path())?;
+        } else {
+            size += metadata.len();
+        }
+    }
+    Ok(size)
+}
+
+/// Format a byte count with binary units, e.g. `1.4 GiB`
+pub fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["KiB", "MiB", "GiB", "TiB", "PiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+/// Parse a size like `500M`, `20G` or `1.5TiB` with binary units, plain
+/// numbers are bytes
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let split = s
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("invalid size '{}', expected e.g. 20G", s))?;
+    let exponent = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 0,
+        "K" | "KB" | "KIB" => 1,
+        "M" | "MB" | "MIB" => 2,
+        "G" | "GB" | "GIB" => 3,
+        "T" | "TB" | "TIB" => 4,
+        _ => return Err(format!("invalid size unit '{}' in '{}'", unit, s)),
+    };
+    Ok((number * 1024f64.powi(exponent)) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_sizes_with_binary_units() {
+        assert_eq!(parse_size("123"), Ok(123));
+        assert_eq!(parse_size("500M"), Ok(500 << 20));
+        assert_eq!(parse_size(" 20 gb "), Ok(20 << 30));
+        assert_eq!(parse_size("1.5TiB"), Ok(3 << 39));
+        assert_eq!(parse_size("4KiB"), Ok(4096));
+        assert!(parse_size("12X").is_err());
+        assert!(parse_size("G").is_err());
+        assert!(parse_size("-1G").is_err());
+    }
+
+    #[test]
+    fn sizes_are_shown_in_binary_units() {
+        assert_eq!(human_size(0), "0 B");
+        assert_eq!(human_size(1023), "1023 B");
+        assert_eq!(human_size(1024), "1.0 KiB");
+        assert_eq!(human_size(1536), "1.5 KiB");
+        assert_eq!(human_size(5 << 30), "5.0 GiB");
+        assert_eq!(human_size(3 << 50), "3.0 PiB");
+        assert_eq!(human_size(2048 << 50), "2048.0 PiB");
+    }
+}
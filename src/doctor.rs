@@ -0,0 +1,403 @@
+/// Finding broken cache items and leftovers of interrupted runs
+///
+/// Problems in the cache are only reported, as the client owns it. In the
+/// output and work directories temp files of crashed conversions can be
+/// removed and items stuck in the converting state reset with `--fix`, which
+/// takes the locks of a conversion, see `lock`, so it can not pull files
+/// from under a running one.
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::error::Context;
+use crate::i18n::tr;
+use crate::{
+    error, ignore, ignored, item_dirs, legacy, mp4, state, ConvertOptions, VideoInfo,
+    VIDEO_METADATA_FILE,
+};
+
+enum Fix {
+    RemoveFile(PathBuf),
+    RemoveDir(PathBuf),
+    /// Mark an item stuck in `Converting` as interrupted
+    ResetState(String),
+}
+
+struct Problem {
+    path: PathBuf,
+    description: String,
+    suggestion: String,
+    fix: Option<Fix>,
+}
+
+impl Problem {
+    fn new(path: &Path, description: &str, suggestion: &str, fix: Option<Fix>) -> Problem {
+        Problem {
+            path: path.to_path_buf(),
+            description: description.to_string(),
+            suggestion: suggestion.to_string(),
+            fix,
+        }
+    }
+}
+
+fn entries(dir: &Path) -> Result<Vec<PathBuf>, error::Error> {
+    let mut entries: Vec<PathBuf> = dir
+        .read_dir()
+        .context("read directory", dir)?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .collect();
+    entries.sort();
+    Ok(entries)
+}
+
+fn has_extension(path: &Path, extension: &str) -> bool {
+    path.extension().is_some_and(|e| e == extension)
+}
+
+fn check_cache_item(dir: &Path, problems: &mut Vec<Problem>) -> Result<(), error::Error> {
+    let files = entries(dir)?;
+    if files.is_empty() {
+        problems.push(Problem::new(
+            dir,
+            tr!("empty cache directory"),
+            tr!("remove it"),
+            Some(Fix::RemoveDir(dir.to_path_buf())),
+        ));
+        return Ok(());
+    }
+    let metadata = dir.join(VIDEO_METADATA_FILE);
+    if !metadata.is_file() {
+        problems.push(Problem::new(
+            dir,
+            tr!("no .videoInfo metadata"),
+            tr!("download it again in the client, or remove it with clean if the client forgot it"),
+            None,
+        ));
+    } else if let Err(e) = fs::read_to_string(&metadata)
+        .map_err(error::Error::from)
+        .and_then(|content| Ok(VideoInfo::parse(&content)?))
+    {
+        problems.push(Problem::new(
+            &metadata,
+            &tr!("unreadable metadata: {}", e),
+            tr!("download it again in the client"),
+            None,
+        ));
+    }
+
+    let media: Vec<&PathBuf> = files.iter().filter(|f| has_extension(f, "m4s")).collect();
+    if media.is_empty() && metadata.is_file() {
+        problems.push(Problem::new(
+            dir,
+            tr!("metadata without any m4s media files"),
+            tr!("finish the download in the client, or remove it with clean"),
+            None,
+        ));
+    }
+    let mut empty = false;
+    for file in &media {
+        if file.metadata().map(|m| m.len()).unwrap_or_default() == 0 {
+            empty = true;
+            problems.push(Problem::new(
+                file,
+                tr!("empty media segment"),
+                tr!("download it again in the client"),
+                None,
+            ));
+        }
+    }
+
+    // The client keeps the video and the audio of an item in an m4s file
+    // each, one left without the other lost its file. Empty files have no
+    // stream to tell.
+    let streams: Vec<bool> = media
+        .iter()
+        .filter_map(|file| mp4::sample_entry(file).ok().flatten())
+        .map(|entry| entry.video)
+        .collect();
+    let missing = match (streams.contains(&true), streams.contains(&false)) {
+        (true, false) => Some(tr!("metadata of a video whose audio m4s file is missing")),
+        (false, true) => Some(tr!("metadata of a video whose video m4s file is missing")),
+        _ => None,
+    };
+    if let Some(description) = missing.filter(|_| metadata.is_file() && !empty) {
+        problems.push(Problem::new(
+            dir,
+            description,
+            tr!("download it again in the client"),
+            None,
+        ));
+    }
+    Ok(())
+}
+
+// A page of a legacy cache, see `legacy`
+fn check_legacy_page(dir: &Path, problems: &mut Vec<Problem>) {
+    if let Err(e) = legacy::video_info(dir) {
+        problems.push(Problem::new(
+            &dir.join(legacy::ENTRY_FILE),
+            &tr!("unreadable metadata: {}", e),
+            tr!("download it again in the client"),
+            None,
+        ));
+        return;
+    }
+    let Ok(segments) = legacy::segments(dir) else {
+        problems.push(Problem::new(
+            dir,
+            tr!("entry.json without any FLV segments"),
+            tr!("finish the download in the client, or remove it with clean"),
+            None,
+        ));
+        return;
+    };
+    for segment in segments {
+        if segment.metadata().map(|m| m.len()).unwrap_or_default() == 0 {
+            problems.push(Problem::new(
+                &segment,
+                tr!("empty media segment"),
+                tr!("download it again in the client"),
+                None,
+            ));
+        }
+    }
+}
+
+const WORK_DIR_PREFIXES: [&str; 3] = [".convert-", ".download-", ".concat-"];
+
+fn is_work_dir(name: &str) -> bool {
+    WORK_DIR_PREFIXES
+        .iter()
+        .any(|prefix| name.starts_with(prefix))
+}
+
+// Temp files are named by `part_path` and `fetch::download`, work
+// directories by `create_work_dir`. Older versions kept them all here.
+fn check_output_dir(
+    dir: &Path,
+    top_level: bool,
+    problems: &mut Vec<Problem>,
+) -> Result<(), error::Error> {
+    for path in entries(dir)? {
+        let name = path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string();
+        if path.is_dir() {
+            if is_work_dir(&name) {
+                problems.push(Problem::new(
+                    &path,
+                    tr!("work directory of an interrupted run"),
+                    tr!("remove it"),
+                    Some(Fix::RemoveDir(path.clone())),
+                ));
+            } else {
+                check_output_dir(&path, false, problems)?;
+            }
+        } else if has_extension(&path, "part") || has_extension(&path, "download") {
+            problems.push(Problem::new(
+                &path,
+                tr!("incomplete file of an interrupted run"),
+                tr!("remove it"),
+                Some(Fix::RemoveFile(path.clone())),
+            ));
+        } else if top_level
+            && (has_extension(&path, "m4s")
+                || has_extension(&path, "ffmeta")
+                || name.ends_with(".json.tmp"))
+        {
+            problems.push(Problem::new(
+                &path,
+                tr!("temp file of an interrupted conversion"),
+                tr!("remove it"),
+                Some(Fix::RemoveFile(path.clone())),
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn check_work_dir(dir: &Path, problems: &mut Vec<Problem>) -> Result<(), error::Error> {
+    for path in entries(dir)? {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        if path.is_dir() && is_work_dir(&name) {
+            problems.push(Problem::new(
+                &path,
+                tr!("work directory of an interrupted run"),
+                tr!("remove it"),
+                Some(Fix::RemoveDir(path.clone())),
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn apply(fix: &Fix, db: &mut state::StateDb) -> Result<(), error::Error> {
+    match fix {
+        Fix::RemoveFile(path) => fs::remove_file(path).context("remove", path)?,
+        Fix::RemoveDir(path) => fs::remove_dir_all(path).context("remove", path)?,
+        Fix::ResetState(item) => db.set(item, state::Status::Interrupted, None),
+    }
+    Ok(())
+}
+
+// Problems in the cache, the output directory with its state `db` and the
+// work directory
+fn check(
+    source_path: &Path,
+    target_path: &Path,
+    db: &state::StateDb,
+    options: &ConvertOptions,
+) -> Result<Vec<Problem>, error::Error> {
+    let mut problems = Vec::new();
+    let ignore = ignore::Ignore::load(source_path, &options.exclude)?;
+    for dir in entries(source_path)?.iter().filter(|dir| dir.is_dir()) {
+        for item in item_dirs(dir) {
+            if ignored(&ignore, &item) {
+                continue;
+            }
+            if legacy::is_item(&item) {
+                check_legacy_page(&item, &mut problems);
+            } else {
+                check_cache_item(&item, &mut problems)?;
+            }
+        }
+    }
+
+    if target_path.is_dir() {
+        check_output_dir(target_path, true, &mut problems)?;
+        for (item, s) in db.items() {
+            if s.status == state::Status::Converting {
+                problems.push(Problem::new(
+                    &target_path.join(item),
+                    tr!("conversion never finished"),
+                    tr!("mark it interrupted so it is converted again"),
+                    Some(Fix::ResetState(item.clone())),
+                ));
+            }
+        }
+    }
+    // Already checked if it is the output directory
+    if options.work_dir.is_dir() && options.work_dir != target_path {
+        check_work_dir(&options.work_dir, &mut problems)?;
+    }
+    Ok(problems)
+}
+
+/// Report problems in the cache and the output directory, repairing the
+/// safe ones if `fix` is set
+pub fn run(
+    source_path: &Path,
+    target_path: &Path,
+    fix: bool,
+    options: &ConvertOptions,
+) -> Result<(), error::Error> {
+    let mut db = state::StateDb::load(target_path)?;
+    let problems = check(source_path, target_path, &db, options)?;
+
+    let mut fixed = 0;
+    for problem in &problems {
+        println!("{}: {}", problem.path.display(), problem.description);
+        let Some(repair) = problem.fix.as_ref().filter(|_| fix) else {
+            println!("{}", tr!("  suggested: {}", problem.suggestion));
+            continue;
+        };
+        match apply(repair, &mut db) {
+            Ok(_) => {
+                println!("{}", tr!("  fixed"));
+                fixed += 1;
+            }
+            Err(e) => error!("Failed to fix {}: {}", problem.path.display(), e),
+        }
+    }
+    if fixed > 0 {
+        db.save()?;
+    }
+
+    let fixable = problems.iter().filter(|p| p.fix.is_some()).count();
+    if problems.is_empty() {
+        println!("{}", tr!("No problems found"));
+    } else if fix {
+        println!(
+            "{}",
+            tr!("{} problems found, {} fixed", problems.len(), fixed)
+        );
+    } else {
+        println!(
+            "{}",
+            tr!(
+                "{} problems found, {} can be fixed with --fix",
+                problems.len(),
+                fixable
+            )
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::{self, Item, StubMuxer, TempDir};
+
+    // A page of a legacy cache below `cache`, with FLV segments `segments`
+    fn legacy_page(cache: &Path, avid: u64, segments: &[&str]) -> PathBuf {
+        let page = cache.join(avid.to_string()).join("1");
+        fs::create_dir_all(page.join("16")).unwrap();
+        let entry = serde_json::json!({ "title": "Legacy", "avid": avid, "type_tag": "16" });
+        fs::write(page.join(legacy::ENTRY_FILE), entry.to_string()).unwrap();
+        for (n, content) in segments.iter().enumerate() {
+            fs::write(page.join(format!("16/{}.blv", n)), content).unwrap();
+        }
+        page
+    }
+
+    #[test]
+    fn check_reports_broken_items_and_leftovers() {
+        let dir = TempDir::new();
+        let cache = dir.path().join("cache");
+        let target = dir.path().join("output");
+        Item::single(111, "Fine").write(&cache);
+        let no_audio = Item::single(222, "No audio").write(&cache);
+        fs::remove_file(no_audio.join("222-1-30280.m4s")).unwrap();
+        let empty = Item::single(333, "Empty").write(&cache);
+        fs::write(empty.join("333-1-30280.m4s"), "").unwrap();
+        fs::create_dir_all(cache.join("444")).unwrap();
+        legacy_page(&cache, 500, &["flv", "flv"]);
+        let unfinished = legacy_page(&cache, 600, &[]);
+        let cut = legacy_page(&cache, 700, &["flv", ""]);
+        fs::create_dir_all(&target).unwrap();
+        fs::write(target.join("UP - Fine.mp4.part"), "").unwrap();
+        let mut db = state::StateDb::load(&target).unwrap();
+        db.set("888", state::Status::Converting, None);
+        let muxer = StubMuxer::default();
+        let options = fixture::options(&[], &dir.path().join("work"), &muxer);
+
+        let problems = check(&cache, &target, &db, &options).unwrap();
+        let found: Vec<(PathBuf, &str)> = problems
+            .iter()
+            .map(|p| (p.path.clone(), p.description.as_str()))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (
+                    no_audio,
+                    "metadata of a video whose audio m4s file is missing"
+                ),
+                (empty.join("333-1-30280.m4s"), "empty media segment"),
+                (cache.join("444"), "empty cache directory"),
+                (unfinished, "entry.json without any FLV segments"),
+                (cut.join("16/1.blv"), "empty media segment"),
+                (
+                    target.join("UP - Fine.mp4.part"),
+                    "incomplete file of an interrupted run"
+                ),
+                (target.join("888"), "conversion never finished"),
+            ]
+        );
+        let fixable = problems.iter().filter(|p| p.fix.is_some()).count();
+        assert_eq!(fixable, 3);
+    }
+}
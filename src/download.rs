@@ -0,0 +1,293 @@
+/// Downloading videos from Bilibili by BV or av id
+///
+/// The DASH streams of each page are downloaded into the work directory
+/// together with client style metadata, then go through the same muxing and
+/// layout as converted cache items. Pages are recorded in the state database as `<bvid>-<page>`, so interrupted downloads resume.
+use std::fs;
+use std::path::Path;
+use std::time::Instant;
+
+use log::*;
+
+use crate::api::{self, Page, Track, Video};
+use crate::error::Context;
+use crate::i18n::tr;
+use crate::video_info::Cover;
+use crate::{
+    check_free_space, cleanup, create_work_dir, deliver, error, fetch, layout, quality,
+    remove_work_dir, retry, runlog, signal, state, ConvertOptions, Inputs, Summary, VideoInfo,
+    VIDEO_METADATA_FILE,
+};
+
+/// Name of a downloaded page in the state database
+pub fn item_name(bvid: &str, page: u32) -> String {
+    format!("{}-{}", bvid, page)
+}
+
+// Metadata in the format of the client's `.videoInfo`
+fn video_info(video: &Video, page: &Page, size: u64) -> VideoInfo {
+    // Single page videos are laid out like single cached videos
+    let title = if video.pages.len() > 1 {
+        page.part.clone()
+    } else {
+        video.title.clone()
+    };
+    // Downloaded by `finish_output`, there is no cached cover file
+    let cover = Cover::from(Some(video.pic.clone()));
+    VideoInfo {
+        uname: video.owner.name.clone(),
+        title,
+        group_title: video.title.clone(),
+        pubdate: video.pubdate,
+        update_time: video.pubdate,
+        total_size: size,
+        item_id: page.cid,
+        cover_path: cover.clone(),
+        group_cover_path: cover,
+        p: page.page,
+        view_points: Vec::new(),
+        cover_url: None,
+        group_cover_url: None,
+        group_id: video.aid,
+        uid: video.owner.mid,
+    }
+}
+
+// Rough size of a track from its bandwidth, the API does not tell
+fn estimated_size(track: &Track, duration: u64) -> u64 {
+    track.bandwidth / 8 * duration
+}
+
+fn download_page(
+    video: &Video,
+    page: &Page,
+    target_path: &Path,
+    sessdata: Option<&str>,
+    options: &ConvertOptions,
+) -> Result<layout::Output, error::Error> {
+    let dash = api::dash(video, page.cid, sessdata)?;
+    let track = quality::pick(dash.video, options.quality, |t| {
+        (t.height.unwrap_or_default(), t.bandwidth)
+    })
+    .ok_or(error::Error::NoMediaStreams)?;
+    let audio = dash
+        .audio
+        .unwrap_or_default()
+        .into_iter()
+        .max_by_key(|t| t.bandwidth);
+    info!(
+        "Selected video {}x{} {}{}",
+        track.width.unwrap_or_default(),
+        track.height.unwrap_or_default(),
+        track.codecs,
+        audio
+            .as_ref()
+            .map(|a| format!(", audio {}", a.codecs))
+            .unwrap_or_default()
+    );
+
+    let mut size = estimated_size(&track, page.duration);
+    if let Some(audio) = &audio {
+        size += estimated_size(audio, page.duration);
+    }
+    // Downloaded streams and the final video
+    check_free_space(size, size, target_path, options)?;
+
+    let work_path = create_work_dir(&format!(".download-{}", page.cid), options)?;
+    let info = video_info(video, page, size);
+    let metadata = work_path.join(VIDEO_METADATA_FILE);
+    fs::write(&metadata, serde_json::to_string(&info)?).context("write", &metadata)?;
+
+    let headers = api::headers(sessdata);
+    let mut inputs = Vec::new();
+    let mut result = Ok(());
+    for track in std::iter::once(&track).chain(audio.as_ref()) {
+        if signal::interrupted() {
+            result = Err(error::Error::Interrupted);
+            break;
+        }
+        let file = work_path.join(format!("{}-{}.m4s", page.cid, track.id));
+        inputs.push(file.clone());
+        info!("Downloading {}", file.display());
+        if let Err(e) = fetch::download(&track.base_url, &file, &headers) {
+            result = Err(e);
+            break;
+        }
+    }
+
+    let result = match result {
+        Ok(_) => deliver(
+            &work_path,
+            &info,
+            &Inputs::temp(inputs),
+            &work_path,
+            target_path,
+            options,
+        ),
+        Err(e) => {
+            cleanup(&inputs, None);
+            Err(e)
+        }
+    };
+    remove_work_dir(&work_path);
+    result
+}
+
+/// Download the given pages of a video, every page if none are given
+pub fn run(
+    target_path: &Path,
+    id: &str,
+    pages: &[u32],
+    sessdata: Option<&str>,
+    options: &ConvertOptions,
+) -> Result<Summary, error::Error> {
+    let video = api::view(id, sessdata)?;
+    info!(
+        "{} {} by {}, {} pages",
+        video.bvid,
+        video.title,
+        video.owner.name,
+        video.pages.len()
+    );
+
+    let mut db = state::StateDb::load(target_path)?;
+    let mut summary = Summary::default();
+    signal::install();
+
+    for page in video
+        .pages
+        .iter()
+        .filter(|p| pages.is_empty() || pages.contains(&p.page))
+    {
+        if signal::interrupted() {
+            break;
+        }
+        let name = item_name(&video.bvid, page.page);
+        let mut record = runlog::ItemRecord::new(&name, "skipped");
+        record.item_id = Some(page.cid);
+        if !options.restart && db.is_converted(&name) {
+            info!(
+                "Skip {}, already downloaded (use --restart to download again)",
+                name
+            );
+            summary.skipped += 1;
+            if let Some(log) = &options.log {
+                log.record(&record)?;
+            }
+            continue;
+        }
+        info!("Page {}: {}", page.page, page.part);
+        db.set(&name, state::Status::Converting, None);
+        db.save()?;
+
+        let start = Instant::now();
+        let result = retry(Path::new(&name), options, || {
+            download_page(&video, page, target_path, sessdata, options)
+        });
+        record.duration = start.elapsed().as_secs_f64();
+        match result {
+            Ok(output) => {
+                record.result = "converted";
+                summary.converted += 1;
+                db.set(&name, state::Status::Converted, None);
+                db.set_output(&name, &output.file);
+                if output.reencoded {
+                    summary.reencoded.push(name.clone());
+                }
+            }
+            Err(error::Error::Interrupted) => {
+                record.result = "interrupted";
+                db.set(&name, state::Status::Interrupted, None)
+            }
+            Err(e) => {
+                error!("Failed to download {}: {}", name, e);
+                record.result = "failed";
+                record.error = Some(e.to_string());
+                summary.failed += 1;
+                db.set(&name, state::Status::Failed, Some(e.to_string()))
+            }
+        }
+        db.save()?;
+        if let Some(log) = &options.log {
+            log.record(&record)?;
+        }
+    }
+
+    if signal::interrupted() {
+        info!("Interrupted, download state saved");
+        return Err(error::Error::Interrupted);
+    }
+    info!(
+        "Downloaded {}, failed {}, skipped {}",
+        summary.converted, summary.failed, summary.skipped
+    );
+    Ok(summary)
+}
+
+/// A video listed by the API, e.g. in a favorites folder
+pub struct Listed {
+    pub bvid: String,
+    pub title: String,
+}
+
+/// Whether a video was downloaded before, with no page left unfinished
+pub fn archived(db: &state::StateDb, bvid: &str) -> bool {
+    let prefix = format!("{}-", bvid);
+    let mut pages = db
+        .items()
+        .filter(|(item, _)| item.starts_with(&prefix))
+        .peekable();
+    pages.peek().is_some() && pages.all(|(_, s)| s.status == state::Status::Converted)
+}
+
+/// Download every listed video that is not archived yet, a failed video
+/// does not stop the others
+pub fn missing(
+    target_path: &Path,
+    videos: &[Listed],
+    sessdata: Option<&str>,
+    dry_run: bool,
+    options: &ConvertOptions,
+) -> Result<Summary, error::Error> {
+    let db = state::StateDb::load(target_path)?;
+    let missing: Vec<&Listed> = videos
+        .iter()
+        .filter(|v| options.restart || !archived(&db, &v.bvid))
+        .collect();
+    let skipped = videos.len() - missing.len();
+
+    if dry_run {
+        println!(
+            "{}",
+            tr!(
+                "Would download {} videos, {} already archived",
+                missing.len(),
+                skipped
+            )
+        );
+        for video in &missing {
+            println!("  + {} {}", video.bvid, video.title);
+        }
+        return Ok(Summary::default());
+    }
+
+    let mut summary = Summary {
+        skipped,
+        ..Default::default()
+    };
+    for video in missing {
+        match run(target_path, &video.bvid, &[], sessdata, options) {
+            Ok(result) => {
+                summary.converted += result.converted;
+                summary.failed += result.failed;
+                summary.skipped += result.skipped;
+            }
+            Err(error::Error::Interrupted) => return Err(error::Error::Interrupted),
+            Err(e) => {
+                error!("Failed to download {} {}: {}", video.bvid, video.title, e);
+                summary.failed += 1;
+            }
+        }
+    }
+    Ok(summary)
+}
@@ -0,0 +1,355 @@
+/// Cache items holding the same video twice
+///
+/// Items are duplicates if their group and item titles are the same after
+/// normalizing them like `--title` does, or if they share a media file with
+/// the same content, as a video downloaded again at another quality keeps
+/// its audio stream. Only media files whose size appears in another item
+/// are hashed, see `hash`. Of each set of duplicates the converted item is
+/// suggested to keep, then the largest, which has the best quality, then
+/// the one cached last; `--pick` asks which one to keep instead and removes
+/// the others like `clean`.
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+
+use log::*;
+use serde::Serialize;
+
+use crate::i18n::tr;
+use crate::list::print_table;
+use crate::select::{item_name, normalize};
+use crate::{
+    disk, error, get_files_by_extension, hash, legacy, output, remove_source, state, CachedVideo,
+    SPECIAL_OFFSET,
+};
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Reason {
+    Title,
+    Content,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Duplicate {
+    pub item: String,
+    pub title: String,
+    pub uname: String,
+    pub bytes: u64,
+    pub converted: bool,
+    #[serde(skip)]
+    pub dir: PathBuf,
+}
+
+/// Items holding the same video, the one to keep first
+#[derive(Serialize, Debug)]
+pub struct Group {
+    pub reasons: Vec<Reason>,
+    pub copies: Vec<Duplicate>,
+}
+
+impl Group {
+    /// Bytes cleaning all but the first copy frees
+    fn reclaimable(&self) -> u64 {
+        self.copies.iter().skip(1).map(|c| c.bytes).sum()
+    }
+}
+
+// Union-find over item indexes, remembering why items were joined
+struct Sets {
+    parent: Vec<usize>,
+    reasons: Vec<(usize, Reason)>,
+}
+
+impl Sets {
+    fn root(&mut self, mut i: usize) -> usize {
+        while self.parent[i] != i {
+            self.parent[i] = self.parent[self.parent[i]];
+            i = self.parent[i];
+        }
+        i
+    }
+
+    fn join(&mut self, a: usize, b: usize, reason: Reason) {
+        let (a, b) = (self.root(a), self.root(b));
+        self.parent[a] = b;
+        self.reasons.push((a, reason));
+    }
+}
+
+// Media files of an item with their size, legacy caches are not compared
+fn media(video: &CachedVideo) -> Vec<(PathBuf, u64)> {
+    if legacy::is_item(&video.dir) {
+        return Vec::new();
+    }
+    get_files_by_extension(&video.dir, "m4s")
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|file| {
+            let size = file.metadata().ok()?.len();
+            Some((file, size))
+        })
+        .collect()
+}
+
+/// Sets of `videos` holding the same video, largest reclaimable space first
+pub fn find(videos: &[CachedVideo], db: &state::StateDb) -> Vec<Group> {
+    let mut sets = Sets {
+        parent: (0..videos.len()).collect(),
+        reasons: Vec::new(),
+    };
+    let mut titles: HashMap<String, usize> = HashMap::new();
+    for (i, video) in videos.iter().enumerate() {
+        let title = normalize(&format!("{} {}", video.info.group_title, video.info.title));
+        if title.is_empty() {
+            continue;
+        }
+        match titles.get(&title) {
+            Some(&first) => sets.join(i, first, Reason::Title),
+            None => {
+                titles.insert(title, i);
+            }
+        }
+    }
+
+    // Only sizes found in several items can be the same content
+    let mut by_size: HashMap<u64, Vec<(usize, PathBuf)>> = HashMap::new();
+    for (i, video) in videos.iter().enumerate() {
+        for (file, size) in media(video) {
+            by_size.entry(size).or_default().push((i, file));
+        }
+    }
+    let mut hashes: HashMap<String, usize> = HashMap::new();
+    for files in by_size.into_values() {
+        if files.iter().all(|(i, _)| *i == files[0].0) {
+            continue;
+        }
+        for (i, file) in files {
+            let digest = match hash::file(&file, SPECIAL_OFFSET) {
+                Ok(digest) => digest,
+                Err(e) => {
+                    warn!("Skip hashing {}: {}", file.display(), e);
+                    continue;
+                }
+            };
+            match hashes.get(&digest) {
+                Some(&first) => {
+                    if sets.root(first) != sets.root(i) {
+                        sets.join(i, first, Reason::Content);
+                    }
+                }
+                None => {
+                    hashes.insert(digest, i);
+                }
+            }
+        }
+    }
+
+    let mut members: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+    for i in 0..videos.len() {
+        let root = sets.root(i);
+        members.entry(root).or_default().push(i);
+    }
+    let mut reasons: BTreeMap<usize, Vec<Reason>> = BTreeMap::new();
+    for (member, reason) in std::mem::take(&mut sets.reasons) {
+        let root = sets.root(member);
+        let reasons = reasons.entry(root).or_default();
+        if !reasons.contains(&reason) {
+            reasons.push(reason);
+            reasons.sort();
+        }
+    }
+
+    let mut groups: Vec<Group> = members
+        .into_iter()
+        .filter(|(_, items)| items.len() > 1)
+        .map(|(root, items)| {
+            let mut items: Vec<&CachedVideo> = items.into_iter().map(|i| &videos[i]).collect();
+            items.sort_by_key(|v| {
+                (
+                    std::cmp::Reverse(db.is_converted(&item_name(v))),
+                    std::cmp::Reverse(v.disk_size),
+                    std::cmp::Reverse(v.info.update_time),
+                )
+            });
+            Group {
+                reasons: reasons.remove(&root).unwrap_or_default(),
+                copies: items
+                    .into_iter()
+                    .map(|v| Duplicate {
+                        item: item_name(v),
+                        title: v.info.title.clone(),
+                        uname: v.info.uname.clone(),
+                        bytes: v.disk_size,
+                        converted: db.is_converted(&item_name(v)),
+                        dir: v.dir.clone(),
+                    })
+                    .collect(),
+            }
+        })
+        .collect();
+    groups.sort_by_key(|g| std::cmp::Reverse(g.reclaimable()));
+    groups
+}
+
+fn print_group(n: usize, group: &Group) {
+    let reasons: Vec<String> = group
+        .reasons
+        .iter()
+        .map(|r| match r {
+            Reason::Title => tr!("same title").to_string(),
+            Reason::Content => tr!("same content").to_string(),
+        })
+        .collect();
+    println!("{}", tr!("Duplicates {} ({}):", n, reasons.join(", ")));
+    let rows: Vec<Vec<String>> = group
+        .copies
+        .iter()
+        .enumerate()
+        .map(|(i, c)| {
+            vec![
+                if i == 0 { "*" } else { "" }.to_string(),
+                (i + 1).to_string(),
+                c.item.clone(),
+                c.uname.clone(),
+                c.title.clone(),
+                disk::human_size(c.bytes),
+                tr!(if c.converted { "yes" } else { "no" }).to_string(),
+            ]
+        })
+        .collect();
+    print_table(
+        &[
+            tr!("KEEP"),
+            "#",
+            tr!("ITEM"),
+            tr!("UP"),
+            tr!("TITLE"),
+            tr!("SIZE"),
+            tr!("CONVERTED"),
+        ],
+        &[false, true, false, false, false, true, false],
+        &rows,
+    );
+}
+
+// Index of the copy to keep, None to leave the group alone
+fn ask(copies: usize) -> io::Result<Option<usize>> {
+    loop {
+        print!(
+            "{}",
+            tr!("Keep which? [1-{}, Enter keeps 1, s skips] ", copies)
+        );
+        io::stdout().flush()?;
+        let mut answer = String::new();
+        if io::stdin().lock().read_line(&mut answer)? == 0 {
+            return Ok(None);
+        }
+        match answer.trim() {
+            "" => return Ok(Some(0)),
+            "s" => return Ok(None),
+            n => match n.parse::<usize>() {
+                Ok(n) if (1..=copies).contains(&n) => return Ok(Some(n - 1)),
+                _ => continue,
+            },
+        }
+    }
+}
+
+/// Print the duplicates among `videos`, as JSON if `json`, and with `pick`
+/// ask for each set which copy to keep, removing the others
+pub fn show(
+    videos: &[CachedVideo],
+    target_path: &Path,
+    json: bool,
+    pick: bool,
+    permanent: bool,
+) -> Result<(), error::Error> {
+    let db = state::StateDb::load(target_path)?;
+    let groups = find(videos, &db);
+    if output::json() {
+        return output::emit("duplicates", &serde_json::json!({ "groups": groups }));
+    }
+    if json {
+        println!("{}", serde_json::to_string_pretty(&groups)?);
+        return Ok(());
+    }
+    if groups.is_empty() {
+        println!("{}", tr!("No duplicates found"));
+        return Ok(());
+    }
+
+    let mut freed = 0;
+    for (n, group) in groups.iter().enumerate() {
+        print_group(n + 1, group);
+        if !pick {
+            let items: Vec<&str> = group.copies[1..].iter().map(|c| c.item.as_str()).collect();
+            println!("{}", tr!("  suggested: bilibili clean {}", items.join(" ")));
+            println!();
+            continue;
+        }
+        let Some(keep) = ask(group.copies.len())? else {
+            println!();
+            continue;
+        };
+        for (i, copy) in group.copies.iter().enumerate() {
+            if i == keep {
+                continue;
+            }
+            info!("Removing directory {}", copy.dir.display());
+            match remove_source(&copy.dir, permanent) {
+                Ok(_) => {
+                    println!("{}", tr!("  removed {}", copy.item));
+                    freed += copy.bytes;
+                }
+                Err(e) => error!("Failed to remove {}: {}", copy.dir.display(), e),
+            }
+        }
+        println!();
+    }
+    let reclaimable: u64 = groups.iter().map(Group::reclaimable).sum();
+    if pick {
+        println!("{}", tr!("Freed {}", disk::human_size(freed)));
+    } else {
+        println!(
+            "{}",
+            tr!(
+                "{} sets of duplicates, cleaning the suggested copies frees {}",
+                groups.len(),
+                disk::human_size(reclaimable)
+            )
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::{Item, TempDir};
+    use crate::get_video_list;
+    use std::fs;
+
+    #[test]
+    fn groups_items_with_the_same_title_or_content() {
+        let dir = TempDir::new();
+        let cache = dir.path().join("cache");
+        Item::single(111, "Song").write(&cache);
+        Item::single(222, "Song").write(&cache);
+        // Downloaded again at another quality, the audio is the same
+        let again = Item::single(333, "Song (1080P)").write(&cache);
+        fs::write(again.join("333-1-30080.m4s"), [1; 64]).unwrap();
+        let unrelated = Item::single(444, "Unrelated").write(&cache);
+        for file in get_files_by_extension(&unrelated, "m4s").unwrap() {
+            fs::write(file, [2; 64]).unwrap();
+        }
+        let videos = get_video_list(&cache).unwrap();
+
+        let groups = find(&videos, &state::StateDb::default());
+        assert_eq!(groups.len(), 1, "{:?}", groups);
+        assert_eq!(groups[0].reasons, [Reason::Title, Reason::Content]);
+        let mut items: Vec<&str> = groups[0].copies.iter().map(|c| c.item.as_str()).collect();
+        items.sort();
+        assert_eq!(items, ["111", "222", "333"]);
+    }
+}
@@ -0,0 +1,190 @@
+/// Season and episode numbers of TV-style parts, e.g. `第1话` or `EP02`
+///
+/// Part titles are matched against patterns in which `{e}` stands for the
+/// episode number and `{s}` for the season, given in digits or in Chinese
+/// numerals. Other characters match themselves, ASCII letters in either
+/// case. A pattern starting with a letter only matches at the start of a
+/// word, so `E{e}` finds `E5` but not the end of `CODE5`. The first pattern
+/// that matches wins; without `{s}` the season is 1. The numbers fill the
+/// `{season}` and `{episode}` placeholders of `--name-template` and the
+/// NFO files of `--nfo`.
+use std::str::FromStr;
+
+pub const DEFAULT_PATTERNS: &[&str] = &[
+    "S{s}E{e}",
+    "第{s}季第{e}话",
+    "第{s}季第{e}集",
+    "第{e}话",
+    "第{e}集",
+    "EP{e}",
+    "E{e}",
+    "P{e}",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Token {
+    Char(char),
+    Season,
+    Episode,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pattern(Vec<Token>);
+
+impl FromStr for Pattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut tokens = Vec::new();
+        let mut rest = s;
+        while let Some(c) = rest.chars().next() {
+            if let Some(after) = rest.strip_prefix("{e}") {
+                tokens.push(Token::Episode);
+                rest = after;
+            } else if let Some(after) = rest.strip_prefix("{s}") {
+                tokens.push(Token::Season);
+                rest = after;
+            } else {
+                tokens.push(Token::Char(c.to_ascii_lowercase()));
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+        if !tokens.contains(&Token::Episode) {
+            return Err(format!("pattern '{}' has no {{e}} for the episode", s));
+        }
+        Ok(Pattern(tokens))
+    }
+}
+
+pub fn default_patterns() -> Vec<Pattern> {
+    DEFAULT_PATTERNS
+        .iter()
+        .map(|p| p.parse().unwrap())
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Episode {
+    pub season: u32,
+    pub episode: u32,
+}
+
+fn chinese_digit(c: char) -> Option<u32> {
+    "零一二三四五六七八九"
+        .chars()
+        .position(|d| d == c)
+        .map(|n| n as u32)
+}
+
+// Value of Chinese numerals like `十二` or `一百零五`
+fn chinese_number(s: &[char]) -> Option<u32> {
+    let (mut total, mut digit) = (0, None);
+    for &c in s {
+        let unit = match c {
+            '十' => 10,
+            '百' => 100,
+            '两' => {
+                digit = Some(2);
+                continue;
+            }
+            c => {
+                digit = Some(chinese_digit(c)?);
+                continue;
+            }
+        };
+        // `十二` leaves out the one of `一十二`
+        total += digit.take().unwrap_or(1) * unit;
+    }
+    Some(total + digit.unwrap_or_default())
+}
+
+fn is_numeral(c: char) -> bool {
+    c.is_ascii_digit() || chinese_digit(c).is_some() || "十百两".contains(c)
+}
+
+// The number at the start of `text` and its length
+fn number(text: &[char]) -> Option<(u32, usize)> {
+    let len = text.iter().take_while(|c| is_numeral(**c)).count();
+    let digits = &text[..len];
+    let value = if digits.iter().all(|c| c.is_ascii_digit()) {
+        digits.iter().collect::<String>().parse().ok()?
+    } else {
+        chinese_number(digits)?
+    };
+    (len > 0).then_some((value, len))
+}
+
+impl Pattern {
+    fn match_at(&self, text: &[char]) -> Option<Episode> {
+        let mut found = Episode {
+            season: 1,
+            episode: 0,
+        };
+        let mut pos = 0;
+        for token in &self.0 {
+            match token {
+                Token::Char(c) => {
+                    if text.get(pos).map(|t| t.to_ascii_lowercase()) != Some(*c) {
+                        return None;
+                    }
+                    pos += 1;
+                }
+                Token::Season | Token::Episode => {
+                    let (value, len) = number(&text[pos..])?;
+                    if *token == Token::Season {
+                        found.season = value;
+                    } else {
+                        found.episode = value;
+                    }
+                    pos += len;
+                }
+            }
+        }
+        Some(found)
+    }
+
+    fn find(&self, title: &str) -> Option<Episode> {
+        let text: Vec<char> = title.chars().collect();
+        let word = matches!(self.0.first(), Some(Token::Char(c)) if c.is_ascii_alphanumeric());
+        (0..text.len()).find_map(|start| {
+            if word && start > 0 && text[start - 1].is_ascii_alphanumeric() {
+                return None;
+            }
+            self.match_at(&text[start..])
+        })
+    }
+}
+
+/// Season and episode of a part titled `title`, by the first matching pattern
+pub fn infer(title: &str, patterns: &[Pattern]) -> Option<Episode> {
+    patterns.iter().find_map(|pattern| pattern.find(title))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn infers_episodes_from_titles() {
+        let patterns = default_patterns();
+        let episode = |title: &str| infer(title, &patterns).map(|e| (e.season, e.episode));
+        assert_eq!(episode("第1话 出发"), Some((1, 1)));
+        assert_eq!(episode("第十二集"), Some((1, 12)));
+        assert_eq!(episode("第二季第一百零五话"), Some((2, 105)));
+        assert_eq!(episode("【合集】ep02 Opening"), Some((1, 2)));
+        assert_eq!(episode("Show s03e07"), Some((3, 7)));
+        assert_eq!(episode("P3"), Some((1, 3)));
+        assert_eq!(episode("CODE5 review"), None);
+        assert_eq!(episode("Vlog"), None);
+
+        let custom: Vec<Pattern> = vec!["Vol.{e}".parse().unwrap()];
+        assert_eq!(
+            infer("vol.4", &custom),
+            Some(Episode {
+                season: 1,
+                episode: 4
+            })
+        );
+        assert!("Season {s}".parse::<Pattern>().is_err());
+    }
+}
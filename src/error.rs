@@ -1,13 +1,82 @@
+use std::path::{Path, PathBuf};
+
 use thiserror::Error;
 
+#[allow(clippy::enum_variant_names)]
 #[derive(Error, Debug)]
 pub enum Error {
     #[error("Invalid argument")]
     InvalidArgument,
     #[error("Command not found")]
     CommandNotFound,
+    #[error("Unable to determine the home directory")]
+    HomeNotFound,
     #[error("Failed to read directory")]
     ReadDirectoryFailed,
+    #[error("Metadata file missing in {0}")]
+    MetadataMissing(PathBuf),
+    #[error("Unable to read media file {0}: {1}")]
+    MediaFileUnreadable(PathBuf, std::io::Error),
+    #[error("Invalid file name: {0}")]
+    InvalidFileName(PathBuf),
+    #[error("Unable to move {0} to the trash, use --permanent to delete it")]
+    TrashFailed(PathBuf),
+    #[error("Unable to determine free disk space")]
+    DiskSpaceUnavailable,
+    #[error("Insufficient disk space: {required} bytes required, {available} bytes available")]
+    InsufficientSpace { required: u64, available: u64 },
+    #[error("ffmpeg exited with status {0}")]
+    FfmpegFailed(i32),
+    #[error("ffmpeg is unsuitable: {0}")]
+    FfmpegUnsupported(String),
+    #[error("ffmpeg timed out after {0} seconds")]
+    Timeout(u64),
+    #[error("Encrypted content in {0}")]
+    EncryptedContent(PathBuf),
+    #[error("Invalid media format")]
+    InvalidMediaFormat,
+    #[error("No video or audio streams found")]
+    NoMediaStreams,
+    #[error("Invalid output file {0}")]
+    OutputInvalid(PathBuf),
+    #[error("ffprobe failed: {0}")]
+    ProbeFailed(String),
+    #[error("No cached videos in group {0}")]
+    GroupNotFound(String),
+    #[error("No cached video titled '{0}'")]
+    TitleNotFound(String),
+    #[error("Several cached videos match '{0}', give the item or a longer title")]
+    TitleAmbiguous(String),
+    #[error("No cached items labeled '{0}'")]
+    TagNotFound(String),
+    #[error("No converted output of {0}, convert it first")]
+    OutputNotFound(String),
+    #[error("Upload failed: {0}")]
+    UploadFailed(String),
+    #[error("rclone failed: {0}")]
+    RcloneFailed(String),
+    #[error("Download failed: {0}")]
+    DownloadFailed(String),
+    #[error("Bilibili API error {0}: {1}")]
+    ApiError(i64, String),
+    #[error("Login failed: {0}")]
+    LoginFailed(String),
+    #[error("Unable to read backup {0}: {1}")]
+    BackupInvalid(PathBuf, String),
+    #[error("{} is in use by another run ({1}), wait for it with --wait-lock or use --force-unlock if it is gone", .0.display())]
+    Locked(PathBuf, String),
+    #[error("Invalid configuration {}: {1}", .0.display())]
+    ConfigInvalid(PathBuf, String),
+    #[error("Unable to split {} into parts of at most {1} bytes", .0.display())]
+    SplitFailed(PathBuf, u64),
+    #[error("Interrupted")]
+    Interrupted,
+    #[error("Unable to {action} {}: {source}", .path.display())]
+    FileError {
+        action: &'static str,
+        path: PathBuf,
+        source: std::io::Error,
+    },
     #[error("IO Error: {0}")]
     IOError(#[from] std::io::Error),
     #[error("Invalid UTF-8 string: {0}")]
@@ -15,3 +84,33 @@ pub enum Error {
     #[error("Invalid JSON format: {0}")]
     SerdeJsonError(#[from] serde_json::Error),
 }
+
+impl Error {
+    /// Whether trying again may succeed, e.g. after a network share hiccup
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            Error::IOError(_)
+                | Error::FileError { .. }
+                | Error::FfmpegFailed(_)
+                | Error::Timeout(_)
+                | Error::DownloadFailed(_)
+        )
+    }
+}
+
+/// Say which file an IO error is about, e.g.
+/// `fs::write(&path, content).context("write", &path)?`
+pub trait Context<T> {
+    fn context(self, action: &'static str, path: &Path) -> Result<T, Error>;
+}
+
+impl<T> Context<T> for std::io::Result<T> {
+    fn context(self, action: &'static str, path: &Path) -> Result<T, Error> {
+        self.map_err(|source| Error::FileError {
+            action,
+            path: path.to_path_buf(),
+            source,
+        })
+    }
+}
@@ -0,0 +1,234 @@
+/// Projected output sizes, to see what a batch would take and free before
+/// running it
+///
+/// Estimates come from the file names and sizes alone, without probing, so
+/// listing hundreds of items stays quick. A cached m4s is named after its
+/// stream, e.g. `123-1-30080.m4s` for 1080p video and `123-1-30280.m4s` for
+/// audio, so of several qualities only the one `--prefer-quality` picks is
+/// counted, and only the largest audio. The client's prefix is stripped from
+/// each. Legacy caches count the segments of one quality directory. Copying
+/// the streams leaves their size as it is, re-encoding does not, so with a
+/// `--profile` the estimate is only a rough upper bound.
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use log::*;
+use serde_json::json;
+
+use crate::i18n::tr;
+use crate::quality::{self, Quality};
+use crate::{
+    disk, error, get_metadata, list, output, queue, state, ConvertOptions, SPECIAL_OFFSET,
+};
+
+// Vertical resolution of the quality codes in cached m4s names
+fn height(code: u32) -> u32 {
+    match code.saturating_sub(30000) {
+        6 => 240,
+        16 => 360,
+        32 => 480,
+        64 | 74 => 720,
+        80 | 112 | 116 => 1080,
+        120 | 125 | 126 => 2160,
+        127 => 4320,
+        _ => 0,
+    }
+}
+
+/// Estimated output size of an item holding `files`, given by their paths
+/// relative to the item directory and sizes
+pub fn output_size(files: &[(PathBuf, u64)], quality: Quality) -> u64 {
+    let mut videos = Vec::new();
+    let mut audio = 0;
+    let mut other = 0;
+    let mut segments: BTreeMap<&Path, u64> = BTreeMap::new();
+    for (path, size) in files {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("m4s") => {
+                let media = size.saturating_sub(SPECIAL_OFFSET);
+                let code = path
+                    .file_stem()
+                    .and_then(|s| s.to_str())
+                    .and_then(|s| s.rsplit('-').next())
+                    .and_then(|s| s.parse::<u32>().ok());
+                match code {
+                    Some(30200..=30299) => audio = audio.max(media),
+                    Some(code @ 30000..=30199) => videos.push((height(code), media)),
+                    _ => other += media,
+                }
+            }
+            Some("blv") => {
+                let dir = path.parent().unwrap_or(Path::new(""));
+                *segments.entry(dir).or_default() += size;
+            }
+            _ => {}
+        }
+    }
+    let video = quality::pick(videos, quality, |v| *v).map(|(_, size)| size);
+    let segments = quality::pick(segments.into_values().collect(), quality, |size| (0, *size));
+    video.unwrap_or_default() + audio + other + segments.unwrap_or_default()
+}
+
+/// Estimated output size of the cache item in `dir`
+pub fn item(dir: &Path, quality: Quality) -> u64 {
+    let mut files = Vec::new();
+    // Legacy caches keep their segments in a directory per quality
+    let mut dirs = vec![PathBuf::new()];
+    while let Some(relative) = dirs.pop() {
+        for entry in fs::read_dir(dir.join(&relative))
+            .into_iter()
+            .flatten()
+            .flatten()
+        {
+            let path = relative.join(entry.file_name());
+            match entry.metadata() {
+                Ok(m) if m.is_dir() && relative.as_os_str().is_empty() => dirs.push(path),
+                Ok(m) if m.is_file() => files.push((path, m.len())),
+                _ => {}
+            }
+        }
+    }
+    output_size(&files, quality)
+}
+
+/// Print the items `jobs` would convert in queue order with their cache and
+/// estimated output sizes, and what `--autoremove` would reclaim, without
+/// converting anything
+pub fn dry_run(
+    jobs: Vec<queue::Job>,
+    target_path: &Path,
+    options: &ConvertOptions,
+) -> Result<(), error::Error> {
+    let db = state::StateDb::load(target_path)?;
+    let mut queue = queue::Queue::new(options.order());
+    for job in jobs {
+        let skipped = options.backup.is_none()
+            && get_metadata(&job.path).is_ok_and(|v| options.layout.uploaders.skips(&v.uname));
+        if (options.restart || !db.is_converted(&job.item)) && !skipped {
+            queue.push(job);
+        }
+    }
+
+    let (mut cache, mut projected) = (0, 0);
+    let mut rows = Vec::new();
+    for job in queue.jobs() {
+        let size = match &options.backup {
+            Some(backup) => output_size(&backup.files(&job.path), options.quality),
+            None => item(&job.path, options.quality),
+        };
+        cache += job.size;
+        projected += size;
+        output::emit(
+            "estimate",
+            &json!({ "item": job.item, "cache_bytes": job.size, "output_bytes": size }),
+        )?;
+        // The path of a backup's item is inside the archive
+        let title = match options.backup {
+            Some(_) => String::new(),
+            None => get_metadata(&job.path).map(|v| v.title).unwrap_or_default(),
+        };
+        rows.push(vec![
+            job.item.clone(),
+            disk::human_size(job.size),
+            disk::human_size(size),
+            title,
+        ]);
+    }
+    let reclaimed = if options.autoremove { cache } else { 0 };
+    let free = disk::available_space(target_path).ok();
+    output::emit(
+        "dry_run",
+        &json!({
+            "items": rows.len(),
+            "cache_bytes": cache,
+            "output_bytes": projected,
+            "reclaimed_bytes": reclaimed,
+            "free_bytes": free,
+        }),
+    )?;
+    if output::json() {
+        return Ok(());
+    }
+
+    if !rows.is_empty() {
+        list::print_table(
+            &["ITEM", "CACHE", "OUTPUT", "TITLE"],
+            &[false, true, true, false],
+            &rows,
+        );
+        println!();
+    }
+    println!(
+        "{}",
+        tr!(
+            "Would convert {} items, about {} of output from {} of cache",
+            rows.len(),
+            disk::human_size(projected),
+            disk::human_size(cache)
+        )
+    );
+    if options.autoremove {
+        println!(
+            "{}",
+            tr!(
+                "--autoremove would reclaim {}, {} net",
+                disk::human_size(cache),
+                disk::human_size(cache.saturating_sub(projected))
+            )
+        );
+    } else if options.backup.is_none() {
+        println!(
+            "{}",
+            tr!(
+                "With --autoremove {} of cache would be reclaimed, {} net",
+                disk::human_size(cache),
+                disk::human_size(cache.saturating_sub(projected))
+            )
+        );
+    }
+    match free {
+        Some(free) if projected > free && !options.autoremove => warn!(
+            "About {} of output does not fit into the {} free on the target",
+            disk::human_size(projected),
+            disk::human_size(free)
+        ),
+        Some(free) => println!("{}", tr!("{} free on the target", disk::human_size(free))),
+        None => {}
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn files(names: &[(&str, u64)]) -> Vec<(PathBuf, u64)> {
+        names.iter().map(|(n, s)| (PathBuf::from(n), *s)).collect()
+    }
+
+    #[test]
+    fn counts_one_quality_of_each_stream() {
+        let cached = files(&[
+            ("123-1-30080.m4s", 1009),
+            ("123-1-30064.m4s", 509),
+            ("123-1-30032.m4s", 209),
+            ("123-1-30280.m4s", 109),
+            ("123-1-30216.m4s", 49),
+            ("cover.jpg", 10),
+            ("danmaku.xml", 10),
+        ]);
+        assert_eq!(output_size(&cached, Quality::Highest), 1100);
+        assert_eq!(output_size(&cached, Quality::Lowest), 300);
+        assert_eq!(output_size(&cached, Quality::Height(720)), 600);
+
+        let legacy = files(&[
+            ("entry.json", 10),
+            ("80/0.blv", 600),
+            ("80/1.blv", 400),
+            ("16/0.blv", 100),
+        ]);
+        assert_eq!(output_size(&legacy, Quality::Highest), 1000);
+        assert_eq!(output_size(&legacy, Quality::Lowest), 100);
+    }
+}
@@ -0,0 +1,140 @@
+/// Remaining time of a conversion, from the throughput of earlier runs
+///
+/// Every converted item records how fast its stages went, stripping the
+/// prefix bytes off the cached streams and muxing them with a profile, in
+/// bytes per second in the state database of the output directory. Each
+/// new measurement moves the recorded throughput part of the way towards
+/// it, so a slower disk or a new ffmpeg show up after a few items. Items
+/// are estimated from their size once every stage they go through was
+/// measured; until then the progress line and JSON output show no ETA.
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::profile::Profile;
+use crate::state::StateDb;
+
+pub const STRIP: &str = "strip";
+
+/// Weight of a new measurement in the recorded throughput
+const WEIGHT: f64 = 0.3;
+
+/// Shorter stages say more about starting ffmpeg than about throughput
+const MIN_SECS: f64 = 1.0;
+
+/// Name the muxing throughput of `profile` is recorded under
+pub fn mux(profile: Profile) -> String {
+    format!("mux-{}", profile.name())
+}
+
+/// Stages an item converted with `profile` goes through
+pub fn stages(strip: bool, profile: Profile) -> Vec<String> {
+    let mut stages = vec![mux(profile)];
+    if strip {
+        stages.insert(0, STRIP.to_string());
+    }
+    stages
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sample {
+    pub stage: String,
+    pub bytes: u64,
+    pub secs: f64,
+}
+
+/// Measurements of the item being converted, taken once it is done
+#[derive(Debug, Default)]
+pub struct Timings(Mutex<Vec<Sample>>);
+
+impl Timings {
+    pub fn record(&self, stage: &str, bytes: u64, elapsed: Duration) {
+        let mut samples = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        samples.push(Sample {
+            stage: stage.to_string(),
+            bytes,
+            secs: elapsed.as_secs_f64(),
+        });
+    }
+
+    pub fn take(&self) -> Vec<Sample> {
+        std::mem::take(&mut *self.0.lock().unwrap_or_else(|e| e.into_inner()))
+    }
+}
+
+/// Fold `samples` into the throughput recorded in `db`
+pub fn learn(db: &mut StateDb, samples: Vec<Sample>) {
+    for sample in samples {
+        if sample.bytes == 0 || sample.secs < MIN_SECS {
+            continue;
+        }
+        let measured = sample.bytes as f64 / sample.secs;
+        let throughput = match db.throughput(&sample.stage) {
+            Some(previous) => previous + WEIGHT * (measured - previous),
+            None => measured,
+        };
+        db.set_throughput(&sample.stage, throughput);
+    }
+}
+
+/// Seconds converting `bytes` through `stages` takes, if all were measured
+pub fn estimate(db: &StateDb, bytes: u64, stages: &[String]) -> Option<f64> {
+    stages
+        .iter()
+        .map(|stage| db.throughput(stage).map(|rate| bytes as f64 / rate))
+        .sum()
+}
+
+/// Remaining time of the current item and of the whole batch including it
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Eta {
+    pub item: Option<f64>,
+    pub batch: Option<f64>,
+}
+
+/// `secs` as `m:ss` or `h:mm:ss`
+pub fn format(secs: f64) -> String {
+    let secs = secs.round() as u64;
+    let (hours, minutes, secs) = (secs / 3600, secs / 60 % 60, secs % 60);
+    if hours > 0 {
+        format!("{}:{:02}:{:02}", hours, minutes, secs)
+    } else {
+        format!("{}:{:02}", minutes, secs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn estimates_from_recorded_throughput() {
+        let mut db = StateDb::default();
+        let stages = stages(true, Profile::H264);
+        assert_eq!(stages, ["strip", "mux-h264"]);
+        assert_eq!(estimate(&db, 1000, &stages), None);
+
+        let timings = Timings::default();
+        timings.record(STRIP, 1000, Duration::from_secs(1));
+        timings.record("mux-h264", 1000, Duration::from_secs(10));
+        // Too short to tell anything
+        timings.record("mux-copy", 1000, Duration::from_millis(20));
+        learn(&mut db, timings.take());
+        assert!(timings.take().is_empty());
+        assert_eq!(estimate(&db, 2000, &stages), Some(22.0));
+        assert_eq!(db.throughput("mux-copy"), None);
+
+        // Later runs move the throughput towards what they measured
+        learn(
+            &mut db,
+            vec![Sample {
+                stage: STRIP.to_string(),
+                bytes: 2000,
+                secs: 1.0,
+            }],
+        );
+        assert_eq!(db.throughput(STRIP), Some(1300.0));
+
+        assert_eq!(format(65.4), "1:05");
+        assert_eq!(format(3725.0), "1:02:05");
+    }
+}
@@ -0,0 +1,155 @@
+/// Removing cache directories to keep the cache within a size budget
+///
+/// Only converted items whose output exists are candidates unless all items
+/// are allowed, so nothing is lost that was not archived.
+use std::path::Path;
+
+use clap::ValueEnum;
+use log::*;
+use serde_json::json;
+
+use crate::i18n::tr;
+use crate::select::item_name;
+use crate::{
+    disk, error, get_video_list, ignore, output, output_valid, remove_source, state, CachedVideo,
+    ConvertOptions,
+};
+
+const DAY_SECS: i64 = 24 * 60 * 60;
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Strategy {
+    /// Least recently cached first
+    Oldest,
+    /// Biggest first, freeing the budget with the fewest removals
+    Largest,
+}
+
+pub struct Policy {
+    /// Remove items until the cache takes at most this many bytes
+    pub keep_under: Option<u64>,
+    /// Only remove items cached more than this many days ago
+    pub older_than: Option<u32>,
+    pub strategy: Strategy,
+    /// Also remove items which were not converted
+    pub all: bool,
+}
+
+// Items `policy` allows to remove, in the order they should go
+fn candidates<'a>(
+    videos: &'a [CachedVideo],
+    target_path: &Path,
+    policy: &Policy,
+    now: i64,
+    options: &ConvertOptions,
+) -> Result<Vec<&'a CachedVideo>, error::Error> {
+    let db = state::StateDb::load(target_path)?;
+    let mut candidates: Vec<&CachedVideo> = videos
+        .iter()
+        .filter(|v| {
+            policy.all
+                || (db.is_converted(&item_name(v))
+                    && output_valid(&options.layout.output(&v.info, target_path).file))
+        })
+        .filter(|v| {
+            policy
+                .older_than
+                .is_none_or(|days| v.info.update_time < now - days as i64 * DAY_SECS)
+        })
+        .collect();
+    match policy.strategy {
+        Strategy::Oldest => candidates.sort_by_key(|v| v.info.update_time),
+        Strategy::Largest => candidates.sort_by_key(|v| std::cmp::Reverse(v.disk_size)),
+    }
+    Ok(candidates)
+}
+
+/// Remove cache directories according to `policy`, only printing them if `dry_run`
+pub fn run(
+    source_path: &Path,
+    target_path: &Path,
+    policy: &Policy,
+    dry_run: bool,
+    options: &ConvertOptions,
+) -> Result<(), error::Error> {
+    let videos = get_video_list(source_path)?;
+    let ignore = ignore::Ignore::load(source_path, &options.exclude)?;
+    let mut total: u64 = videos.iter().map(|v| v.disk_size).sum();
+    let now = chrono::Utc::now().timestamp();
+
+    let mut removed = 0;
+    let mut freed = 0;
+    let candidates = candidates(&videos, target_path, policy, now, options)?;
+    for video in candidates
+        .into_iter()
+        .filter(|v| !ignore.matches(&item_name(v), Some(&v.info)))
+    {
+        if policy.keep_under.is_some_and(|budget| total <= budget) {
+            break;
+        }
+        let name = item_name(video);
+        if dry_run && !output::json() {
+            println!(
+                "{}",
+                tr!(
+                    "Would remove {} ({})",
+                    name,
+                    disk::human_size(video.disk_size)
+                )
+            );
+        } else if !dry_run {
+            info!("Removing directory {}", video.dir.display());
+            if let Err(e) = remove_source(&video.dir, options.permanent) {
+                error!("Failed to remove {}: {}", name, e);
+                continue;
+            }
+        }
+        output::emit(
+            "removed",
+            &json!({ "item": name, "bytes": video.disk_size, "dry_run": dry_run }),
+        )?;
+        removed += 1;
+        freed += video.disk_size;
+        total -= video.disk_size;
+    }
+
+    output::emit(
+        "clean",
+        &json!({
+            "removed": removed,
+            "freed_bytes": freed,
+            "cache_bytes": total,
+            "dry_run": dry_run,
+        }),
+    )?;
+
+    let message = if dry_run {
+        "Would remove {} items, freeing {}, the cache would take {}"
+    } else {
+        "Removed {} items, freeing {}, the cache now takes {}"
+    };
+    if !output::json() {
+        println!(
+            "{}",
+            tr!(
+                message,
+                removed,
+                disk::human_size(freed),
+                disk::human_size(total)
+            )
+        );
+    }
+    if let Some(budget) = policy.keep_under.filter(|budget| total > *budget) {
+        warn!(
+            "Still above {}, the {} remaining items may not be removed{}",
+            disk::human_size(budget),
+            videos.len() - removed,
+            if policy.all {
+                ""
+            } else {
+                " (use --all to include unconverted items)"
+            }
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,54 @@
+/// Archiving a favorites folder
+///
+/// The folder is listed through the API and every video not yet in the
+/// archive is downloaded, so repeated runs only fetch newly starred videos.
+use std::path::Path;
+
+use log::*;
+
+use crate::i18n::tr;
+use crate::{api, download, error, ConvertOptions, Summary};
+
+// Media type of videos, folders also hold audio and collections
+const VIDEO: i64 = 2;
+
+/// Download the videos of favorites folder `fid` missing from the archive
+pub fn run(
+    target_path: &Path,
+    fid: u64,
+    sessdata: Option<&str>,
+    dry_run: bool,
+    options: &ConvertOptions,
+) -> Result<Summary, error::Error> {
+    let (title, medias) = api::favorites(fid, sessdata)?;
+    info!("Favorites folder {}: {} entries", title, medias.len());
+
+    let mut videos = Vec::new();
+    for media in medias {
+        if media.kind != VIDEO {
+            debug!("Skip {} {}, not a video", media.bvid, media.title);
+        } else if media.attr & 1 != 0 {
+            warn!("Skip {} {}, no longer available", media.bvid, media.title);
+        } else {
+            videos.push(download::Listed {
+                bvid: media.bvid,
+                title: media.title,
+            });
+        }
+    }
+
+    let summary = download::missing(target_path, &videos, sessdata, dry_run, options)?;
+    if !dry_run {
+        println!("{}", tr!("Favorites {} synced", title));
+        println!(
+            "{}",
+            tr!(
+                "  downloaded {}, failed {}, skipped {}",
+                summary.converted,
+                summary.failed,
+                summary.skipped
+            )
+        );
+    }
+    Ok(summary)
+}
@@ -0,0 +1,86 @@
+/// HTTPS downloads through curl, which brings TLS, proxy settings and
+/// redirects without adding an HTTP client to the build.
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use log::*;
+
+use crate::error::{self, Context};
+
+const TIMEOUT_SECS: &str = "60";
+
+/// Normalize cover URLs from metadata, which are often protocol relative
+/// (`//i0.hdslb.com/...`) or plain http, to https
+pub fn https_url(url: &str) -> Option<String> {
+    let url = url.trim();
+    if let Some(rest) = url.strip_prefix("https://") {
+        Some(format!("https://{}", rest))
+    } else if let Some(rest) = url.strip_prefix("http://") {
+        Some(format!("https://{}", rest))
+    } else {
+        url.strip_prefix("//")
+            .map(|rest| format!("https://{}", rest))
+    }
+}
+
+fn curl(headers: &[String]) -> Command {
+    let mut cmd = Command::new("curl");
+    cmd.args(["-fsSL", "--proto", "=https"]);
+    for header in headers {
+        cmd.arg("-H").arg(header);
+    }
+    cmd.stdin(Stdio::null());
+    cmd
+}
+
+fn failed(url: &str, stderr: &[u8]) -> error::Error {
+    error::Error::DownloadFailed(format!(
+        "{}: {}",
+        url,
+        String::from_utf8_lossy(stderr).trim()
+    ))
+}
+
+/// Fetch a small response, e.g. from an API, into memory
+pub fn get(url: &str, headers: &[String]) -> Result<Vec<u8>, error::Error> {
+    debug!("Fetching {}", url);
+    let output = curl(headers)
+        .args(["-m", TIMEOUT_SECS])
+        .arg(url)
+        .output()
+        .map_err(|_| error::Error::CommandNotFound)?;
+    if !output.status.success() {
+        return Err(failed(url, &output.stderr));
+    }
+    Ok(output.stdout)
+}
+
+/// Download `url` to `path`, going through a temp file so a failed
+/// transfer never leaves a truncated file behind. Large downloads have no
+/// overall time limit, only one on stalled transfers.
+pub fn download(url: &str, path: &Path, headers: &[String]) -> Result<(), error::Error> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".download");
+    debug!("Downloading {} to {}", url, path.display());
+    let output = curl(headers)
+        .args([
+            "--connect-timeout",
+            "30",
+            "--speed-limit",
+            "1",
+            "--speed-time",
+            TIMEOUT_SECS,
+        ])
+        .arg("-o")
+        .arg(&tmp)
+        .arg(url)
+        .output()
+        .map_err(|_| error::Error::CommandNotFound)?;
+    if !output.status.success() {
+        let _ = fs::remove_file(&tmp);
+        return Err(failed(url, &output.stderr));
+    }
+    fs::rename(&tmp, path).context("rename", Path::new(&tmp))?;
+    Ok(())
+}
@@ -0,0 +1,381 @@
+/// Running ffmpeg and ffprobe
+use std::fmt::{self, Display};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+use log::*;
+
+use crate::container::Container;
+use crate::error;
+use crate::profile::Profile;
+use crate::runner::{self, CommandRunner};
+
+pub const DEFAULT_FFMPEG: &str = "ffmpeg";
+
+/// Oldest ffmpeg known to handle the cached media
+pub const MIN_VERSION: Version = Version { major: 4, minor: 0 };
+
+/// First ffmpeg with the `-readrate` input option
+const READRATE_VERSION: Version = Version { major: 5, minor: 0 };
+
+/// Release version of ffmpeg
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub struct Version {
+    pub major: u32,
+    pub minor: u32,
+}
+
+impl Version {
+    /// Parse the first line of `ffmpeg -version`, e.g. `ffmpeg version
+    /// 6.1.1-3ubuntu5 Copyright ...` or `ffmpeg version n7.0`. Builds from
+    /// git like `N-113000-g...` have no release version.
+    fn parse(output: &str) -> Option<Version> {
+        let version = output
+            .lines()
+            .next()?
+            .split_whitespace()
+            .skip_while(|word| *word != "version")
+            .nth(1)?
+            .trim_start_matches('n');
+        let mut numbers = version
+            .split(|c: char| !c.is_ascii_digit())
+            .map(|n| n.parse::<u32>());
+        let major = numbers.next()?.ok()?;
+        let minor = numbers.next().and_then(|n| n.ok()).unwrap_or(0);
+        Some(Version { major, minor })
+    }
+}
+
+impl Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
+/// Components listed by `ffmpeg -demuxers`, `-muxers`, `-encoders` and
+/// `-filters`
+#[derive(Debug, Clone, Copy)]
+pub enum Component {
+    Demuxer,
+    Muxer,
+    Encoder,
+    Filter,
+}
+
+impl Component {
+    fn list_arg(&self) -> &'static str {
+        match self {
+            Component::Demuxer => "-demuxers",
+            Component::Muxer => "-muxers",
+            Component::Encoder => "-encoders",
+            Component::Filter => "-filters",
+        }
+    }
+}
+
+impl Display for Component {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Component::Demuxer => "demuxer",
+            Component::Muxer => "muxer",
+            Component::Encoder => "encoder",
+            Component::Filter => "filter",
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Ffmpeg {
+    /// ffmpeg binary, either a bare name looked up in PATH or a path
+    pub path: PathBuf,
+    /// Arguments appended to every conversion, before the output file
+    pub extra_args: Vec<String>,
+    /// Run ffmpeg with reduced CPU priority
+    pub low_priority: bool,
+    /// Passed as `-threads` to limit the threads ffmpeg uses
+    pub threads: Option<u32>,
+    /// Kill a run of ffmpeg taking longer than this, e.g. hanging on a
+    /// corrupted segment
+    pub timeout: Option<Duration>,
+    // Whether inputs can be read from an offset, detected on first use
+    skip_initial_bytes: OnceLock<bool>,
+    // Release version, detected on first use
+    version: OnceLock<Option<Version>>,
+    /// Runs ffmpeg and ffprobe, processes unless replaced in tests
+    pub runner: Arc<dyn CommandRunner>,
+}
+
+// Niceness of low priority children, the same as `nice` without arguments
+#[cfg(unix)]
+const LOW_PRIORITY_NICENESS: &str = "10";
+
+#[cfg(windows)]
+const BELOW_NORMAL_PRIORITY_CLASS: u32 = 0x0000_4000;
+
+impl Ffmpeg {
+    pub fn new(path: PathBuf, extra_args: Vec<String>) -> Ffmpeg {
+        Ffmpeg {
+            path,
+            extra_args,
+            low_priority: false,
+            threads: None,
+            timeout: None,
+            skip_initial_bytes: OnceLock::new(),
+            version: OnceLock::new(),
+            runner: Arc::new(runner::System),
+        }
+    }
+
+    /// ffprobe next to the configured ffmpeg, e.g. `~/bin/ffprobe` for
+    /// `~/bin/ffmpeg`, or from PATH when ffmpeg is too.
+    pub fn ffprobe(&self) -> PathBuf {
+        let name = match self.path.extension() {
+            Some(ext) => format!("ffprobe.{}", ext.to_string_lossy()),
+            None => "ffprobe".to_string(),
+        };
+        self.path.with_file_name(name)
+    }
+
+    /// Command running ffmpeg, at reduced priority if requested. A `-threads`
+    /// limit is not included as its position matters, see `thread_args`.
+    #[cfg(unix)]
+    pub fn command(&self) -> Command {
+        if self.low_priority {
+            let mut cmd = Command::new("nice");
+            cmd.args(["-n", LOW_PRIORITY_NICENESS]).arg(&self.path);
+            cmd
+        } else {
+            Command::new(&self.path)
+        }
+    }
+
+    #[cfg(windows)]
+    pub fn command(&self) -> Command {
+        use std::os::windows::process::CommandExt;
+        let mut cmd = Command::new(&self.path);
+        if self.low_priority {
+            cmd.creation_flags(BELOW_NORMAL_PRIORITY_CLASS);
+        }
+        cmd
+    }
+
+    #[cfg(not(any(unix, windows)))]
+    pub fn command(&self) -> Command {
+        Command::new(&self.path)
+    }
+
+    /// Release version of this ffmpeg, None for builds from git. Fails if
+    /// ffmpeg can not be run.
+    pub fn version(&self) -> Result<Option<Version>, error::Error> {
+        if let Some(version) = self.version.get() {
+            return Ok(*version);
+        }
+        let output = self
+            .runner
+            .output(
+                Command::new(&self.path)
+                    .arg("-version")
+                    .stdin(Stdio::null()),
+            )
+            .map_err(|_| error::Error::CommandNotFound)?;
+        let version = Version::parse(&String::from_utf8_lossy(&output.stdout));
+        Ok(*self.version.get_or_init(|| version))
+    }
+
+    /// Whether this ffmpeg was built with the `kind` component `name`
+    pub fn has(&self, kind: Component, name: &str) -> Result<bool, error::Error> {
+        // Listings look like ` D  mov,mp4,m4a,3gp,3g2,mj2 QuickTime / MOV`
+        // or ` V....D libx264 libx264 H.264 ...`, after a legend in the same format
+        let output = self
+            .runner
+            .output(
+                Command::new(&self.path)
+                    .args(["-hide_banner", kind.list_arg()])
+                    .stdin(Stdio::null())
+                    .stderr(Stdio::null()),
+            )
+            .map_err(|_| error::Error::CommandNotFound)?;
+        Ok(String::from_utf8_lossy(&output.stdout).lines().any(|line| {
+            line.split_whitespace()
+                .nth(1)
+                .is_some_and(|names| names.split(',').any(|n| n == name))
+        }))
+    }
+
+    /// Whether this ffmpeg has the `-readrate` input option
+    pub fn has_readrate(&self) -> bool {
+        // Builds from git are assumed recent
+        self.version()
+            .is_ok_and(|version| version.is_none_or(|v| v >= READRATE_VERSION))
+    }
+
+    /// Whether this ffmpeg has the `-skip_initial_bytes` input option, so
+    /// cached media can be read in place instead of stripped copies
+    pub fn skips_initial_bytes(&self) -> bool {
+        *self.skip_initial_bytes.get_or_init(|| {
+            let supported = self
+                .runner
+                .output(
+                    Command::new(&self.path)
+                        .args(["-hide_banner", "-h", "full"])
+                        .stdin(Stdio::null())
+                        .stderr(Stdio::null()),
+                )
+                .map(|output| {
+                    String::from_utf8_lossy(&output.stdout).contains("skip_initial_bytes")
+                })
+                .unwrap_or(false);
+            debug!("ffmpeg supports -skip_initial_bytes: {}", supported);
+            supported
+        })
+    }
+
+    /// `-threads N` if limited, placed right before the output file
+    pub fn thread_args(&self) -> Vec<String> {
+        match self.threads {
+            Some(threads) => vec!["-threads".to_string(), threads.to_string()],
+            None => Vec::new(),
+        }
+    }
+
+    /// Mux the inputs of `job` into `output_file`, re-encoding as its
+    /// profile says
+    pub fn mux(&self, job: &MuxJob, output_file: &Path) -> Result<(), error::Error> {
+        // ffmpeg [-f concat -safe 0] [-readrate R] [-skip_initial_bytes N] -i source [-i source [...]] [-i chapters -map_chapters N]
+        //        [-map F:S [...]] -c copy|<profile codecs> [-c:a aac -b:a 192k] [container args]
+        //        [-movflags +faststart]
+        //        [-metadata key=value [...]] [-threads N] [extra args] -y -f format targetfile
+        let mut cmd = self.command();
+        for input in job.inputs {
+            if job.concat {
+                cmd.args(["-f", "concat", "-safe", "0"]);
+            }
+            if let Some(rate) = job.read_rate.filter(|_| self.has_readrate()) {
+                cmd.arg("-readrate").arg(format!("{:.3}", rate));
+            }
+            if job.skip_bytes > 0 {
+                cmd.arg("-skip_initial_bytes")
+                    .arg(job.skip_bytes.to_string());
+            }
+            cmd.arg("-i").arg(input);
+        }
+        if let Some(chapters) = job.chapters {
+            cmd.arg("-i").arg(chapters);
+            cmd.arg("-map_chapters").arg(job.inputs.len().to_string());
+        }
+        for map in job.maps {
+            cmd.arg("-map").arg(map);
+        }
+        cmd.args(job.profile.codec_args());
+        // The last codec option matching a stream wins over `-c copy`
+        if job.transcode_audio {
+            cmd.args(["-c:a", "aac", "-b:a", "192k"]);
+        }
+        cmd.args(job.container.muxer_args(job.profile));
+        if job.faststart {
+            cmd.args(job.container.faststart_args());
+        }
+        for (key, value) in job.tags {
+            cmd.arg("-metadata").arg(format!("{}={}", key, value));
+        }
+        cmd.args(self.thread_args());
+        cmd.args(&self.extra_args);
+        // A .part file left by an interrupted run is overwritten, with stdin
+        // closed ffmpeg would otherwise refuse and fail the item every time
+        cmd.arg("-y")
+            .arg("-f")
+            .arg(job.container.format())
+            .arg(output_file);
+        self.run(cmd)
+    }
+
+    /// Run ffmpeg to completion, killing it if an interrupt arrives or it
+    /// exceeds the timeout
+    pub fn run(&self, mut cmd: Command) -> Result<(), error::Error> {
+        cmd.stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null());
+        self.runner.run(&mut cmd, self.timeout)
+    }
+}
+
+/// Writes the output file of a mux job. Ffmpeg is the real one, the
+/// pipeline tests give a stub instead.
+pub trait Muxer: Send + Sync {
+    fn mux(&self, job: &MuxJob, output_file: &Path) -> Result<(), error::Error>;
+}
+
+impl Muxer for Ffmpeg {
+    fn mux(&self, job: &MuxJob, output_file: &Path) -> Result<(), error::Error> {
+        Ffmpeg::mux(self, job, output_file)
+    }
+}
+
+/// What goes into one output file
+pub struct MuxJob<'a> {
+    pub inputs: &'a [PathBuf],
+    /// Inputs are concat demuxer lists rather than media files
+    pub concat: bool,
+    /// Read inputs at this multiple of their native rate, if ffmpeg is
+    /// recent enough
+    pub read_rate: Option<f64>,
+    /// Bytes to skip at the start of every input
+    pub skip_bytes: u64,
+    /// `-map` specifiers of the streams to take, ffmpeg chooses if empty
+    pub maps: &'a [String],
+    pub profile: Profile,
+    /// Re-encode the audio to AAC whatever the profile does with it
+    pub transcode_audio: bool,
+    /// ffmetadata file with chapters
+    pub chapters: Option<&'a Path>,
+    pub tags: &'a [(&'a str, String)],
+    /// Container format, explicit so the output name needs no matching extension
+    pub container: Container,
+    /// Move the index to the front, see `Container::faststart_args`
+    pub faststart: bool,
+}
+
+/// Split a command line string into arguments the way a POSIX shell would
+/// for simple cases: whitespace separates arguments, single and double
+/// quotes group them and backslash escapes the next character.
+pub fn split_args(line: &str) -> Result<Vec<String>, error::Error> {
+    let mut args = Vec::new();
+    let mut current = String::new();
+    let mut in_arg = false;
+    let mut quote: Option<char> = None;
+    let mut chars = line.chars();
+
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some('"'), '\\') | (None, '\\') => {
+                current.push(chars.next().ok_or(error::Error::InvalidArgument)?);
+                in_arg = true;
+            }
+            (Some(_), c) => current.push(c),
+            (None, '\'' | '"') => {
+                quote = Some(c);
+                in_arg = true;
+            }
+            (None, c) if c.is_whitespace() => {
+                if in_arg {
+                    args.push(std::mem::take(&mut current));
+                    in_arg = false;
+                }
+            }
+            (None, c) => {
+                current.push(c);
+                in_arg = true;
+            }
+        }
+    }
+    if quote.is_some() {
+        return Err(error::Error::InvalidArgument);
+    }
+    if in_arg {
+        args.push(current);
+    }
+    Ok(args)
+}
@@ -0,0 +1,318 @@
+/// Synthetic caches, a stub muxer and a scripted command runner, to test the
+/// conversion pipeline without ffmpeg or a copy of the client's cache
+///
+/// Items look like the client writes them: a `.videoInfo`, a cover and one
+/// video and one audio `.m4s`, each being the client's prefix bytes followed
+/// by the init boxes of a tiny mp4. ffmpeg and ffprobe are never found, so
+/// cached media are stripped into temp files and outputs only checked for
+/// their size. The stub muxer records every job as one line, which tests
+/// compare as a snapshot of what the pipeline asked for.
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Output};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use clap::Parser;
+
+use crate::error::{self, Context};
+use crate::ffmpeg::{MuxJob, Muxer};
+use crate::profile::Profile;
+use crate::runner::{self, CommandRunner};
+use crate::{convert_options, Args, ConvertOptions, SPECIAL_OFFSET};
+
+/// Time of the fixed clock tests run with
+pub const NOW: i64 = 1_700_000_000;
+
+static DIRS: AtomicUsize = AtomicUsize::new(0);
+
+/// Temp directory removed again when dropped
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new() -> TempDir {
+        let n = DIRS.fetch_add(1, Ordering::Relaxed);
+        let path = std::env::temp_dir().join(format!("bilibili-test-{}-{}", std::process::id(), n));
+        fs::create_dir_all(&path).unwrap();
+        TempDir(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+fn mp4_box(kind: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+    let mut data = ((payload.len() + 8) as u32).to_be_bytes().to_vec();
+    data.extend_from_slice(kind);
+    data.extend_from_slice(payload);
+    data
+}
+
+// The client's prefix, then ftyp and a moov with the handler of the track
+// and its sample entry, HEVC at 1920x1080 for video and AAC for audio
+fn segment(handler: &[u8; 4]) -> Vec<u8> {
+    let mut hdlr = vec![0; 8];
+    hdlr.extend_from_slice(handler);
+    hdlr.extend_from_slice(&[0; 13]);
+    let entry = if handler == b"vide" {
+        let mut fields = vec![0; 24];
+        fields.extend_from_slice(&1920u16.to_be_bytes());
+        fields.extend_from_slice(&1080u16.to_be_bytes());
+        fields.extend_from_slice(&[0; 50]);
+        mp4_box(b"hvc1", &fields)
+    } else {
+        mp4_box(b"mp4a", &[0; 28])
+    };
+    let stsd = mp4_box(
+        b"stsd",
+        &[&[0, 0, 0, 0, 0, 0, 0, 1], entry.as_slice()].concat(),
+    );
+    let minf = mp4_box(b"minf", &mp4_box(b"stbl", &stsd));
+    let mdia = mp4_box(b"mdia", &[mp4_box(b"hdlr", &hdlr), minf].concat());
+    let trak = mp4_box(b"trak", &mdia);
+    let mut data = vec![b'0'; SPECIAL_OFFSET as usize];
+    data.extend(mp4_box(b"ftyp", b"iso5\0\0\x02\0iso6mp41"));
+    data.extend(mp4_box(
+        b"moov",
+        &[mp4_box(b"mvhd", &[0; 100]), trak].concat(),
+    ));
+    data
+}
+
+/// Fragments numbered `sequence`, each a `moof` with its `mfhd` and a tiny
+/// `mdat`, to append to a segment
+pub fn fragments(sequence: &[u32]) -> Vec<u8> {
+    let mut data = Vec::new();
+    for n in sequence {
+        let mfhd = mp4_box(b"mfhd", &[&[0; 4], n.to_be_bytes().as_slice()].concat());
+        data.extend(mp4_box(b"moof", &mfhd));
+        data.extend(mp4_box(b"mdat", &[0; 16]));
+    }
+    data
+}
+
+/// A cached item of the client, `<cache>/<item_id>/`
+pub struct Item<'a> {
+    pub item_id: u64,
+    pub uname: &'a str,
+    pub group_title: &'a str,
+    pub title: &'a str,
+    pub p: u32,
+}
+
+impl Item<'_> {
+    /// A video of its own, not part of a group
+    pub fn single(item_id: u64, title: &str) -> Item<'_> {
+        Item {
+            item_id,
+            uname: "UP",
+            group_title: title,
+            title,
+            p: 1,
+        }
+    }
+
+    /// Write the item into `cache`, returning its directory
+    pub fn write(&self, cache: &Path) -> PathBuf {
+        let dir = cache.join(self.item_id.to_string());
+        fs::create_dir_all(&dir).unwrap();
+        let video = segment(b"vide");
+        let audio = segment(b"soun");
+        let cover = dir.join("cover.jpg");
+        fs::write(&cover, b"\xff\xd8\xff\xd9").unwrap();
+        let info = serde_json::json!({
+            "uname": self.uname,
+            "title": self.title,
+            "groupTitle": self.group_title,
+            "pubdate": NOW,
+            "updateTime": NOW,
+            "totalSize": video.len() + audio.len(),
+            "itemId": self.item_id,
+            "coverPath": cover,
+            "groupCoverPath": cover,
+            "p": self.p,
+        });
+        fs::write(dir.join(".videoInfo"), info.to_string()).unwrap();
+        fs::write(dir.join(format!("{}-1-30080.m4s", self.item_id)), video).unwrap();
+        fs::write(dir.join(format!("{}-1-30280.m4s", self.item_id)), audio).unwrap();
+        dir
+    }
+}
+
+/// Muxer recording its jobs instead of running ffmpeg
+#[derive(Clone, Default)]
+pub struct StubMuxer {
+    jobs: Arc<Mutex<Vec<String>>>,
+    fail_copy: bool,
+}
+
+impl StubMuxer {
+    /// A muxer failing jobs that copy the streams, like ffmpeg on broken
+    /// timestamps
+    pub fn failing_copy() -> StubMuxer {
+        StubMuxer {
+            fail_copy: true,
+            ..StubMuxer::default()
+        }
+    }
+
+    pub fn jobs(&self) -> Vec<String> {
+        self.jobs.lock().unwrap().clone()
+    }
+}
+
+// Kind of the box at `offset`, "ftyp" if the prefix bytes are skipped right
+fn first_box(file: &Path, offset: u64) -> String {
+    let mut header = [0; 8];
+    let read = fs::File::open(file).and_then(|mut f| {
+        f.seek(SeekFrom::Start(offset))?;
+        f.read_exact(&mut header)
+    });
+    match read {
+        Ok(_) => String::from_utf8_lossy(&header[4..]).to_string(),
+        Err(_) => "?".to_string(),
+    }
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+impl Muxer for StubMuxer {
+    fn mux(&self, job: &MuxJob, output_file: &Path) -> Result<(), error::Error> {
+        let inputs: Vec<String> = job
+            .inputs
+            .iter()
+            .map(|i| format!("{}({})", file_name(i), first_box(i, job.skip_bytes)))
+            .collect();
+        let tags: Vec<String> = job
+            .tags
+            .iter()
+            .map(|(k, v)| format!("{}={}", k, v))
+            .collect();
+        self.jobs.lock().unwrap().push(format!(
+            "{} maps=[{}] profile={:?}{} chapters={} tags=[{}] -> {}",
+            inputs.join(" "),
+            job.maps.join(" "),
+            job.profile,
+            if job.transcode_audio { "+aac" } else { "" },
+            job.chapters.is_some(),
+            tags.join(" "),
+            file_name(output_file)
+        ));
+        if self.fail_copy && job.profile == Profile::Copy {
+            return Err(error::Error::FfmpegFailed(1));
+        }
+        fs::write(output_file, b"muxed").context("write", output_file)
+    }
+}
+
+/// Runner answering ffmpeg and ffprobe from a script instead of running them
+#[derive(Debug)]
+pub struct ScriptedRunner {
+    /// Output of a command with the given arguments, None if it fails
+    answer: fn(&[String]) -> Option<String>,
+    commands: Mutex<Vec<String>>,
+}
+
+impl ScriptedRunner {
+    pub fn new(answer: fn(&[String]) -> Option<String>) -> Arc<ScriptedRunner> {
+        Arc::new(ScriptedRunner {
+            answer,
+            commands: Mutex::new(Vec::new()),
+        })
+    }
+
+    /// Command lines run so far
+    pub fn commands(&self) -> Vec<String> {
+        self.commands.lock().unwrap().clone()
+    }
+
+    fn answer(&self, cmd: &Command) -> Option<String> {
+        self.commands
+            .lock()
+            .unwrap()
+            .push(runner::command_line(cmd));
+        let args: Vec<String> = cmd
+            .get_args()
+            .map(|a| a.to_string_lossy().to_string())
+            .collect();
+        (self.answer)(&args)
+    }
+}
+
+#[cfg(unix)]
+fn exit_status(code: i32) -> ExitStatus {
+    use std::os::unix::process::ExitStatusExt;
+    ExitStatus::from_raw(code << 8)
+}
+
+#[cfg(windows)]
+fn exit_status(code: i32) -> ExitStatus {
+    use std::os::windows::process::ExitStatusExt;
+    ExitStatus::from_raw(code as u32)
+}
+
+impl CommandRunner for ScriptedRunner {
+    fn output(&self, cmd: &mut Command) -> io::Result<Output> {
+        let answer = self.answer(cmd);
+        Ok(Output {
+            status: exit_status(if answer.is_some() { 0 } else { 1 }),
+            stdout: answer.unwrap_or_default().into_bytes(),
+            stderr: Vec::new(),
+        })
+    }
+
+    fn run(&self, cmd: &mut Command, _: Option<Duration>) -> Result<(), error::Error> {
+        match self.answer(cmd) {
+            Some(_) => Ok(()),
+            None => Err(error::Error::FfmpegFailed(1)),
+        }
+    }
+}
+
+/// Options of `bilibili <args> convert`, muxing with `muxer`, keeping
+/// intermediate files in `work_dir` and recording the fixed clock
+pub fn options(args: &[&str], work_dir: &Path, muxer: &StubMuxer) -> ConvertOptions {
+    let missing = work_dir.join("no-ffmpeg");
+    let mut argv: Vec<OsString> = vec!["bilibili".into(), "--ffmpeg-path".into(), missing.into()];
+    argv.extend(["--work-dir".into(), work_dir.into()]);
+    argv.extend(args.iter().map(OsString::from));
+    argv.push("convert".into());
+    let args = Args::try_parse_from(argv).unwrap();
+    let mut options = convert_options(&args).unwrap();
+    options.muxer = Some(Box::new(muxer.clone()));
+    options.clock = || NOW;
+    options
+}
+
+/// Files below `dir` relative to it, sorted
+pub fn tree(dir: &Path) -> Vec<String> {
+    let mut files = Vec::new();
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(current) = dirs.pop() {
+        for entry in fs::read_dir(&current).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else {
+                let relative = path.strip_prefix(dir).unwrap();
+                files.push(relative.to_string_lossy().replace('\\', "/"));
+            }
+        }
+    }
+    files.sort();
+    files
+}
@@ -0,0 +1,115 @@
+/// Commands and a webhook run after every converted item, for chaining
+/// uploads or notifications without wrapping the whole CLI.
+///
+/// Commands run through the shell with the item described in environment
+/// variables, the webhook receives the same fields as a JSON POST.
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use log::*;
+use serde::Serialize;
+
+#[derive(Debug, Default)]
+pub struct Hooks {
+    pub on_success: Option<String>,
+    pub on_failure: Option<String>,
+    /// URL posted to after every item, successful or not
+    pub webhook: Option<String>,
+}
+
+/// What happened to one item
+#[derive(Serialize, Debug)]
+pub struct Event<'a> {
+    pub item: &'a str,
+    pub item_id: Option<u64>,
+    pub title: Option<&'a str>,
+    pub result: &'a str,
+    pub output: Option<&'a Path>,
+    pub error: Option<&'a str>,
+}
+
+impl Event<'_> {
+    fn env(&self) -> Vec<(&'static str, String)> {
+        let mut vars = vec![
+            ("BILIBILI_ITEM", self.item.to_string()),
+            ("BILIBILI_RESULT", self.result.to_string()),
+        ];
+        if let Some(item_id) = self.item_id {
+            vars.push(("BILIBILI_ITEM_ID", item_id.to_string()));
+        }
+        if let Some(title) = self.title {
+            vars.push(("BILIBILI_TITLE", title.to_string()));
+        }
+        if let Some(output) = self.output {
+            vars.push(("BILIBILI_OUTPUT", output.to_string_lossy().to_string()));
+        }
+        if let Some(error) = self.error {
+            vars.push(("BILIBILI_ERROR", error.to_string()));
+        }
+        vars
+    }
+}
+
+fn shell(command: &str) -> Command {
+    if cfg!(windows) {
+        let mut cmd = Command::new("cmd");
+        cmd.arg("/C").arg(command);
+        cmd
+    } else {
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c").arg(command);
+        cmd
+    }
+}
+
+fn run_command(command: &str, event: &Event) {
+    debug!("Running hook {}", command);
+    let status = shell(command)
+        .envs(event.env())
+        .stdin(Stdio::null())
+        .status();
+    match status {
+        Ok(status) if status.success() => {}
+        Ok(status) => warn!("Hook for {} failed: {}", event.item, status),
+        Err(e) => warn!("Unable to run hook for {}: {}", event.item, e),
+    }
+}
+
+fn post(url: &str, event: &Event) {
+    let body = match serde_json::to_string(event) {
+        Ok(body) => body,
+        Err(e) => return warn!("Unable to encode webhook body: {}", e),
+    };
+    // curl handles https and proxies, which a hand written client would not
+    let status = Command::new("curl")
+        .args(["-fsS", "-m", "30", "-X", "POST"])
+        .args(["-H", "Content-Type: application/json"])
+        .arg("--data-binary")
+        .arg(body)
+        .arg(url)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .status();
+    match status {
+        Ok(status) if status.success() => {}
+        Ok(status) => warn!("Webhook for {} failed: {}", event.item, status),
+        Err(e) => warn!("Unable to run curl for webhook: {}", e),
+    }
+}
+
+impl Hooks {
+    /// Run the hooks matching the event, failures are only logged
+    pub fn run(&self, event: &Event) {
+        let command = if event.error.is_none() {
+            &self.on_success
+        } else {
+            &self.on_failure
+        };
+        if let Some(command) = command {
+            run_command(command, event);
+        }
+        if let Some(url) = &self.webhook {
+            post(url, event);
+        }
+    }
+}
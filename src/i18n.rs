@@ -0,0 +1,351 @@
+/// Translations of the messages users read, selected by `--lang` or the locale
+///
+/// Messages are looked up by their English text, which is also shown when a
+/// catalog lacks a message, and `{}` placeholders are filled in order by
+/// `tr!`. Debug output and error details stay English, they are for bug
+/// reports.
+use std::env;
+use std::fmt::{Display, Write};
+use std::sync::OnceLock;
+
+use clap::ValueEnum;
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Lang {
+    /// English
+    En,
+    /// Simplified Chinese
+    Zh,
+}
+
+static LANG: OnceLock<Lang> = OnceLock::new();
+
+/// Select the language of messages, from the locale if not given
+pub fn init(lang: Option<Lang>) {
+    let _ = LANG.set(lang.unwrap_or_else(from_locale));
+}
+
+// The first set of LC_ALL, LC_MESSAGES and LANG, e.g. `zh_CN.UTF-8`
+fn from_locale() -> Lang {
+    let locale = ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .iter()
+        .filter_map(|name| env::var(name).ok())
+        .find(|value| !value.is_empty())
+        .unwrap_or_default();
+    if locale.starts_with("zh") {
+        Lang::Zh
+    } else {
+        Lang::En
+    }
+}
+
+/// `english` in the selected language
+pub fn message(english: &'static str) -> &'static str {
+    let catalog = match LANG.get() {
+        Some(Lang::Zh) => ZH,
+        _ => return english,
+    };
+    catalog
+        .iter()
+        .find(|(en, _)| *en == english)
+        .map(|(_, translated)| *translated)
+        .unwrap_or(english)
+}
+
+/// Fill the `{}` placeholders of `template` with `args` in order
+pub fn fill(template: &str, args: &[&dyn Display]) -> String {
+    let mut result = String::with_capacity(template.len());
+    let mut args = args.iter();
+    let mut parts = template.split("{}");
+    if let Some(first) = parts.next() {
+        result.push_str(first);
+    }
+    for part in parts {
+        if let Some(arg) = args.next() {
+            let _ = write!(result, "{}", arg);
+        }
+        result.push_str(part);
+    }
+    result
+}
+
+/// Translate a message, `tr!("Converted {}", n)` fills in the placeholders
+macro_rules! tr {
+    ($english:expr) => {
+        $crate::i18n::message($english)
+    };
+    ($english:expr, $($arg:expr),+ $(,)?) => {
+        $crate::i18n::fill(
+            $crate::i18n::message($english),
+            &[$(&$arg as &dyn std::fmt::Display),+],
+        )
+    };
+}
+pub(crate) use tr;
+
+const ZH: &[(&str, &str)] = &[
+    // Setup
+    ("Error: {}", "错误：{}"),
+    ("ffmpeg is not installed or not found at {}", "未安装 ffmpeg，或在 {} 找不到 ffmpeg"),
+    (
+        "ffmpeg {} is too old, please install {} or later",
+        "ffmpeg {} 版本过旧，请安装 {} 或更新的版本",
+    ),
+    (
+        "Your ffmpeg lacks the {} demuxer needed to read cached media, install a full build of ffmpeg",
+        "当前 ffmpeg 缺少读取缓存所需的 {} 解封装器，请安装完整版 ffmpeg",
+    ),
+    (
+        "Your ffmpeg lacks the {} muxer needed to write the outputs, install a full build of ffmpeg",
+        "当前 ffmpeg 缺少写入输出所需的 {} 封装器，请安装完整版 ffmpeg",
+    ),
+    (
+        "Your ffmpeg lacks the {} encoder needed for the selected profile, choose --profile copy",
+        "当前 ffmpeg 缺少所选配置需要的 {} 编码器，请改用 --profile copy",
+    ),
+    (
+        "Your ffmpeg lacks the {} encoder needed for a profile in the uploader settings, choose another one",
+        "当前 ffmpeg 缺少 UP 主设置中的配置需要的 {} 编码器，请选择其他配置",
+    ),
+    (
+        "Your ffmpeg lacks the {} encoder needed for --compat-target, install a full build of ffmpeg",
+        "当前 ffmpeg 缺少 --compat-target 需要的 {} 编码器，请安装完整版 ffmpeg",
+    ),
+    (
+        "Your ffmpeg lacks the {} encoder needed for the fallback profile, choose another one",
+        "当前 ffmpeg 缺少备用配置需要的 {} 编码器，请选择其他配置",
+    ),
+    (
+        "Your ffmpeg lacks the {} filter needed to burn danmaku, install a build with libass",
+        "当前 ffmpeg 缺少烧录弹幕所需的 {} 滤镜，请安装带 libass 的版本",
+    ),
+    (
+        "Your ffmpeg lacks the {} encoder needed to burn danmaku, choose another --profile",
+        "当前 ffmpeg 缺少烧录弹幕所需的 {} 编码器，请选择其他 --profile",
+    ),
+    // Conversion
+    ("Converted {}, failed {}, skipped {}", "已转换 {}，失败 {}，跳过 {}"),
+    ("Encrypted items skipped: {}", "已跳过加密的项目：{}"),
+    ("Interrupted, conversion state saved", "已中断，转换状态已保存"),
+    ("Re-encoded as copying the streams failed: {}", "复制流失败，已重新编码：{}"),
+    ("Not copied to the rclone remote: {}", "未复制到 rclone 远端：{}"),
+    ("Not uploaded: {}", "未上传：{}"),
+    ("Quarantined: {}", "已隔离：{}"),
+    ("ETA {}", "预计剩余 {}"),
+    ("ETA {}, {} in total", "预计剩余 {}，全部 {}"),
+    (
+        "Moved {} to {} after failing {} runs in a row",
+        "已将 {} 移至 {}，已连续失败 {} 次",
+    ),
+    ("Failed to process {}: {}", "处理 {} 失败：{}"),
+    ("{} cached videos match '{}':", "有 {} 个缓存视频匹配“{}”："),
+    // Login
+    ("Logged in as {}", "已登录：{}"),
+    ("Logged out", "已退出登录"),
+    ("Not logged in", "未登录"),
+    (
+        "Install qrencode to show the QR code here, or open this URL on the phone:",
+        "安装 qrencode 可在此显示二维码，或在手机上打开以下链接：",
+    ),
+    (
+        "Scan this QR code with the Bilibili app and confirm the login:",
+        "请使用哔哩哔哩 App 扫描二维码并确认登录：",
+    ),
+    ("Scanned, confirm the login in the app", "已扫描，请在 App 中确认登录"),
+    // Downloads
+    ("Would download {} videos, {} already archived", "将下载 {} 个视频，{} 个已存档"),
+    ("Uploader {} archived", "UP 主 {} 已存档"),
+    ("Favorites {} synced", "收藏夹 {} 已同步"),
+    ("  downloaded {}, failed {}, skipped {}", "  已下载 {}，失败 {}，跳过 {}"),
+    // Sync
+    ("Would convert {} new items", "将转换 {} 个新项目"),
+    ("Would remove {} converted sources, freeing {}", "将删除 {} 个已转换的缓存，释放 {}"),
+    ("Sync finished", "同步完成"),
+    ("  converted {}, failed {}, skipped {}", "  已转换 {}，失败 {}，跳过 {}"),
+    ("  removed {} sources converted earlier, freed {}", "  已删除 {} 个之前转换的缓存，释放 {}"),
+    ("  {} converted items have no output", "  {} 个已转换的项目没有输出文件"),
+    ("  {} archived items no longer cached", "  {} 个已存档的项目已不在缓存中"),
+    // Index
+    ("Exported {} items to {}", "已导出 {} 个项目到 {}"),
+    ("Imported {} items", "已导入 {} 个项目"),
+    ("Recorded {} converted items", "已记录 {} 个已转换的项目"),
+    ("Restored {} items", "已恢复 {} 个项目"),
+    // Convert --dry-run
+    (
+        "Would convert {} items, about {} of output from {} of cache",
+        "将转换 {} 个项目，约 {} 的输出，来自 {} 的缓存",
+    ),
+    ("--autoremove would reclaim {}, {} net", "--autoremove 将回收 {}，净释放 {}"),
+    (
+        "With --autoremove {} of cache would be reclaimed, {} net",
+        "使用 --autoremove 将回收 {} 的缓存，净释放 {}",
+    ),
+    ("{} free on the target", "目标位置剩余 {}"),
+    // Clean
+    ("Would remove {} ({})", "将删除 {}（{}）"),
+    (
+        "Would remove {} items, freeing {}, the cache would take {}",
+        "将删除 {} 个项目，释放 {}，缓存将占用 {}",
+    ),
+    (
+        "Removed {} items, freeing {}, the cache now takes {}",
+        "已删除 {} 个项目，释放 {}，缓存现占用 {}",
+    ),
+    // Stats
+    ("Cache: {} items, {}", "缓存：{} 个项目，{}"),
+    ("Archive: {} videos, {}", "存档：{} 个视频，{}"),
+    ("Converted {} of {} cached items ({}%)", "已转换 {} / {} 个缓存项目（{}%）"),
+    ("Average bitrate {} kb/s", "平均码率 {} kb/s"),
+    ("UP", "UP 主"),
+    ("MONTH", "月份"),
+    ("CACHED", "缓存"),
+    ("SIZE", "大小"),
+    ("ARCHIVED", "存档"),
+    ("LARGEST", "最大项目"),
+    ("TITLE", "标题"),
+    ("CONVERTED", "已转换"),
+    ("yes", "是"),
+    ("no", "否"),
+    // Verify
+    ("duration {}s, the source has {}s", "时长 {} 秒，源文件为 {} 秒"),
+    ("no {} stream", "没有{}流"),
+    ("{} re-encoded: {}", "{} 已重新编码：{}"),
+    ("{}, the source has {}", "{}，源文件为 {}"),
+    ("empty output", "输出为空"),
+    ("output missing", "输出缺失"),
+    ("output unknown", "输出未知"),
+    ("no streams", "没有媒体流"),
+    ("unreadable: {}", "无法读取：{}"),
+    ("not cached anymore, not compared", "已不在缓存中，未比较"),
+    ("source unreadable, not compared: {}", "源文件无法读取，未比较：{}"),
+    ("{} is not converted", "{} 尚未转换"),
+    ("ok", "正常"),
+    ("FAILED", "失败"),
+    ("{} outputs verified, {} with problems", "已检查 {} 个输出，{} 个有问题"),
+    // Doctor
+    ("empty cache directory", "缓存目录为空"),
+    ("no .videoInfo metadata", "缺少 .videoInfo 元数据"),
+    ("unreadable metadata: {}", "元数据无法读取：{}"),
+    ("metadata without any m4s media files", "有元数据但没有 m4s 媒体文件"),
+    ("empty media segment", "媒体文件为空"),
+    ("work directory of an interrupted run", "中断运行留下的工作目录"),
+    ("incomplete file of an interrupted run", "中断运行留下的不完整文件"),
+    ("temp file of an interrupted conversion", "中断转换留下的临时文件"),
+    ("conversion never finished", "转换未完成"),
+    ("remove it", "删除它"),
+    ("download it again in the client", "在客户端中重新下载"),
+    (
+        "download it again in the client, or remove it with clean if the client forgot it",
+        "在客户端中重新下载；如果客户端已不记得它，可用 clean 删除",
+    ),
+    (
+        "finish the download in the client, or remove it with clean",
+        "在客户端中完成下载，或用 clean 删除",
+    ),
+    (
+        "mark it interrupted so it is converted again",
+        "标记为已中断，以便重新转换",
+    ),
+    ("  suggested: {}", "  建议：{}"),
+    ("  fixed", "  已修复"),
+    ("No problems found", "未发现问题"),
+    ("{} problems found, {} fixed", "发现 {} 个问题，已修复 {} 个"),
+    (
+        "{} problems found, {} can be fixed with --fix",
+        "发现 {} 个问题，其中 {} 个可用 --fix 修复",
+    ),
+    ("cache", "缓存"),
+    ("work", "工作"),
+    ("output", "输出"),
+    ("strip", "去除前缀"),
+    ("mux ({})", "封装（{}）"),
+    ("not needed, ffmpeg reads the cache in place", "无需，ffmpeg 直接读取缓存"),
+    (
+        "Converting the whole cache of {} takes about {}",
+        "转换全部 {} 缓存约需 {}",
+    ),
+    (
+        "No recommendations, nothing on this setup slows conversions down",
+        "没有建议，当前环境没有拖慢转换的因素",
+    ),
+    (
+        "Put --work-dir on the disk of the output directory, it writes {} while the work directory writes {}",
+        "建议将 --work-dir 放到输出目录所在的磁盘，其写入速度为 {}，而工作目录仅为 {}",
+    ),
+    (
+        "{} only writes {}, like a network share or a USB 2 stick, large batches take hours there",
+        "{} 写入速度仅为 {}，类似网络共享或 USB 2 存储，大批量转换会耗时数小时",
+    ),
+    (
+        "This ffmpeg has no -skip_initial_bytes, every item is copied to strip it first; a newer ffmpeg reads the cache in place",
+        "此 ffmpeg 不支持 -skip_initial_bytes，每个条目都要先复制以去除前缀；较新的 ffmpeg 可直接读取缓存",
+    ),
+    (
+        "--io-limit caps copies at {} though the disks manage {}",
+        "--io-limit 将复制限制在 {}，而磁盘可达 {}",
+    ),
+    (
+        "Re-encoding limits the speed and ffmpeg may only use {} of {} threads, raise --threads",
+        "重新编码限制了速度，且 ffmpeg 只能使用 {} 个线程（共 {} 个），请调高 --threads",
+    ),
+    (
+        "Re-encoding with the {} profile limits the speed, --profile copy or --compat-target only re-encodes what has to be",
+        "使用 {} 配置重新编码限制了速度，--profile copy 或 --compat-target 只重新编码必要的流",
+    ),
+    ("Nothing matches '{}'", "没有与 '{}' 匹配的条目"),
+    ("same title", "标题相同"),
+    ("same content", "内容相同"),
+    ("Duplicates {} ({}):", "重复项 {}（{}）："),
+    ("Keep which? [1-{}, Enter keeps 1, s skips] ", "保留哪一个？[1-{}，回车保留 1，s 跳过] "),
+    ("  suggested: bilibili clean {}", "  建议：bilibili clean {}"),
+    ("  removed {}", "  已删除 {}"),
+    ("Freed {}", "已释放 {}"),
+    ("No duplicates found", "未发现重复项"),
+    (
+        "{} sets of duplicates, cleaning the suggested copies frees {}",
+        "{} 组重复项，清理建议的副本可释放 {}",
+    ),
+    ("KEEP", "保留"),
+    ("ITEM", "项目"),
+    // Fragment gaps
+    ("fragment {} missing", "缺少第 {} 个分片"),
+    ("fragments {}-{} missing", "缺少第 {}-{} 个分片"),
+    ("fragment {} followed by {}", "第 {} 个分片之后是第 {} 个"),
+    ("cut off at the end", "末尾不完整"),
+    (
+        "Cached media missing fragments, the outputs may stutter: {}",
+        "缓存媒体缺少分片，输出可能会卡顿：{}",
+    ),
+    ("metadata of a video whose audio m4s file is missing", "视频的音频 m4s 文件缺失"),
+    ("metadata of a video whose video m4s file is missing", "视频的画面 m4s 文件缺失"),
+    ("entry.json without any FLV segments", "有 entry.json 但没有 FLV 分段"),
+
+    // Info
+    ("Directory", "目录"),
+    ("Item", "项目"),
+    ("Group", "合集"),
+    ("Title", "标题"),
+    ("Page", "分P"),
+    ("Size", "大小"),
+    ("On disk", "占用空间"),
+    ("Published", "发布时间"),
+    ("Updated", "更新时间"),
+    ("Cover", "封面"),
+    ("Group cover", "合集封面"),
+    ("Media", "媒体"),
+    ("probe failed: {}", "探测失败：{}"),
+    ("sha256 failed: {}", "sha256 计算失败：{}"),
+    ("none", "无"),
+    ("Danmaku", "弹幕"),
+    ("Subtitles", "字幕"),
+    ("Output", "输出"),
+    ("Status", "状态"),
+    ("Converting", "转换中"),
+    ("Converted", "已转换"),
+    ("Failed", "失败"),
+    ("Interrupted", "已中断"),
+    ("Quarantined", "已隔离"),
+    ("Converted (not recorded)", "已转换（未记录）"),
+    ("New", "新"),
+    ("Tags", "标签"),
+];
@@ -0,0 +1,110 @@
+/// Cache items kept out of conversions and cleaning, e.g. caches kept raw
+///
+/// `.bilibiliignore` in the cache directory holds one pattern per line,
+/// lines starting with `#` are comments, and `--exclude` adds more. A
+/// pattern excludes an item if it is the item's directory name or id, the
+/// name of its uploader, or a glob matching its title, with `*` standing
+/// for any characters and `?` for one. Only scanning the whole cache skips
+/// excluded items, items given by name are converted or removed anyway.
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+
+use crate::error::{self, Context};
+use crate::VideoInfo;
+
+pub const IGNORE_FILE: &str = ".bilibiliignore";
+
+#[derive(Debug, Default)]
+pub struct Ignore {
+    patterns: Vec<String>,
+}
+
+// Whether `text` matches `pattern` as a whole
+fn glob(pattern: &[char], text: &[char]) -> bool {
+    match pattern.split_first() {
+        None => text.is_empty(),
+        Some(('*', rest)) => (0..=text.len()).any(|skip| glob(rest, &text[skip..])),
+        Some(('?', rest)) => !text.is_empty() && glob(rest, &text[1..]),
+        Some((c, rest)) => text.first() == Some(c) && glob(rest, &text[1..]),
+    }
+}
+
+impl Ignore {
+    pub fn new(patterns: impl IntoIterator<Item = String>) -> Ignore {
+        Ignore {
+            patterns: patterns
+                .into_iter()
+                .map(|p| p.trim().to_string())
+                .filter(|p| !p.is_empty() && !p.starts_with('#'))
+                .collect(),
+        }
+    }
+
+    /// Patterns of the ignore file in `source_path`, if there is one, and
+    /// `exclude`
+    pub fn load(source_path: &Path, exclude: &[String]) -> Result<Ignore, error::Error> {
+        let path = source_path.join(IGNORE_FILE);
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e).context("read", &path),
+        };
+        Ok(Ignore::new(
+            content
+                .lines()
+                .map(String::from)
+                .chain(exclude.iter().cloned()),
+        ))
+    }
+
+    /// Whether the item `item` with the metadata `video_info`, if readable,
+    /// is excluded
+    pub fn matches(&self, item: &str, video_info: Option<&VideoInfo>) -> bool {
+        self.patterns.iter().any(|pattern| {
+            if pattern == item {
+                return true;
+            }
+            let Some(info) = video_info else {
+                return false;
+            };
+            let pattern_chars: Vec<char> = pattern.chars().collect();
+            *pattern == info.item_id.to_string()
+                || *pattern == info.uname
+                || glob(&pattern_chars, &info.title.chars().collect::<Vec<_>>())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::TempDir;
+
+    #[test]
+    fn excludes_items_uploaders_and_titles() {
+        let dir = TempDir::new();
+        fs::write(
+            dir.path().join(IGNORE_FILE),
+            "# kept raw\n111\n\n  UP Raw  \n",
+        )
+        .unwrap();
+        let ignore = Ignore::load(dir.path(), &["*直播回放 ??".to_string()]).unwrap();
+        let info = |item_id: u64, uname: &str, title: &str| {
+            VideoInfo::parse(&format!(
+                r#"{{"uname":"{}","title":"{}","itemId":{}}}"#,
+                uname, title, item_id
+            ))
+            .unwrap()
+        };
+
+        assert!(ignore.matches("111", None));
+        assert!(ignore.matches("c_333", Some(&info(111, "UP", "Part"))));
+        assert!(ignore.matches("222", Some(&info(222, "UP Raw", "Part"))));
+        assert!(ignore.matches("222", Some(&info(222, "UP", "周末直播回放 上篇"))));
+        assert!(!ignore.matches("222", Some(&info(222, "UP", "直播回放 上"))));
+        assert!(!ignore.matches("222", None));
+        assert!(!ignore.matches("# kept raw", None));
+        assert!(Ignore::load(&dir.path().join("missing"), &[]).is_ok());
+    }
+}
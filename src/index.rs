@@ -0,0 +1,157 @@
+/// Moving the conversion state database between machines, or rebuilding it
+///
+/// The export holds the state of every item, its labels and its output with
+/// absolute paths. On import the paths are rewritten with the given remaps,
+/// so the archive can have moved in between, and merged into the state of
+/// the output directory with the newer state of an item winning.
+///
+/// Without an export the state can be rebuilt from the metadata copied next
+/// to every output. Cached items are recorded by their item id as their
+/// cache directory is named, downloaded pages by their cid, as their BV id
+/// is not part of the metadata.
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use clap::Subcommand;
+use log::*;
+use serde::{Deserialize, Serialize};
+
+use crate::archive;
+use crate::error::{self, Context};
+use crate::i18n::tr;
+use crate::state::{ItemState, StateDb, Status};
+
+#[derive(Subcommand, Debug)]
+pub enum Action {
+    /// Write the state of the output directory to a file
+    Export { file: PathBuf },
+    /// Merge an exported state into the output directory
+    Import {
+        file: PathBuf,
+        /// Replace the path prefix FROM by TO, e.g. /mnt/old=/mnt/new
+        #[arg(long, value_parser = parse_remap, value_name = "FROM=TO")]
+        remap: Vec<(PathBuf, PathBuf)>,
+    },
+    /// Record the videos found in the output directory as converted
+    Rebuild,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Index {
+    /// Output directory the index was exported from
+    target: PathBuf,
+    items: BTreeMap<String, ItemState>,
+    #[serde(default)]
+    tags: BTreeMap<String, BTreeSet<String>>,
+}
+
+pub fn parse_remap(s: &str) -> Result<(PathBuf, PathBuf), String> {
+    match s.split_once('=') {
+        Some((from, to)) if !from.is_empty() => Ok((PathBuf::from(from), PathBuf::from(to))),
+        _ => Err(format!("invalid remap '{}', expected FROM=TO", s)),
+    }
+}
+
+fn remap(path: &Path, remaps: &[(PathBuf, PathBuf)]) -> PathBuf {
+    remaps
+        .iter()
+        .find_map(|(from, to)| path.strip_prefix(from).ok().map(|rest| to.join(rest)))
+        .unwrap_or_else(|| path.to_path_buf())
+}
+
+fn export(target_path: &Path, file: &Path) -> Result<(), error::Error> {
+    let db = StateDb::load(target_path)?;
+    let mut items = BTreeMap::new();
+    for (item, state) in db.items() {
+        let mut state = state.clone();
+        state.output = db.output(item);
+        items.insert(item.clone(), state);
+    }
+    let index = Index {
+        target: target_path.to_path_buf(),
+        items,
+        tags: db
+            .labels()
+            .map(|(item, labels)| (item.clone(), labels.clone()))
+            .collect(),
+    };
+    fs::write(file, serde_json::to_string_pretty(&index)?).context("write", file)?;
+    println!(
+        "{}",
+        tr!("Exported {} items to {}", index.items.len(), file.display())
+    );
+    Ok(())
+}
+
+fn import(
+    target_path: &Path,
+    file: &Path,
+    remaps: &[(PathBuf, PathBuf)],
+) -> Result<(), error::Error> {
+    let content = fs::read_to_string(file).context("read", file)?;
+    let index: Index = serde_json::from_str(&content)?;
+    // Without a remap outputs below the old output directory move with it
+    let mut remaps = remaps.to_vec();
+    remaps.push((index.target.clone(), target_path.to_path_buf()));
+
+    let mut db = StateDb::load(target_path)?;
+    let mut merged = 0;
+    let mut missing = 0;
+    for (item, mut state) in index.items {
+        let output = state.output.take().map(|file| remap(&file, &remaps));
+        if !db.merge(&item, state) {
+            continue;
+        }
+        merged += 1;
+        if let Some(output) = output {
+            if !output.is_file() {
+                debug!("Output of {} not found at {}", item, output.display());
+                missing += 1;
+            }
+            db.set_output(&item, &output);
+        }
+    }
+    for (item, labels) in &index.tags {
+        for label in labels {
+            db.tag(item, label);
+        }
+    }
+    fs::create_dir_all(target_path).context("create directory", target_path)?;
+    db.save()?;
+
+    println!("{}", tr!("Imported {} items", merged));
+    if missing > 0 {
+        warn!(
+            "The outputs of {} imported items do not exist, check the remaps",
+            missing
+        );
+    }
+    Ok(())
+}
+
+fn rebuild(target_path: &Path) -> Result<(), error::Error> {
+    let mut db = StateDb::load(target_path)?;
+    let mut added = 0;
+    for entry in archive::scan(target_path)? {
+        let item = entry.info.item_id.to_string();
+        if !db.is_converted(&item) {
+            db.set(&item, Status::Converted, None);
+            added += 1;
+        } else if db.output(&item).is_some_and(|file| file.is_file()) {
+            continue;
+        }
+        db.set_output(&item, &entry.file);
+    }
+    db.save()?;
+    println!("{}", tr!("Recorded {} converted items", added));
+    Ok(())
+}
+
+pub fn run(target_path: &Path, action: &Action) -> Result<(), error::Error> {
+    match action {
+        Action::Export { file } => export(target_path, file),
+        Action::Import { file, remap } => import(target_path, file, remap),
+        Action::Rebuild => rebuild(target_path),
+    }
+}
@@ -0,0 +1,331 @@
+/// Decompressing deflate streams (RFC 1951), the compression of zip archives
+///
+/// Output is written as it is produced, keeping only the 32KiB window back
+/// references can reach, so members of several GB need no more memory than
+/// small ones. Stored blocks, which archivers use for media that does not
+/// compress, are copied straight through.
+use std::io::{self, Read, Write};
+
+const WINDOW: usize = 32 * 1024;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+// Order in which the code length code lengths of a dynamic block are sent
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+// Reads the input least significant bit first
+struct Bits<R> {
+    input: R,
+    buffer: Vec<u8>,
+    pos: usize,
+    end: usize,
+    bits: u64,
+    count: u32,
+}
+
+impl<R: Read> Bits<R> {
+    fn fill(&mut self) -> io::Result<()> {
+        if self.pos == self.end {
+            self.end = self.input.read(&mut self.buffer)?;
+            self.pos = 0;
+            if self.end == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+        }
+        Ok(())
+    }
+
+    fn take(&mut self, n: u32) -> io::Result<u32> {
+        while self.count < n {
+            self.fill()?;
+            self.bits |= (self.buffer[self.pos] as u64) << self.count;
+            self.pos += 1;
+            self.count += 8;
+        }
+        let value = (self.bits & ((1 << n) - 1)) as u32;
+        self.bits >>= n;
+        self.count -= n;
+        Ok(value)
+    }
+
+    // Copy `len` bytes after skipping to the next byte boundary
+    fn copy_bytes(&mut self, mut len: usize, output: &mut Window<impl Write>) -> io::Result<()> {
+        self.take(self.count % 8)?;
+        while len > 0 && self.count > 0 {
+            output.push(self.take(8)? as u8)?;
+            len -= 1;
+        }
+        while len > 0 {
+            self.fill()?;
+            let n = len.min(self.end - self.pos);
+            output.extend(&self.buffer[self.pos..self.pos + n])?;
+            self.pos += n;
+            len -= n;
+        }
+        Ok(())
+    }
+}
+
+// Canonical Huffman code, decoded a bit at a time
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Huffman {
+        let mut counts = [0u16; 16];
+        for &len in lengths {
+            counts[len as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut offsets = [0u16; 16];
+        for len in 1..16 {
+            offsets[len] = offsets[len - 1] + counts[len - 1];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                symbols[offsets[len as usize] as usize] = symbol as u16;
+                offsets[len as usize] += 1;
+            }
+        }
+        Huffman { counts, symbols }
+    }
+
+    fn decode(&self, bits: &mut Bits<impl Read>) -> io::Result<u16> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for &count in &self.counts[1..] {
+            code |= bits.take(1)? as i32;
+            let count = count as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(invalid("invalid Huffman code"))
+    }
+}
+
+// The output with the history back references copy from
+struct Window<W> {
+    output: W,
+    data: Vec<u8>,
+    written: u64,
+}
+
+impl<W: Write> Window<W> {
+    fn push(&mut self, byte: u8) -> io::Result<()> {
+        self.data.push(byte);
+        self.flush_old()
+    }
+
+    fn extend(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.data.extend_from_slice(bytes);
+        self.flush_old()
+    }
+
+    fn copy(&mut self, distance: usize, len: usize) -> io::Result<()> {
+        if distance > self.data.len() {
+            return Err(invalid("distance too far back"));
+        }
+        let start = self.data.len() - distance;
+        for i in 0..len {
+            self.data.push(self.data[start + i]);
+        }
+        self.flush_old()
+    }
+
+    // Write out what no back reference can reach anymore
+    fn flush_old(&mut self) -> io::Result<()> {
+        if self.data.len() >= 4 * WINDOW {
+            let old = self.data.len() - WINDOW;
+            self.output.write_all(&self.data[..old])?;
+            self.data.drain(..old);
+            self.written += old as u64;
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> io::Result<u64> {
+        self.output.write_all(&self.data)?;
+        Ok(self.written + self.data.len() as u64)
+    }
+}
+
+fn fixed_codes() -> (Huffman, Huffman) {
+    let mut lengths = [8u8; 288];
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    (Huffman::new(&lengths), Huffman::new(&[5; 30]))
+}
+
+fn dynamic_codes(bits: &mut Bits<impl Read>) -> io::Result<(Huffman, Huffman)> {
+    let literals = bits.take(5)? as usize + 257;
+    let distances = bits.take(5)? as usize + 1;
+    let code_lengths = bits.take(4)? as usize + 4;
+    let mut lengths = [0u8; 19];
+    for &i in &CODE_LENGTH_ORDER[..code_lengths] {
+        lengths[i] = bits.take(3)? as u8;
+    }
+    let code = Huffman::new(&lengths);
+
+    let mut lengths = Vec::with_capacity(literals + distances);
+    while lengths.len() < literals + distances {
+        let (len, repeat) = match code.decode(bits)? {
+            len @ 0..=15 => (len as u8, 1),
+            16 => {
+                let previous = *lengths
+                    .last()
+                    .ok_or_else(|| invalid("repeat without length"))?;
+                (previous, 3 + bits.take(2)?)
+            }
+            17 => (0, 3 + bits.take(3)?),
+            _ => (0, 11 + bits.take(7)?),
+        };
+        lengths.extend(std::iter::repeat_n(len, repeat as usize));
+    }
+    if lengths.len() > literals + distances {
+        return Err(invalid("too many code lengths"));
+    }
+    Ok((
+        Huffman::new(&lengths[..literals]),
+        Huffman::new(&lengths[literals..]),
+    ))
+}
+
+fn inflate_block(
+    bits: &mut Bits<impl Read>,
+    output: &mut Window<impl Write>,
+    (literal, distance): &(Huffman, Huffman),
+) -> io::Result<()> {
+    loop {
+        let symbol = literal.decode(bits)? as usize;
+        if symbol < 256 {
+            output.push(symbol as u8)?;
+            continue;
+        }
+        if symbol == 256 {
+            return Ok(());
+        }
+        let i = symbol - 257;
+        if i >= LENGTH_BASE.len() {
+            return Err(invalid("invalid length code"));
+        }
+        let len = LENGTH_BASE[i] as usize + bits.take(LENGTH_EXTRA[i] as u32)? as usize;
+        let i = distance.decode(bits)? as usize;
+        if i >= DISTANCE_BASE.len() {
+            return Err(invalid("invalid distance code"));
+        }
+        let back = DISTANCE_BASE[i] as usize + bits.take(DISTANCE_EXTRA[i] as u32)? as usize;
+        output.copy(back, len)?;
+    }
+}
+
+/// Decompress the deflate stream of `input` into `output`, returning the
+/// decompressed size
+pub fn inflate(input: impl Read, output: impl Write) -> io::Result<u64> {
+    let mut bits = Bits {
+        input,
+        buffer: vec![0; 64 * 1024],
+        pos: 0,
+        end: 0,
+        bits: 0,
+        count: 0,
+    };
+    let mut window = Window {
+        output,
+        data: Vec::with_capacity(4 * WINDOW),
+        written: 0,
+    };
+    loop {
+        let last = bits.take(1)? == 1;
+        match bits.take(2)? {
+            0 => {
+                bits.take(bits.count % 8)?;
+                let len = bits.take(16)?;
+                if bits.take(16)? != !len & 0xffff {
+                    return Err(invalid("corrupt stored block length"));
+                }
+                bits.copy_bytes(len as usize, &mut window)?;
+            }
+            1 => inflate_block(&mut bits, &mut window, &fixed_codes())?,
+            2 => {
+                let codes = dynamic_codes(&mut bits)?;
+                inflate_block(&mut bits, &mut window, &codes)?;
+            }
+            _ => return Err(invalid("invalid block type")),
+        }
+        if last {
+            return window.finish();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unhex(hex: &str) -> Vec<u8> {
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    fn decompress(hex: &str) -> Vec<u8> {
+        let mut output = Vec::new();
+        let written = inflate(&unhex(hex)[..], &mut output).unwrap();
+        assert_eq!(written, output.len() as u64);
+        output
+    }
+
+    #[test]
+    fn inflates_every_block_type() {
+        assert_eq!(
+            decompress("010c00f3ff73746f72656420626c6f636b"),
+            b"stored block"
+        );
+        assert_eq!(
+            decompress("cb48cdc9c957c8402293327332411800"),
+            b"hello hello hello bilibili"
+        );
+        let text = "The quick brown fox jumps over the lazy dog. ".repeat(4)
+            + "Pack my box with five dozen liquor jugs.";
+        assert_eq!(
+            decompress(concat!(
+                "cdcbc70180201005d1567e05d4e2c10640490656b250bd5b86e779b33a8d58fd764225ea",
+                "01865e1cf57e32a8e984c2f9927360272bb0fe032f92dd3da018755f1c8c6f9ad3d4019",
+                "78f9512bf368b0f"
+            )),
+            text.as_bytes()
+        );
+    }
+
+    #[test]
+    fn rejects_truncated_streams() {
+        let mut output = Vec::new();
+        assert!(inflate(&unhex("cb48cdc9c957c840")[..], &mut output).is_err());
+    }
+}
@@ -0,0 +1,118 @@
+/// Detailed information about a single cache item
+use std::fmt::Display;
+use std::path::Path;
+
+use crate::i18n::tr;
+use crate::list::{self, Column};
+use crate::{disk, error, get_files_by_extension, hash, probe, state, SPECIAL_OFFSET};
+use crate::{CachedVideo, ConvertOptions};
+
+// Danmaku is cached as XML or already converted ASS, subtitles as SRT/VTT
+const DANMAKU_EXTENSIONS: &[&str] = &["xml", "ass"];
+const SUBTITLE_EXTENSIONS: &[&str] = &["srt", "vtt"];
+
+// A labelled line, the values stay aligned with translated labels
+fn field(label: &'static str, value: impl Display) {
+    println!(
+        "{} {}",
+        list::pad(&format!("{}:", tr!(label)), 12, false),
+        value
+    );
+}
+
+/// Print the parsed metadata of a video
+pub fn print_metadata(video: &CachedVideo) {
+    let info = &video.info;
+    field("Directory", video.dir.display());
+    field("Item", info.item_id);
+    field("UP", &info.uname);
+    field("Group", &info.group_title);
+    field("Title", &info.title);
+    field("Page", info.p);
+    field("Size", disk::human_size(info.total_size));
+    field("On disk", disk::human_size(video.disk_size));
+    field("Published", list::cell(video, Column::Pubdate));
+    field("Updated", list::cell(video, Column::Updated));
+    field("Cover", &info.cover_path);
+    field("Group cover", &info.group_cover_path);
+}
+
+fn file_names(path: &Path, extensions: &[&str]) -> String {
+    let names: Vec<String> = extensions
+        .iter()
+        .flat_map(|ext| get_files_by_extension(path, ext).unwrap_or_default())
+        .filter_map(|f| f.file_name().map(|n| n.to_string_lossy().to_string()))
+        .collect();
+    if names.is_empty() {
+        tr!("none").to_string()
+    } else {
+        names.join(", ")
+    }
+}
+
+/// Print everything known about one cache item, with the hashes of its
+/// media if `hash`
+pub fn show(
+    video: &CachedVideo,
+    target_path: &Path,
+    hash: bool,
+    options: &ConvertOptions,
+) -> Result<(), error::Error> {
+    print_metadata(video);
+
+    println!("{}:", tr!("Media"));
+    for media in get_files_by_extension(&video.dir, "m4s")? {
+        let size = media.metadata().map(|m| m.len()).unwrap_or_default();
+        let streams = match probe::streams(&options.ffmpeg, &media) {
+            Ok(streams) => streams
+                .iter()
+                .map(|s| s.describe())
+                .collect::<Vec<_>>()
+                .join(", "),
+            Err(e) => tr!("probe failed: {}", e),
+        };
+        println!(
+            "  {}  {}  {}",
+            media.file_name().unwrap_or_default().to_string_lossy(),
+            disk::human_size(size),
+            streams
+        );
+        if hash {
+            match hash::file(&media, SPECIAL_OFFSET) {
+                Ok(digest) => println!("    sha256 {}", digest),
+                Err(e) => println!("    {}", tr!("sha256 failed: {}", e)),
+            }
+        }
+    }
+    field("Danmaku", file_names(&video.dir, DANMAKU_EXTENSIONS));
+    field("Subtitles", file_names(&video.dir, SUBTITLE_EXTENSIONS));
+
+    let final_file = options.layout.output(&video.info, target_path).file;
+    field("Output", final_file.display());
+
+    let name = list::cell(video, Column::Dir);
+    let db = state::StateDb::load(target_path)?;
+    let status = match db.get(&name) {
+        Some(item) => {
+            let label = tr!(match item.status {
+                state::Status::Converting => "Converting",
+                state::Status::Converted => "Converted",
+                state::Status::Failed => "Failed",
+                state::Status::Interrupted => "Interrupted",
+                state::Status::Quarantined => "Quarantined",
+            });
+            match &item.error {
+                Some(e) => format!("{}: {}", label, e),
+                None => label.to_string(),
+            }
+        }
+        None if final_file.exists() => tr!("Converted (not recorded)").to_string(),
+        None => tr!("New").to_string(),
+    };
+    field("Status", status);
+    let tags: Vec<&str> = db.tags(&name).map(String::as_str).collect();
+    if !tags.is_empty() {
+        field("Tags", tags.join(", "));
+    }
+    Ok(())
+}
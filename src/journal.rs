@@ -0,0 +1,189 @@
+/// Removing converted sources so that a crash never loses an item
+///
+/// With `--autoremove` a source is only removed once its output is synced
+/// to disk and recorded as converted. Before a source is removed, an entry
+/// naming it and its output is appended to a journal in the output
+/// directory and synced, and a second one marks the removal done. A run
+/// finding a removal that was not done, e.g. after a power cut, finishes it
+/// if the output is still there, as a half removed source would only
+/// convert into a broken video, and keeps what is left of the source
+/// otherwise.
+use std::fs::{self, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use log::*;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{self, Context};
+use crate::{output_valid, remove_source};
+
+const JOURNAL_FILE: &str = ".bilibili-journal.jsonl";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum Step {
+    Removing,
+    Removed,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Entry {
+    item: String,
+    step: Step,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    source: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    output: Option<PathBuf>,
+}
+
+pub struct Journal {
+    path: PathBuf,
+}
+
+impl Journal {
+    pub fn new(target_path: &Path) -> Journal {
+        Journal {
+            path: target_path.join(JOURNAL_FILE),
+        }
+    }
+
+    fn append(&self, entry: &Entry) -> Result<(), error::Error> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .context("open", &self.path)?;
+        writeln!(file, "{}", serde_json::to_string(entry)?).context("write", &self.path)?;
+        file.sync_data().context("write", &self.path)
+    }
+
+    // Removals begun but not done, oldest first
+    fn pending(&self) -> Result<Vec<Entry>, error::Error> {
+        let file = match fs::File::open(&self.path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e).context("open", &self.path),
+        };
+        let mut pending: Vec<Entry> = Vec::new();
+        for line in BufReader::new(file).lines() {
+            let line = line.context("read", &self.path)?;
+            // The last line of a crash may be cut off
+            let Ok(entry) = serde_json::from_str::<Entry>(&line) else {
+                continue;
+            };
+            pending.retain(|p| p.item != entry.item);
+            if entry.step == Step::Removing {
+                pending.push(entry);
+            }
+        }
+        Ok(pending)
+    }
+
+    /// Remove the source of `item` converted into `output`, recording the
+    /// removal so an interrupted one is finished by the next run
+    pub fn remove(
+        &self,
+        item: &str,
+        source: &Path,
+        output: &Path,
+        permanent: bool,
+    ) -> Result<(), error::Error> {
+        self.append(&Entry {
+            item: item.to_string(),
+            step: Step::Removing,
+            source: Some(source.to_path_buf()),
+            output: Some(output.to_path_buf()),
+        })?;
+        remove_source(source, permanent)?;
+        self.append(&Entry {
+            item: item.to_string(),
+            step: Step::Removed,
+            source: None,
+            output: None,
+        })
+    }
+
+    /// Finish the removals an earlier run was interrupted in, then start
+    /// the journal afresh
+    pub fn recover(&self, permanent: bool) -> Result<(), error::Error> {
+        for entry in self.pending()? {
+            let (Some(source), Some(output)) = (&entry.source, &entry.output) else {
+                continue;
+            };
+            if source.exists() && output_valid(output) {
+                warn!(
+                    "Finishing the removal of {}, converted into {}",
+                    source.display(),
+                    output.display()
+                );
+                remove_source(source, permanent)?;
+            } else if source.exists() {
+                warn!(
+                    "Keeping what is left of {}, its output {} is missing",
+                    source.display(),
+                    output.display()
+                );
+            }
+            self.append(&Entry {
+                step: Step::Removed,
+                source: None,
+                output: None,
+                ..entry
+            })?;
+        }
+        match fs::remove_file(&self.path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                Err(e).context("remove", &self.path)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::TempDir;
+
+    #[test]
+    fn finishes_interrupted_removals() {
+        let dir = TempDir::new();
+        let journal = Journal::new(dir.path());
+        let (done, half, lost) = (
+            dir.path().join("111"),
+            dir.path().join("222"),
+            dir.path().join("333"),
+        );
+        for source in [&done, &half, &lost] {
+            fs::create_dir(source).unwrap();
+            fs::write(source.join("media.m4s"), "media").unwrap();
+        }
+        let output = dir.path().join("video.mp4");
+        fs::write(&output, "video").unwrap();
+
+        journal.remove("111", &done, &output, true).unwrap();
+        assert!(!done.exists());
+        // Crashed while removing 222 and 333
+        for (item, source, output) in [
+            ("222", &half, output.clone()),
+            ("333", &lost, dir.path().join("missing.mp4")),
+        ] {
+            journal
+                .append(&Entry {
+                    item: item.to_string(),
+                    step: Step::Removing,
+                    source: Some(source.clone()),
+                    output: Some(output),
+                })
+                .unwrap();
+        }
+        assert_eq!(journal.pending().unwrap().len(), 2);
+
+        journal.recover(true).unwrap();
+        assert!(!half.exists());
+        assert!(lost.join("media.m4s").exists());
+        assert!(journal.pending().unwrap().is_empty());
+        assert!(!dir.path().join(JOURNAL_FILE).exists());
+    }
+}
@@ -0,0 +1,411 @@
+/// Layout of the output directory
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use chrono::DateTime;
+use clap::ValueEnum;
+use serde::Deserialize;
+
+use crate::container::Container;
+use crate::episode::{self, Episode};
+use crate::sanitize::Sanitizer;
+use crate::share;
+use crate::uploaders::Uploaders;
+use crate::VideoInfo;
+
+pub const DEFAULT_TEMPLATE: &str = "{uname} - {title} [{item_id}]";
+
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Organize {
+    /// `<uname> - <group>/<p> <title>/`, or `<uname> - <title>/` for single videos
+    Group,
+    /// `<uname>/<group>/<p> <title>/`, or `<uname>/<title>/` for single videos
+    ByUp,
+    /// `<year>/<month>/` followed by the group layout
+    ByDate,
+    /// All videos in the output directory, named after the name template
+    Flat,
+}
+
+pub struct Layout {
+    pub organize: Organize,
+    /// File name template of the flat layout, see `render`
+    pub template: String,
+    pub sanitizer: Sanitizer,
+    /// Patterns finding episode numbers in part titles, see `episode`
+    pub episodes: Vec<episode::Pattern>,
+    /// Layout settings of single uploaders, see `uploaders`
+    pub uploaders: Uploaders,
+    /// Container of the videos, naming their extension
+    pub container: Container,
+}
+
+/// Where the files of one converted item go
+pub struct Output {
+    pub dir: PathBuf,
+    pub file: PathBuf,
+    /// Whether `dir` belongs to this item alone, rather than being shared
+    pub own_dir: bool,
+    /// Set once written if copying the streams failed and the video was
+    /// re-encoded with the fallback profile
+    pub reencoded: bool,
+    /// Gaps found in the cached segments before muxing, see `mp4::continuity`
+    pub gaps: Vec<String>,
+}
+
+impl Output {
+    /// Path of a file stored next to the video, e.g. cover art. In a shared
+    /// directory it is prefixed with the video name so items do not clash.
+    pub fn side_file(&self, name: &str) -> PathBuf {
+        if self.own_dir {
+            self.dir.join(name)
+        } else {
+            let stem = self
+                .file
+                .file_stem()
+                .map(|s| s.to_string_lossy().to_string())
+                .unwrap_or_default();
+            self.dir.join(format!("{}.{}", stem, name))
+        }
+    }
+}
+
+fn pubdate(video_info: &VideoInfo, format: &str) -> String {
+    DateTime::from_timestamp(video_info.pubdate, 0)
+        .map(|dt| dt.format(format).to_string())
+        .unwrap_or_default()
+}
+
+/// Expand `{uname}`, `{title}`, `{group}`, `{p}`, `{item_id}` and `{date}`,
+/// and `{season}` and `{episode}` as two digits if `episode` is known
+pub fn render(template: &str, video_info: &VideoInfo, episode: Option<Episode>) -> String {
+    let number = |n: Option<u32>| n.map(|n| format!("{:02}", n)).unwrap_or_default();
+    template
+        .replace("{uname}", &video_info.uname)
+        .replace("{title}", &video_info.title)
+        .replace("{group}", &video_info.group_title)
+        .replace("{p}", &video_info.p.to_string())
+        .replace("{item_id}", &video_info.item_id.to_string())
+        .replace("{date}", &pubdate(video_info, "%Y-%m-%d"))
+        .replace("{season}", &number(episode.map(|e| e.season)))
+        .replace("{episode}", &number(episode.map(|e| e.episode)))
+}
+
+impl Layout {
+    /// Season and episode of an item, if its title has them
+    pub fn episode(&self, video_info: &VideoInfo) -> Option<Episode> {
+        episode::infer(&video_info.title, &self.episodes)
+    }
+
+    // `<prefix><group>/<p> <title>` for parts of a group, `<prefix><title>` otherwise.
+    // Names that can not be used fall back to the group's key, keeping its
+    // parts together, and to the item id.
+    fn item_dir(&self, video_info: &VideoInfo, base: PathBuf, prefix: &str) -> PathBuf {
+        let sanitizer = &self.sanitizer;
+        let item_id = video_info.item_id.to_string();
+        if video_info.group_title != video_info.title {
+            let group = format!("{}{}", prefix, video_info.group_title);
+            base.join(sanitizer.name(&group, &video_info.group_key()))
+                .join(sanitizer.name(
+                    &format!("{} {}", video_info.p, video_info.title),
+                    &format!("{} {}", video_info.p, item_id),
+                ))
+        } else {
+            base.join(sanitizer.name(&format!("{}{}", prefix, video_info.title), &item_id))
+        }
+    }
+
+    /// Output paths of an item, disambiguated when another video already
+    /// occupies them, see `disambiguate`.
+    pub fn output(&self, video_info: &VideoInfo, target_path: &Path) -> Output {
+        let output = disambiguate(self.preferred_output(video_info, target_path), video_info);
+        Output {
+            dir: share::long_path(output.dir),
+            file: share::long_path(output.file),
+            ..output
+        }
+    }
+
+    fn preferred_output(&self, video_info: &VideoInfo, target_path: &Path) -> Output {
+        let overrides = self.uploaders.get(&video_info.uname);
+        let target = match overrides.and_then(|o| o.subdir.as_ref()) {
+            Some(subdir) => target_path.join(subdir),
+            None => target_path.to_path_buf(),
+        };
+        let target_path = target.as_path();
+        let extension = self.container.extension();
+        let file_name = format!("{}.{}", video_info.item_id, extension);
+        let dir = match overrides.and_then(|o| o.organize).unwrap_or(self.organize) {
+            Organize::Group => {
+                let prefix = format!("{} - ", video_info.uname);
+                self.item_dir(video_info, target_path.to_path_buf(), &prefix)
+            }
+            Organize::ByUp => {
+                let up = self
+                    .sanitizer
+                    .name(&video_info.uname, &video_info.uploader_key());
+                self.item_dir(video_info, target_path.join(up), "")
+            }
+            Organize::ByDate => {
+                let base = target_path
+                    .join(pubdate(video_info, "%Y"))
+                    .join(pubdate(video_info, "%m"));
+                let prefix = format!("{} - ", video_info.uname);
+                self.item_dir(video_info, base, &prefix)
+            }
+            Organize::Flat => {
+                let name = self.sanitizer.name(
+                    &render(
+                        overrides
+                            .and_then(|o| o.name_template.as_deref())
+                            .unwrap_or(&self.template),
+                        video_info,
+                        self.episode(video_info),
+                    ),
+                    &video_info.item_id.to_string(),
+                );
+                return Output {
+                    dir: target_path.to_path_buf(),
+                    file: target_path.join(format!("{}.{}", name, extension)),
+                    own_dir: false,
+                    reencoded: false,
+                    gaps: Vec::new(),
+                };
+            }
+        };
+        Output {
+            file: dir.join(file_name),
+            dir,
+            own_dir: true,
+            reencoded: false,
+            gaps: Vec::new(),
+        }
+    }
+}
+
+// Item id recorded in the metadata copied next to an existing output
+fn existing_item_id(output: &Output) -> Option<u64> {
+    let content = fs::read_to_string(output.side_file("videoInfo.json")).ok()?;
+    let value: serde_json::Value = serde_json::from_str(&content).ok()?;
+    value.get("itemId")?.as_u64()
+}
+
+// The existing output whose name differs from the one of `output` only in
+// case, the same name on a case-insensitive share
+fn case_variant(output: &Output) -> Option<Output> {
+    if output.own_dir {
+        let dir = share::case_collision(&output.dir)?;
+        Some(Output {
+            file: dir.join(output.file.file_name()?),
+            dir,
+            own_dir: true,
+            reencoded: false,
+            gaps: Vec::new(),
+        })
+    } else {
+        Some(Output {
+            dir: output.dir.clone(),
+            file: share::case_collision(&output.file)?,
+            own_dir: false,
+            reencoded: false,
+            gaps: Vec::new(),
+        })
+    }
+}
+
+fn exists(output: &Output) -> bool {
+    if output.own_dir {
+        output.dir.exists()
+    } else {
+        output.file.exists()
+    }
+}
+
+// Whether the output paths are taken by a different video
+fn occupied(output: &Output, item_id: u64) -> bool {
+    if exists(output) {
+        // Outputs without metadata are left alone as they cannot be told apart
+        return existing_item_id(output).is_some_and(|id| id != item_id);
+    }
+    // Unless known to be this video's, as it would be written into otherwise
+    case_variant(output).is_some_and(|variant| existing_item_id(&variant) != Some(item_id))
+}
+
+// Append `suffix` to the item's own directory, or to the file name in a shared one
+fn with_suffix(output: &Output, suffix: &str) -> Output {
+    if output.own_dir {
+        let mut name = output.dir.file_name().unwrap_or_default().to_owned();
+        name.push(suffix);
+        let dir = output.dir.with_file_name(name);
+        let file = dir.join(output.file.file_name().unwrap_or_default());
+        Output {
+            dir,
+            file,
+            own_dir: true,
+            reencoded: false,
+            gaps: Vec::new(),
+        }
+    } else {
+        let stem = output
+            .file
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy();
+        let ext = output
+            .file
+            .extension()
+            .unwrap_or_default()
+            .to_string_lossy();
+        Output {
+            dir: output.dir.clone(),
+            file: output.dir.join(format!("{}{}.{}", stem, suffix, ext)),
+            own_dir: false,
+            reencoded: false,
+            gaps: Vec::new(),
+        }
+    }
+}
+
+/// Two different videos may end up with the same name, e.g. re-uploads or
+/// identical titles. Instead of merging them into one directory the later
+/// one gets its item id appended, then a counter if even that is taken.
+/// Names differing only in case count as the same, and an earlier output
+/// of the video named so is reused.
+pub fn disambiguate(output: Output, video_info: &VideoInfo) -> Output {
+    let item_id = video_info.item_id;
+    if !exists(&output) {
+        if let Some(variant) = case_variant(&output) {
+            if existing_item_id(&variant) == Some(item_id) {
+                return variant;
+            }
+        }
+    }
+    if !occupied(&output, item_id) {
+        return output;
+    }
+    let candidate = with_suffix(&output, &format!(" [{}]", item_id));
+    if !occupied(&candidate, item_id) {
+        return candidate;
+    }
+    let mut counter = 2;
+    loop {
+        let candidate = with_suffix(&output, &format!(" ({})", counter));
+        if !occupied(&candidate, item_id) {
+            return candidate;
+        }
+        counter += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::TempDir;
+
+    fn info(item_id: u64) -> VideoInfo {
+        VideoInfo::parse(&format!(r#"{{"itemId":{}}}"#, item_id)).unwrap()
+    }
+
+    // Write the metadata of video `item_id` next to `output`
+    fn record(output: &Output, item_id: u64) {
+        fs::create_dir_all(&output.dir).unwrap();
+        let metadata = format!(r#"{{"itemId":{}}}"#, item_id);
+        fs::write(output.side_file("videoInfo.json"), metadata).unwrap();
+    }
+
+    #[test]
+    fn later_videos_get_their_id_then_a_counter() {
+        let dir = TempDir::new();
+        let output = |name: &str| Output {
+            dir: dir.path().join(name),
+            file: dir.path().join(name).join("video.mp4"),
+            own_dir: true,
+            reencoded: false,
+            gaps: Vec::new(),
+        };
+        let named = |item_id: u64| disambiguate(output("Song"), &info(item_id)).dir;
+
+        assert_eq!(named(111), dir.path().join("Song"));
+        record(&output("Song"), 111);
+        assert_eq!(named(111), dir.path().join("Song"));
+        assert_eq!(named(222), dir.path().join("Song [222]"));
+        record(&output("Song [222]"), 333);
+        assert_eq!(named(222), dir.path().join("Song (2)"));
+        record(&output("Song (2)"), 444);
+        assert_eq!(named(222), dir.path().join("Song (3)"));
+        // Outputs without metadata can not be told apart and are reused
+        fs::create_dir_all(dir.path().join("Untracked")).unwrap();
+        let untracked = disambiguate(output("Untracked"), &info(555));
+        assert_eq!(untracked.dir, dir.path().join("Untracked"));
+
+        // Files in a shared directory get the suffix on their name
+        let shared = || Output {
+            dir: dir.path().join("UP"),
+            file: dir.path().join("UP/Song.mp4"),
+            own_dir: false,
+            reencoded: false,
+            gaps: Vec::new(),
+        };
+        record(&shared(), 111);
+        fs::write(shared().file, "mp4").unwrap();
+        let other = disambiguate(shared(), &info(222));
+        assert_eq!(other.file, dir.path().join("UP/Song [222].mp4"));
+        assert_eq!(
+            other.side_file("videoInfo.json"),
+            dir.path().join("UP/Song [222].videoInfo.json")
+        );
+        assert_eq!(disambiguate(shared(), &info(111)).file, shared().file);
+    }
+
+    #[test]
+    fn ascii_names_fall_back_to_the_group_and_uploader() {
+        let dir = TempDir::new();
+        let muxer = crate::fixture::StubMuxer::default();
+        let args = ["--ascii-names", "--organize", "by-up"];
+        let options = crate::fixture::options(&args, dir.path(), &muxer);
+        let part = |item_id: u64, p: u32, ids: &str| {
+            let metadata = format!(
+                r#"{{"itemId":{},"uname":"神","groupTitle":"原神","title":"演示{}","p":{}{}}}"#,
+                item_id, p, p, ids
+            );
+            let output = options
+                .layout
+                .output(&VideoInfo::parse(&metadata).unwrap(), dir.path());
+            output.file.strip_prefix(dir.path()).unwrap().to_path_buf()
+        };
+
+        let ids = r#","groupId":100,"uid":7"#;
+        assert_eq!(part(111, 1, ids), Path::new("7/100/1 111/111.mp4"));
+        assert_eq!(part(222, 2, ids), Path::new("7/100/2 222/222.mp4"));
+        // Without ids in the metadata the names are hashed, alike for the group
+        let (first, second) = (part(111, 1, ""), part(222, 2, ""));
+        assert_eq!(
+            first.parent().unwrap().parent(),
+            second.parent().unwrap().parent()
+        );
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn names_differing_only_in_case_are_the_same() {
+        let dir = TempDir::new();
+        let existing = dir.path().join("up - song");
+        fs::create_dir_all(&existing).unwrap();
+        fs::write(existing.join("videoInfo.json"), r#"{"itemId":111}"#).unwrap();
+        let output = || Output {
+            dir: dir.path().join("UP - Song"),
+            file: dir.path().join("UP - Song/222.mp4"),
+            own_dir: true,
+            reencoded: false,
+            gaps: Vec::new(),
+        };
+        let info = |id: u64| VideoInfo::parse(&format!(r#"{{"itemId":{}}}"#, id)).unwrap();
+
+        let other = disambiguate(output(), &info(222));
+        assert_eq!(other.dir, dir.path().join("UP - Song [222]"));
+        let same = disambiguate(output(), &info(111));
+        assert_eq!(same.dir, existing);
+        assert_eq!(same.file, existing.join("222.mp4"));
+    }
+}
@@ -0,0 +1,140 @@
+/// Caches of older Android clients
+///
+/// They keep an `entry.json` instead of `.videoInfo` in a directory per
+/// page, `<avid>/<page>/`, and the media as FLV segments `0.blv`, `1.blv`,
+/// ... in a subdirectory named after the quality. Every page is an item of
+/// its own, found below the directory of its video. The segments each carry
+/// an FLV header, so they are joined with the concat demuxer.
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::error::{self, Context};
+use crate::video_info::Cover;
+use crate::VideoInfo;
+
+pub const ENTRY_FILE: &str = "entry.json";
+const SEGMENT_EXTENSIONS: [&str; 2] = ["blv", "flv"];
+
+#[derive(Deserialize, Default)]
+struct PageData {
+    #[serde(default)]
+    cid: u64,
+    #[serde(default)]
+    page: u32,
+    #[serde(default)]
+    part: String,
+}
+
+#[derive(Deserialize)]
+struct Entry {
+    #[serde(default)]
+    title: String,
+    #[serde(default)]
+    owner_name: String,
+    #[serde(default)]
+    owner_id: u64,
+    #[serde(default)]
+    cover: String,
+    #[serde(default)]
+    total_bytes: u64,
+    /// Milliseconds
+    #[serde(default)]
+    time_create_stamp: i64,
+    #[serde(default)]
+    time_update_stamp: i64,
+    #[serde(default)]
+    avid: u64,
+    /// Name of the directory holding the segments
+    #[serde(default)]
+    type_tag: String,
+    #[serde(default)]
+    page_data: PageData,
+}
+
+fn entries(dir: &Path) -> Vec<PathBuf> {
+    let mut entries: Vec<PathBuf> = dir
+        .read_dir()
+        .map(|entries| entries.filter_map(|e| e.ok().map(|e| e.path())).collect())
+        .unwrap_or_default();
+    entries.sort();
+    entries
+}
+
+fn load(dir: &Path) -> Result<Entry, error::Error> {
+    let file = dir.join(ENTRY_FILE);
+    let content = fs::read_to_string(&file).context("read", &file)?;
+    Ok(serde_json::from_str(&content)?)
+}
+
+/// Whether `dir` is the page directory of a legacy cache
+pub fn is_item(dir: &Path) -> bool {
+    dir.join(ENTRY_FILE).is_file()
+}
+
+/// Page directories below the video directory `dir` of a legacy cache
+pub fn pages(dir: &Path) -> Vec<PathBuf> {
+    entries(dir).into_iter().filter(|p| is_item(p)).collect()
+}
+
+/// Metadata of a page directory in the format of the client's `.videoInfo`
+pub fn video_info(dir: &Path) -> Result<VideoInfo, error::Error> {
+    let entry = load(dir)?;
+    let page = entry.page_data;
+    let cover = Cover::from(Some(entry.cover));
+    Ok(VideoInfo {
+        uname: entry.owner_name,
+        title: if page.part.is_empty() {
+            entry.title.clone()
+        } else {
+            page.part
+        },
+        group_title: entry.title,
+        pubdate: entry.time_create_stamp / 1000,
+        update_time: entry.time_update_stamp / 1000,
+        total_size: entry.total_bytes,
+        item_id: if page.cid > 0 { page.cid } else { entry.avid },
+        cover_path: cover.clone(),
+        group_cover_path: cover,
+        cover_url: None,
+        group_cover_url: None,
+        p: page.page.max(1),
+        view_points: Vec::new(),
+        group_id: entry.avid,
+        uid: entry.owner_id,
+    })
+}
+
+fn segment_number(path: &Path) -> u64 {
+    path.file_stem()
+        .and_then(|s| s.to_str())
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(u64::MAX)
+}
+
+/// FLV segments of a page directory in playing order
+pub fn segments(dir: &Path) -> Result<Vec<PathBuf>, error::Error> {
+    let entry = load(dir)?;
+    let quality_dir = dir.join(&entry.type_tag);
+    // The quality is not recorded by every client version
+    let dirs = if !entry.type_tag.is_empty() && quality_dir.is_dir() {
+        vec![quality_dir]
+    } else {
+        entries(dir).into_iter().filter(|p| p.is_dir()).collect()
+    };
+    for dir in dirs {
+        let mut segments: Vec<PathBuf> = entries(&dir)
+            .into_iter()
+            .filter(|p| {
+                p.extension()
+                    .is_some_and(|e| SEGMENT_EXTENSIONS.iter().any(|s| e == *s))
+            })
+            .collect();
+        if !segments.is_empty() {
+            segments.sort_by_key(|p| segment_number(p));
+            return Ok(segments);
+        }
+    }
+    Err(error::Error::NoMediaStreams)
+}
@@ -0,0 +1,3 @@
+/// Parts of the converter that other programs may build on, the `bilibili`
+/// binary uses them from here too
+pub mod queue;
@@ -0,0 +1,389 @@
+/// Listing of cached videos
+use std::cell::OnceCell;
+use std::fs;
+use std::path::Path;
+
+use clap::ValueEnum;
+
+use crate::mp4::{self, SampleEntry};
+use crate::quality::{self, Quality};
+use crate::{disk, estimate, item_name, state, CachedVideo};
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum SortKey {
+    Title,
+    Pubdate,
+    Size,
+    Up,
+}
+
+/// Conversion state of an item as recorded in the output directory
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Status {
+    /// Not converted yet, also when a conversion was interrupted
+    New,
+    Converted,
+    /// The last conversion failed, see the ERROR column
+    Failed,
+}
+
+impl Status {
+    pub fn of(db: &state::StateDb, item: &str) -> Status {
+        match db.get(item).map(|s| s.status) {
+            Some(state::Status::Converted) => Status::Converted,
+            Some(state::Status::Failed) => Status::Failed,
+            _ => Status::New,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Status::New => "new",
+            Status::Converted => "converted",
+            Status::Failed => "failed",
+        }
+    }
+}
+
+/// Which of the cached items are listed
+pub struct Filter<'a> {
+    /// Only items with this label
+    pub tag: Option<&'a str>,
+    pub status: Option<Status>,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Column {
+    Id,
+    Dir,
+    Up,
+    Group,
+    Title,
+    Page,
+    Size,
+    Disk,
+    /// Estimated size of the converted video
+    Output,
+    Pubdate,
+    Updated,
+    /// Conversion state, see `Status`
+    Status,
+    /// Why the last conversion failed
+    Error,
+    /// Video codec, e.g. HEVC or AV1
+    Codec,
+    /// Picture size of the video, e.g. 1920x1080
+    Resolution,
+    /// Audio codec
+    Audio,
+}
+
+/// Formats of the streams an item is converted from, read from the init
+/// segments of its cached files
+#[derive(Debug, Default)]
+struct Media {
+    video: Option<SampleEntry>,
+    audio: Option<SampleEntry>,
+}
+
+impl Media {
+    /// Streams in `dir`, the video picked at `quality` like a conversion does
+    fn read(dir: &Path, quality: Quality) -> Media {
+        let mut paths: Vec<_> = fs::read_dir(dir)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|e| e == "m4s"))
+            .collect();
+        paths.sort();
+        let mut media = Media::default();
+        let mut videos = Vec::new();
+        for path in paths {
+            match mp4::sample_entry(&path) {
+                Ok(Some(entry)) if entry.video => {
+                    let size = path.metadata().map(|m| m.len()).unwrap_or_default();
+                    videos.push((entry, size));
+                }
+                Ok(Some(entry)) => {
+                    media.audio.get_or_insert(entry);
+                }
+                _ => {}
+            }
+        }
+        media.video = quality::pick(videos, quality, |(entry, size)| {
+            (entry.height as u32, *size)
+        })
+        .map(|(entry, _)| entry);
+        media
+    }
+
+    fn cell(&self, column: Column) -> String {
+        match column {
+            Column::Codec => self.video.map(|v| v.codec()),
+            Column::Resolution => self.video.map(|v| format!("{}x{}", v.width, v.height)),
+            Column::Audio => self.audio.map(|a| a.codec()),
+            _ => None,
+        }
+        .unwrap_or_default()
+    }
+}
+
+pub fn sort(videos: &mut [CachedVideo], key: SortKey, reverse: bool) {
+    match key {
+        SortKey::Title => videos.sort_by(|a, b| a.info.title.cmp(&b.info.title)),
+        SortKey::Pubdate => videos.sort_by_key(|v| v.info.pubdate),
+        SortKey::Size => videos.sort_by_key(|v| v.disk_size),
+        SortKey::Up => videos.sort_by(|a, b| a.info.uname.cmp(&b.info.uname)),
+    }
+    if reverse {
+        videos.reverse();
+    }
+}
+
+fn format_timestamp(timestamp: i64) -> String {
+    chrono::DateTime::from_timestamp(timestamp, 0)
+        .map(|dt| dt.to_string())
+        .unwrap_or_default()
+}
+
+pub fn cell(video: &CachedVideo, column: Column) -> String {
+    let info = &video.info;
+    match column {
+        Column::Id => info.item_id.to_string(),
+        Column::Dir => item_name(&video.dir),
+        Column::Up => info.uname.clone(),
+        Column::Group => info.group_title.clone(),
+        Column::Title => info.title.clone(),
+        Column::Page => info.p.to_string(),
+        Column::Size => info.total_size.to_string(),
+        Column::Disk => video.disk_size.to_string(),
+        Column::Output => estimate::item(&video.dir, Quality::Highest).to_string(),
+        Column::Pubdate => format_timestamp(info.pubdate),
+        Column::Updated => format_timestamp(info.update_time),
+        // Recorded in the output directory, see `print`
+        Column::Status | Column::Error => String::new(),
+        Column::Codec | Column::Resolution | Column::Audio => {
+            Media::read(&video.dir, Quality::Highest).cell(column)
+        }
+    }
+}
+
+/// Columns shown when none are selected
+pub const DEFAULT_COLUMNS: &[Column] = &[
+    Column::Id,
+    Column::Up,
+    Column::Group,
+    Column::Title,
+    Column::Page,
+    Column::Size,
+    Column::Disk,
+    Column::Pubdate,
+];
+
+impl Column {
+    fn header(&self) -> &'static str {
+        match self {
+            Column::Id => "ID",
+            Column::Dir => "DIR",
+            Column::Up => "UP",
+            Column::Group => "GROUP",
+            Column::Title => "TITLE",
+            Column::Page => "PAGE",
+            Column::Size => "SIZE",
+            Column::Disk => "DISK",
+            Column::Output => "OUTPUT",
+            Column::Pubdate => "PUBDATE",
+            Column::Updated => "UPDATED",
+            Column::Status => "STATUS",
+            Column::Error => "ERROR",
+            Column::Codec => "CODEC",
+            Column::Resolution => "RESOLUTION",
+            Column::Audio => "AUDIO",
+        }
+    }
+
+    fn right_aligned(&self) -> bool {
+        matches!(
+            self,
+            Column::Id | Column::Page | Column::Size | Column::Disk | Column::Output
+        )
+    }
+}
+
+/// Terminal width of a string, CJK and full-width characters take two cells
+pub fn display_width(s: &str) -> usize {
+    s.chars()
+        .map(|c| match c as u32 {
+            0x1100..=0x115F
+            | 0x2E80..=0x303E
+            | 0x3041..=0x33FF
+            | 0x3400..=0x4DBF
+            | 0x4E00..=0x9FFF
+            | 0xA000..=0xA4CF
+            | 0xAC00..=0xD7A3
+            | 0xF900..=0xFAFF
+            | 0xFE30..=0xFE4F
+            | 0xFF00..=0xFF60
+            | 0xFFE0..=0xFFE6
+            | 0x1F300..=0x1FAFF
+            | 0x20000..=0x3FFFD => 2,
+            _ => 1,
+        })
+        .sum()
+}
+
+pub fn pad(s: &str, width: usize, right: bool) -> String {
+    let fill = " ".repeat(width.saturating_sub(display_width(s)));
+    if right {
+        format!("{}{}", fill, s)
+    } else {
+        format!("{}{}", s, fill)
+    }
+}
+
+/// Print rows as a table with aligned columns and a header line
+pub fn print_table(headers: &[&str], right_aligned: &[bool], rows: &[Vec<String>]) {
+    let mut widths: Vec<usize> = headers.iter().map(|h| display_width(h)).collect();
+    for row in rows {
+        for (i, cell) in row.iter().enumerate() {
+            widths[i] = widths[i].max(display_width(cell));
+        }
+    }
+    let format_row = |cells: Vec<&str>| {
+        cells
+            .iter()
+            .enumerate()
+            .map(|(i, c)| pad(c, widths[i], right_aligned[i]))
+            .collect::<Vec<_>>()
+            .join("  ")
+            .trim_end()
+            .to_string()
+    };
+    println!("{}", format_row(headers.to_vec()));
+    for row in rows {
+        println!("{}", format_row(row.iter().map(|c| c.as_str()).collect()));
+    }
+}
+
+/// Print the videos as a table of the selected columns, with sizes in
+/// human readable units unless `bytes` is set. Output sizes are estimated
+/// and streams picked at `quality`, conversion states come from `db`.
+pub fn print(
+    videos: &[CachedVideo],
+    columns: &[Column],
+    bytes: bool,
+    quality: Quality,
+    db: &state::StateDb,
+) {
+    let columns = if columns.is_empty() {
+        DEFAULT_COLUMNS
+    } else {
+        columns
+    };
+    let headers: Vec<&str> = columns.iter().map(|c| c.header()).collect();
+    let right_aligned: Vec<bool> = columns.iter().map(|c| c.right_aligned()).collect();
+    let rows: Vec<Vec<String>> = videos
+        .iter()
+        .map(|video| {
+            // Read once for all the stream columns of the row
+            let media = OnceCell::new();
+            columns
+                .iter()
+                .map(|c| match c {
+                    Column::Size if !bytes => disk::human_size(video.info.total_size),
+                    Column::Disk if !bytes => disk::human_size(video.disk_size),
+                    Column::Output => {
+                        let size = estimate::item(&video.dir, quality);
+                        if bytes {
+                            size.to_string()
+                        } else {
+                            disk::human_size(size)
+                        }
+                    }
+                    Column::Status => Status::of(db, &cell(video, Column::Dir)).name().to_string(),
+                    Column::Error => db
+                        .get(&cell(video, Column::Dir))
+                        .and_then(|s| s.error.clone())
+                        .unwrap_or_default(),
+                    Column::Codec | Column::Resolution | Column::Audio => media
+                        .get_or_init(|| Media::read(&video.dir, quality))
+                        .cell(*c),
+                    _ => cell(video, *c),
+                })
+                .collect()
+        })
+        .collect();
+    print_table(&headers, &right_aligned, &rows);
+}
+
+/// Print item count, total sizes, what converting the items not converted
+/// yet would take and reclaim, and the free space of both volumes
+pub fn print_totals(
+    videos: &[CachedVideo],
+    source_path: &Path,
+    target_path: &Path,
+    bytes: bool,
+    quality: Quality,
+) {
+    let size = |n: u64| {
+        if bytes {
+            n.to_string()
+        } else {
+            disk::human_size(n)
+        }
+    };
+    let free = |path: &Path| {
+        disk::available_space(path)
+            .map(size)
+            .unwrap_or_else(|_| "unknown".to_string())
+    };
+    let total: u64 = videos.iter().map(|v| v.info.total_size).sum();
+    let on_disk: u64 = videos.iter().map(|v| v.disk_size).sum();
+    println!();
+    println!(
+        "{} items, {} total, {} on disk",
+        videos.len(),
+        size(total),
+        size(on_disk)
+    );
+    let db = state::StateDb::load(target_path).unwrap_or_default();
+    let pending: Vec<&CachedVideo> = videos
+        .iter()
+        .filter(|v| !db.is_converted(&cell(v, Column::Dir)))
+        .collect();
+    let output: u64 = pending
+        .iter()
+        .map(|v| estimate::item(&v.dir, quality))
+        .sum();
+    let cache: u64 = pending.iter().map(|v| v.disk_size).sum();
+    println!(
+        "{} not converted: about {} of output, --autoremove would reclaim {} ({} net)",
+        pending.len(),
+        size(output),
+        size(cache),
+        size(cache.saturating_sub(output))
+    );
+    println!(
+        "Free space: {} on source, {} on target",
+        free(source_path),
+        free(target_path)
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wide_characters_take_two_columns() {
+        assert_eq!(display_width(""), 0);
+        assert_eq!(display_width("Café"), 4);
+        assert_eq!(display_width("教程"), 4);
+        assert_eq!(display_width("カナ한국"), 8);
+        assert_eq!(display_width("ＡＢ"), 4);
+        assert_eq!(display_width("🎉 x"), 4);
+        assert_eq!(pad("教程", 6, false), "教程  ");
+        assert_eq!(pad("教程", 6, true), "  教程");
+    }
+}
@@ -0,0 +1,193 @@
+/// Keeping simultaneous runs, e.g. a cron job and a manual one, apart
+///
+/// Commands that convert, remove or restore items take a lock file in the
+/// cache directory and in the output directory, so a second run can not
+/// convert the same item again or remove a source the first one still
+/// reads. The second run exits with the holder of the lock, or waits for it
+/// with `--wait-lock`. The lock is held by the operating system and goes
+/// away with the process, also after a crash. Where file systems do not
+/// support locks, e.g. some network shares, the holder's pid written into
+/// the file is checked instead, and `--force-unlock` removes a lock whose
+/// holder can not be told to be running, e.g. without `/proc`. A lock held
+/// by a running process is never removed.
+use std::fs::{self, File, OpenOptions};
+use std::io::{ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+
+use log::*;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{self, Context};
+
+const LOCK_FILE: &str = ".bilibili.lock";
+
+/// Who holds a lock, written into the lock file
+#[derive(Serialize, Deserialize, Debug)]
+struct Holder {
+    pid: u32,
+    command: String,
+    started: String,
+}
+
+impl Holder {
+    fn read(file: &mut File) -> Option<Holder> {
+        let mut content = String::new();
+        file.read_to_string(&mut content).ok()?;
+        serde_json::from_str(&content).ok()
+    }
+
+    fn describe(holder: Option<&Holder>) -> String {
+        match holder {
+            Some(h) => format!("{} with pid {} since {}", h.command, h.pid, h.started),
+            None => "unknown".to_string(),
+        }
+    }
+}
+
+/// A held lock of a directory, released when dropped
+#[derive(Debug)]
+pub struct Lock {
+    file: File,
+    path: PathBuf,
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        // The file stays, removing it would let a waiting run lock a removed file
+        let _ = self.file.set_len(0);
+        debug!("Released {}", self.path.display());
+    }
+}
+
+// Whether the process `pid` is still running, None if this can not be told
+fn running(pid: u32) -> Option<bool> {
+    let proc = Path::new("/proc");
+    proc.is_dir().then(|| proc.join(pid.to_string()).exists())
+}
+
+fn open(path: &Path) -> Result<File, error::Error> {
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)
+        .context("open", path)
+}
+
+/// Lock `dir` for `command`, waiting for another run holding it if `wait`.
+/// With `force` a lock file whose holder may be gone is removed, where the
+/// file system has no locks and the holder's pid can not be checked.
+pub fn acquire(dir: &Path, command: &str, wait: bool, force: bool) -> Result<Lock, error::Error> {
+    let path = dir.join(LOCK_FILE);
+    let mut file = open(&path)?;
+
+    match file.try_lock() {
+        Ok(()) => {}
+        Err(fs::TryLockError::WouldBlock) => {
+            let holder = Holder::describe(Holder::read(&mut file).as_ref());
+            if !wait {
+                return Err(error::Error::Locked(dir.to_path_buf(), holder));
+            }
+            warn!("{} is in use by {}, waiting for it", dir.display(), holder);
+            file.lock().context("lock", &path)?;
+        }
+        Err(fs::TryLockError::Error(e)) if e.kind() == ErrorKind::Unsupported => {
+            warn!(
+                "File locks are not supported in {}, only checking the pid of other runs",
+                dir.display()
+            );
+            let holder = Holder::read(&mut file).filter(|h| h.pid != std::process::id());
+            if let Some(holder) = holder {
+                match running(holder.pid) {
+                    // Left behind by a run that is gone
+                    Some(false) => {}
+                    None if force => {
+                        warn!(
+                            "Removing the lock {} of {}",
+                            path.display(),
+                            Holder::describe(Some(&holder))
+                        );
+                        drop(file);
+                        fs::remove_file(&path).context("remove", &path)?;
+                        file = open(&path)?;
+                    }
+                    _ => {
+                        return Err(error::Error::Locked(
+                            dir.to_path_buf(),
+                            Holder::describe(Some(&holder)),
+                        ))
+                    }
+                }
+            }
+        }
+        Err(fs::TryLockError::Error(e)) => return Err(e).context("lock", &path),
+    }
+
+    let holder = Holder {
+        pid: std::process::id(),
+        command: command.to_string(),
+        started: chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+    };
+    file.set_len(0).context("write", &path)?;
+    (&file)
+        .write_all(serde_json::to_string(&holder)?.as_bytes())
+        .context("write", &path)?;
+    debug!("Locked {}", path.display());
+    Ok(Lock { file, path })
+}
+
+/// Lock each of `dirs` once, in order, see `acquire`. Directories that do
+/// not exist yet are skipped, a run creating them has nothing to race on.
+pub fn acquire_all(
+    dirs: &[&Path],
+    command: &str,
+    wait: bool,
+    force: bool,
+) -> Result<Vec<Lock>, error::Error> {
+    let mut locks: Vec<Lock> = Vec::new();
+    for dir in dirs {
+        let path = dir.join(LOCK_FILE);
+        if dir.is_dir() && !locks.iter().any(|lock| lock.path == path) {
+            locks.push(acquire(dir, command, wait, force)?);
+        }
+    }
+    Ok(locks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::TempDir;
+
+    #[test]
+    fn keeps_a_second_run_out() {
+        let dir = TempDir::new();
+        let lock = acquire(dir.path(), "convert", false, false).unwrap();
+        match acquire(dir.path(), "clean", false, false) {
+            Err(error::Error::Locked(path, holder)) => {
+                assert_eq!(path, dir.path());
+                assert!(holder.starts_with("convert with pid"), "{}", holder);
+            }
+            other => panic!("expected a locked error, got {:?}", other),
+        }
+        drop(lock);
+        let lock = acquire(dir.path(), "clean", false, false).unwrap();
+
+        // A running holder keeps its lock even when forced
+        assert!(matches!(
+            acquire(dir.path(), "convert", false, true),
+            Err(error::Error::Locked(..))
+        ));
+        drop(lock);
+        let forced = acquire(dir.path(), "convert", false, true).unwrap();
+        assert!(acquire(dir.path(), "convert", false, false).is_err());
+        drop(forced);
+        assert_eq!(
+            acquire_all(&[dir.path(), dir.path()], "sync", false, false)
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+}
@@ -1,71 +1,152 @@
+mod api;
+mod archive;
+mod archive_up;
+mod auth;
+mod backup;
+mod bench;
+mod chapters;
+mod compat;
+mod completions;
+mod concat;
+mod container;
+mod copy_range;
+mod cover;
+mod danmaku;
+mod dirs;
+mod disk;
+mod doctor;
+mod download;
+mod duplicates;
+mod episode;
 mod error;
+mod estimate;
+mod eta;
+mod evict;
+mod favorites;
+mod fetch;
+mod ffmpeg;
+#[cfg(test)]
+mod fixture;
+mod hash;
+mod hooks;
+mod i18n;
+mod ignore;
+mod index;
+mod inflate;
+mod info;
+mod journal;
+mod layout;
+mod legacy;
+mod list;
+mod lock;
+mod metrics;
+mod mmap;
+mod mp4;
+mod nfo;
+mod notify;
+mod open;
+mod output;
+mod player;
+mod playlists;
+mod probe;
+mod profile;
+mod progress;
+mod quality;
+mod quarantine;
+mod rclone;
+mod runlog;
+mod runner;
+mod sanitize;
+mod search;
+mod select;
+mod serve;
+mod service;
+mod share;
+mod signal;
+mod split;
+mod state;
+mod stats;
+mod sync;
+mod throttle;
+mod thumbnails;
+mod trash;
+mod tui;
+mod upload;
+mod uploaders;
+mod verify;
+mod video_info;
 
 /// Bilibili Video converter
 /// by merging cached files to the target video.
+use std::collections::HashMap;
 use std::env;
-use std::fmt::Display;
 use std::fs;
-use std::io::{Read, Seek};
+use std::io::{Read, Seek, SeekFrom};
 use std::path::Path;
 use std::path::PathBuf;
-use std::process::Command;
+use std::process::ExitCode;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use chrono::DateTime;
-use clap::{Parser, Subcommand};
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
 use log::*;
-use serde::Deserialize;
+use serde::Serialize;
+
+use bilibili::queue;
+use error::Context;
+use i18n::tr;
+use video_info::{Cover, VideoInfo};
 
 // The special file offset bilibili client cached
 const SPECIAL_OFFSET: u64 = 9;
 
-const DEFAULT_SOURCE_DIR: &str = "Movies/bilibili";
-const DEFAULT_TARGET_DIR: &str = "Movies/output";
 const VIDEO_METADATA_FILE: &str = ".videoInfo";
 
-#[derive(Deserialize)]
-struct VideoInfo {
-    uname: String,
-    title: String,
-    #[serde(rename = "groupTitle")]
-    group_title: String, // group title, multiple items can be in the same group
-    pubdate: i64,
-    #[serde(rename = "updateTime")]
-    update_time: i64,
-    #[serde(rename = "totalSize")]
-    total_size: u64,
-    #[serde(rename = "itemId")]
-    item_id: u64,
-    #[serde(rename = "coverPath")]
-    cover_path: String, // should be Path later
-    #[serde(rename = "groupCoverPath")]
-    group_cover_path: String, // should be Path later
-    p: u32, // appears like an index of items in same group
-}
-
-impl Display for VideoInfo {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        let dt = DateTime::from_timestamp(self.pubdate, 0).expect("invalid timestamp");
-        let message = format!(
-            "[{}] {} - {}, Page<{}>, UP<{}>, Size<{}>, Updated<{}>",
-            self.item_id, self.group_title, self.title, self.p, self.uname, self.total_size, dt
-        );
-        f.write_str(message.as_str())
-    }
+// Process exit codes
+const EXIT_ITEMS_FAILED: u8 = 1; // some items failed to convert
+const EXIT_SETUP_FAILED: u8 = 2; // environment or setup errors, nothing was processed
+const EXIT_INTERRUPTED: u8 = 3;
+
+// Delay before the first retry of a failed item, doubled for each further one
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+// Extra space kept free on the target besides the stripped temp files and the final video
+const SPACE_HEADROOM: u64 = 64 * 1024 * 1024;
+
+// Stripping checks for interrupts after each chunk of this size
+const STRIP_CHUNK: u64 = 64 * 1024 * 1024;
+
+// Bytes at the end of a partial stripped file compared with the source before resuming it
+const RESUME_CHECK: u64 = 64 * 1024;
+
+/// A video in the cache directory
+struct CachedVideo {
+    dir: PathBuf,
+    info: VideoInfo,
+    disk_size: u64, // actual size of the cache directory
 }
 
 fn get_metadata(path: &Path) -> Result<VideoInfo, error::Error> {
     let metafile = path.join(VIDEO_METADATA_FILE);
-    let metadata_string = fs::read(&metafile)?;
+    if !metafile.is_file() {
+        if legacy::is_item(path) {
+            return legacy::video_info(path);
+        }
+        return Err(error::Error::MetadataMissing(path.to_path_buf()));
+    }
+    let metadata_string = fs::read(&metafile).context("read", &metafile)?;
     let metadata = String::from_utf8(metadata_string)?;
 
-    Ok(serde_json::from_str(&metadata)?)
+    Ok(VideoInfo::parse(&metadata)?)
 }
 
-fn get_files_by_extension(path: &Path, extension: &str) -> Vec<PathBuf> {
+fn get_files_by_extension(path: &Path, extension: &str) -> Result<Vec<PathBuf>, error::Error> {
     let mut filelist = Vec::new();
-    let files = path.read_dir().unwrap();
+    let files = path
+        .read_dir()
+        .map_err(|_| error::Error::ReadDirectoryFailed)?;
     for f in files {
-        let pathbuf = f.unwrap().path();
+        let pathbuf = f.map_err(|_| error::Error::ReadDirectoryFailed)?.path();
         let entry = pathbuf.as_path();
         if let Some(ext) = entry.extension() {
             if ext == extension {
@@ -74,134 +155,940 @@ fn get_files_by_extension(path: &Path, extension: &str) -> Vec<PathBuf> {
         }
     }
     debug!("get_files_by_extension {}: {:?}", extension, filelist);
-    filelist
+    // Keep a stable order of inputs for ffmpeg
+    filelist.sort();
+    Ok(filelist)
+}
+
+// Bytes of `output` already holding the stripped start of `source`, 0 if
+// there is no partial output or its tail does not match the source
+fn stripped_bytes(source: &mut fs::File, output: &Path) -> std::io::Result<u64> {
+    let Ok(done) = output.metadata().map(|m| m.len()) else {
+        return Ok(0);
+    };
+    let total = source.metadata()?.len().saturating_sub(SPECIAL_OFFSET);
+    if done == 0 || done > total {
+        return Ok(0);
+    }
+    let window = done.min(RESUME_CHECK);
+    let mut expected = vec![0; window as usize];
+    source.seek(SeekFrom::Start(SPECIAL_OFFSET + done - window))?;
+    source.read_exact(&mut expected)?;
+    let mut actual = vec![0; window as usize];
+    let mut partial = fs::File::open(output)?;
+    partial.seek(SeekFrom::Start(done - window))?;
+    partial.read_exact(&mut actual)?;
+    Ok(if expected == actual { done } else { 0 })
 }
 
-fn copy_to(source: &Path, target_dir: &Path) -> Result<(), error::Error> {
-    let src_filename = source.file_name().ok_or(error::Error::InvalidArgument)?;
-    let target_filename = target_dir.join(src_filename);
-    fs::copy(source, &target_filename)?;
+/// Copy a cached m4s without the client's prefix bytes to `output`, inside
+/// the kernel where possible. A partial copy left by an interrupted run is
+/// continued where it stopped.
+fn strip_media(source: &Path, output: &Path, limit: Option<u64>) -> Result<(), error::Error> {
+    let unreadable = |e| error::Error::MediaFileUnreadable(source.to_path_buf(), e);
+    let mut f = fs::File::open(source).map_err(unreadable)?;
+    let mut done = stripped_bytes(&mut f, output).map_err(unreadable)?;
+    let mut out = if done > 0 {
+        info!("Resuming {} after {} bytes", output.display(), done);
+        fs::OpenOptions::new()
+            .write(true)
+            .open(output)
+            .context("open", output)?
+    } else {
+        fs::File::create(output).context("create", output)?
+    };
+
+    // Rate limits need the data to pass through here
+    if limit.is_none() {
+        loop {
+            match copy_range::copy(&f, SPECIAL_OFFSET + done, &out, done, STRIP_CHUNK) {
+                Ok(0) => return Ok(()),
+                Ok(copied) => done += copied,
+                Err(e) if copy_range::unsupported(&e) => {
+                    debug!(
+                        "Copying {} in the kernel failed, streaming it: {}",
+                        source.display(),
+                        e
+                    );
+                    break;
+                }
+                Err(e) => return Err(e).context("copy", source),
+            }
+            if signal::interrupted() {
+                return Err(error::Error::Interrupted);
+            }
+        }
+    }
+
+    f.seek(SeekFrom::Start(SPECIAL_OFFSET + done))
+        .map_err(unreadable)?;
+    out.seek(SeekFrom::Start(done)).context("seek", output)?;
+    while throttle::copy(&mut (&mut f).take(STRIP_CHUNK), &mut out, limit)? > 0 {
+        if signal::interrupted() {
+            return Err(error::Error::Interrupted);
+        }
+    }
     Ok(())
 }
 
-fn ffmpeg_copy(input_media: &Vec<PathBuf>, output_file: &Path) -> Result<(), error::Error> {
-    // ffmpeg -i source [-i source [...]] -c copy targetfile
-    let mut cmd = Command::new("ffmpeg");
-    for input in input_media {
-        cmd.arg("-i").arg(input);
+/// Copy a cover next to the output as `<name>.<ext>`, or under the name of
+/// the cached file if None, converting it if asked to. The client purges
+/// cover files from its cache, in which case it is downloaded again from the
+/// URL in the metadata. A missing cover is not worth failing the item for,
+/// so it only warns.
+fn copy_cover(
+    dir: &Path,
+    cover: &Cover,
+    url: Option<&str>,
+    name: Option<&str>,
+    output: &layout::Output,
+    options: &ConvertOptions,
+) {
+    let target =
+        |original: &str| output.side_file(&cover::file_name(original, name, options.cover));
+    if let Cover::Path(source) = cover {
+        // Paths are absolute, so a moved cache or an extracted backup has
+        // the cover in the item directory instead
+        let moved = source.file_name().map(|name| dir.join(name));
+        if let Some(source) = Some(source.clone())
+            .into_iter()
+            .chain(moved)
+            .find(|p| p.is_file())
+        {
+            let target = target(&source.file_name().unwrap_or_default().to_string_lossy());
+            let copied = match options.cover {
+                Some(conversion) => cover::convert(&options.ffmpeg, &source, &target, conversion),
+                None => throttle::copy_file(&source, &target, options.io_limit)
+                    .map(|_| ())
+                    .context("copy", &source),
+            };
+            if let Err(e) = copied {
+                warn!("Failed to copy cover {}: {}", source.display(), e);
+            }
+            return;
+        }
+    }
+    // Both covers usually point to the same file, which may be downloaded already
+    let original = cover.file_name();
+    if original.as_ref().is_some_and(|n| target(n).is_file()) {
+        return;
+    }
+    let url = match cover {
+        Cover::Url(url) => Some(url.as_str()),
+        _ => url,
+    };
+    let Some(url) = url.and_then(fetch::https_url) else {
+        warn!("Cover {} is missing and has no URL, skipped", cover);
+        return;
+    };
+    // The name the cached file had, or the one of the URL
+    let original = original
+        .or_else(|| Cover::Url(url.clone()).file_name())
+        .unwrap_or_else(|| "cover.jpg".to_string());
+    info!("Cover {} is missing, downloading {}", cover, url);
+    // A cover to convert is downloaded into the work directory first
+    let item = dir.file_name().unwrap_or_default().to_string_lossy();
+    let download = match options.cover {
+        Some(_) => options.work_dir.join(format!("{}-{}", item, original)),
+        None => target(&original),
+    };
+    if let Err(e) = fetch::download(&url, &download, &[]) {
+        warn!("Failed to download cover: {}", e);
+        return;
+    }
+    if let Some(conversion) = options.cover {
+        let target = target(&original);
+        if let Err(e) = cover::convert(&options.ffmpeg, &download, &target, conversion) {
+            warn!("Failed to convert cover {}: {}", download.display(), e);
+        }
+        let _ = fs::remove_file(&download);
+    }
+}
+
+// Container tags so players show the real title and uploader
+fn metadata_tags(video_info: &VideoInfo) -> Vec<(&'static str, String)> {
+    let mut tags = vec![
+        ("title", video_info.title.clone()),
+        ("artist", video_info.uname.clone()),
+    ];
+    if let Some(dt) = DateTime::from_timestamp(video_info.pubdate, 0) {
+        tags.push(("date", dt.format("%Y-%m-%d").to_string()));
+    }
+    if video_info.group_title != video_info.title {
+        tags.push(("album", video_info.group_title.clone()));
+        tags.push(("track", video_info.p.to_string()));
+    }
+    tags
+}
+
+// Remove temp media files and, if the conversion did not finish, the partial output
+fn cleanup(input_media: &Vec<PathBuf>, partial_output: Option<&Path>) {
+    for media in input_media {
+        if fs::remove_file(media.as_path()).is_err() {
+            error!(
+                "Failed to remove temporary file {}",
+                media.as_path().display()
+            );
+        }
+    }
+    if let Some(output) = partial_output {
+        if output.exists() && fs::remove_file(output).is_err() {
+            error!("Failed to remove partial output {}", output.display());
+        }
+    }
+}
+
+/// Make sure there is room for `temp` bytes of intermediate files in the
+/// work directory and `output` bytes of outputs on the target, plus some
+/// headroom.
+fn check_free_space(
+    temp: u64,
+    output: u64,
+    target_path: &Path,
+    options: &ConvertOptions,
+) -> Result<(), error::Error> {
+    if disk::same_filesystem(&options.work_dir, target_path) {
+        return check_space(temp + output, target_path);
+    }
+    check_space(temp, &options.work_dir)?;
+    check_space(output, target_path)
+}
+
+fn check_space(required: u64, path: &Path) -> Result<(), error::Error> {
+    let required = required + SPACE_HEADROOM;
+    match disk::available_space(path) {
+        Ok(available) => {
+            debug!("Free space on {}: {} bytes", path.display(), available);
+            if available < required {
+                return Err(error::Error::InsufficientSpace {
+                    required,
+                    available,
+                });
+            }
+        }
+        Err(e) => warn!("Skip free space check on {}: {}", path.display(), e),
     }
-    cmd.args(["-c", "copy"]).arg(output_file);
-    cmd.output()?;
     Ok(())
 }
 
-fn process(path: &Path, target_path: &Path) -> Result<(), error::Error> {
-    let video_info = get_metadata(path).unwrap();
-    info!("Video: {}", video_info);
+/// Create the directory `name` for intermediate files in the work directory
+fn create_work_dir(name: &str, options: &ConvertOptions) -> Result<PathBuf, error::Error> {
+    let work_path = options.work_dir.join(name);
+    fs::create_dir_all(&work_path).context("create directory", &work_path)?;
+    Ok(work_path)
+}
+
+/// Remove a directory created by `create_work_dir`
+fn remove_work_dir(work_path: &Path) {
+    if let Err(e) = fs::remove_dir_all(work_path) {
+        warn!("Failed to remove {}: {}", work_path.display(), e);
+    }
+}
+
+/// Media files muxed into an output
+struct Inputs {
+    files: Vec<PathBuf>,
+    /// Prefix bytes ffmpeg skips at the start of each file
+    skip_bytes: u64,
+    /// Streams to take from the files, all if empty
+    maps: Vec<String>,
+    /// The files are concat demuxer lists rather than media files
+    concat: bool,
+    /// The files are temp copies to remove once muxed, not the cache itself
+    temp: bool,
+    /// Formats of the streams, if they could be read, see `compat`
+    formats: Vec<mp4::SampleEntry>,
+    /// Fragments missing from the cached media, see `mp4::continuity`
+    gaps: Vec<String>,
+}
 
-    let media = get_files_by_extension(path, "m4s");
+impl Inputs {
+    /// Temp media files, already without prefix bytes
+    fn temp(files: Vec<PathBuf>) -> Inputs {
+        Inputs {
+            files,
+            skip_bytes: 0,
+            maps: Vec::new(),
+            concat: false,
+            temp: true,
+            formats: Vec::new(),
+            gaps: Vec::new(),
+        }
+    }
+
+    fn remove_temp(&self) {
+        if self.temp {
+            cleanup(&self.files, None);
+        }
+    }
+}
+
+/// Select the cached media of an item and strip them into `work_path`,
+/// unless ffmpeg can read past the prefix bytes of the cache files itself
+fn strip_inputs(
+    path: &Path,
+    work_path: &Path,
+    options: &ConvertOptions,
+) -> Result<Inputs, error::Error> {
+    if legacy::is_item(path) {
+        let list = work_path.join("segments.txt");
+        concat::write_list(&legacy::segments(path)?, &list)?;
+        return Ok(Inputs {
+            concat: true,
+            ..Inputs::temp(vec![list])
+        });
+    }
+    let media = get_files_by_extension(path, "m4s")?;
     debug!("Media files: {:?}", media);
+    let quality::Selection { files: media, maps } =
+        quality::select(&options.ffmpeg, media, options.quality)?;
 
+    // DRM protected streams would only produce unplayable output
+    for m in &media {
+        match mp4::is_encrypted(m) {
+            Ok(true) => return Err(error::Error::EncryptedContent(m.clone())),
+            Ok(false) => {}
+            Err(e) => warn!("Unable to check encryption of {}: {}", m.display(), e),
+        }
+    }
+
+    // ffmpeg muxes a segment with missing fragments without complaint, the
+    // output just stutters or freezes there
+    let gaps: Vec<String> = media
+        .iter()
+        .filter_map(|m| match mp4::continuity(m) {
+            Ok(continuity) if !continuity.is_complete() => {
+                let name = m.file_name().unwrap_or_default().to_string_lossy();
+                warn!("{} is incomplete: {}", m.display(), continuity.describe());
+                Some(format!("{}: {}", name, continuity.describe()))
+            }
+            Ok(_) => None,
+            Err(e) => {
+                warn!("Unable to check fragments of {}: {}", m.display(), e);
+                None
+            }
+        })
+        .collect();
+
+    let formats = media
+        .iter()
+        .filter_map(|m| mp4::sample_entry(m).ok().flatten())
+        .collect();
+    if options.ffmpeg.skips_initial_bytes() {
+        return Ok(Inputs {
+            files: media,
+            skip_bytes: SPECIAL_OFFSET,
+            maps,
+            concat: false,
+            temp: false,
+            formats,
+            gaps,
+        });
+    }
+
+    let start = Instant::now();
+    let bytes = files_size(&media);
     let mut input_media: Vec<PathBuf> = Vec::new();
     for m in media {
+        // Stripped files are kept for the next run to continue
+        if signal::interrupted() {
+            return Err(error::Error::Interrupted);
+        }
         let p = m.as_path();
-        let output_name = p.file_name().unwrap().to_str().unwrap();
-
-        let mut f = fs::File::open(p).unwrap();
-        let mut data: Vec<u8> = Vec::new();
-        f.seek(std::io::SeekFrom::Start(SPECIAL_OFFSET)).unwrap();
-        f.read_to_end(&mut data).unwrap();
+        let output_name = p
+            .file_name()
+            .and_then(|n| n.to_str())
+            .ok_or_else(|| error::Error::InvalidFileName(p.to_path_buf()))?;
 
-        let output = target_path.join(output_name);
-        fs::write(&output, data);
+        let output = work_path.join(output_name);
+        match strip_media(p, &output, options.io_limit) {
+            Err(error::Error::Interrupted) => return Err(error::Error::Interrupted),
+            Err(e) => {
+                input_media.push(output);
+                cleanup(&input_media, None);
+                return Err(e);
+            }
+            Ok(()) => {}
+        }
         input_media.push(output);
     }
+    options.timings.record(eta::STRIP, bytes, start.elapsed());
+    // Stripped copies keep the order, so the maps still apply
+    Ok(Inputs {
+        maps,
+        formats,
+        gaps,
+        ..Inputs::temp(input_media)
+    })
+}
 
-    // Create target output directory
-    let target_dir = if video_info.group_title != video_info.title {
-        target_path
-            .join(format!("{} - {}", video_info.uname, video_info.group_title))
-            .join(format!("{} {}", video_info.p, video_info.title))
+/// Mux `inputs` into `output_file`, true if copying the streams failed and
+/// they were re-encoded with the fallback profile. Temp inputs are removed
+/// whether it succeeded or not, the partial output only on failure.
+fn mux(
+    inputs: &Inputs,
+    video_info: &VideoInfo,
+    work_path: &Path,
+    view_points: &[chapters::Chapter],
+    options: &ConvertOptions,
+    output_file: &Path,
+) -> Result<bool, error::Error> {
+    let chapters_file = work_path.join(format!("{}.ffmeta", video_info.item_id));
+    let chapters = if view_points.is_empty() {
+        None
     } else {
-        target_path.join(format!(
-            "{} - {}",
-            video_info.uname, video_info.title
-        ))
+        debug!("Chapters: {:?}", view_points);
+        match chapters::write_ffmetadata(view_points, &chapters_file) {
+            Ok(_) => Some(chapters_file.as_path()),
+            Err(e) => {
+                warn!("Skip chapters: {}", e);
+                None
+            }
+        }
+    };
+    let tags = metadata_tags(video_info);
+    // A profile in the uploader settings is followed as it is
+    let overridden = options
+        .layout
+        .uploaders
+        .get(&video_info.uname)
+        .is_some_and(|overrides| overrides.profile.is_some());
+    let plan = options
+        .compat
+        .filter(|_| !overridden)
+        .and_then(|target| target.plan(&inputs.formats));
+    if let Some(plan) = plan {
+        debug!("Compatibility plan: {:?}", plan);
+    }
+    let job = ffmpeg::MuxJob {
+        inputs: &inputs.files,
+        concat: inputs.concat,
+        read_rate: options
+            .io_limit
+            .and_then(|limit| read_rate(&options.ffmpeg, &inputs.files, inputs.skip_bytes, limit)),
+        skip_bytes: inputs.skip_bytes,
+        maps: &inputs.maps,
+        profile: plan.map_or(options.profile_for(video_info), |plan| plan.profile),
+        transcode_audio: plan.is_some_and(|plan| plan.audio),
+        chapters,
+        tags: &tags,
+        container: options.layout.container,
+        faststart: options.faststart,
     };
-        
-    fs::create_dir_all(&target_dir)?;
+    let start = Instant::now();
+    let mut muxed = options.muxer().mux(&job, output_file).map(|_| false);
+    // The inputs of concat are lists, not the media read
+    if muxed.is_ok() && !inputs.concat {
+        options.timings.record(
+            &eta::mux(job.profile),
+            files_size(job.inputs),
+            start.elapsed(),
+        );
+    }
+    // Broken timestamps or codecs mp4 cannot hold fail the copy, not an encode
+    let fallback = options
+        .fallback_profile
+        .filter(|_| job.profile == profile::Profile::Copy);
+    if let (Err(error::Error::FfmpegFailed(status)), Some(fallback)) = (&muxed, fallback) {
+        warn!(
+            "Copying the streams failed with status {}, re-encoding with the {:?} profile",
+            status, fallback
+        );
+        cleanup(&Vec::new(), Some(output_file));
+        let job = ffmpeg::MuxJob {
+            profile: fallback,
+            ..job
+        };
+        muxed = options.muxer().mux(&job, output_file).map(|_| true);
+    }
+    if chapters.is_some() {
+        let _ = fs::remove_file(&chapters_file);
+    }
+    inputs.remove_temp();
+    if muxed.is_err() {
+        cleanup(&Vec::new(), Some(output_file));
+    }
+    muxed
+}
 
-    let final_file = target_dir
-        .as_path()
-        .join(format!("{}.mp4", video_info.item_id));
-    debug!("Final file: {:?}", final_file);
+/// Strip the cached media of an item into `work_path` if needed and mux
+/// them into `output_file`, see `mux`
+fn remux(
+    path: &Path,
+    video_info: &VideoInfo,
+    work_path: &Path,
+    view_points: &[chapters::Chapter],
+    options: &ConvertOptions,
+    output_file: &Path,
+) -> Result<(), error::Error> {
+    let inputs = strip_inputs(path, work_path, options)?;
+    mux(
+        &inputs,
+        video_info,
+        work_path,
+        view_points,
+        options,
+        output_file,
+    )
+    .map(|_| ())
+}
 
-    ffmpeg_copy(&input_media, &final_file)?;
+/// Total size of the existing `files`
+fn files_size(files: &[PathBuf]) -> u64 {
+    files
+        .iter()
+        .filter_map(|file| file.metadata().ok())
+        .map(|m| m.len())
+        .sum()
+}
 
-    // Remove temp media files use for ffmpeg
-    for media in input_media {
-        if fs::remove_file(media.as_path()).is_err() {
-            error!(
-                "Failed to remove temporary file {}",
-                media.as_path().display()
+/// ffmpeg can only limit reading relative to the native frame rate, so the
+/// byte limit is turned into a multiple of the media's own byte rate.
+fn read_rate(
+    ffmpeg: &ffmpeg::Ffmpeg,
+    inputs: &[PathBuf],
+    skip_bytes: u64,
+    limit: u64,
+) -> Option<f64> {
+    let first = inputs.first()?;
+    let duration = if skip_bytes > 0 {
+        probe::media_duration(ffmpeg, first)
+    } else {
+        probe::duration(ffmpeg, first)
+    }
+    .ok()?;
+    let bytes = files_size(inputs);
+    if bytes == 0 || duration <= 0.0 {
+        return None;
+    }
+    Some(limit as f64 * duration / bytes as f64)
+}
+
+/// Copy cover art and metadata next to a finished output and set its
+/// modification time if requested.
+fn finish_output(
+    path: &Path,
+    video_info: &VideoInfo,
+    output: &layout::Output,
+    options: &ConvertOptions,
+) -> Result<(), error::Error> {
+    // Copy photos to target directory, the video is fine without them
+    if options.covers {
+        debug!("Copy cover art");
+        copy_cover(
+            path,
+            &video_info.cover_path,
+            video_info.cover_url.as_deref(),
+            (!options.original_cover_names).then_some("poster"),
+            output,
+            options,
+        );
+        if video_info.group_cover_path != video_info.cover_path {
+            debug!("Copy group cover art");
+            copy_cover(
+                path,
+                &video_info.group_cover_path,
+                video_info.group_cover_url.as_deref(),
+                (!options.original_cover_names).then_some(options.group_cover.name()),
+                output,
+                options,
             );
         }
     }
 
-    // Copy photos to target directory
-    debug!("Copy cover art");
-    copy_to(Path::new(&video_info.cover_path), &target_dir)?;
-    debug!("Copy group cover art");
-    copy_to(Path::new(&video_info.group_cover_path), &target_dir)?;
-
-    // Copy metadata to target directory
+    // Copy metadata to target directory, legacy caches get theirs converted
     debug!("Copy metadata");
-    fs::copy(
-        path.join(VIDEO_METADATA_FILE),
-        target_dir.join("videoInfo.json"),
+    let metadata = output.side_file("videoInfo.json");
+    let copied = if legacy::is_item(path) {
+        serde_json::to_string(video_info)
+            .map_err(error::Error::from)
+            .and_then(|content| fs::write(&metadata, content).context("write", &metadata))
+    } else {
+        throttle::copy_file(&path.join(VIDEO_METADATA_FILE), &metadata, options.io_limit)
+            .map(|_| ())
+            .context("copy", path)
+    };
+    if let Err(e) = copied {
+        warn!("Failed to copy metadata of {}: {}", path.display(), e);
+    }
+
+    if options.nfo {
+        if let Err(e) = nfo::write(output, video_info, options.layout.episode(video_info)) {
+            warn!("Failed to write the NFO of {}: {}", path.display(), e);
+        }
+    }
+
+    thumbnails::generate(
+        &options.ffmpeg,
+        output,
+        &options.thumbnails,
+        options.thumbnail_grid,
     );
 
+    // Done last, copying files into the directory would update its mtime again
+    if let Some(mtime) = options.mtime {
+        let timestamp = match mtime {
+            MtimeSource::Pubdate => video_info.pubdate,
+            MtimeSource::Update => video_info.update_time,
+        };
+        let time = UNIX_EPOCH + Duration::from_secs(timestamp.max(0) as u64);
+        debug!("Set modification time to {}", timestamp);
+        // Like the side files, the video is fine with the time it was written
+        let set = set_mtime(&output.file, time).and_then(|_| match output.own_dir {
+            true => set_mtime(&output.dir, time),
+            false => Ok(()),
+        });
+        if let Err(e) = set {
+            warn!(
+                "Failed to set the modification time of {}: {}",
+                output.file.display(),
+                e
+            );
+        }
+    }
     Ok(())
 }
 
-/// Handle a directory
-/// path: the directory to process
-/// autoremove: if true, remove the source directory after successful processing
-fn handle_dir(path: &Path, target_path: &Path, autoremove: bool) {
-    let result = process(path, target_path);
-    if result.is_err() {
-        error!("Failed to process {}: {:?}", path.display(), result);
+/// Convert one cache item, returning the path of the final video
+fn process(
+    path: &Path,
+    target_path: &Path,
+    options: &ConvertOptions,
+) -> Result<layout::Output, error::Error> {
+    let video_info = get_metadata(path)?;
+    info!("Video: {}", video_info);
+
+    // Stripped temp files, unless ffmpeg reads the cache in place, and the
+    // final video are both about `total_size` bytes
+    let temp = if options.ffmpeg.skips_initial_bytes() {
+        0
     } else {
-        if autoremove {
-            match fs::remove_dir_all(path) {
-                Ok(_) => {
-                    info!("Removed source directory {}", path.display());
+        video_info.total_size
+    };
+    // Splitting writes the parts next to the whole output
+    let split = options
+        .max_size
+        .is_some_and(|max| video_info.total_size > max);
+    let output = video_info.total_size * if split { 2 } else { 1 };
+    check_free_space(temp, output, target_path, options)?;
+
+    let work_path = create_work_dir(&format!(".convert-{}", video_info.item_id), options)?;
+    let result = strip_inputs(path, &work_path, options)
+        .and_then(|inputs| deliver(path, &video_info, &inputs, &work_path, target_path, options));
+    // The next run continues the stripped files of an interrupted one
+    if !matches!(result, Err(error::Error::Interrupted)) {
+        remove_work_dir(&work_path);
+    }
+    result
+}
+
+/// Mux temp media `inputs` into the output of an item and add its side
+/// files. `path` is the directory holding the item's metadata and covers,
+/// `work_path` the one for further intermediate files.
+fn deliver(
+    path: &Path,
+    video_info: &VideoInfo,
+    inputs: &Inputs,
+    work_path: &Path,
+    target_path: &Path,
+    options: &ConvertOptions,
+) -> Result<layout::Output, error::Error> {
+    // Create target output directory
+    let output = options.layout.output(video_info, target_path);
+    if let Err(e) = share::create_dir_all(&output.dir) {
+        inputs.remove_temp();
+        return Err(e.into());
+    }
+
+    let final_file = output.file.clone();
+    debug!("Final file: {:?}", final_file);
+
+    // ffmpeg writes to a .part file which is renamed only once it is known
+    // to be good, so a crash never leaves a complete looking broken video.
+    // The second pass of faststart rewrites the .part file in place before
+    // ffmpeg exits, a crash during it leaves a .part file too.
+    let part_file = part_path(&final_file);
+    let mut output = output;
+    let muxed = mux(
+        inputs,
+        video_info,
+        work_path,
+        &video_info.view_points,
+        options,
+        &part_file,
+    )
+    .and_then(|reencoded| {
+        output.reencoded = reencoded;
+        output.gaps = inputs.gaps.clone();
+        check_output(&options.ffmpeg, &part_file)
+    })
+    .and_then(|_| rename_synced(&part_file, &final_file));
+    if let Err(e) = muxed {
+        cleanup(&Vec::new(), Some(&part_file));
+        // Only succeeds if nothing else was written there
+        if output.own_dir {
+            let _ = fs::remove_dir(&output.dir);
+        }
+        return Err(e);
+    }
+
+    danmaku::burn(path, &output, work_path, options);
+    if let Some(max) = options.max_size {
+        let container = options.layout.container;
+        let parts = split::split(
+            &options.ffmpeg,
+            &output.file,
+            max,
+            container,
+            options.faststart,
+        )?;
+        if parts.len() > 1 {
+            info!("Split {} into {} parts", final_file.display(), parts.len());
+        }
+    }
+    finish_output(path, video_info, &output, options)?;
+    playlists::update(target_path, video_info, options);
+    Ok(output)
+}
+
+/// Sync a finished `part_file` to disk and rename it to `final_file`, so
+/// the output survives a crash before its source is removed
+fn rename_synced(part_file: &Path, final_file: &Path) -> Result<(), error::Error> {
+    fs::File::open(part_file)
+        .and_then(|f| f.sync_all())
+        .context("sync", part_file)?;
+    share::rename(part_file, final_file).context("rename", part_file)?;
+    // The rename itself is only durable once the directory is synced
+    #[cfg(unix)]
+    if let Some(dir) = final_file.parent() {
+        fs::File::open(dir)
+            .and_then(|f| f.sync_all())
+            .context("sync", dir)?;
+    }
+    Ok(())
+}
+
+fn part_path(final_file: &Path) -> PathBuf {
+    let mut name = final_file.as_os_str().to_owned();
+    name.push(".part");
+    PathBuf::from(name)
+}
+
+/// Sanity check of a freshly written output: it must not be empty and,
+/// when ffprobe is available, contain at least one stream.
+fn check_output(ffmpeg: &ffmpeg::Ffmpeg, file: &Path) -> Result<(), error::Error> {
+    let size = file.metadata().context("read metadata of", file)?.len();
+    if size == 0 {
+        return Err(error::Error::OutputInvalid(file.to_path_buf()));
+    }
+    match probe::output_streams(ffmpeg, file) {
+        Ok(streams) if streams.is_empty() => Err(error::Error::OutputInvalid(file.to_path_buf())),
+        Ok(_) => Ok(()),
+        Err(error::Error::CommandNotFound) => {
+            debug!(
+                "ffprobe not available, only checked size of {}",
+                file.display()
+            );
+            Ok(())
+        }
+        Err(e) => Err(e),
+    }
+}
+
+// An output is good enough to delete its source if it exists and is not empty
+fn output_valid(final_file: &Path) -> bool {
+    final_file.metadata().map(|m| m.len() > 0).unwrap_or(false)
+}
+
+/// Open the file or directory `path` to change its times
+#[cfg(not(windows))]
+fn open_for_times(path: &Path) -> std::io::Result<fs::File> {
+    fs::File::open(path)
+}
+
+/// Open the file or directory `path` to change its times, directories open
+/// only with backup semantics and read-only handles cannot set times
+#[cfg(windows)]
+fn open_for_times(path: &Path) -> std::io::Result<fs::File> {
+    use std::os::windows::fs::OpenOptionsExt;
+    const FILE_WRITE_ATTRIBUTES: u32 = 0x0100;
+    const FILE_FLAG_BACKUP_SEMANTICS: u32 = 0x0200_0000;
+    fs::OpenOptions::new()
+        .access_mode(FILE_WRITE_ATTRIBUTES)
+        .custom_flags(FILE_FLAG_BACKUP_SEMANTICS)
+        .open(path)
+}
+
+fn set_mtime(path: &Path, time: SystemTime) -> Result<(), error::Error> {
+    open_for_times(path)
+        .and_then(|f| f.set_modified(time))
+        .context("set modification time of", path)?;
+    Ok(())
+}
+
+/// Run `attempt` again after transient failures, up to `options.retries`
+/// times with exponentially growing delays
+fn retry<T>(
+    what: &Path,
+    options: &ConvertOptions,
+    mut attempt: impl FnMut() -> Result<T, error::Error>,
+) -> Result<T, error::Error> {
+    let mut delay = RETRY_DELAY;
+    for retry in 1.. {
+        match attempt() {
+            Err(e) if e.is_transient() && retry <= options.retries => {
+                warn!(
+                    "Failed to process {}: {}, retry {} of {} in {}s",
+                    what.display(),
+                    e,
+                    retry,
+                    options.retries,
+                    delay.as_secs()
+                );
+                // Sleep in steps so an interrupt does not have to wait
+                let until = Instant::now() + delay;
+                while Instant::now() < until {
+                    if signal::interrupted() {
+                        return Err(error::Error::Interrupted);
+                    }
+                    std::thread::sleep(Duration::from_millis(100));
                 }
-                Err(e) => error!(
-                    "Failed to remove source directory {}: {}",
-                    path.display(),
-                    e.to_string()
-                ),
+                delay *= 2;
             }
+            result => return result,
         }
     }
+    unreachable!()
+}
+
+/// Handle a directory
+/// path: the directory to process
+fn handle_dir(
+    path: &Path,
+    target_path: &Path,
+    options: &ConvertOptions,
+) -> Result<layout::Output, error::Error> {
+    let result = retry(path, options, || process(path, target_path, options));
+    if let Err(e) = &result {
+        error!("{}", tr!("Failed to process {}: {}", path.display(), e));
+    }
+    result
+}
+
+/// Remove the source directory `path` of `item` converted into `output`,
+/// only called once the conversion is recorded in the state database
+fn autoremove(
+    item: &str,
+    path: &Path,
+    output: &layout::Output,
+    journal: &journal::Journal,
+    uploaded: bool,
+    options: &ConvertOptions,
+) {
+    // An output removed after a verified upload counts as valid
+    let uploaded = uploaded && options.remove_uploaded;
+    if !uploaded && !output_valid(&output.file) {
+        warn!(
+            "Keep source directory {}, output {} is missing or empty",
+            path.display(),
+            output.file.display()
+        );
+        return;
+    }
+    match journal.remove(item, path, &output.file, options.permanent) {
+        Ok(_) => {
+            info!("Removed source directory {}", path.display());
+        }
+        Err(e) => error!(
+            "Failed to remove source directory {}: {}",
+            path.display(),
+            e.to_string()
+        ),
+    }
+}
+
+// Legacy Android caches hold a directory per page below the video's
+fn item_dirs(path: &Path) -> Vec<PathBuf> {
+    let pages = legacy::pages(path);
+    if pages.is_empty() || path.join(VIDEO_METADATA_FILE).is_file() {
+        vec![path.to_path_buf()]
+    } else {
+        pages
+    }
+}
+
+/// Whether the cache item in `dir` is excluded from scans of the cache
+fn ignored(ignore: &ignore::Ignore, dir: &Path) -> bool {
+    let item = item_name(dir);
+    let excluded = ignore.matches(&item, get_metadata(dir).ok().as_ref());
+    if excluded {
+        info!(
+            "Skip {}, excluded by {} or --exclude",
+            item,
+            ignore::IGNORE_FILE
+        );
+    }
+    excluded
+}
+
+/// Name the state and the command line know the cache item in `dir` by: its
+/// directory, and for a page of a legacy cache `<avid>/<page>`, as the page
+/// directories of every video are numbered from 1
+fn item_name(dir: &Path) -> String {
+    let name = |dir: &Path| {
+        dir.file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string()
+    };
+    match dir.parent().filter(|_| legacy::is_item(dir)) {
+        Some(video) => format!("{}/{}", name(video), name(dir)),
+        None => name(dir),
+    }
+}
+
+/// Directory of the cache item `item`, also one named by its page alone as
+/// earlier versions did for legacy caches
+fn item_path(source_path: &Path, item: &str) -> PathBuf {
+    let path = source_path.join(item);
+    if path.is_dir() {
+        return path;
+    }
+    source_path
+        .read_dir()
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok().map(|e| e.path().join(item)))
+        .find(|page| legacy::is_item(page))
+        .unwrap_or(path)
 }
 
-fn get_video_list(path: &Path) -> Result<Vec<VideoInfo>, error::Error> {
-    
-    let mut video_list = Vec::<VideoInfo>::new();
+fn get_cached_video(path: &Path) -> Result<CachedVideo, error::Error> {
+    Ok(CachedVideo {
+        dir: path.to_path_buf(),
+        info: get_metadata(path)?,
+        disk_size: disk::dir_size(path)?,
+    })
+}
+
+fn get_video_list(path: &Path) -> Result<Vec<CachedVideo>, error::Error> {
+    let mut video_list = Vec::<CachedVideo>::new();
 
     let subdirs = path
-    .read_dir()
-    .map_err(|_| error::Error::ReadDirectoryFailed)?;
+        .read_dir()
+        .map_err(|_| error::Error::ReadDirectoryFailed)?;
 
     for dir in subdirs {
         match dir {
             Ok(entry) => {
                 let path = entry.path();
                 if path.is_dir() {
-                    let video_info = get_metadata(&path)?;
-                    video_list.push(video_info);
+                    for path in item_dirs(&path) {
+                        match get_cached_video(&path) {
+                            Ok(video) => video_list.push(video),
+                            Err(e) => warn!("Skip {}: {}", path.display(), e),
+                        }
+                    }
                 }
             }
             Err(e) => error!("Failed to read directory: {}", e),
@@ -209,18 +1096,262 @@ fn get_video_list(path: &Path) -> Result<Vec<VideoInfo>, error::Error> {
     }
 
     Ok(video_list)
-
 }
 
 #[derive(Subcommand, Debug)]
 enum Commands {
-    List,
+    /// List cached videos
+    List {
+        /// Sort videos by the given key
+        #[arg(long, value_enum)]
+        sort: Option<list::SortKey>,
+        /// Reverse the sort order
+        #[arg(long, default_value_t = false)]
+        reverse: bool,
+        /// Comma separated columns to print
+        #[arg(long, value_enum, value_delimiter = ',')]
+        columns: Vec<list::Column>,
+        /// Show sizes as raw byte counts
+        #[arg(long, default_value_t = false)]
+        bytes: bool,
+        /// Only list items with this label
+        #[arg(long)]
+        tag: Option<String>,
+        /// Only list items in this conversion state, failed ones with the error
+        #[arg(long, value_enum)]
+        status: Option<list::Status>,
+    },
+    /// Convert cached videos to the output directory
+    #[command(group(clap::ArgGroup::new("single").args(["item", "title"])))]
     Convert {
         item: Option<String>,
+        /// Select the item by its title, or some words of it, instead of its directory
+        #[arg(long, conflicts_with = "item")]
+        title: Option<String>,
+        /// Convert the cached items with this label
+        #[arg(long, conflicts_with_all = ["item", "title"])]
+        tag: Option<String>,
+        /// Join all parts of this group into a single video with a chapter per part
+        #[arg(long, conflicts_with_all = ["item", "title", "tag"])]
+        concat: Option<String>,
+        /// Only print the items, their estimated output and what --autoremove would reclaim
+        #[arg(long, default_value_t = false, conflicts_with = "concat")]
+        dry_run: bool,
+        /// Start --player on the output once the item is converted
+        #[arg(
+            long,
+            default_value_t = false,
+            requires = "single",
+            conflicts_with = "dry_run"
+        )]
+        play: bool,
+    },
+    /// Label an item, e.g. keep or watch-later, to select it later with --tag
+    Tag {
+        item: String,
+        label: String,
+        /// Remove the label instead
+        #[arg(long, default_value_t = false)]
+        remove: bool,
     },
+    /// Remove cached videos
+    #[command(group(clap::ArgGroup::new("policy").args(["keep_under", "older_than"]).multiple(true)))]
     Clean {
         item: Option<String>,
+        /// Remove converted items until the cache takes at most this much, e.g. 50G
+        #[arg(long, value_parser = disk::parse_size, conflicts_with = "item")]
+        keep_under: Option<u64>,
+        /// Remove converted items cached more than this many days ago
+        #[arg(long, value_name = "DAYS", conflicts_with = "item")]
+        older_than: Option<u32>,
+        /// Which items go first when keeping under the size
+        #[arg(long, value_enum, default_value_t = evict::Strategy::Oldest, requires = "policy")]
+        strategy: evict::Strategy,
+        /// Also remove items which were not converted
+        #[arg(long, default_value_t = false, requires = "policy")]
+        all: bool,
+        /// Only print what would be removed
+        #[arg(long, default_value_t = false, requires = "policy")]
+        dry_run: bool,
+    },
+    /// Show sizes per uploader and month, conversion progress and the largest items
+    Stats {
+        /// Print the statistics as JSON
+        #[arg(long, default_value_t = false)]
+        json: bool,
+        /// Report cached items with the same title or content and which copies to clean
+        #[arg(long, default_value_t = false)]
+        duplicates: bool,
+        /// Ask which copy of each duplicate to keep and remove the others
+        #[arg(
+            long,
+            default_value_t = false,
+            requires = "duplicates",
+            conflicts_with = "json"
+        )]
+        pick: bool,
+    },
+    /// Find broken cache items and leftovers of interrupted runs
+    Doctor {
+        /// Remove leftovers and reset stuck items, refused while a conversion is running
+        #[arg(long, default_value_t = false)]
+        fix: bool,
+    },
+    /// Measure the disks, stripping and muxing, and recommend options for faster batches
+    Bench {
+        /// Synthetic data written to each directory, more than the memory gives disk speeds
+        #[arg(long, value_parser = disk::parse_size, default_value = "256M")]
+        size: u64,
+    },
+    /// Check that the outputs of converted items are complete and playable
+    Verify {
+        /// Only check these items
+        item: Vec<String>,
+        /// Also compare duration, resolution and audio channels with the cached media
+        #[arg(long, default_value_t = false)]
+        deep: bool,
+        /// Seconds the duration of an output may differ from its source with --deep
+        #[arg(long, value_name = "SECONDS", default_value_t = 1.0, requires = "deep")]
+        tolerance: f64,
+    },
+    /// Export or import the conversion state, to move the archive elsewhere
+    Index {
+        #[command(subcommand)]
+        action: index::Action,
+    },
+    /// Pack raw cache items into a backup archive, e.g. before cleaning them
+    Export {
+        #[arg(required_unless_present = "all")]
+        item: Vec<String>,
+        /// Pack every cached item
+        #[arg(long, default_value_t = false, conflicts_with = "item")]
+        all: bool,
+        /// Archive to write, a .tar or a .tar.zst compressed with zstd
+        #[arg(long)]
+        to: PathBuf,
+    },
+    /// Put items of a backup back into the cache, to convert them again
+    Restore {
+        archive: PathBuf,
+        /// Items to restore, all by default
+        item: Vec<String>,
+        /// Only list the items in the backup
+        #[arg(long, default_value_t = false, conflicts_with = "item")]
+        list: bool,
     },
+    /// Find items of the cache and the archive by title, uploader or label
+    Search {
+        /// Words of the title, the uploader or labels
+        #[arg(required = true, num_args = 1..)]
+        query: Vec<String>,
+    },
+    /// Open the output of a converted item with the default application
+    Open {
+        item: String,
+        /// Show it in the file manager instead
+        #[arg(long, default_value_t = false)]
+        reveal: bool,
+    },
+    /// Show everything known about a cached video
+    Info {
+        item: String,
+        /// Also print the SHA-256 of each media file, without the prefix bytes
+        #[arg(long)]
+        hash: bool,
+    },
+    /// Browse cached videos interactively
+    Tui,
+    /// Keep converting new cache items and serve their status over HTTP
+    Serve {
+        /// Address to listen on
+        #[arg(long, default_value = serve::DEFAULT_LISTEN)]
+        listen: String,
+        /// Seconds between scans of the cache directory
+        #[arg(long, default_value_t = 60)]
+        interval: u64,
+    },
+    /// Download a video from Bilibili by BV or av id
+    Download {
+        id: String,
+        /// Comma separated pages to download, all by default
+        #[arg(long, value_delimiter = ',')]
+        pages: Vec<u32>,
+        /// SESSDATA cookie of a logged in account for higher qualities,
+        /// BILIBILI_SESSDATA or the stored login by default
+        #[arg(long)]
+        sessdata: Option<String>,
+    },
+    /// Download the videos of a favorites folder missing from the archive
+    SyncFavorites {
+        /// Folder id, the fid in the folder's URL
+        fid: u64,
+        /// SESSDATA cookie, needed for private folders
+        #[arg(long)]
+        sessdata: Option<String>,
+        /// Only print what would be downloaded
+        #[arg(long, default_value_t = false)]
+        dry_run: bool,
+    },
+    /// Download the videos of an uploader missing from the archive, laid out by uploader
+    ArchiveUp {
+        /// Uploader id, the number in the space URL
+        mid: u64,
+        /// Only videos published on or after this day, e.g. 2024-01-31
+        #[arg(long)]
+        since: Option<chrono::NaiveDate>,
+        /// Only videos published on or before this day
+        #[arg(long)]
+        until: Option<chrono::NaiveDate>,
+        /// Only videos with this word in the title, may be repeated
+        #[arg(long = "keyword")]
+        keywords: Vec<String>,
+        /// SESSDATA cookie of a logged in account for higher qualities
+        #[arg(long)]
+        sessdata: Option<String>,
+        /// Only print what would be downloaded
+        #[arg(long, default_value_t = false)]
+        dry_run: bool,
+    },
+    /// Log in for downloads, by QR code unless the cookies are given
+    Login {
+        /// SESSDATA cookie copied from a logged in browser
+        #[arg(long)]
+        sessdata: Option<String>,
+        /// bili_jct cookie of the same login
+        #[arg(long, requires = "sessdata")]
+        bili_jct: Option<String>,
+    },
+    /// Remove the stored login
+    Logout,
+    /// Convert new cache items and remove sources converted by earlier runs
+    Sync {
+        /// Only print what would change
+        #[arg(long, default_value_t = false)]
+        dry_run: bool,
+    },
+    /// Install a systemd user unit or launchd agent running sync with the current options
+    InstallService {
+        /// Run serve continuously instead of sync on a schedule
+        #[arg(long, default_value_t = false)]
+        serve: bool,
+        /// Seconds between sync runs
+        #[arg(long, default_value_t = 3600)]
+        interval: u64,
+        /// Print the service files instead of writing them
+        #[arg(long, default_value_t = false)]
+        print: bool,
+    },
+    /// Upload converted videos to remote storage
+    Upload {
+        /// sftp://host/path, webdav(s)://host/path or s3://bucket/prefix
+        destination: upload::Destination,
+        /// Files or directories below the output directory, all of it by default
+        paths: Vec<PathBuf>,
+    },
+    /// Print a shell completion script
+    Completions { shell: completions::Shell },
+    #[command(name = completions::ITEMS_COMMAND, hide = true)]
+    CompleteItems,
 }
 
 // Command line arguments
@@ -232,64 +1363,503 @@ enum Commands {
 struct Args {
     #[command(subcommand)]
     command: Commands,
-    /// Enable debug output
-    #[arg(short, default_value_t = false)]
-    verbose: bool,
+    /// Show more output: -v info, -vv debug, -vvv trace
+    #[arg(short, action = clap::ArgAction::Count, conflicts_with = "quiet")]
+    verbose: u8,
+    /// Only show errors
+    #[arg(short, long, default_value_t = false)]
+    quiet: bool,
+    /// Print results as text, or as JSON lines for scripts
+    #[arg(long, value_enum, default_value_t = output::Format::Text)]
+    format: output::Format,
+    /// Print every ffmpeg, ffprobe, rclone and upload command before running it
+    #[arg(long, default_value_t = false)]
+    show_commands: bool,
+    /// Read media files for hashing and probing instead of mapping them into memory
+    #[arg(long, default_value_t = false)]
+    no_mmap: bool,
+    /// Language of messages, from LANG by default
+    #[arg(long, value_enum)]
+    lang: Option<i18n::Lang>,
+    /// Cache directory of the client, if it is not in the default location
+    #[arg(long, value_name = "DIR")]
+    cache_dir: Option<PathBuf>,
     /// Remove source files after successful conversion
     #[arg(long, default_value_t = false)]
     autoremove: bool,
+    /// Delete removed cache directories permanently instead of moving them to the trash
+    #[arg(long, default_value_t = false)]
+    permanent: bool,
     /// Do not overwrite target file if exists
     #[arg(long, default_value_t = false)]
     no_overwrite: bool,
+    /// Conversion order, smallest first by default with --autoremove
+    #[arg(long, value_enum)]
+    order: Option<queue::Order>,
+    /// Ignore saved conversion state and convert every item again
+    #[arg(long, default_value_t = false)]
+    restart: bool,
+    /// Wait for another run using the cache or output directory instead of exiting
+    #[arg(long, default_value_t = false)]
+    wait_lock: bool,
+    /// Take over the locks of the cache and output directory from a run that may be gone
+    #[arg(long, default_value_t = false)]
+    force_unlock: bool,
+    /// Character used in place of illegal characters in output names
+    #[arg(long, default_value_t = sanitize::DEFAULT_REPLACEMENT)]
+    replace_char: char,
+    /// Maximum length in bytes of each output directory name
+    #[arg(long, default_value_t = sanitize::DEFAULT_MAX_LENGTH)]
+    max_name_length: usize,
+    /// Use ASCII-only output names, romanizing kana and hangul, falling back to the ids of the group, uploader or item for names mostly of Chinese characters
+    #[arg(long, default_value_t = false)]
+    ascii_names: bool,
+    /// Layout of the output directory
+    #[arg(long, value_enum, default_value_t = layout::Organize::Group)]
+    organize: layout::Organize,
+    /// Name of outputs in the flat layout: {uname} {title} {group} {p} {item_id} {date} {season} {episode}
+    #[arg(long, default_value = layout::DEFAULT_TEMPLATE)]
+    name_template: String,
+    /// Pattern finding episode numbers in part titles for {season} and {episode}, e.g. "第{e}话"
+    #[arg(long = "episode-pattern", value_name = "PATTERN")]
+    episode_patterns: Vec<episode::Pattern>,
+    /// Never convert or clean items with this id, uploader or title glob when scanning the cache, like lines of .bilibiliignore in it
+    #[arg(long, value_name = "PATTERN")]
+    exclude: Vec<String>,
+    /// Settings of single uploaders [default: ~/.config/bilibili/uploaders.json if it exists]
+    #[arg(long, value_name = "FILE")]
+    uploaders: Option<PathBuf>,
+    /// Write a Kodi style NFO next to every output, describing episodes as parts of a show
+    #[arg(long, default_value_t = false)]
+    nfo: bool,
+    /// Set the modification time of outputs from the video's timestamp
+    #[arg(long, value_enum)]
+    set_mtime: Option<MtimeSource>,
+    /// Append a record for every processed item to this file
+    #[arg(long)]
+    log_file: Option<PathBuf>,
+    /// Format of the records in the log file
+    #[arg(long, value_enum, default_value_t = runlog::LogFormat::Text)]
+    log_format: runlog::LogFormat,
+    /// ffmpeg binary to use, ffprobe is expected next to it
+    #[arg(long, default_value = ffmpeg::DEFAULT_FFMPEG)]
+    ffmpeg_path: PathBuf,
+    /// Extra arguments passed to ffmpeg, e.g. "-max_muxing_queue_size 1024"
+    #[arg(long, allow_hyphen_values = true)]
+    ffmpeg_args: Option<String>,
+    /// Video quality to pick when several are cached: highest, lowest or a resolution like 1080p
+    #[arg(long, default_value = "highest")]
+    prefer_quality: quality::Quality,
+    /// Copy the cached streams, or re-encode the video for players lacking its codec
+    #[arg(long, value_enum, default_value_t = profile::Profile::Copy)]
+    profile: profile::Profile,
+    /// Re-encode with this profile when copying the streams fails
+    #[arg(long, value_enum)]
+    fallback_profile: Option<profile::Profile>,
+    /// Re-encode only the streams this device can not play, copying the others
+    #[arg(long, value_enum, value_name = "DEVICE", conflicts_with = "profile")]
+    compat_target: Option<compat::Target>,
+    /// Container of the outputs
+    #[arg(long, value_enum, default_value_t = container::Container::Mp4)]
+    container: container::Container,
+    /// Put the index of mp4 and mov outputs first, so they start playing at
+    /// once when streamed, e.g. from a NAS (the default)
+    #[arg(long, overrides_with = "no_faststart")]
+    faststart: bool,
+    /// Leave the index of mp4 and mov outputs at the end
+    #[arg(long, overrides_with = "faststart")]
+    no_faststart: bool,
+    /// Split outputs larger than this, e.g. 4G for FAT32, into parts at keyframes
+    #[arg(long, value_name = "SIZE", value_parser = disk::parse_size)]
+    max_size: Option<u64>,
+    /// Also render the danmaku onto a re-encoded copy `<name>.danmaku.mp4`,
+    /// for players that cannot overlay ASS subtitles
+    #[arg(long)]
+    burn_danmaku: bool,
+    /// Comma separated previews to generate next to each output
+    #[arg(long, value_enum, value_delimiter = ',')]
+    thumbnails: Vec<thumbnails::Kind>,
+    /// Columns and rows of the contact sheet
+    #[arg(long, default_value = "4x4")]
+    thumbnail_grid: thumbnails::Grid,
+    /// Do not copy cover art next to the outputs
+    #[arg(long, default_value_t = false)]
+    no_covers: bool,
+    /// Name the group cover is written under, the item cover is written as poster.<ext>
+    #[arg(long, value_enum, default_value = "fanart")]
+    group_cover_name: cover::GroupCover,
+    /// Keep the file names covers have in the cache instead of poster and the group cover name
+    #[arg(long, default_value_t = false, conflicts_with = "group_cover_name")]
+    original_cover_names: bool,
+    /// Convert cover art into this format
+    #[arg(long, value_enum, conflicts_with = "no_covers")]
+    cover_format: Option<cover::Format>,
+    /// Shrink cover art to fit within this many pixels, converting it as --cover-format, JPEG by default
+    #[arg(long, value_name = "PIXELS", conflicts_with = "no_covers")]
+    cover_max: Option<u32>,
+    /// Keep .m3u8 playlists per uploader and per series in the Playlists directory
+    #[arg(long, default_value_t = false)]
+    playlists: bool,
+    /// Limit reading and writing to this many MB/s, ffmpeg 5.0 or later also gets a -readrate hint
+    #[arg(long, value_name = "MB/s")]
+    io_limit: Option<f64>,
+    /// Run ffmpeg with reduced CPU priority, to keep using the machine meanwhile
+    #[arg(long, default_value_t = false)]
+    low_priority: bool,
+    /// Limit the number of threads ffmpeg uses
+    #[arg(long)]
+    threads: Option<u32>,
+    /// Directory for intermediate files, ideally on fast local storage [default: system temp directory]
+    #[arg(long, value_name = "DIR")]
+    work_dir: Option<PathBuf>,
+    /// Move items failing in several runs in a row out of the cache into this directory
+    #[arg(long, value_name = "DIR")]
+    quarantine_dir: Option<PathBuf>,
+    /// Runs in a row an item has to fail in to be moved into the quarantine directory
+    #[arg(
+        long,
+        value_name = "RUNS",
+        default_value_t = 3,
+        requires = "quarantine_dir",
+        value_parser = clap::value_parser!(u32).range(1..)
+    )]
+    quarantine_after: u32,
+    /// Retry items failing with IO or ffmpeg errors this many times, with growing delays
+    #[arg(long, default_value_t = 0)]
+    retries: u32,
+    /// Kill ffmpeg and fail the item when one run of it takes longer than this
+    #[arg(long, value_name = "SECS")]
+    timeout: Option<u64>,
+    /// Show a desktop notification when a conversion batch finishes
+    #[arg(long, default_value_t = false)]
+    notify: bool,
+    /// Player for convert --play, e.g. "mpv --fs" [default: mpv or vlc if installed, else the system default]
+    #[arg(long, allow_hyphen_values = true)]
+    player: Option<String>,
+    /// Shell command run after each converted item, see BILIBILI_* environment variables
+    #[arg(long)]
+    on_success: Option<String>,
+    /// Shell command run after each failed item
+    #[arg(long)]
+    on_failure: Option<String>,
+    /// URL receiving a JSON POST after each item
+    #[arg(long)]
+    webhook: Option<String>,
+    /// Upload each converted item to sftp://host/path, webdav(s)://host/path or s3://bucket/prefix
+    #[arg(long)]
+    upload_to: Option<upload::Destination>,
+    /// Remove local outputs once their upload was verified
+    #[arg(long, default_value_t = false)]
+    remove_uploaded: bool,
+    /// Copy each converted item to this rclone remote, e.g. nas:videos
+    #[arg(long)]
+    rclone_remote: Option<String>,
+}
+
+/// Which metadata timestamp becomes the modification time of the output
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum MtimeSource {
+    Pubdate,
+    Update,
+}
+
+// Settings shared by every item of a conversion run
+struct ConvertOptions {
+    permanent: bool,
+    order: Option<queue::Order>,
+    ffmpeg: ffmpeg::Ffmpeg,
+    quality: quality::Quality,
+    profile: profile::Profile,
+    fallback_profile: Option<profile::Profile>,
+    compat: Option<compat::Target>,
+    max_size: Option<u64>, // bytes
+    faststart: bool,
+    burn_danmaku: bool,
+    mtime: Option<MtimeSource>,
+    log: Option<runlog::RunLog>,
+    autoremove: bool,
+    restart: bool,
+    layout: layout::Layout,
+    thumbnails: Vec<thumbnails::Kind>,
+    thumbnail_grid: thumbnails::Grid,
+    covers: bool,
+    cover: Option<cover::Conversion>,
+    group_cover: cover::GroupCover,
+    original_cover_names: bool,
+    nfo: bool,
+    exclude: Vec<String>,
+    playlists: bool,
+    quarantine: Option<quarantine::Quarantine>,
+    retries: u32,
+    work_dir: PathBuf,
+    io_limit: Option<u64>, // bytes per second
+    hooks: hooks::Hooks,
+    upload: Option<upload::Destination>,
+    remove_uploaded: bool,
+    rclone_remote: Option<String>,
+    /// Replaces ffmpeg for muxing, see `muxer`
+    muxer: Option<Box<dyn ffmpeg::Muxer>>,
+    clock: state::Clock,
+    /// Backup the cache items are extracted from, see `backup`
+    backup: Option<backup::Backup>,
+    /// Stage durations of the current item, see `eta`
+    timings: eta::Timings,
+}
+
+impl ConvertOptions {
+    fn muxer(&self) -> &dyn ffmpeg::Muxer {
+        match &self.muxer {
+            Some(muxer) => muxer.as_ref(),
+            None => &self.ffmpeg,
+        }
+    }
+
+    // On a nearly full disk, small items first let autoremove free space early
+    fn order(&self) -> queue::Order {
+        self.order.unwrap_or(if self.autoremove {
+            queue::Order::Smallest
+        } else {
+            queue::Order::Name
+        })
+    }
+
+    /// Profile of `--profile`, unless the uploader of the video has another
+    fn profile_for(&self, video_info: &VideoInfo) -> profile::Profile {
+        self.layout
+            .uploaders
+            .get(&video_info.uname)
+            .and_then(|overrides| overrides.profile)
+            .unwrap_or(self.profile)
+    }
 }
 
-fn check_environment() -> Result<(), error::Error> {
+fn check_environment(options: &ConvertOptions) -> Result<(), error::Error> {
+    let ffmpeg = &options.ffmpeg;
 
     // Check if ffmpeg is available
-    if Command::new("ffmpeg").arg("-version").output().is_err() {
-        eprintln!("ffmpeg is not installed or not found in PATH");
+    let Ok(version) = ffmpeg.version() else {
+        eprintln!(
+            "{}",
+            tr!(
+                "ffmpeg is not installed or not found at {}",
+                ffmpeg.path.display()
+            )
+        );
         return Err(error::Error::CommandNotFound);
+    };
+    match version {
+        Some(version) if version < ffmpeg::MIN_VERSION => {
+            eprintln!(
+                "{}",
+                tr!(
+                    "ffmpeg {} is too old, please install {} or later",
+                    version,
+                    ffmpeg::MIN_VERSION
+                )
+            );
+            return Err(error::Error::FfmpegUnsupported(format!(
+                "version {}",
+                version
+            )));
+        }
+        Some(version) => debug!("ffmpeg version {}", version),
+        None => debug!("ffmpeg built from git, assuming it is recent"),
+    }
+
+    // Checked up front rather than failing every item of a batch
+    let mut required = vec![
+        (
+            ffmpeg::Component::Demuxer,
+            "mov",
+            "Your ffmpeg lacks the {} demuxer needed to read cached media, install a full build of ffmpeg",
+        ),
+        (
+            ffmpeg::Component::Muxer,
+            options.layout.container.format(),
+            "Your ffmpeg lacks the {} muxer needed to write the outputs, install a full build of ffmpeg",
+        ),
+    ];
+    if let Some(encoder) = options.profile.video_encoder() {
+        required.push((
+            ffmpeg::Component::Encoder,
+            encoder,
+            "Your ffmpeg lacks the {} encoder needed for the selected profile, choose --profile copy",
+        ));
+    }
+    for encoder in options
+        .layout
+        .uploaders
+        .profiles()
+        .filter_map(|p| p.video_encoder())
+    {
+        required.push((
+            ffmpeg::Component::Encoder,
+            encoder,
+            "Your ffmpeg lacks the {} encoder needed for a profile in the uploader settings, choose another one",
+        ));
+    }
+    // Unplayable video is always re-encoded to H.264
+    if let Some(encoder) = options.compat.and(profile::Profile::H264.video_encoder()) {
+        required.push((
+            ffmpeg::Component::Encoder,
+            encoder,
+            "Your ffmpeg lacks the {} encoder needed for --compat-target, install a full build of ffmpeg",
+        ));
+    }
+    if let Some(encoder) = options.fallback_profile.and_then(|p| p.video_encoder()) {
+        required.push((
+            ffmpeg::Component::Encoder,
+            encoder,
+            "Your ffmpeg lacks the {} encoder needed for the fallback profile, choose another one",
+        ));
+    }
+    if options.burn_danmaku {
+        required.push((
+            ffmpeg::Component::Filter,
+            "subtitles",
+            "Your ffmpeg lacks the {} filter needed to burn danmaku, install a build with libass",
+        ));
+        if let Some(encoder) = danmaku::profile(options).video_encoder() {
+            required.push((
+                ffmpeg::Component::Encoder,
+                encoder,
+                "Your ffmpeg lacks the {} encoder needed to burn danmaku, choose another --profile",
+            ));
+        }
+    }
+    for (kind, name, message) in required {
+        if !ffmpeg.has(kind, name)? {
+            eprintln!("{}", tr!(message, name));
+            return Err(error::Error::FfmpegUnsupported(format!(
+                "no {} {}",
+                name, kind
+            )));
+        }
     }
     Ok(())
 }
 
-fn prepare_output_directory(home: &String) -> Result<PathBuf, error::Error> {
+fn prepare_output_directory(dirs: &dirs::Dirs) -> Result<PathBuf, error::Error> {
     // Create target directory before processing
-    let target_path = Path::new(home).join(DEFAULT_TARGET_DIR);
+    let target_path = dirs.target.clone();
     debug!("Target directory: {}", target_path.display());
-    fs::create_dir_all(&target_path)?;
+    fs::create_dir_all(&target_path).context("create directory", &target_path)?;
     Ok(target_path)
 }
 
 // Print video list to console
-fn show_video_list(source_path: &Path) -> Result<(), error::Error> {
-    let videos = get_video_list(&source_path)?;
-    for video in videos {
-        println!("{}", video);
+fn show_video_list(
+    dirs: &dirs::Dirs,
+    sort: Option<list::SortKey>,
+    reverse: bool,
+    columns: &[list::Column],
+    bytes: bool,
+    filter: &list::Filter,
+    quality: quality::Quality,
+) -> Result<(), error::Error> {
+    let (source_path, target_path) = (&dirs.source, &dirs.target);
+    let mut videos = get_video_list(source_path)?;
+    let db = state::StateDb::load(target_path)?;
+    if let Some(label) = filter.tag {
+        let tagged = db.tagged(label);
+        videos.retain(|v| tagged.iter().any(|item| v.dir.ends_with(item)));
+    }
+    if let Some(status) = filter.status {
+        videos.retain(|v| list::Status::of(&db, &list::cell(v, list::Column::Dir)) == status);
+    }
+    // Failed items are listed to see why
+    let mut columns = columns.to_vec();
+    if columns.is_empty() && filter.status == Some(list::Status::Failed) {
+        columns = [list::DEFAULT_COLUMNS, &[list::Column::Error]].concat();
+    }
+    if let Some(key) = sort {
+        list::sort(&mut videos, key, reverse);
+    } else if reverse {
+        videos.reverse();
     }
+    list::print(&videos, &columns, bytes, quality, &db);
+    list::print_totals(&videos, source_path, target_path, bytes, quality);
     Ok(())
 }
 
-// Clean video cache
-fn clean_cached_video(source_path: &Path, item: Option<String>) -> Result<(), error::Error> {
+/// Cached items labeled `label`, labels of items no longer cached are kept
+/// for when they are downloaded again
+fn tagged_items(
+    source_path: &Path,
+    target_path: &Path,
+    label: &str,
+) -> Result<Vec<String>, error::Error> {
+    let items: Vec<String> = state::StateDb::load(target_path)?
+        .tagged(label)
+        .into_iter()
+        .filter(|item| item_path(source_path, item).is_dir())
+        .collect();
+    if items.is_empty() {
+        return Err(error::Error::TagNotFound(label.to_string()));
+    }
+    Ok(items)
+}
+
+fn tag_item(
+    source_path: &Path,
+    target_path: &Path,
+    item: &str,
+    label: &str,
+    remove: bool,
+) -> Result<(), error::Error> {
+    let mut db = state::StateDb::load(target_path)?;
+    if remove {
+        if !db.untag(item, label) {
+            warn!("{} is not labeled {}", item, label);
+        }
+    } else {
+        if !item_path(source_path, item).is_dir() && db.get(item).is_none() {
+            warn!("{} is neither cached nor converted", item);
+        }
+        db.tag(item, label);
+    }
+    // The database lives in the output directory
+    fs::create_dir_all(target_path).context("create directory", target_path)?;
+    db.save()
+}
+
+/// Remove a cache directory, moving it to the trash unless `permanent`
+fn remove_source(path: &Path, permanent: bool) -> Result<(), error::Error> {
+    if permanent {
+        fs::remove_dir_all(path).context("remove", path)?;
+    } else {
+        trash::move_to_trash(path)?;
+    }
+    Ok(())
+}
 
+// Clean video cache
+fn clean_cached_video(
+    source_path: &Path,
+    item: Option<String>,
+    permanent: bool,
+    exclude: &[String],
+) -> Result<(), error::Error> {
     if let Some(item) = item {
-        let item_path = source_path.join(item);
+        let item_path = item_path(source_path, &item);
         info!("Removing directory {}", item_path.display());
-        fs::remove_dir_all(item_path)?;
+        remove_source(&item_path, permanent)?;
+        output::emit("removed", &serde_json::json!({ "path": item_path }))?;
     } else {
         let subdirs = source_path
-        .read_dir()
-        .map_err(|_| error::Error::ReadDirectoryFailed)?;
-        
+            .read_dir()
+            .map_err(|_| error::Error::ReadDirectoryFailed)?;
+        let ignore = ignore::Ignore::load(source_path, exclude)?;
+
         for dir in subdirs {
             match dir {
                 Ok(entry) => {
                     let p = entry.path();
                     let path = p.as_path();
-                    if path.is_dir() {
+                    if path.is_dir() && !ignored(&ignore, path) {
                         info!("Removing directory {}", entry.path().display());
-                        fs::remove_dir_all(path)?;
+                        remove_source(path, permanent)?;
+                        output::emit("removed", &serde_json::json!({ "path": path }))?;
                     }
                 }
                 Err(e) => error!("Failed to read directory: {}", e),
@@ -299,70 +1869,1282 @@ fn clean_cached_video(source_path: &Path, item: Option<String>) -> Result<(), er
     Ok(())
 }
 
-fn convert_video(home: &String, item: Option<String>, autoremove: bool) -> Result<(), error::Error> {
+/// Upload the given outputs, or the whole output directory
+fn upload_outputs(
+    target_path: &Path,
+    destination: &upload::Destination,
+    paths: &[PathBuf],
+    remove: bool,
+) -> Result<(), error::Error> {
+    let mut files = Vec::new();
+    if paths.is_empty() {
+        upload::collect(target_path, &mut files)?;
+    }
+    for path in paths {
+        // Relative paths may be given from anywhere, or relative to the output directory
+        let path = if path.exists() {
+            fs::canonicalize(path).context("resolve", path)?
+        } else {
+            target_path.join(path)
+        };
+        upload::collect(&path, &mut files)?;
+    }
+    let target_path = fs::canonicalize(target_path).context("resolve", target_path)?;
+    let files: Vec<PathBuf> = files
+        .into_iter()
+        .map(|f| fs::canonicalize(&f).unwrap_or(f))
+        .collect();
+    upload::files(destination, &target_path, &files, remove)?;
+    info!("Uploaded {} files to {}", files.len(), destination);
+    Ok(())
+}
+
+/// Outcome of a conversion run
+#[derive(Default, Debug, Serialize)]
+struct Summary {
+    converted: usize,
+    failed: usize,
+    skipped: usize,
+    encrypted: Vec<String>,     // failed because of DRM protection
+    reencoded: Vec<String>,     // converted with the fallback profile as copying failed
+    gaps: Vec<String>,          // converted from cached media missing fragments
+    remote_failed: Vec<String>, // converted, but not copied to the rclone remote
+    upload_failed: Vec<String>, // converted, but not uploaded to --upload-to
+    quarantined: Vec<String>,   // failed too often, moved out of the cache
+}
+
+/// Convert the given cache items, or every item in the cache if none are given
+fn convert_video(
+    dirs: &dirs::Dirs,
+    selected: Vec<String>,
+    options: &ConvertOptions,
+) -> Result<Summary, error::Error> {
+    let jobs = item_jobs(dirs, &selected, options)?;
+    convert_jobs(dirs, jobs, options)
+}
+
+/// Convert the cache items of `jobs` into the output directory
+fn convert_jobs(
+    dirs: &dirs::Dirs,
+    jobs: Vec<queue::Job>,
+    options: &ConvertOptions,
+) -> Result<Summary, error::Error> {
+    check_environment(options)?;
 
-    check_environment()?;
+    // prepare output directory before processing
+    let target_path = prepare_output_directory(dirs)?;
+    convert_items(jobs, &target_path, options)
+}
 
-    let source_path = Path::new(&home).join(DEFAULT_SOURCE_DIR);
+/// Jobs for the items in `selected`, or for all cached items if it is empty
+fn item_jobs(
+    dirs: &dirs::Dirs,
+    selected: &[String],
+    options: &ConvertOptions,
+) -> Result<Vec<queue::Job>, error::Error> {
+    if let Some(backup) = &options.backup {
+        return Ok(backup.jobs(selected));
+    }
+
+    let source_path = &dirs.source;
     let subdirs = source_path
         .read_dir()
         .map_err(|_| error::Error::ReadDirectoryFailed)?;
 
-    // prepare output directory before processing
-    let target_path = prepare_output_directory(&home)?;
-
-    // Handle the item if specified, otherwise process all by iterating over subdirectories
-    // TODO Make video processing in a uniform way by passing items to process
-    if let Some(item) = item {
-        let item_path = source_path.join(item);
-        handle_dir(&item_path, &target_path, autoremove);
+    // Handle the items if specified, otherwise process all by iterating over subdirectories
+    let mut items: Vec<PathBuf> = Vec::new();
+    if !selected.is_empty() {
+        items.extend(selected.iter().map(|item| item_path(source_path, item)));
     } else {
+        let ignore = ignore::Ignore::load(source_path, &options.exclude)?;
         for dir in subdirs {
             match dir {
                 Ok(entry) => {
                     let path = entry.path();
                     if path.is_dir() {
-                        handle_dir(&path, &target_path, autoremove);
+                        let dirs = item_dirs(&path).into_iter();
+                        items.extend(dirs.filter(|dir| !ignored(&ignore, dir)));
                     }
                 }
                 Err(e) => error!("Failed to read directory: {}", e),
             }
         }
     }
-    Ok(())
+    Ok(items.iter().map(|path| item_job(path, false)).collect())
+}
+
+/// The job for the cache item at `path`, sized and dated from the cache
+fn item_job(path: &Path, selected: bool) -> queue::Job {
+    queue::Job {
+        item: item_name(path),
+        path: path.to_path_buf(),
+        selected,
+        size: disk::dir_size(path).unwrap_or_default(),
+        pubdate: get_metadata(path).map(|v| v.pubdate).unwrap_or_default(),
+    }
+}
+
+/// Seconds converting `job` is expected to take, zero if it is skipped,
+/// None until all its stages were measured
+fn job_eta(
+    job: &queue::Job,
+    profiles: &HashMap<String, Option<profile::Profile>>,
+    db: &state::StateDb,
+    options: &ConvertOptions,
+) -> Option<f64> {
+    if !options.restart && db.is_converted(&job.item) {
+        return Some(0.0);
+    }
+    match profiles
+        .get(&job.item)
+        .copied()
+        .unwrap_or(Some(options.profile))
+    {
+        Some(profile) => {
+            let stages = eta::stages(!options.ffmpeg.skips_initial_bytes(), profile);
+            eta::estimate(db, job.size, &stages)
+        }
+        None => Some(0.0),
+    }
+}
+
+/// Convert the cache items of `jobs` into `target_path`, recording their
+/// state there
+fn convert_items(
+    jobs: Vec<queue::Job>,
+    target_path: &Path,
+    options: &ConvertOptions,
+) -> Result<Summary, error::Error> {
+    let journal = journal::Journal::new(target_path);
+    journal.recover(options.permanent)?;
+    let mut queue = queue::Queue::new(options.order());
+    // Profiles of the items for their ETA, None for skipped uploaders
+    let mut profiles = HashMap::new();
+    for job in jobs {
+        // Unless the removal of a converted source was just finished
+        if options.backup.is_some() || job.path.exists() {
+            if let Ok(video_info) = get_metadata(&job.path) {
+                let skipped = options.layout.uploaders.skips(&video_info.uname);
+                let profile = Some(options.profile_for(&video_info)).filter(|_| !skipped);
+                profiles.insert(job.item.clone(), profile);
+            }
+            queue.push(job);
+        }
+    }
+
+    let mut db = state::StateDb::load(target_path)?.with_clock(options.clock);
+    let mut summary = Summary::default();
+    let mut progress = progress::Progress::new(queue.len());
+    signal::install();
+
+    while let Some(job) = queue.pop() {
+        if signal::interrupted() {
+            break;
+        }
+        let estimate = |job: &queue::Job| job_eta(job, &profiles, &db, options);
+        let item_eta = estimate(&job);
+        let eta = eta::Eta {
+            item: item_eta,
+            batch: queue.jobs().iter().map(estimate).chain([item_eta]).sum(),
+        };
+        let (name, mut path) = (job.item, job.path);
+        progress.start(&name, eta);
+        // Items of a backup are extracted one at a time and removed after
+        let mut extracted = None;
+        let mut extract_error = None;
+        if let Some(backup) = &options.backup {
+            if options.restart || !db.is_converted(&name) {
+                match backup.extract(&path, &options.work_dir) {
+                    Ok(dir) => path = extracted.insert(dir).path().to_path_buf(),
+                    Err(e) => extract_error = Some(e),
+                }
+            }
+        }
+        let mut record = runlog::ItemRecord::new(&name, "skipped");
+        let video_info = get_metadata(&path).ok();
+        if let Some(video_info) = &video_info {
+            record.item_id = Some(video_info.item_id);
+            record.bytes = video_info.total_size;
+        }
+
+        if !options.restart && db.is_converted(&name) {
+            info!(
+                "Skip {}, already converted (use --restart to convert again)",
+                name
+            );
+            if let Some(log) = &options.log {
+                log.record(&record)?;
+            }
+            output::emit(
+                "item",
+                &hooks::Event {
+                    item: &name,
+                    item_id: record.item_id,
+                    title: video_info.as_ref().map(|v| v.title.as_str()),
+                    result: record.result,
+                    output: None,
+                    error: None,
+                },
+            )?;
+            summary.skipped += 1;
+            progress.advance();
+            continue;
+        }
+        let uploaders = &options.layout.uploaders;
+        if let Some(video_info) = video_info.as_ref().filter(|v| uploaders.skips(&v.uname)) {
+            info!(
+                "Skip {}, videos of {} are skipped in the uploader settings",
+                name, video_info.uname
+            );
+            if let Some(log) = &options.log {
+                log.record(&record)?;
+            }
+            output::emit(
+                "item",
+                &hooks::Event {
+                    item: &name,
+                    item_id: record.item_id,
+                    title: Some(&video_info.title),
+                    result: record.result,
+                    output: None,
+                    error: None,
+                },
+            )?;
+            summary.skipped += 1;
+            progress.advance();
+            continue;
+        }
+        db.set(&name, state::Status::Converting, None);
+        db.save()?;
+        output::emit(
+            "start",
+            &serde_json::json!({ "item": &name, "eta_secs": eta.item, "batch_eta_secs": eta.batch }),
+        )?;
+
+        let start = Instant::now();
+        let result = match extract_error {
+            Some(e) => Err(e),
+            None => handle_dir(&path, target_path, options),
+        };
+        drop(extracted);
+        record.duration = start.elapsed().as_secs_f64();
+        let timings = options.timings.take();
+        match &result {
+            Ok(output) => {
+                record.result = "converted";
+                eta::learn(&mut db, timings);
+                summary.converted += 1;
+                db.set(&name, state::Status::Converted, None);
+                db.set_output(&name, &output.file);
+                if output.reencoded {
+                    summary.reencoded.push(name.clone());
+                }
+                if !output.gaps.is_empty() {
+                    summary
+                        .gaps
+                        .push(format!("{} ({})", name, output.gaps.join("; ")));
+                }
+            }
+            Err(error::Error::Interrupted) => {
+                record.result = "interrupted";
+                db.set(&name, state::Status::Interrupted, None)
+            }
+            Err(e) => {
+                record.result = "failed";
+                summary.failed += 1;
+                if let error::Error::EncryptedContent(_) = e {
+                    summary.encrypted.push(name.clone());
+                }
+                record.error = Some(e.to_string());
+                db.set(&name, state::Status::Failed, Some(e.to_string()))
+            }
+        }
+        // The local output is fine, so a failed upload or copy does not fail the item
+        let mut uploaded = false;
+        if let (Ok(output), Some(destination)) = (&result, &options.upload) {
+            match upload::output(destination, target_path, output, options.remove_uploaded) {
+                Ok(()) => uploaded = true,
+                Err(e) => {
+                    error!("Failed to upload {} to {}: {}", name, destination, e);
+                    summary.upload_failed.push(format!("{} ({})", name, e));
+                    record.error = Some(e.to_string());
+                }
+            }
+        }
+        if let (Ok(output), Some(remote)) = (&result, &options.rclone_remote) {
+            if let Err(e) = rclone::copy_output(remote, target_path, output) {
+                error!("Failed to copy {} to {}: {}", name, remote, e);
+                summary.remote_failed.push(format!("{} ({})", name, e));
+                record.error = Some(e.to_string());
+            }
+        }
+        db.save()?;
+        // Items of a backup stay in it
+        let quarantine = options
+            .quarantine
+            .as_ref()
+            .filter(|_| options.backup.is_none());
+        if let (Err(e), Some(quarantine)) = (&result, quarantine) {
+            let failures = db.get(&name).map_or(0, |s| s.failures);
+            match quarantine.admit(&name, &path, &e.to_string(), failures) {
+                Ok(Some(moved)) => {
+                    warn!(
+                        "{}",
+                        tr!(
+                            "Moved {} to {} after failing {} runs in a row",
+                            name,
+                            moved.display(),
+                            failures
+                        )
+                    );
+                    db.set(&name, state::Status::Quarantined, Some(e.to_string()));
+                    db.save()?;
+                    summary.quarantined.push(name.clone());
+                }
+                Ok(None) => {}
+                Err(e) => error!("Failed to quarantine {}: {}", name, e),
+            }
+        }
+        if let (Ok(output), true) = (&result, options.autoremove) {
+            autoremove(&name, &path, output, &journal, uploaded, options);
+        }
+        if let Some(log) = &options.log {
+            log.record(&record)?;
+        }
+        let event = hooks::Event {
+            item: &name,
+            item_id: record.item_id,
+            title: video_info.as_ref().map(|v| v.title.as_str()),
+            result: record.result,
+            output: result.as_ref().ok().map(|o| o.file.as_path()),
+            error: record.error.as_deref(),
+        };
+        output::emit("item", &event)?;
+        if record.result != "interrupted" {
+            options.hooks.run(&event);
+        }
+        progress.advance();
+    }
+
+    if signal::interrupted() {
+        progress.finish(tr!("Interrupted, conversion state saved"));
+        info!("Interrupted, conversion state saved");
+        output::emit("interrupted", &summary)?;
+        return Err(error::Error::Interrupted);
+    }
+    output::emit("summary", &summary)?;
+    let message = tr!(
+        "Converted {}, failed {}, skipped {}",
+        summary.converted,
+        summary.failed,
+        summary.skipped
+    );
+    progress.finish(&message);
+    info!("{}", message);
+    if !summary.encrypted.is_empty() {
+        warn!(
+            "{}",
+            tr!("Encrypted items skipped: {}", summary.encrypted.join(", "))
+        );
+    }
+    if !summary.reencoded.is_empty() {
+        warn!(
+            "{}",
+            tr!(
+                "Re-encoded as copying the streams failed: {}",
+                summary.reencoded.join(", ")
+            )
+        );
+    }
+    if !summary.gaps.is_empty() {
+        warn!(
+            "{}",
+            tr!(
+                "Cached media missing fragments, the outputs may stutter: {}",
+                summary.gaps.join(", ")
+            )
+        );
+    }
+    if !summary.remote_failed.is_empty() {
+        warn!(
+            "{}",
+            tr!(
+                "Not copied to the rclone remote: {}",
+                summary.remote_failed.join(", ")
+            )
+        );
+    }
+    if !summary.upload_failed.is_empty() {
+        warn!(
+            "{}",
+            tr!("Not uploaded: {}", summary.upload_failed.join(", "))
+        );
+    }
+    if !summary.quarantined.is_empty() {
+        warn!("{}", tr!("Quarantined: {}", summary.quarantined.join(", ")));
+    }
+    Ok(summary)
 }
 
-fn main() -> Result<(), error::Error> {
+/// Join all parts of a group into one video, see `concat`
+fn convert_group(
+    dirs: &dirs::Dirs,
+    group: &str,
+    options: &ConvertOptions,
+) -> Result<Summary, error::Error> {
+    check_environment(options)?;
+    let source_path = &dirs.source;
+    let target_path = prepare_output_directory(dirs)?;
+    signal::install();
+    let file = concat::convert(source_path, &target_path, group, options)?;
+    info!("Joined into {}", file.display());
+    output::emit(
+        "joined",
+        &serde_json::json!({ "group": group, "output": file }),
+    )?;
+    let summary = Summary {
+        converted: 1,
+        ..Summary::default()
+    };
+    output::emit("summary", &summary)?;
+    Ok(summary)
+}
 
+fn convert_options(args: &Args) -> Result<ConvertOptions, error::Error> {
+    let log = match &args.log_file {
+        Some(path) => Some(runlog::RunLog::open(path, args.log_format)?),
+        None => None,
+    };
+    let extra_args = match &args.ffmpeg_args {
+        Some(line) => ffmpeg::split_args(line)?,
+        None => Vec::new(),
+    };
+    let io_limit = match args.io_limit {
+        Some(limit) if limit > 0.0 => Some((limit * 1_000_000.0) as u64),
+        Some(_) => return Err(error::Error::InvalidArgument),
+        None => None,
+    };
+    let mut ffmpeg = ffmpeg::Ffmpeg::new(args.ffmpeg_path.clone(), extra_args);
+    ffmpeg.low_priority = args.low_priority;
+    ffmpeg.threads = args.threads;
+    ffmpeg.timeout = args.timeout.map(Duration::from_secs);
+    Ok(ConvertOptions {
+        permanent: args.permanent,
+        order: args.order,
+        ffmpeg,
+        quality: args.prefer_quality,
+        profile: args.profile,
+        fallback_profile: args.fallback_profile,
+        compat: args.compat_target,
+        max_size: args.max_size,
+        faststart: !args.no_faststart,
+        burn_danmaku: args.burn_danmaku,
+        mtime: args.set_mtime,
+        log,
+        autoremove: args.autoremove,
+        restart: args.restart,
+        layout: layout::Layout {
+            organize: args.organize,
+            template: args.name_template.clone(),
+            episodes: if args.episode_patterns.is_empty() {
+                episode::default_patterns()
+            } else {
+                args.episode_patterns.clone()
+            },
+            uploaders: uploaders::Uploaders::load(args.uploaders.as_deref())?,
+            container: args.container,
+            sanitizer: sanitize::Sanitizer::new(
+                args.replace_char,
+                args.max_name_length,
+                args.ascii_names,
+            )?,
+        },
+        thumbnails: args.thumbnails.clone(),
+        thumbnail_grid: args.thumbnail_grid,
+        covers: !args.no_covers,
+        cover: cover::Conversion::new(args.cover_format, args.cover_max),
+        group_cover: args.group_cover_name,
+        original_cover_names: args.original_cover_names,
+        nfo: args.nfo,
+        exclude: args.exclude.clone(),
+        playlists: args.playlists,
+        quarantine: args
+            .quarantine_dir
+            .clone()
+            .map(|dir| quarantine::Quarantine {
+                dir,
+                after: args.quarantine_after,
+            }),
+        retries: args.retries,
+        work_dir: args.work_dir.clone().unwrap_or_else(env::temp_dir),
+        io_limit,
+        hooks: hooks::Hooks {
+            on_success: args.on_success.clone(),
+            on_failure: args.on_failure.clone(),
+            webhook: args.webhook.clone(),
+        },
+        upload: args.upload_to.clone(),
+        remove_uploaded: args.remove_uploaded,
+        rclone_remote: args.rclone_remote.clone(),
+        muxer: None,
+        clock: state::now,
+        backup: None,
+        timings: eta::Timings::default(),
+    })
+}
+
+fn main() -> ExitCode {
     let args = Args::parse();
+    i18n::init(args.lang);
+    output::init(args.format);
+    runner::show_commands(args.show_commands);
+    if args.no_mmap {
+        mmap::disable();
+    }
 
-    let log_level = match args.verbose {
-        true => LevelFilter::Debug,
-        false =>  LevelFilter::Info,
+    // Warnings only by default, interactive runs show a progress line instead
+    let log_level = match (args.quiet, args.verbose) {
+        (true, _) => LevelFilter::Error,
+        (false, 0) => LevelFilter::Warn,
+        (false, 1) => LevelFilter::Info,
+        (false, 2) => LevelFilter::Debug,
+        (false, _) => LevelFilter::Trace,
     };
 
     let mut builder = env_logger::Builder::new();
     builder.filter_level(log_level).init();
 
-    let home = env::var("HOME").expect("Unable to get home directory");
-    
-    debug!("Home: {}", home);
+    match run(args) {
+        Ok(summary)
+            if summary.failed > 0
+                || !summary.remote_failed.is_empty()
+                || !summary.upload_failed.is_empty() =>
+        {
+            ExitCode::from(EXIT_ITEMS_FAILED)
+        }
+        Ok(_) => ExitCode::SUCCESS,
+        Err(error::Error::Interrupted) => ExitCode::from(EXIT_INTERRUPTED),
+        Err(e) => {
+            let _ = output::emit("error", &serde_json::json!({ "message": e.to_string() }));
+            eprintln!("{}", tr!("Error: {}", e));
+            ExitCode::from(EXIT_SETUP_FAILED)
+        }
+    }
+}
+
+// SESSDATA from the command line, the environment or the stored login
+fn login_cookie(sessdata: &Option<String>) -> Option<String> {
+    sessdata
+        .clone()
+        .or_else(|| env::var("BILIBILI_SESSDATA").ok())
+        .or_else(|| auth::load().map(|c| c.sessdata))
+}
+
+fn login(sessdata: &Option<String>, bili_jct: &Option<String>) -> Result<(), error::Error> {
+    let credentials = match sessdata {
+        Some(sessdata) => auth::Credentials {
+            sessdata: sessdata.clone(),
+            bili_jct: bili_jct.clone(),
+        },
+        None => auth::qr_login()?,
+    };
+    let nav =
+        api::nav(&credentials.sessdata).map_err(|e| error::Error::LoginFailed(e.to_string()))?;
+    if !nav.is_login {
+        return Err(error::Error::LoginFailed(
+            "the cookie is not logged in".to_string(),
+        ));
+    }
+    auth::store(&credentials)?;
+    println!("{}", tr!("Logged in as {}", nav.uname));
+    Ok(())
+}
+
+/// Play the recorded output of the converted `item` with `player`
+fn play_output(target_path: &Path, item: &str, player: Option<&str>) -> Result<(), error::Error> {
+    let player = match player {
+        Some(line) => ffmpeg::split_args(line)?,
+        None => Vec::new(),
+    };
+    let db = state::StateDb::load(target_path)?;
+    match db.output(item).filter(|file| file.is_file()) {
+        Some(file) => player::play(&player, &file),
+        None => {
+            warn!("No output of {} to play", item);
+            Ok(())
+        }
+    }
+}
+
+// Directories a command changes, which other runs must not change meanwhile
+fn locked_dirs<'a>(command: &Commands, dirs: &'a dirs::Dirs) -> Vec<&'a Path> {
+    let (source, target) = (dirs.source.as_path(), dirs.target.as_path());
+    // A backup is only read
+    let source = Some(source).filter(|path| !backup::is_backup(path));
+    let dirs = match command {
+        Commands::Convert { dry_run: true, .. }
+        | Commands::Clean { dry_run: true, .. }
+        | Commands::Restore { list: true, .. }
+        | Commands::Doctor { fix: false } => vec![],
+        Commands::Convert { .. }
+        | Commands::Clean { .. }
+        | Commands::Doctor { .. }
+        | Commands::Stats { pick: true, .. }
+        | Commands::Tui
+        | Commands::Serve { .. }
+        | Commands::Sync { .. } => vec![source, Some(target)],
+        Commands::Export { .. } | Commands::Restore { .. } => vec![source],
+        Commands::Tag { .. }
+        | Commands::Index { .. }
+        | Commands::Download { .. }
+        | Commands::SyncFavorites { .. }
+        | Commands::ArchiveUp { .. }
+        | Commands::Upload { .. } => vec![Some(target)],
+        Commands::List { .. }
+        | Commands::Stats { .. }
+        | Commands::Verify { .. }
+        | Commands::Bench { .. }
+        | Commands::Info { .. }
+        | Commands::Open { .. }
+        | Commands::Search { .. }
+        | Commands::Login { .. }
+        | Commands::Logout
+        | Commands::InstallService { .. }
+        | Commands::Completions { .. }
+        | Commands::CompleteItems => vec![],
+    };
+    dirs.into_iter().flatten().collect()
+}
+
+/// Run the selected subcommand, item failures are reported in the summary
+fn run(args: Args) -> Result<Summary, error::Error> {
+    let home = env::home_dir().ok_or(error::Error::HomeNotFound)?;
+
+    debug!("Home: {}", home.display());
+    let dirs = dirs::Dirs::resolve(&home, args.cache_dir.clone());
     debug!("autoremove: {}", args.autoremove);
+    debug!("permanent: {}", args.permanent);
     debug!("no overwrite: {}", args.no_overwrite);
-    
-    let source_path = Path::new(&home).join(DEFAULT_SOURCE_DIR);
+    debug!("restart: {}", args.restart);
+
+    let source_path = dirs.source.clone();
     debug!("Source directory: {}", source_path.display());
 
-    match args.command {
-        Commands::List => {
-            show_video_list(&source_path)
-        },
-        Commands::Convert { item } => {
-            convert_video(&home, item, args.autoremove)
-        },
+    let command_line = env::args().collect::<Vec<_>>().join(" ");
+    let _locks = lock::acquire_all(
+        &locked_dirs(&args.command, &dirs),
+        &command_line,
+        args.wait_lock,
+        args.force_unlock,
+    )?;
+
+    let result = match args.command {
+        Commands::List {
+            sort,
+            reverse,
+            ref columns,
+            bytes,
+            ref tag,
+            status,
+        } => {
+            let filter = list::Filter {
+                tag: tag.as_deref(),
+                status,
+            };
+            show_video_list(
+                &dirs,
+                sort,
+                reverse,
+                columns,
+                bytes,
+                &filter,
+                args.prefer_quality,
+            )
+        }
+        Commands::Convert {
+            ref item,
+            ref title,
+            ref tag,
+            ref concat,
+            dry_run,
+            play,
+        } => {
+            let mut options = convert_options(&args)?;
+            if backup::is_backup(&source_path) {
+                if options.autoremove {
+                    warn!("Ignoring --autoremove, items of a backup are not removed from it");
+                    options.autoremove = false;
+                }
+                options.backup = Some(backup::Backup::open(&source_path)?);
+            }
+            let selected = match (title, tag) {
+                (Some(title), _) => vec![select::by_title(&source_path, title)?],
+                (_, Some(label)) => tagged_items(&source_path, &dirs.target, label)?,
+                _ => item.iter().cloned().collect(),
+            };
+            if dry_run {
+                return estimate::dry_run(
+                    item_jobs(&dirs, &selected, &options)?,
+                    &dirs.target,
+                    &options,
+                )
+                .map(|_| Summary::default());
+            }
+            // --play requires a single item
+            let play = selected.first().cloned().filter(|_| play);
+            let result = match concat {
+                Some(group) => convert_group(&dirs, group, &options),
+                None => convert_video(&dirs, selected, &options),
+            };
+            if args.notify {
+                notify::batch(&result);
+            }
+            if let (Some(item), Ok(summary)) = (&play, &result) {
+                // A missing player does not fail the conversion
+                if summary.failed == 0 {
+                    if let Err(e) = play_output(&dirs.target, item, args.player.as_deref()) {
+                        error!("Failed to play {}: {}", item, e);
+                    }
+                }
+            }
+            return result;
+        }
         // this is danger and should need a confirmation
-        Commands::Clean { item } => {
-            clean_cached_video(&source_path, item)
+        Commands::Clean {
+            keep_under,
+            older_than,
+            strategy,
+            all,
+            dry_run,
+            ..
+        } if keep_under.is_some() || older_than.is_some() => {
+            let options = convert_options(&args)?;
+            let target_path = dirs.target.clone();
+            let policy = evict::Policy {
+                keep_under,
+                older_than,
+                strategy,
+                all,
+            };
+            evict::run(&source_path, &target_path, &policy, dry_run, &options)
+        }
+        Commands::Clean { ref item, .. } => {
+            clean_cached_video(&source_path, item.clone(), args.permanent, &args.exclude)
+        }
+        Commands::Tag {
+            ref item,
+            ref label,
+            remove,
+        } => tag_item(&source_path, &dirs.target, item, label, remove),
+        Commands::Index { ref action } => index::run(&dirs.target, action),
+        Commands::Export {
+            ref item,
+            all,
+            ref to,
+        } => {
+            let videos = if all {
+                get_video_list(&source_path)?
+            } else {
+                item.iter()
+                    .map(|item| get_cached_video(&item_path(&source_path, item)))
+                    .collect::<Result<_, _>>()?
+            };
+            backup::export(&source_path, &videos, to)
+        }
+        Commands::Restore {
+            ref archive,
+            list: true,
+            ..
+        } => {
+            for entry in backup::list(archive)? {
+                println!(
+                    "{}\t{}\t{} - {}",
+                    entry.item,
+                    disk::human_size(entry.size),
+                    entry.uname,
+                    entry.title
+                );
+            }
+            Ok(())
+        }
+        Commands::Restore {
+            ref archive,
+            ref item,
+            ..
+        } => {
+            let restored = backup::restore(archive, &source_path, item)?;
+            println!("{}", tr!("Restored {} items", restored));
+            Ok(())
+        }
+        Commands::Info { ref item, hash } => {
+            let options = convert_options(&args)?;
+            let video = get_cached_video(&item_path(&source_path, item))?;
+            let target_path = dirs.target.clone();
+            info::show(&video, &target_path, hash, &options)
+        }
+        Commands::Search { ref query } => search::run(&source_path, &dirs.target, &query.join(" ")),
+        Commands::Open { ref item, reveal } => open::run(&dirs.target, item, reveal),
+        Commands::Stats {
+            json,
+            duplicates: true,
+            pick,
+        } => {
+            let target_path = dirs.target.clone();
+            let ignore = ignore::Ignore::load(&source_path, &args.exclude)?;
+            let mut videos = get_video_list(&source_path)?;
+            videos.retain(|video| !ignored(&ignore, &video.dir));
+            duplicates::show(&videos, &target_path, json, pick, args.permanent)
+        }
+        Commands::Stats { json, .. } => {
+            let options = convert_options(&args)?;
+            let target_path = dirs.target.clone();
+            stats::show(&get_video_list(&source_path)?, &target_path, json, &options)
+        }
+        Commands::Verify {
+            ref item,
+            deep,
+            tolerance,
+        } => {
+            let options = convert_options(&args)?;
+            check_environment(&options)?;
+            let target_path = dirs.target.clone();
+            return verify::run(&source_path, &target_path, item, deep, tolerance, &options);
+        }
+        Commands::Doctor { fix } => {
+            let options = convert_options(&args)?;
+            let target_path = dirs.target.clone();
+            doctor::run(&source_path, &target_path, fix, &options)
+        }
+        Commands::Bench { size } => {
+            let options = convert_options(&args)?;
+            check_environment(&options)?;
+            let target_path = prepare_output_directory(&dirs)?;
+            bench::run(&source_path, &target_path, size, &options)
+        }
+        Commands::Tui => {
+            let options = convert_options(&args)?;
+            tui::run(&dirs, &source_path, &options)
+        }
+        Commands::Serve {
+            ref listen,
+            interval,
+        } => {
+            let options = convert_options(&args)?;
+            check_environment(&options)?;
+            let target_path = prepare_output_directory(&dirs)?;
+            serve::run(&dirs, &source_path, &target_path, listen, interval, options)
+        }
+        Commands::Download {
+            ref id,
+            ref pages,
+            ref sessdata,
+        } => {
+            let options = convert_options(&args)?;
+            check_environment(&options)?;
+            let target_path = prepare_output_directory(&dirs)?;
+            let sessdata = login_cookie(sessdata);
+            return download::run(&target_path, id, pages, sessdata.as_deref(), &options);
+        }
+        Commands::SyncFavorites {
+            fid,
+            ref sessdata,
+            dry_run,
+        } => {
+            let options = convert_options(&args)?;
+            check_environment(&options)?;
+            let target_path = prepare_output_directory(&dirs)?;
+            let sessdata = login_cookie(sessdata);
+            return favorites::run(&target_path, fid, sessdata.as_deref(), dry_run, &options);
         }
+        Commands::ArchiveUp {
+            mid,
+            since,
+            until,
+            ref keywords,
+            ref sessdata,
+            dry_run,
+        } => {
+            let mut options = convert_options(&args)?;
+            options.layout.organize = layout::Organize::ByUp;
+            check_environment(&options)?;
+            let target_path = prepare_output_directory(&dirs)?;
+            let filter = archive_up::Filter {
+                since,
+                until,
+                keywords: keywords.clone(),
+            };
+            let sessdata = login_cookie(sessdata);
+            return archive_up::run(
+                &target_path,
+                mid,
+                &filter,
+                sessdata.as_deref(),
+                dry_run,
+                &options,
+            );
+        }
+        Commands::Login {
+            ref sessdata,
+            ref bili_jct,
+        } => login(sessdata, bili_jct),
+        Commands::Logout => {
+            if auth::remove()? {
+                println!("{}", tr!("Logged out"));
+            } else {
+                println!("{}", tr!("Not logged in"));
+            }
+            Ok(())
+        }
+        Commands::Sync { dry_run } => {
+            let options = convert_options(&args)?;
+            let target_path = dirs.target.clone();
+            return sync::run(&dirs, &source_path, &target_path, dry_run, &options);
+        }
+        Commands::InstallService {
+            serve,
+            interval,
+            print,
+        } => {
+            let service = service::Service::new("install-service", serve, interval)?;
+            service::install(&service, print)
+        }
+        Commands::Upload {
+            ref destination,
+            ref paths,
+        } => {
+            let target_path = dirs.target.clone();
+            upload_outputs(&target_path, destination, paths, args.remove_uploaded)
+        }
+        Commands::Completions { shell } => {
+            completions::print(&Args::command(), shell);
+            Ok(())
+        }
+        Commands::CompleteItems => completions::print_items(&source_path),
+    };
+    result.map(|_| Summary::default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::{self, Item, ScriptedRunner, StubMuxer, TempDir};
+
+    const PART: Item = Item {
+        item_id: 111,
+        uname: "UP",
+        group_title: "Group",
+        title: "Part",
+        p: 2,
+    };
+
+    #[test]
+    fn process_strips_muxes_and_dates_item() {
+        let dir = TempDir::new();
+        let item = PART.write(&dir.path().join("cache"));
+        let target = dir.path().join("output");
+        let muxer = StubMuxer::default();
+        let args = ["--set-mtime", "pubdate"];
+        let options = fixture::options(&args, &dir.path().join("work"), &muxer);
+
+        let output = process(&item, &target, &options).unwrap();
+        assert_eq!(output.file, target.join("UP - Group/2 Part/111.mp4"));
+        assert!(!output.reencoded);
+        assert_eq!(
+            muxer.jobs(),
+            [
+                "111-1-30080.m4s(ftyp) 111-1-30280.m4s(ftyp) maps=[] profile=Copy chapters=false \
+              tags=[title=Part artist=UP date=2023-11-14 album=Group track=2] -> 111.mp4.part"
+            ]
+        );
+        assert_eq!(
+            fixture::tree(&target),
+            [
+                "UP - Group/2 Part/111.mp4",
+                "UP - Group/2 Part/poster.jpg",
+                "UP - Group/2 Part/videoInfo.json",
+            ]
+        );
+        // Stripped temp files go with the work directory of the item
+        assert!(fixture::tree(&dir.path().join("work")).is_empty());
+        let pubdate = UNIX_EPOCH + Duration::from_secs(fixture::NOW as u64);
+        for path in [&output.file, &output.dir] {
+            assert_eq!(path.metadata().unwrap().modified().unwrap(), pubdate);
+        }
+    }
+
+    #[test]
+    fn process_fails_without_leaving_outputs() {
+        let dir = TempDir::new();
+        let cache = dir.path().join("cache");
+        let target = dir.path().join("output");
+        let work = dir.path().join("work");
+
+        // Copying fails and there is no fallback profile
+        let item = PART.write(&cache);
+        let muxer = StubMuxer::failing_copy();
+        let options = fixture::options(&[], &work, &muxer);
+        assert!(process(&item, &target, &options).is_err());
+        assert_eq!(muxer.jobs().len(), 1);
+
+        // DRM protected streams are not muxed at all
+        let muxer = StubMuxer::default();
+        let options = fixture::options(&[], &work, &muxer);
+        let video = item.join("111-1-30080.m4s");
+        let data = fs::read(&video).unwrap();
+        let at = data.windows(4).position(|w| w == b"hvc1").unwrap();
+        let data = [&data[..at], b"encv", &data[at + 4..]].concat();
+        fs::write(&video, data).unwrap();
+        assert!(matches!(
+            process(&item, &target, &options),
+            Err(error::Error::EncryptedContent(path)) if path == video
+        ));
+
+        fs::write(item.join(VIDEO_METADATA_FILE), "{").unwrap();
+        assert!(matches!(
+            process(&item, &target, &options),
+            Err(error::Error::SerdeJsonError(_))
+        ));
+        assert!(muxer.jobs().is_empty());
+        assert!(fixture::tree(&target).is_empty());
+        assert!(fixture::tree(&work).is_empty());
+    }
+
+    // ffmpeg with stdin closed, failing instead of asking whether an
+    // existing output may be overwritten
+    fn ffmpeg_answer(args: &[String]) -> Option<String> {
+        if args.iter().any(|a| a == "-show_entries") {
+            return Some(r#"{"streams":[{"index":0,"codec_type":"video"}]}"#.to_string());
+        }
+        if !args.iter().any(|a| a == "-i") {
+            return None;
+        }
+        let output = Path::new(args.last()?);
+        if output.exists() && !args.iter().any(|a| a == "-y") {
+            return None;
+        }
+        fs::write(output, "muxed").ok()?;
+        Some(String::new())
+    }
+
+    #[test]
+    fn process_overwrites_part_file_of_interrupted_run() {
+        let dir = TempDir::new();
+        let item = PART.write(&dir.path().join("cache"));
+        let target = dir.path().join("output");
+        let part = target.join("UP - Group/2 Part/111.mp4.part");
+        fs::create_dir_all(part.parent().unwrap()).unwrap();
+        fs::write(&part, "interrupted").unwrap();
+        let muxer = StubMuxer::default();
+        let mut options = fixture::options(&[], &dir.path().join("work"), &muxer);
+        options.muxer = None;
+        options.ffmpeg.runner = ScriptedRunner::new(ffmpeg_answer);
+
+        let output = process(&item, &target, &options).unwrap();
+        assert_eq!(fs::read_to_string(&output.file).unwrap(), "muxed");
+        assert!(!part.exists());
+    }
+
+    #[test]
+    fn process_reencodes_when_copy_fails_or_the_device_lacks_a_codec() {
+        let dir = TempDir::new();
+        let item = PART.write(&dir.path().join("cache"));
+        let work = dir.path().join("work");
+        let muxer = StubMuxer::failing_copy();
+        let options = fixture::options(&["--fallback-profile", "h264"], &work, &muxer);
+
+        let output = process(&item, &dir.path().join("output"), &options).unwrap();
+        assert!(output.reencoded);
+        let profiles: Vec<bool> = muxer
+            .jobs()
+            .iter()
+            .map(|job| job.contains("profile=H264"))
+            .collect();
+        assert_eq!(profiles, [false, true]);
+
+        // The cached streams are HEVC and AAC
+        let muxer = StubMuxer::default();
+        for (target, profile) in [("ios", "profile=Copy "), ("tv-h264", "profile=H264 ")] {
+            let options = fixture::options(&["--compat-target", target], &work, &muxer);
+            process(&item, &dir.path().join(target), &options).unwrap();
+            assert!(muxer.jobs().last().unwrap().contains(profile), "{}", target);
+        }
+    }
+
+    #[test]
+    fn process_reports_fragments_missing_from_the_cache() {
+        let dir = TempDir::new();
+        let item = PART.write(&dir.path().join("cache"));
+        let video = item.join("111-1-30080.m4s");
+        let mut data = fs::read(&video).unwrap();
+        data.extend(fixture::fragments(&[1, 2, 4]));
+        fs::write(&video, data).unwrap();
+        let muxer = StubMuxer::default();
+        let options = fixture::options(&[], &dir.path().join("work"), &muxer);
+
+        let output = process(&item, &dir.path().join("output"), &options).unwrap();
+        assert_eq!(output.gaps, ["111-1-30080.m4s: fragment 3 missing"]);
+    }
+
+    #[test]
+    fn strip_media_resumes_matching_partial_output() {
+        let dir = TempDir::new();
+        let item = PART.write(&dir.path().join("cache"));
+        let source = item.join("111-1-30080.m4s");
+        let stripped = fs::read(&source).unwrap()[SPECIAL_OFFSET as usize..].to_vec();
+        let output = dir.path().join("stripped.m4s");
+
+        fs::write(&output, &stripped[..20]).unwrap();
+        strip_media(&source, &output, None).unwrap();
+        assert_eq!(fs::read(&output).unwrap(), stripped);
+
+        // A partial output not matching the source is stripped again
+        fs::write(&output, [b'x'; 20]).unwrap();
+        strip_media(&source, &output, None).unwrap();
+        assert_eq!(fs::read(&output).unwrap(), stripped);
+    }
+
+    #[test]
+    fn convert_items_records_state_and_skips_converted() {
+        let dir = TempDir::new();
+        let cache = dir.path().join("cache");
+        let target = dir.path().join("output");
+        let broken = cache.join("333");
+        fs::create_dir_all(&broken).unwrap();
+        fs::create_dir_all(&target).unwrap();
+        let items = vec![
+            Item::single(222, "Single").write(&cache),
+            PART.write(&cache),
+            broken,
+        ];
+        let muxer = StubMuxer::default();
+        let options = fixture::options(&[], &dir.path().join("work"), &muxer);
+
+        let jobs = |items: &[PathBuf]| items.iter().map(|p| item_job(p, false)).collect();
+        let summary = convert_items(jobs(&items), &target, &options).unwrap();
+        assert_eq!(
+            (summary.converted, summary.failed, summary.skipped),
+            (2, 1, 0)
+        );
+        let state = fs::read_to_string(target.join(".bilibili-state.json")).unwrap();
+        let mut state: serde_json::Value = serde_json::from_str(&state).unwrap();
+        assert!(state["items"]["333"]["error"].take().is_string());
+        assert_eq!(
+            state,
+            serde_json::json!({
+                "items": {
+                    "111": {
+                        "status": "converted",
+                        "updated": fixture::NOW,
+                        "output": "UP - Group/2 Part/111.mp4",
+                    },
+                    "222": {
+                        "status": "converted",
+                        "updated": fixture::NOW,
+                        "output": "UP - Single/222.mp4",
+                    },
+                    "333": {
+                        "status": "failed",
+                        "error": null,
+                        "updated": fixture::NOW,
+                        "failures": 1,
+                    },
+                }
+            })
+        );
+
+        // Only the failed item is tried again
+        let summary = convert_items(jobs(&items), &target, &options).unwrap();
+        assert_eq!(
+            (summary.converted, summary.failed, summary.skipped),
+            (0, 1, 2)
+        );
+        assert_eq!(muxer.jobs().len(), 2);
+
+        // and moved aside when failing for the third time
+        let quarantine = dir.path().join("quarantine");
+        let args = ["--quarantine-dir", quarantine.to_str().unwrap()];
+        let options = fixture::options(&args, &dir.path().join("work"), &muxer);
+        let summary = convert_items(jobs(&items), &target, &options).unwrap();
+        assert_eq!(summary.quarantined, ["333"]);
+        assert!(!items[2].exists());
+        assert!(quarantine
+            .join("333")
+            .join(quarantine::REASON_FILE)
+            .is_file());
+        let db = state::StateDb::load(&target).unwrap();
+        assert_eq!(db.get("333").unwrap().status, state::Status::Quarantined);
+    }
+
+    #[test]
+    fn legacy_pages_are_named_by_their_video() {
+        let dir = TempDir::new();
+        let cache = dir.path().join("cache");
+        let target = dir.path().join("output");
+        fs::create_dir_all(&target).unwrap();
+        for (avid, cid) in [(100, 1001), (200, 2001)] {
+            let page = cache.join(avid.to_string()).join("1");
+            fs::create_dir_all(page.join("16")).unwrap();
+            fs::write(page.join("16/0.blv"), "flv").unwrap();
+            let entry = serde_json::json!({
+                "title": format!("Video {}", avid),
+                "owner_name": "UP",
+                "avid": avid,
+                "type_tag": "16",
+                "page_data": { "cid": cid, "page": 1 },
+            });
+            fs::write(page.join(legacy::ENTRY_FILE), entry.to_string()).unwrap();
+        }
+
+        let mut items: Vec<String> = get_video_list(&cache)
+            .unwrap()
+            .iter()
+            .map(|video| item_name(&video.dir))
+            .collect();
+        items.sort();
+        assert_eq!(items, ["100/1", "200/1"]);
+        assert_eq!(item_path(&cache, "200/1"), cache.join("200/1"));
+        // as named by earlier versions
+        assert!(legacy::is_item(&item_path(&cache, "1")));
+
+        let muxer = StubMuxer::default();
+        let options = fixture::options(&[], &dir.path().join("work"), &muxer);
+        let jobs = ["100", "200"]
+            .iter()
+            .map(|avid| item_job(&cache.join(avid).join("1"), false))
+            .collect();
+        let summary = convert_items(jobs, &target, &options).unwrap();
+        assert_eq!(summary.converted, 2);
+        let db = state::StateDb::load(&target).unwrap();
+        assert_eq!(db.get("100/1").unwrap().status, state::Status::Converted);
+        assert_eq!(db.get("200/1").unwrap().status, state::Status::Converted);
+    }
+
+    #[test]
+    fn failed_upload_keeps_the_item_converted() {
+        let dir = TempDir::new();
+        let cache = dir.path().join("cache");
+        let target = dir.path().join("output");
+        fs::create_dir_all(&target).unwrap();
+        let item = Item::single(222, "Single").write(&cache);
+        // Nothing listens on a port just released
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let destination = format!("webdav://127.0.0.1:{}/videos", port);
+        let muxer = StubMuxer::default();
+        let args = ["--upload-to", &destination, "--remove-uploaded"];
+        let options = fixture::options(&args, &dir.path().join("work"), &muxer);
+
+        let jobs = vec![item_job(&item, false)];
+        let summary = convert_items(jobs, &target, &options).unwrap();
+        assert_eq!((summary.converted, summary.failed), (1, 0));
+        assert_eq!(summary.upload_failed.len(), 1);
+        assert!(summary.upload_failed[0].starts_with("222 ("));
+        // The output is kept, as it was never verified remotely
+        assert!(target.join("UP - Single/222.mp4").is_file());
+        let db = state::StateDb::load(&target).unwrap();
+        assert_eq!(db.get("222").unwrap().status, state::Status::Converted);
     }
 }
@@ -0,0 +1,210 @@
+/// Metrics of the daemon in the Prometheus text format
+///
+/// `serve` answers `GET /metrics` with counters of the items converted and
+/// failed and the bytes they took in the cache since the start, the depth of
+/// the queue, how long the current item has been converting and when the
+/// last scan and conversion finished, so an alert can fire when the
+/// archiver is stuck. Every ffmpeg run is timed through a runner wrapping
+/// the one of `Ffmpeg`, into a histogram of durations.
+use std::fmt::Write;
+use std::io;
+use std::process::{Command, Output};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::error;
+use crate::runner::CommandRunner;
+
+/// Upper bounds of the ffmpeg duration buckets, in seconds
+const BUCKETS: [f64; 8] = [1.0, 5.0, 15.0, 60.0, 300.0, 900.0, 1800.0, 3600.0];
+
+#[derive(Debug, Default)]
+struct Histogram {
+    counts: [u64; BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+/// Durations of ffmpeg runs
+#[derive(Debug, Default)]
+pub struct Durations(Mutex<Histogram>);
+
+impl Durations {
+    pub fn observe(&self, elapsed: Duration) {
+        let secs = elapsed.as_secs_f64();
+        let mut histogram = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        for (bound, count) in BUCKETS.iter().zip(histogram.counts.iter_mut()) {
+            if secs <= *bound {
+                *count += 1;
+            }
+        }
+        histogram.count += 1;
+        histogram.sum += secs;
+    }
+}
+
+/// Runs commands with `inner`, timing each run of ffmpeg
+#[derive(Debug)]
+pub struct TimedRunner {
+    pub inner: Arc<dyn CommandRunner>,
+    pub durations: Arc<Durations>,
+}
+
+impl CommandRunner for TimedRunner {
+    fn output(&self, cmd: &mut Command) -> io::Result<Output> {
+        self.inner.output(cmd)
+    }
+
+    fn run(&self, cmd: &mut Command, timeout: Option<Duration>) -> Result<(), error::Error> {
+        let start = Instant::now();
+        let result = self.inner.run(cmd, timeout);
+        self.durations.observe(start.elapsed());
+        result
+    }
+}
+
+/// State of the daemon when the metrics are asked for
+#[derive(Debug, Default)]
+pub struct Snapshot {
+    pub converted: usize,
+    pub failed: usize,
+    /// Cache bytes of the converted items
+    pub bytes: u64,
+    pub queued: usize,
+    pub paused: bool,
+    /// Seconds the current item has been converting, None if idle
+    pub converting: Option<f64>,
+    /// Unix timestamps
+    pub last_scan: Option<i64>,
+    pub last_conversion: Option<i64>,
+}
+
+/// The metrics in the Prometheus text exposition format
+pub fn render(snapshot: &Snapshot, durations: &Durations) -> String {
+    let s = snapshot;
+    let mut metrics = vec![
+        (
+            "bilibili_items_converted_total",
+            "counter",
+            "Cache items converted since the start",
+            s.converted.to_string(),
+        ),
+        (
+            "bilibili_items_failed_total",
+            "counter",
+            "Cache items failing to convert since the start",
+            s.failed.to_string(),
+        ),
+        (
+            "bilibili_processed_bytes_total",
+            "counter",
+            "Cache bytes of the converted items",
+            s.bytes.to_string(),
+        ),
+        (
+            "bilibili_queue_depth",
+            "gauge",
+            "Items waiting to be converted",
+            s.queued.to_string(),
+        ),
+        (
+            "bilibili_paused",
+            "gauge",
+            "Whether the queue is paused",
+            u8::from(s.paused).to_string(),
+        ),
+        (
+            "bilibili_converting_seconds",
+            "gauge",
+            "Seconds the current item has been converting, 0 if idle",
+            s.converting.unwrap_or_default().to_string(),
+        ),
+    ];
+    // Absent until it happened, so `absent()` or `time() - ...` alerts work
+    if let Some(time) = s.last_scan {
+        metrics.push((
+            "bilibili_last_scan_timestamp_seconds",
+            "gauge",
+            "When the cache was last scanned",
+            time.to_string(),
+        ));
+    }
+    if let Some(time) = s.last_conversion {
+        metrics.push((
+            "bilibili_last_conversion_timestamp_seconds",
+            "gauge",
+            "When an item was last converted",
+            time.to_string(),
+        ));
+    }
+    let mut out = String::new();
+    for (name, kind, help, value) in metrics {
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} {}", name, kind);
+        let _ = writeln!(out, "{} {}", name, value);
+    }
+
+    let histogram = durations.0.lock().unwrap_or_else(|e| e.into_inner());
+    let name = "bilibili_ffmpeg_duration_seconds";
+    let _ = writeln!(out, "# HELP {} Duration of ffmpeg runs", name);
+    let _ = writeln!(out, "# TYPE {} histogram", name);
+    for (bound, count) in BUCKETS.iter().zip(histogram.counts) {
+        let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, bound, count);
+    }
+    let _ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, histogram.count);
+    let _ = writeln!(out, "{}_sum {}", name, histogram.sum);
+    let _ = writeln!(out, "{}_count {}", name, histogram.count);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ffmpeg::Ffmpeg;
+    use crate::fixture::ScriptedRunner;
+    use std::path::PathBuf;
+
+    #[test]
+    fn renders_counters_and_ffmpeg_durations() {
+        let durations = Arc::new(Durations::default());
+        let mut ffmpeg = Ffmpeg::new(PathBuf::from("ffmpeg"), Vec::new());
+        ffmpeg.runner = Arc::new(TimedRunner {
+            inner: ScriptedRunner::new(|_| Some(String::new())),
+            durations: durations.clone(),
+        });
+        ffmpeg.run(ffmpeg.command()).unwrap();
+        durations.observe(Duration::from_secs(100));
+
+        let snapshot = Snapshot {
+            converted: 2,
+            failed: 1,
+            bytes: 4096,
+            queued: 3,
+            last_scan: Some(1_700_000_000),
+            ..Snapshot::default()
+        };
+        let text = render(&snapshot, &durations);
+        for line in [
+            "# TYPE bilibili_items_converted_total counter",
+            "bilibili_items_converted_total 2",
+            "bilibili_items_failed_total 1",
+            "bilibili_processed_bytes_total 4096",
+            "bilibili_queue_depth 3",
+            "bilibili_paused 0",
+            "bilibili_converting_seconds 0",
+            "bilibili_last_scan_timestamp_seconds 1700000000",
+            "bilibili_ffmpeg_duration_seconds_bucket{le=\"1\"} 1",
+            "bilibili_ffmpeg_duration_seconds_bucket{le=\"300\"} 2",
+            "bilibili_ffmpeg_duration_seconds_bucket{le=\"+Inf\"} 2",
+            "bilibili_ffmpeg_duration_seconds_count 2",
+        ] {
+            assert!(
+                text.lines().any(|l| l == line),
+                "{} missing from\n{}",
+                line,
+                text
+            );
+        }
+        assert!(!text.contains("bilibili_last_conversion_timestamp_seconds"));
+    }
+}
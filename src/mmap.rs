@@ -0,0 +1,105 @@
+/// Read-only memory maps of media files
+///
+/// Hashing or walking the boxes of a multi-GB segment through a map reads
+/// it straight from the page cache instead of copying it into buffers, and
+/// tells the kernel the pages can go once read. Maps are only taken of
+/// cache files and outputs, which nothing writes while they are read.
+/// `--no-mmap` falls back to plain reads, e.g. for network shares where a
+/// vanishing server would fault the process instead of failing a read.
+use std::fs::File;
+use std::io;
+use std::ops::Deref;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static DISABLED: AtomicBool = AtomicBool::new(false);
+
+/// Read files instead of mapping them for the rest of the run
+pub fn disable() {
+    DISABLED.store(true, Ordering::Relaxed);
+}
+
+#[cfg(unix)]
+mod ffi {
+    use std::os::raw::{c_int, c_void};
+
+    pub const PROT_READ: c_int = 1;
+    pub const MAP_PRIVATE: c_int = 2;
+    pub const MADV_SEQUENTIAL: c_int = 2;
+
+    extern "C" {
+        pub fn mmap(
+            addr: *mut c_void,
+            len: usize,
+            prot: c_int,
+            flags: c_int,
+            fd: c_int,
+            offset: i64,
+        ) -> *mut c_void;
+        pub fn munmap(addr: *mut c_void, len: usize) -> c_int;
+        pub fn madvise(addr: *mut c_void, len: usize, advice: c_int) -> c_int;
+    }
+}
+
+/// A file mapped into memory, unmapped when dropped
+pub struct Mapping {
+    ptr: *mut u8,
+    len: usize,
+}
+
+// The map is read-only and owned by this value
+unsafe impl Send for Mapping {}
+unsafe impl Sync for Mapping {}
+
+/// Map all of `file` for reading sequentially, None if maps are disabled,
+/// not available on this system or the file is empty
+#[cfg(unix)]
+pub fn map(file: &File) -> io::Result<Option<Mapping>> {
+    use std::os::unix::io::AsRawFd;
+
+    let len = file.metadata()?.len() as usize;
+    if DISABLED.load(Ordering::Relaxed) || len == 0 {
+        return Ok(None);
+    }
+    let ptr = unsafe {
+        ffi::mmap(
+            std::ptr::null_mut(),
+            len,
+            ffi::PROT_READ,
+            ffi::MAP_PRIVATE,
+            file.as_raw_fd(),
+            0,
+        )
+    };
+    // MAP_FAILED
+    if ptr as isize == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    // Only a hint, reading works without it
+    unsafe { ffi::madvise(ptr, len, ffi::MADV_SEQUENTIAL) };
+    Ok(Some(Mapping {
+        ptr: ptr.cast(),
+        len,
+    }))
+}
+
+#[cfg(not(unix))]
+pub fn map(_: &File) -> io::Result<Option<Mapping>> {
+    Ok(None)
+}
+
+impl Deref for Mapping {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        unsafe {
+            ffi::munmap(self.ptr.cast(), self.len);
+        }
+    }
+}
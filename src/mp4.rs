@@ -0,0 +1,334 @@
+/// Minimal ISO BMFF (mp4/m4s) box reading for cached segments
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+use crate::i18n::tr;
+use crate::mmap;
+use crate::{error, SPECIAL_OFFSET};
+
+// Sample entries and boxes that only appear in protected (CENC) streams
+const ENCRYPTION_BOXES: &[&[u8; 4]] = &[b"encv", b"enca", b"tenc", b"pssh", b"sinf"];
+
+// Upper bound of the init segment read into memory
+const MAX_INIT_SIZE: u64 = 4 * 1024 * 1024;
+
+// Upper bound of a fragment header read into memory, they hold sample tables only
+const MAX_MOOF_SIZE: u64 = 1024 * 1024;
+
+/// Format of the first track of a segment, as its sample description says
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SampleEntry {
+    /// Sample entry type, e.g. `hvc1` or `mp4a`
+    pub format: [u8; 4],
+    pub video: bool,
+    /// Picture size of video tracks, zero for audio
+    pub width: u16,
+    pub height: u16,
+}
+
+impl SampleEntry {
+    /// Common name of the codec, e.g. `HEVC`
+    pub fn codec(&self) -> String {
+        match &self.format {
+            b"avc1" | b"avc3" => "H.264",
+            b"hvc1" | b"hev1" => "HEVC",
+            b"av01" => "AV1",
+            b"vp09" => "VP9",
+            b"mp4a" => "AAC",
+            b"ac-3" => "AC-3",
+            b"ec-3" => "E-AC-3",
+            b"fLaC" => "FLAC",
+            b"Opus" => "Opus",
+            b"encv" | b"enca" => "encrypted",
+            other => return String::from_utf8_lossy(other).trim().to_string(),
+        }
+        .to_string()
+    }
+}
+
+pub struct BoxHeader {
+    pub kind: [u8; 4],
+    pub offset: u64, // start of the box in the file
+    pub size: u64,   // including the header
+    pub header_size: u64,
+}
+
+/// Read the box header at the current position, `None` at end of file
+pub fn read_header(f: &mut File) -> Result<Option<BoxHeader>, error::Error> {
+    let offset = f.stream_position()?;
+    let mut header = [0u8; 8];
+    match f.read_exact(&mut header) {
+        Ok(_) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+    let mut size = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as u64;
+    let kind = [header[4], header[5], header[6], header[7]];
+    let mut header_size = 8;
+    if size == 1 {
+        let mut large = [0u8; 8];
+        f.read_exact(&mut large)?;
+        size = u64::from_be_bytes(large);
+        header_size = 16;
+    } else if size == 0 {
+        // Box extends to the end of the file
+        size = f.metadata()?.len() - offset;
+    }
+    if size < header_size {
+        return Err(error::Error::InvalidMediaFormat);
+    }
+    Ok(Some(BoxHeader {
+        kind,
+        offset,
+        size,
+        header_size,
+    }))
+}
+
+/// Open a cached segment positioned after the client's prefix bytes
+pub fn open_cached(path: &Path) -> Result<File, error::Error> {
+    let mut f =
+        File::open(path).map_err(|e| error::Error::MediaFileUnreadable(path.to_path_buf(), e))?;
+    f.seek(SeekFrom::Start(SPECIAL_OFFSET))?;
+    Ok(f)
+}
+
+// Content of the first top-level box of `kind` in mapped cache data
+fn find_top_level<'a>(data: &'a [u8], kind: &[u8; 4]) -> Result<Option<&'a [u8]>, error::Error> {
+    let mut offset = SPECIAL_OFFSET as usize;
+    while offset + 8 <= data.len() {
+        let field = |at: usize, len: usize| &data[offset + at..offset + at + len];
+        let mut size = u32::from_be_bytes(field(0, 4).try_into().unwrap()) as u64;
+        let mut header_size = 8;
+        if size == 1 {
+            if offset + 16 > data.len() {
+                return Err(error::Error::InvalidMediaFormat);
+            }
+            size = u64::from_be_bytes(field(8, 8).try_into().unwrap());
+            header_size = 16;
+        } else if size == 0 {
+            size = (data.len() - offset) as u64;
+        }
+        if size < header_size {
+            return Err(error::Error::InvalidMediaFormat);
+        }
+        let end = (offset as u64 + size).min(data.len() as u64) as usize;
+        if field(4, 4) == kind {
+            return Ok(Some(&data[offset + header_size as usize..end]));
+        }
+        offset = end;
+    }
+    Ok(None)
+}
+
+// Content of the first box of `kind` among the boxes filling `data`
+fn child<'a>(data: &'a [u8], kind: &[u8; 4]) -> Option<&'a [u8]> {
+    let mut offset = 0;
+    while offset + 8 <= data.len() {
+        let size = u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap()) as usize;
+        // Boxes of the init segment are small, 64-bit sizes only show up for media data
+        let end = if size == 0 { data.len() } else { offset + size };
+        if (size != 0 && size < 8) || end > data.len() {
+            return None;
+        }
+        if &data[offset + 4..offset + 8] == kind {
+            return Some(&data[offset + 8..end]);
+        }
+        offset = end;
+    }
+    None
+}
+
+/// Read the content of the first top-level box of the given kind
+pub fn read_top_level(path: &Path, kind: &[u8; 4]) -> Result<Option<Vec<u8>>, error::Error> {
+    let mut f = open_cached(path)?;
+    let unmapped = |e| error::Error::MediaFileUnreadable(path.to_path_buf(), e);
+    if let Some(mapping) = mmap::map(&f).map_err(unmapped)? {
+        let content = find_top_level(&mapping, kind)?;
+        return Ok(content.map(|c| c[..c.len().min(MAX_INIT_SIZE as usize)].to_vec()));
+    }
+    while let Some(header) = read_header(&mut f)? {
+        if &header.kind == kind {
+            let len = (header.size - header.header_size).min(MAX_INIT_SIZE);
+            let mut data = vec![0u8; len as usize];
+            f.read_exact(&mut data)?;
+            return Ok(Some(data));
+        }
+        f.seek(SeekFrom::Start(header.offset + header.size))?;
+    }
+    Ok(None)
+}
+
+/// Whether the init segment (`moov`) of a cached segment declares
+/// encrypted samples or carries DRM system headers.
+pub fn is_encrypted(path: &Path) -> Result<bool, error::Error> {
+    let moov = match read_top_level(path, b"moov")? {
+        Some(moov) => moov,
+        None => return Ok(false),
+    };
+    Ok(moov
+        .windows(4)
+        .any(|w| ENCRYPTION_BOXES.iter().any(|kind| w == kind.as_slice())))
+}
+
+/// Sample entry of the first track of a cached segment, None without one
+pub fn sample_entry(path: &Path) -> Result<Option<SampleEntry>, error::Error> {
+    let Some(moov) = read_top_level(path, b"moov")? else {
+        return Ok(None);
+    };
+    let entry = || {
+        let mdia = child(child(&moov, b"trak")?, b"mdia")?;
+        let hdlr = child(mdia, b"hdlr")?;
+        let video = hdlr.get(8..12)? == b"vide";
+        let stsd = child(child(child(mdia, b"minf")?, b"stbl")?, b"stsd")?;
+        // Version, flags and the entry count come before the first entry
+        let entry = stsd.get(8..)?;
+        let size = u32::from_be_bytes(entry.get(..4)?.try_into().ok()?) as usize;
+        let format: [u8; 4] = entry.get(4..8)?.try_into().ok()?;
+        let field = |at: usize| {
+            let at = 8 + at;
+            entry
+                .get(at..at + 2)
+                .filter(|_| at + 2 <= size)
+                .map(|b| u16::from_be_bytes([b[0], b[1]]))
+        };
+        // Visual entries hold the picture size after 24 bytes of other fields
+        let (width, height) = if video {
+            (field(24)?, field(26)?)
+        } else {
+            (0, 0)
+        };
+        Some(SampleEntry {
+            format,
+            video,
+            width,
+            height,
+        })
+    };
+    Ok(entry())
+}
+
+/// Fragments of a cached segment that are missing or cut off, as a download
+/// resumed at the wrong place leaves them
+#[derive(Debug, Default, PartialEq)]
+pub struct Continuity {
+    /// Sequence numbers of the fragments before and after each gap
+    pub gaps: Vec<(u32, u32)>,
+    /// Whether the last box ends past the end of the file
+    pub truncated: bool,
+}
+
+impl Continuity {
+    pub fn is_complete(&self) -> bool {
+        self.gaps.is_empty() && !self.truncated
+    }
+
+    /// What is wrong, e.g. `fragments 4-6 missing`
+    pub fn describe(&self) -> String {
+        let mut problems: Vec<String> = self
+            .gaps
+            .iter()
+            .map(|&(before, after)| match after.checked_sub(before) {
+                Some(2) => tr!("fragment {} missing", before + 1),
+                Some(n) if n > 2 => tr!("fragments {}-{} missing", before + 1, after - 1),
+                _ => tr!("fragment {} followed by {}", before, after),
+            })
+            .collect();
+        if self.truncated {
+            problems.push(tr!("cut off at the end").to_string());
+        }
+        problems.join(", ")
+    }
+}
+
+/// Check that the fragments (`moof`) of a cached segment are numbered
+/// without gaps by their `mfhd`, and that the file is not cut off
+pub fn continuity(path: &Path) -> Result<Continuity, error::Error> {
+    let mut f = open_cached(path)?;
+    let len = f.metadata()?.len();
+    let mut continuity = Continuity::default();
+    let mut last: Option<u32> = None;
+    while let Some(header) = read_header(&mut f)? {
+        let end = header.offset + header.size;
+        if end > len {
+            continuity.truncated = true;
+            break;
+        }
+        if &header.kind == b"moof" {
+            let size = (header.size - header.header_size).min(MAX_MOOF_SIZE);
+            let mut moof = vec![0u8; size as usize];
+            f.read_exact(&mut moof)?;
+            // Version and flags come before the sequence number
+            let sequence = child(&moof, b"mfhd")
+                .and_then(|mfhd| mfhd.get(4..8))
+                .map(|n| u32::from_be_bytes(n.try_into().unwrap()));
+            if let Some(sequence) = sequence {
+                if let Some(last) = last.filter(|&last| sequence != last.wrapping_add(1)) {
+                    continuity.gaps.push((last, sequence));
+                }
+                last = Some(sequence);
+            }
+        }
+        f.seek(SeekFrom::Start(end))?;
+    }
+    Ok(continuity)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::{fragments, Item, TempDir};
+
+    #[test]
+    fn reads_codec_and_size_from_init_segment() {
+        let dir = TempDir::new();
+        let item = Item::single(111, "Single").write(dir.path());
+
+        let video = sample_entry(&item.join("111-1-30080.m4s"))
+            .unwrap()
+            .unwrap();
+        assert!(video.video);
+        assert_eq!(
+            (video.codec(), video.width, video.height),
+            ("HEVC".to_string(), 1920, 1080)
+        );
+        let audio = sample_entry(&item.join("111-1-30280.m4s"))
+            .unwrap()
+            .unwrap();
+        assert!(!audio.video);
+        assert_eq!((audio.codec(), audio.width), ("AAC".to_string(), 0));
+
+        let none = dir.path().join("none.m4s");
+        std::fs::write(&none, vec![b'0'; SPECIAL_OFFSET as usize]).unwrap();
+        assert_eq!(sample_entry(&none).unwrap(), None);
+    }
+
+    #[test]
+    fn finds_missing_and_cut_off_fragments() {
+        use std::io::Write;
+        let dir = TempDir::new();
+        let item = Item::single(111, "Single").write(dir.path());
+        let append = |file: &str, data: &[u8]| {
+            let mut f = std::fs::OpenOptions::new()
+                .append(true)
+                .open(item.join(file))
+                .unwrap();
+            f.write_all(data).unwrap();
+        };
+        append("111-1-30280.m4s", &fragments(&[1, 2, 3]));
+        let audio = continuity(&item.join("111-1-30280.m4s")).unwrap();
+        assert!(audio.is_complete(), "{:?}", audio);
+
+        append("111-1-30080.m4s", &fragments(&[1, 2, 5, 6, 8, 3]));
+        // An mdat announcing more than was written
+        append("111-1-30080.m4s", &[0, 0, 1, 0, b'm', b'd', b'a', b't', 0]);
+        let video = continuity(&item.join("111-1-30080.m4s")).unwrap();
+        assert_eq!(video.gaps, [(2, 5), (6, 8), (8, 3)]);
+        assert!(video.truncated);
+        assert_eq!(
+            video.describe(),
+            "fragments 3-4 missing, fragment 7 missing, fragment 8 followed by 3, cut off at the end"
+        );
+    }
+}
@@ -0,0 +1,92 @@
+/// Kodi style NFO files next to outputs, read by Jellyfin, Emby and Kodi
+///
+/// Parts with an episode number, see `episode`, are described as episodes
+/// of a show named after their group, so media servers list them as
+/// seasons of one show. Other videos are described as movies.
+use std::fs;
+use std::path::PathBuf;
+
+use chrono::DateTime;
+
+use crate::episode::Episode;
+use crate::error::{self, Context};
+use crate::layout::Output;
+use crate::VideoInfo;
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// NFO document of a video
+pub fn render(video_info: &VideoInfo, episode: Option<Episode>) -> String {
+    let date = DateTime::from_timestamp(video_info.pubdate, 0)
+        .map(|dt| dt.format("%Y-%m-%d").to_string())
+        .unwrap_or_default();
+    let mut fields = vec![("title", escape(&video_info.title))];
+    let root = match episode {
+        Some(episode) => {
+            fields.push(("showtitle", escape(&video_info.group_title)));
+            fields.push(("season", episode.season.to_string()));
+            fields.push(("episode", episode.episode.to_string()));
+            fields.push(("aired", date));
+            "episodedetails"
+        }
+        None => {
+            fields.push(("premiered", date));
+            "movie"
+        }
+    };
+    fields.push(("studio", escape(&video_info.uname)));
+
+    let mut nfo = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n");
+    nfo.push_str(&format!("<{}>\n", root));
+    for (name, value) in fields {
+        nfo.push_str(&format!("  <{0}>{1}</{0}>\n", name, value));
+    }
+    nfo.push_str(&format!(
+        "  <uniqueid type=\"bilibili\" default=\"true\">{}</uniqueid>\n",
+        video_info.item_id
+    ));
+    nfo.push_str(&format!("</{}>\n", root));
+    nfo
+}
+
+/// Where the NFO of `output` goes: next to the video, named like it
+pub fn path(output: &Output) -> PathBuf {
+    output.file.with_extension("nfo")
+}
+
+/// Write the NFO of a converted video
+pub fn write(
+    output: &Output,
+    video_info: &VideoInfo,
+    episode: Option<Episode>,
+) -> Result<(), error::Error> {
+    let path = path(output);
+    fs::write(&path, render(video_info, episode)).context("write", &path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn describes_episodes_of_a_show() {
+        let info = VideoInfo::parse(
+            r#"{"uname":"UP & Co","title":"第2话 <Finale>","groupTitle":"Show","pubdate":1700000000,"updateTime":0,"totalSize":0,"itemId":111,"coverPath":"","groupCoverPath":"","p":2}"#,
+        )
+        .unwrap();
+        let nfo = render(
+            &info,
+            Some(Episode {
+                season: 1,
+                episode: 2,
+            }),
+        );
+        assert!(nfo.contains("<episodedetails>\n  <title>第2话 &lt;Finale&gt;</title>\n  <showtitle>Show</showtitle>\n  <season>1</season>\n  <episode>2</episode>\n  <aired>2023-11-14</aired>\n  <studio>UP &amp; Co</studio>\n"), "{}", nfo);
+        assert!(render(&info, None).contains("<movie>\n  <title>"));
+    }
+}
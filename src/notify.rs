@@ -0,0 +1,76 @@
+/// Desktop notifications, sent through the tools every desktop already has
+/// (osascript on macOS, notify-send on Linux and BSDs, PowerShell on Windows)
+/// rather than linking against a notification library.
+use std::process::{Command, Stdio};
+
+use log::*;
+
+use crate::{error, Summary};
+
+const APP_NAME: &str = "bilibili";
+
+fn applescript_string(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn powershell_string(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "''"))
+}
+
+fn command(title: &str, message: &str) -> Command {
+    if cfg!(target_os = "macos") {
+        let mut cmd = Command::new("osascript");
+        cmd.arg("-e").arg(format!(
+            "display notification {} with title {}",
+            applescript_string(message),
+            applescript_string(title)
+        ));
+        cmd
+    } else if cfg!(windows) {
+        // A balloon tip from the tray works without registering an app id
+        let script = format!(
+            "Add-Type -AssemblyName System.Windows.Forms; \
+             $n = New-Object System.Windows.Forms.NotifyIcon; \
+             $n.Icon = [System.Drawing.SystemIcons]::Information; \
+             $n.Visible = $true; \
+             $n.ShowBalloonTip(10000, {}, {}, 'Info'); \
+             Start-Sleep -Seconds 10; $n.Dispose()",
+            powershell_string(title),
+            powershell_string(message)
+        );
+        let mut cmd = Command::new("powershell");
+        cmd.args(["-NoProfile", "-Command", &script]);
+        cmd
+    } else {
+        let mut cmd = Command::new("notify-send");
+        cmd.args(["--app-name", APP_NAME, title, message]);
+        cmd
+    }
+}
+
+/// Show a notification, failures are only logged as nobody may be watching
+pub fn send(title: &str, message: &str) {
+    let result = command(title, message)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status();
+    match result {
+        Ok(status) if status.success() => {}
+        Ok(status) => warn!("Notification failed: {}", status),
+        Err(e) => warn!("Unable to send notification: {}", e),
+    }
+}
+
+/// Notify about the outcome of a conversion batch
+pub fn batch(result: &Result<Summary, error::Error>) {
+    let message = match result {
+        Ok(summary) => format!(
+            "Converted {}, failed {}, skipped {}",
+            summary.converted, summary.failed, summary.skipped
+        ),
+        Err(error::Error::Interrupted) => "Interrupted, conversion state saved".to_string(),
+        Err(e) => format!("Conversion failed: {}", e),
+    };
+    send("Bilibili conversion finished", &message);
+}
@@ -0,0 +1,65 @@
+/// Opening the output of an item without looking for it
+///
+/// The output of a converted item is found through the conversion state, so
+/// `open <item>` works whatever the layout templates made of its path. It is
+/// opened with the default application of the system, or with `--reveal`
+/// its directory is shown in the file manager, with the file selected where
+/// the file manager can do that.
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use crate::error::{self, Context};
+use crate::state;
+
+/// Command opening `file` with the default application of the system
+pub fn system_opener(file: &Path) -> Command {
+    let mut cmd = if cfg!(target_os = "macos") {
+        Command::new("open")
+    } else if cfg!(windows) {
+        // The empty title keeps `start` from taking a quoted path for one
+        let mut cmd = Command::new("cmd");
+        cmd.args(["/C", "start", ""]);
+        cmd
+    } else {
+        Command::new("xdg-open")
+    };
+    cmd.arg(file);
+    cmd
+}
+
+/// Command showing `file` in the file manager
+fn reveal_command(file: &Path) -> Command {
+    if cfg!(target_os = "macos") {
+        let mut cmd = Command::new("open");
+        cmd.arg("-R").arg(file);
+        cmd
+    } else if cfg!(windows) {
+        let mut cmd = Command::new("explorer");
+        cmd.arg(format!("/select,{}", file.display()));
+        cmd
+    } else {
+        // xdg-open has no way to select a file, it opens the directory
+        system_opener(file.parent().unwrap_or(Path::new(".")))
+    }
+}
+
+/// Open the output of the converted `item`, or its directory with `reveal`
+pub fn run(target_path: &Path, item: &str, reveal: bool) -> Result<(), error::Error> {
+    let db = state::StateDb::load(target_path)?;
+    let file = db
+        .output(item)
+        .filter(|file| file.is_file())
+        .ok_or_else(|| error::Error::OutputNotFound(item.to_string()))?;
+    println!("{}", file.display());
+    let mut cmd = if reveal {
+        reveal_command(&file)
+    } else {
+        system_opener(&file)
+    };
+    cmd.stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .context("open", &file)?;
+    Ok(())
+}
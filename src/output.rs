@@ -0,0 +1,47 @@
+/// Machine readable console output for scripts and GUIs
+///
+/// With `--format json` commands print one JSON object per line on stdout
+/// instead of their text output, each tagged with an `event` field. Logs
+/// keep going to stderr in either format.
+use std::sync::OnceLock;
+
+use clap::ValueEnum;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::error;
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Default)]
+pub enum Format {
+    #[default]
+    Text,
+    Json,
+}
+
+static FORMAT: OnceLock<Format> = OnceLock::new();
+
+/// Select the output format for the rest of the run
+pub fn init(format: Format) {
+    let _ = FORMAT.set(format);
+}
+
+/// Whether JSON output was selected, text output must be skipped then
+pub fn json() -> bool {
+    FORMAT.get() == Some(&Format::Json)
+}
+
+/// Print `value` as a JSON line tagged as `event`, if JSON output was selected
+pub fn emit<T: Serialize>(event: &str, value: &T) -> Result<(), error::Error> {
+    if !json() {
+        return Ok(());
+    }
+    let value = match serde_json::to_value(value)? {
+        Value::Object(mut fields) => {
+            fields.insert("event".to_string(), Value::from(event));
+            Value::Object(fields)
+        }
+        other => serde_json::json!({ "event": event, "value": other }),
+    };
+    println!("{}", value);
+    Ok(())
+}
@@ -0,0 +1,93 @@
+/// Watching an output right after converting it
+///
+/// `convert <item> --play` starts `--player` on the output once the item is
+/// converted, or was already, with the file as its last argument. Without
+/// it mpv or VLC is used if one is in PATH, otherwise the application the
+/// system opens videos with. The player runs on its own and keeps running
+/// when bilibili exits.
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use log::*;
+
+use crate::error::{self, Context};
+use crate::open::system_opener;
+
+/// Players tried in this order when `--player` is not given
+const PLAYERS: [&str; 2] = ["mpv", "vlc"];
+
+// Executable `name` in one of the directories of `path`, like `which`
+fn find_program(name: &str, path: &std::ffi::OsStr) -> Option<PathBuf> {
+    let names = if cfg!(windows) {
+        vec![format!("{}.exe", name)]
+    } else {
+        vec![name.to_string()]
+    };
+    env::split_paths(path)
+        .flat_map(|dir| names.iter().map(move |name| dir.join(name)))
+        .find(|file| file.is_file())
+}
+
+/// Command playing `file` with the words of `--player`, or the first known
+/// player in `path`
+fn command(player: &[String], file: &Path, path: &std::ffi::OsStr) -> Command {
+    let mut cmd = match player.split_first() {
+        Some((program, args)) => {
+            let mut cmd = Command::new(program);
+            cmd.args(args);
+            cmd
+        }
+        None => match PLAYERS.iter().find_map(|name| find_program(name, path)) {
+            Some(program) => Command::new(program),
+            None => return system_opener(file),
+        },
+    };
+    cmd.arg(file);
+    cmd
+}
+
+/// Start playing `file` in the background with `player`, see `command`
+pub fn play(player: &[String], file: &Path) -> Result<(), error::Error> {
+    let mut cmd = command(player, file, &env::var_os("PATH").unwrap_or_default());
+    info!("Playing {}", file.display());
+    cmd.stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .context("start a player for", file)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::TempDir;
+    use crate::runner::command_line;
+    use std::fs;
+
+    #[test]
+    fn plays_with_the_configured_or_an_installed_player() {
+        let dir = TempDir::new();
+        let file = Path::new("/out/111.mp4");
+        let player = ["mpv".to_string(), "--fs".to_string()];
+        assert_eq!(
+            command_line(&command(&player, file, dir.path().as_os_str())),
+            "mpv --fs /out/111.mp4"
+        );
+
+        let bin = dir.path().join("bin");
+        fs::create_dir_all(&bin).unwrap();
+        let path = env::join_paths([dir.path().join("missing"), bin.clone()]).unwrap();
+        let vlc = bin.join(if cfg!(windows) { "vlc.exe" } else { "vlc" });
+        fs::write(&vlc, "").unwrap();
+        assert_eq!(
+            command_line(&command(&[], file, &path)),
+            format!("{} /out/111.mp4", vlc.display())
+        );
+
+        fs::remove_file(&vlc).unwrap();
+        let opener = command_line(&system_opener(file));
+        assert_eq!(command_line(&command(&[], file, &path)), opener);
+    }
+}
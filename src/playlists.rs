@@ -0,0 +1,106 @@
+/// M3U playlists of the archive, one per uploader and one per series
+///
+/// Outputs are found through `archive::scan`, so the playlists follow
+/// whatever layout was used. After a conversion only the playlists of the
+/// converted item are rewritten, and only if they changed.
+use std::fs;
+use std::path::{Component, Path};
+
+use log::*;
+
+use crate::archive::{self, Entry};
+use crate::error::Context;
+use crate::{error, ConvertOptions, VideoInfo};
+
+const PLAYLIST_DIR: &str = "Playlists";
+
+// Path of `file` as seen from the playlist directory, with forward slashes
+// so the playlists work on every platform
+fn relative(target_path: &Path, file: &Path) -> String {
+    let mut path = String::from("..");
+    for component in file.strip_prefix(target_path).unwrap_or(file).components() {
+        if let Component::Normal(name) = component {
+            path.push('/');
+            path.push_str(&name.to_string_lossy());
+        }
+    }
+    path
+}
+
+fn label(info: &VideoInfo) -> String {
+    if info.group_title != info.title {
+        format!("{} - {} - {}", info.uname, info.group_title, info.title)
+    } else {
+        format!("{} - {}", info.uname, info.title)
+    }
+}
+
+fn write(target_path: &Path, name: &str, entries: &[&Entry]) -> Result<(), error::Error> {
+    let mut content = String::from("#EXTM3U\n");
+    for entry in entries {
+        content.push_str(&format!(
+            "#EXTINF:-1,{}\n{}\n",
+            label(&entry.info),
+            relative(target_path, &entry.file)
+        ));
+    }
+    let path = target_path
+        .join(PLAYLIST_DIR)
+        .join(format!("{}.m3u8", name));
+    if fs::read_to_string(&path).is_ok_and(|existing| existing == content) {
+        return Ok(());
+    }
+    let dir = target_path.join(PLAYLIST_DIR);
+    fs::create_dir_all(&dir).context("create directory", &dir)?;
+    fs::write(&path, content).context("write", &path)?;
+    debug!("Wrote playlist {}", path.display());
+    Ok(())
+}
+
+fn rewrite(
+    target_path: &Path,
+    video_info: &VideoInfo,
+    options: &ConvertOptions,
+) -> Result<(), error::Error> {
+    let entries = archive::scan(target_path)?;
+    let sanitizer = &options.layout.sanitizer;
+
+    // Uploads in publishing order, parts of a series in their given order
+    let mut up: Vec<&Entry> = entries
+        .iter()
+        .filter(|e| e.info.uname == video_info.uname)
+        .collect();
+    up.sort_by_key(|e| (e.info.pubdate, e.info.group_title.clone(), e.info.p));
+    write(
+        target_path,
+        &sanitizer.name(&video_info.uname, &video_info.uploader_key()),
+        &up,
+    )?;
+
+    // Single videos are no series
+    if video_info.group_title != video_info.title {
+        let mut series: Vec<&Entry> = up
+            .into_iter()
+            .filter(|e| e.info.group_title == video_info.group_title)
+            .collect();
+        series.sort_by_key(|e| e.info.p);
+        let name = format!("{} - {}", video_info.uname, video_info.group_title);
+        write(
+            target_path,
+            &sanitizer.name(&name, &video_info.group_key()),
+            &series,
+        )?;
+    }
+    Ok(())
+}
+
+/// Rewrite the playlists of the uploader and the series of `video_info` if
+/// enabled, failures are only warned about as the video itself is fine
+pub fn update(target_path: &Path, video_info: &VideoInfo, options: &ConvertOptions) {
+    if !options.playlists {
+        return;
+    }
+    if let Err(e) = rewrite(target_path, video_info, options) {
+        warn!("Failed to update playlists: {}", e);
+    }
+}
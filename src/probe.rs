@@ -0,0 +1,124 @@
+/// Stream information of cached media via ffprobe
+use std::path::Path;
+use std::process::Command;
+
+use serde::Deserialize;
+
+use crate::error;
+use crate::ffmpeg::Ffmpeg;
+use crate::SPECIAL_OFFSET;
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Stream {
+    /// Index of the stream in its file
+    #[serde(default)]
+    pub index: u32,
+    pub codec_type: String,
+    pub codec_name: Option<String>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub bit_rate: Option<String>,
+    pub channels: Option<u32>,
+    pub duration: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ProbeOutput {
+    #[serde(default)]
+    streams: Vec<Stream>,
+}
+
+#[derive(Deserialize)]
+struct FormatOutput {
+    format: Format,
+}
+
+#[derive(Deserialize)]
+struct Format {
+    duration: Option<String>,
+}
+
+impl Stream {
+    /// Short description like `video h264 1920x1080` or `audio aac 2ch`
+    pub fn describe(&self) -> String {
+        let codec = self.codec_name.as_deref().unwrap_or("unknown");
+        match (self.width, self.height, self.channels) {
+            (Some(w), Some(h), _) => format!("{} {} {}x{}", self.codec_type, codec, w, h),
+            (_, _, Some(ch)) => format!("{} {} {}ch", self.codec_type, codec, ch),
+            _ => format!("{} {}", self.codec_type, codec),
+        }
+    }
+}
+
+/// Probe the streams of a cached m4s file, skipping the client's prefix bytes
+pub fn streams(ffmpeg: &Ffmpeg, media: &Path) -> Result<Vec<Stream>, error::Error> {
+    probe_streams(ffmpeg, media, SPECIAL_OFFSET)
+}
+
+/// Probe the streams of a converted output file
+pub fn output_streams(ffmpeg: &Ffmpeg, file: &Path) -> Result<Vec<Stream>, error::Error> {
+    probe_streams(ffmpeg, file, 0)
+}
+
+fn probe_streams(ffmpeg: &Ffmpeg, file: &Path, skip: u64) -> Result<Vec<Stream>, error::Error> {
+    // ffprobe -v error [-skip_initial_bytes 9] -show_entries stream=... -of json file
+    let mut cmd = Command::new(ffmpeg.ffprobe());
+    cmd.args(["-v", "error"]);
+    if skip > 0 {
+        cmd.arg("-skip_initial_bytes").arg(skip.to_string());
+    }
+    cmd.args([
+        "-show_entries",
+        "stream=index,codec_type,codec_name,width,height,bit_rate,channels,duration",
+        "-of",
+        "json",
+    ])
+    .arg(file);
+    let output = ffmpeg
+        .runner
+        .output(&mut cmd)
+        .map_err(|_| error::Error::CommandNotFound)?;
+    if !output.status.success() {
+        return Err(error::Error::ProbeFailed(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+    let probe: ProbeOutput = serde_json::from_slice(&output.stdout)?;
+    Ok(probe.streams)
+}
+
+/// Duration in seconds of a converted output file
+pub fn duration(ffmpeg: &Ffmpeg, file: &Path) -> Result<f64, error::Error> {
+    probe_duration(ffmpeg, file, 0)
+}
+
+/// Duration in seconds of a cached m4s file
+pub fn media_duration(ffmpeg: &Ffmpeg, media: &Path) -> Result<f64, error::Error> {
+    probe_duration(ffmpeg, media, SPECIAL_OFFSET)
+}
+
+fn probe_duration(ffmpeg: &Ffmpeg, file: &Path, skip: u64) -> Result<f64, error::Error> {
+    // ffprobe -v error [-skip_initial_bytes 9] -show_entries format=duration -of json file
+    let mut cmd = Command::new(ffmpeg.ffprobe());
+    cmd.args(["-v", "error"]);
+    if skip > 0 {
+        cmd.arg("-skip_initial_bytes").arg(skip.to_string());
+    }
+    cmd.args(["-show_entries", "format=duration", "-of", "json"])
+        .arg(file);
+    let output = ffmpeg
+        .runner
+        .output(&mut cmd)
+        .map_err(|_| error::Error::CommandNotFound)?;
+    if !output.status.success() {
+        return Err(error::Error::ProbeFailed(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+    let probe: FormatOutput = serde_json::from_slice(&output.stdout)?;
+    probe
+        .format
+        .duration
+        .and_then(|d| d.parse().ok())
+        .ok_or_else(|| error::Error::ProbeFailed(format!("no duration for {}", file.display())))
+}
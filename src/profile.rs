@@ -0,0 +1,50 @@
+/// Encoding profiles, from copying the cached streams as they are to
+/// re-encoding the video for players lacking the cached codec
+use clap::ValueEnum;
+use serde::Deserialize;
+
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum Profile {
+    /// Copy the streams without re-encoding, fast and lossless
+    #[default]
+    Copy,
+    /// Re-encode the video to H.264, playable almost everywhere
+    H264,
+    /// Re-encode the video to HEVC, smaller at the same quality
+    H265,
+}
+
+impl Profile {
+    /// Name of the profile as given to `--profile`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Profile::Copy => "copy",
+            Profile::H264 => "h264",
+            Profile::H265 => "h265",
+        }
+    }
+
+    /// ffmpeg encoder the profile needs, none for copying
+    pub fn video_encoder(&self) -> Option<&'static str> {
+        match self {
+            Profile::Copy => None,
+            Profile::H264 => Some("libx264"),
+            Profile::H265 => Some("libx265"),
+        }
+    }
+
+    /// Codec arguments of ffmpeg, audio is always copied
+    pub fn codec_args(&self) -> Vec<&'static str> {
+        match self {
+            Profile::Copy => vec!["-c", "copy"],
+            Profile::H264 => vec![
+                "-c:v", "libx264", "-crf", "23", "-preset", "medium", "-c:a", "copy",
+            ],
+            // The container adds the tag players need, see `Container::muxer_args`
+            Profile::H265 => vec![
+                "-c:v", "libx265", "-crf", "28", "-preset", "medium", "-c:a", "copy",
+            ],
+        }
+    }
+}
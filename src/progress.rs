@@ -0,0 +1,89 @@
+/// A progress line on stderr, the only output of interactive runs by default
+///
+/// It is only drawn when stderr is a terminal and neither JSON output nor
+/// info logs are enabled, so cron mails and log files never contain it.
+use std::io::{self, IsTerminal, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use log::LevelFilter;
+
+use crate::eta::{self, Eta};
+use crate::{output, tr};
+
+const BAR_WIDTH: usize = 24;
+const MAX_NAME: usize = 40;
+
+// Set while the full screen of `tui` owns the terminal
+static SUPPRESSED: AtomicBool = AtomicBool::new(false);
+
+/// Draw no progress lines until called with false again
+pub fn suppress(suppressed: bool) {
+    SUPPRESSED.store(suppressed, Ordering::Relaxed);
+}
+
+fn suppressed() -> bool {
+    SUPPRESSED.load(Ordering::Relaxed)
+}
+
+pub struct Progress {
+    total: usize,
+    done: usize,
+    enabled: bool,
+}
+
+impl Progress {
+    pub fn new(total: usize) -> Progress {
+        Progress {
+            total,
+            done: 0,
+            enabled: total > 0
+                && io::stderr().is_terminal()
+                && !output::json()
+                && log::max_level() <= LevelFilter::Warn,
+        }
+    }
+
+    /// Show that work on `item` started, expected to take as long as `eta`
+    pub fn start(&self, item: &str, eta: Eta) {
+        if !self.enabled || suppressed() {
+            return;
+        }
+        let filled = BAR_WIDTH * self.done / self.total;
+        let mut name: String = item.chars().take(MAX_NAME).collect();
+        if name.len() < item.len() {
+            name.push('…');
+        }
+        let remaining = match (eta.item, eta.batch) {
+            (Some(item), Some(batch)) if self.done + 1 < self.total => {
+                format!(
+                    " ({})",
+                    tr!("ETA {}, {} in total", eta::format(item), eta::format(batch))
+                )
+            }
+            (Some(item), _) => format!(" ({})", tr!("ETA {}", eta::format(item))),
+            (None, _) => String::new(),
+        };
+        eprint!(
+            "\r\x1b[K[{}{}] {}/{} {}{}",
+            "#".repeat(filled),
+            "-".repeat(BAR_WIDTH - filled),
+            self.done + 1,
+            self.total,
+            name,
+            remaining
+        );
+        let _ = io::stderr().flush();
+    }
+
+    /// Count the current item as done
+    pub fn advance(&mut self) {
+        self.done = (self.done + 1).min(self.total);
+    }
+
+    /// Replace the progress line by `message`
+    pub fn finish(&self, message: &str) {
+        if self.enabled && !suppressed() {
+            eprintln!("\r\x1b[K{}", message);
+        }
+    }
+}
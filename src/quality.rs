@@ -0,0 +1,276 @@
+/// Selection of one video and one audio stream from caches holding
+/// several qualities or audio languages.
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use log::*;
+
+use crate::error;
+use crate::ffmpeg::Ffmpeg;
+use crate::probe;
+
+/// Seconds an audio track may differ from the video to belong to it
+const MAX_DURATION_DIFFERENCE: f64 = 2.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Quality {
+    Highest,
+    Lowest,
+    /// Preferred vertical resolution, e.g. 1080
+    Height(u32),
+}
+
+impl FromStr for Quality {
+    type Err = String;
+
+    /// Accepts `highest`, `lowest`, `1080`, `1080p` or `1920x1080`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim().to_lowercase();
+        match s.as_str() {
+            "highest" => return Ok(Quality::Highest),
+            "lowest" => return Ok(Quality::Lowest),
+            _ => {}
+        }
+        let height = match s.split_once('x') {
+            Some((_, height)) => height,
+            None => s.trim_end_matches('p'),
+        };
+        height
+            .parse()
+            .map(Quality::Height)
+            .map_err(|_| format!("invalid quality '{}'", s))
+    }
+}
+
+struct Candidate {
+    path: PathBuf,
+    stream: probe::Stream,
+    size: u64,
+}
+
+impl Candidate {
+    fn height(&self) -> u32 {
+        self.stream.height.unwrap_or_default()
+    }
+
+    fn duration(&self) -> Option<f64> {
+        self.stream.duration.as_deref().and_then(|d| d.parse().ok())
+    }
+
+    fn bit_rate(&self) -> u64 {
+        // DASH segments often lack a stream bit rate, the file size is a fair proxy
+        self.stream
+            .bit_rate
+            .as_deref()
+            .and_then(|b| b.parse().ok())
+            .unwrap_or(self.size)
+    }
+}
+
+/// Pick the preferred of several video streams, ranked by the
+/// `(height, bit rate)` returned by `key`
+pub fn pick<T>(mut videos: Vec<T>, quality: Quality, key: impl Fn(&T) -> (u32, u64)) -> Option<T> {
+    videos.sort_by_key(|v| key(v));
+    match quality {
+        Quality::Highest => videos.pop(),
+        Quality::Lowest => videos.into_iter().next(),
+        Quality::Height(target) => {
+            // The best one not above the target, otherwise the smallest above it
+            let below = videos.iter().rposition(|v| key(v).0 <= target);
+            let index = below.unwrap_or(0);
+            if videos.is_empty() {
+                None
+            } else {
+                Some(videos.swap_remove(index))
+            }
+        }
+    }
+}
+
+fn pick_video(videos: Vec<Candidate>, quality: Quality) -> Option<Candidate> {
+    pick(videos, quality, |c| (c.height(), c.bit_rate()))
+}
+
+/// Media files to pass to ffmpeg, with the streams to take from them
+pub struct Selection {
+    pub files: Vec<PathBuf>,
+    /// `-map` specifiers, empty to leave the choice to ffmpeg
+    pub maps: Vec<String>,
+}
+
+impl Selection {
+    fn all(files: Vec<PathBuf>) -> Selection {
+        Selection {
+            files,
+            maps: Vec::new(),
+        }
+    }
+
+    fn add(&mut self, candidate: &Candidate) {
+        let file = match self.files.iter().position(|f| *f == candidate.path) {
+            Some(file) => file,
+            None => {
+                self.files.push(candidate.path.clone());
+                self.files.len() - 1
+            }
+        };
+        self.maps
+            .push(format!("{}:{}", file, candidate.stream.index));
+    }
+}
+
+// Streams with the same codec, length and bit rate are copies of the same
+// track, as in caches holding one audio track for every video quality
+fn fingerprint(candidate: &Candidate) -> (Option<String>, Option<i64>, u64) {
+    (
+        candidate.stream.codec_name.clone(),
+        candidate.duration().map(|d| (d * 10.0).round() as i64),
+        candidate.bit_rate(),
+    )
+}
+
+fn dedup(audios: Vec<Candidate>) -> Vec<Candidate> {
+    let mut unique: Vec<Candidate> = Vec::new();
+    for audio in audios {
+        match unique
+            .iter()
+            .find(|u| fingerprint(u) == fingerprint(&audio))
+        {
+            Some(u) => debug!(
+                "Audio of {} duplicates {}",
+                audio.path.display(),
+                u.path.display()
+            ),
+            None => unique.push(audio),
+        }
+    }
+    unique
+}
+
+// The best audio track, one as long as the video if there is any
+fn pick_audio(audios: Vec<Candidate>, video: Option<&Candidate>) -> Option<Candidate> {
+    let length = video.and_then(Candidate::duration);
+    let matches = |c: &Candidate| match (length, c.duration()) {
+        (Some(video), Some(audio)) => (video - audio).abs() <= MAX_DURATION_DIFFERENCE,
+        _ => true,
+    };
+    let (matching, others): (Vec<Candidate>, Vec<Candidate>) =
+        dedup(audios).into_iter().partition(matches);
+    let candidates = if matching.is_empty() {
+        if !others.is_empty() {
+            warn!("No audio track is as long as the video");
+        }
+        others
+    } else {
+        matching
+    };
+    candidates.into_iter().max_by_key(|c| c.bit_rate())
+}
+
+/// Pick the media files and streams to pass to ffmpeg: one video and one
+/// audio stream, even if a file holds both or several files hold the same
+/// audio. When probing is not possible every file is used, like before
+/// stream selection existed.
+pub fn select(
+    ffmpeg: &Ffmpeg,
+    media: Vec<PathBuf>,
+    quality: Quality,
+) -> Result<Selection, error::Error> {
+    if media.len() <= 1 {
+        return Ok(Selection::all(media));
+    }
+
+    let mut videos = Vec::new();
+    let mut audios = Vec::new();
+    for path in &media {
+        let streams = match probe::streams(ffmpeg, path) {
+            Ok(streams) => streams,
+            Err(e) => {
+                warn!(
+                    "Unable to probe {}, using all media files: {}",
+                    path.display(),
+                    e
+                );
+                return Ok(Selection::all(media));
+            }
+        };
+        let size = path.metadata().map(|m| m.len()).unwrap_or_default();
+        for stream in streams {
+            let candidate = Candidate {
+                path: path.clone(),
+                stream,
+                size,
+            };
+            match candidate.stream.codec_type.as_str() {
+                "video" => videos.push(candidate),
+                "audio" => audios.push(candidate),
+                _ => {}
+            }
+        }
+    }
+
+    let mut selection = Selection::all(Vec::new());
+    let video = pick_video(videos, quality);
+    if let Some(video) = &video {
+        info!(
+            "Selected video {} ({})",
+            video.path.display(),
+            video.stream.describe()
+        );
+        selection.add(video);
+    }
+    if let Some(audio) = pick_audio(audios, video.as_ref()) {
+        info!(
+            "Selected audio {} ({})",
+            audio.path.display(),
+            audio.stream.describe()
+        );
+        selection.add(&audio);
+    }
+    if !selection.files.is_empty() {
+        Ok(selection)
+    } else if media.len() == 2 {
+        // A plain video and audio pair works without probing, as before
+        Ok(Selection::all(media))
+    } else {
+        Err(error::Error::NoMediaStreams)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::ScriptedRunner;
+
+    // ffprobe of the video and two copies of the same audio track
+    fn probe(args: &[String]) -> Option<String> {
+        let stream = if args.last()?.ends_with("-30080.m4s") {
+            r#"{"index": 0, "codec_type": "video", "codec_name": "hevc", "width": 1920, "height": 1080, "duration": "60.0"}"#
+        } else {
+            r#"{"index": 0, "codec_type": "audio", "codec_name": "aac", "bit_rate": "128000", "channels": 2, "duration": "60.0"}"#
+        };
+        Some(format!(r#"{{"streams": [{}]}}"#, stream))
+    }
+
+    #[test]
+    fn selects_video_and_one_copy_of_audio() {
+        let runner = ScriptedRunner::new(probe);
+        let mut ffmpeg = Ffmpeg::new(PathBuf::from("ffmpeg"), Vec::new());
+        ffmpeg.runner = runner.clone();
+        let media: Vec<PathBuf> = ["1-30080.m4s", "1-30280.m4s", "2-30280.m4s"]
+            .iter()
+            .map(PathBuf::from)
+            .collect();
+
+        let selection = select(&ffmpeg, media.clone(), Quality::Highest).unwrap();
+        assert_eq!(selection.files, media[..2]);
+        assert_eq!(selection.maps, ["0:0", "1:0"]);
+        assert_eq!(runner.commands().len(), 3);
+        assert_eq!(
+            runner.commands()[0],
+            "ffprobe -v error -skip_initial_bytes 9 -show_entries \
+             stream=index,codec_type,codec_name,width,height,bit_rate,channels,duration \
+             -of json 1-30080.m4s"
+        );
+    }
+}
@@ -0,0 +1,89 @@
+/// Moving cache items that keep failing out of the way
+///
+/// With `--quarantine-dir`, an item whose conversion failed in
+/// `--quarantine-after` runs in a row is moved into that directory, with
+/// `.bilibili-quarantine.json` in it saying why, and recorded as quarantined,
+/// so routine runs stop trying it again. Moving it back into the cache tries
+/// it once more. On the file system of the cache items are simply moved, on
+/// another one they are copied there and removed from the cache after.
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+use crate::error::{self, Context};
+use crate::{share, trash};
+
+pub const REASON_FILE: &str = ".bilibili-quarantine.json";
+
+#[derive(Debug, Clone)]
+pub struct Quarantine {
+    pub dir: PathBuf,
+    /// Failed runs in a row after which an item is moved
+    pub after: u32,
+}
+
+#[derive(Serialize)]
+struct Reason<'a> {
+    item: &'a str,
+    error: &'a str,
+    failures: u32,
+    quarantined: String,
+}
+
+impl Quarantine {
+    /// Move the cache item `item` in `path`, which failed `failures` times
+    /// with `error` last, into the quarantine if it failed often enough,
+    /// returning where it went
+    pub fn admit(
+        &self,
+        item: &str,
+        path: &Path,
+        error: &str,
+        failures: u32,
+    ) -> Result<Option<PathBuf>, error::Error> {
+        if failures < self.after {
+            return Ok(None);
+        }
+        fs::create_dir_all(&self.dir).context("create directory", &self.dir)?;
+        let target = trash::unique_name(&self.dir, path)?;
+        share::rename(path, &target).context("move", path)?;
+        let reason = Reason {
+            item,
+            error,
+            failures,
+            quarantined: chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+        };
+        let file = target.join(REASON_FILE);
+        fs::write(&file, serde_json::to_string_pretty(&reason)?).context("write", &file)?;
+        Ok(Some(target))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::TempDir;
+
+    #[test]
+    fn moves_items_failing_too_often() {
+        let dir = TempDir::new();
+        let quarantine = Quarantine {
+            dir: dir.path().join("quarantine"),
+            after: 2,
+        };
+        let item = dir.path().join("cache/111");
+        fs::create_dir_all(&item).unwrap();
+        fs::create_dir_all(quarantine.dir.join("111")).unwrap();
+
+        assert_eq!(quarantine.admit("111", &item, "broken", 1).unwrap(), None);
+        assert!(item.is_dir());
+        let moved = quarantine.admit("111", &item, "broken", 2).unwrap();
+        assert_eq!(moved, Some(quarantine.dir.join("111.1")));
+        assert!(!item.exists());
+        let reason = fs::read_to_string(quarantine.dir.join("111.1").join(REASON_FILE)).unwrap();
+        let reason: serde_json::Value = serde_json::from_str(&reason).unwrap();
+        assert_eq!(reason["error"], "broken");
+        assert_eq!(reason["failures"], 2);
+    }
+}
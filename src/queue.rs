@@ -0,0 +1,214 @@
+/// The cache items of a run waiting to be converted
+///
+/// Items the user asked for, e.g. through `/convert` of `serve` or picked
+/// in `tui`, come first in the order they were asked for, the others
+/// follow `--order`. Once queued, items only change
+/// places when moved explicitly, so reordering one mid-run leaves the rest
+/// where they were. A paused queue keeps its items but hands none out until
+/// it is resumed; the item being converted finishes either way.
+use std::cmp::Ordering;
+use std::path::PathBuf;
+
+use clap::ValueEnum;
+
+/// Order in which cache items are converted
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Order {
+    Name,
+    Smallest,
+    Largest,
+    /// Earliest published first
+    Oldest,
+}
+
+/// A cache item waiting to be converted
+#[derive(Clone, Debug)]
+pub struct Job {
+    pub item: String,
+    pub path: PathBuf,
+    /// Requested by the user rather than found by a scan
+    pub selected: bool,
+    pub size: u64,
+    pub pubdate: i64,
+}
+
+#[derive(Debug)]
+pub struct Queue {
+    order: Order,
+    jobs: Vec<Job>,
+    paused: bool,
+}
+
+impl Queue {
+    pub fn new(order: Order) -> Self {
+        Queue {
+            order,
+            jobs: Vec::new(),
+            paused: false,
+        }
+    }
+
+    // Selected jobs first, as they were queued, then by the order, ties by
+    // path so an interrupted batch resumes in the same sequence
+    fn compare(&self, a: &Job, b: &Job) -> Ordering {
+        if a.selected || b.selected {
+            return b.selected.cmp(&a.selected);
+        }
+        let by_order = match self.order {
+            Order::Name => Ordering::Equal,
+            Order::Smallest => a.size.cmp(&b.size),
+            Order::Largest => b.size.cmp(&a.size),
+            Order::Oldest => a.pubdate.cmp(&b.pubdate),
+        };
+        by_order.then_with(|| a.path.cmp(&b.path))
+    }
+
+    /// Queue `job` before the first job it sorts before, false if its item
+    /// is queued already
+    pub fn push(&mut self, job: Job) -> bool {
+        if self.contains(&job.item) {
+            return false;
+        }
+        let index = self
+            .jobs
+            .iter()
+            .position(|queued| self.compare(&job, queued) == Ordering::Less)
+            .unwrap_or(self.jobs.len());
+        self.jobs.insert(index, job);
+        true
+    }
+
+    /// Mark a queued item as selected by the user, converting it after the
+    /// items selected before it, false if it is not queued
+    pub fn select(&mut self, item: &str) -> bool {
+        let Some(mut job) = self.remove(item) else {
+            return false;
+        };
+        job.selected = true;
+        let index = self
+            .jobs
+            .iter()
+            .take_while(|queued| queued.selected)
+            .count();
+        self.jobs.insert(index, job);
+        true
+    }
+
+    /// Move a queued item to `index`, or to the end if the queue is shorter,
+    /// false if it is not queued
+    pub fn move_to(&mut self, item: &str, index: usize) -> bool {
+        let Some(job) = self.remove(item) else {
+            return false;
+        };
+        self.jobs.insert(index.min(self.jobs.len()), job);
+        true
+    }
+
+    pub fn remove(&mut self, item: &str) -> Option<Job> {
+        let index = self.jobs.iter().position(|job| job.item == item)?;
+        Some(self.jobs.remove(index))
+    }
+
+    /// The job to convert next, None if the queue is empty or paused
+    pub fn pop(&mut self) -> Option<Job> {
+        if self.paused || self.jobs.is_empty() {
+            return None;
+        }
+        Some(self.jobs.remove(0))
+    }
+
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    pub fn resume(&mut self) {
+        self.paused = false;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn contains(&self, item: &str) -> bool {
+        self.jobs.iter().any(|job| job.item == item)
+    }
+
+    pub fn len(&self) -> usize {
+        self.jobs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.jobs.is_empty()
+    }
+
+    /// The waiting jobs, next first
+    pub fn jobs(&self) -> &[Job] {
+        &self.jobs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn job(item: &str, size: u64, pubdate: i64) -> Job {
+        Job {
+            item: item.to_string(),
+            path: PathBuf::from("cache").join(item),
+            selected: false,
+            size,
+            pubdate,
+        }
+    }
+
+    fn items(queue: &Queue) -> Vec<&str> {
+        queue.jobs().iter().map(|job| job.item.as_str()).collect()
+    }
+
+    #[test]
+    fn orders_selected_items_first() {
+        let mut queue = Queue::new(Order::Smallest);
+        queue.push(job("111", 30, 3));
+        queue.push(job("222", 10, 2));
+        queue.push(Job {
+            selected: true,
+            ..job("333", 20, 1)
+        });
+        queue.push(job("444", 10, 4));
+        queue.push(Job {
+            selected: true,
+            ..job("555", 10, 5)
+        });
+        assert!(!queue.push(job("111", 0, 0)));
+        assert_eq!(items(&queue), ["333", "555", "222", "444", "111"]);
+        assert!(queue.select("111"));
+        assert!(queue.select("444"));
+        assert!(!queue.select("666"));
+        assert_eq!(items(&queue), ["333", "555", "111", "444", "222"]);
+
+        let mut queue = Queue::new(Order::Oldest);
+        for job in [job("111", 30, 3), job("222", 10, 2), job("333", 20, 1)] {
+            queue.push(job);
+        }
+        assert_eq!(items(&queue), ["333", "222", "111"]);
+    }
+
+    #[test]
+    fn reorders_and_pauses() {
+        let mut queue = Queue::new(Order::Name);
+        for item in ["111", "222", "333", "444"] {
+            queue.push(job(item, 0, 0));
+        }
+        assert!(queue.move_to("111", 2));
+        assert!(queue.move_to("222", 10));
+        assert!(queue.select("444"));
+        assert!(!queue.move_to("555", 0));
+        assert_eq!(items(&queue), ["444", "333", "111", "222"]);
+
+        queue.pause();
+        assert!(queue.pop().is_none());
+        queue.resume();
+        assert_eq!(queue.pop().map(|job| job.item), Some("444".to_string()));
+        assert_eq!(queue.len(), 3);
+    }
+}
@@ -0,0 +1,80 @@
+/// Copying converted items to an rclone remote
+///
+/// Each file is copied with `rclone copyto`, which verifies sizes and
+/// checksums itself. Transient failures are retried with a growing delay.
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::Duration;
+
+use log::*;
+
+use crate::{error, layout, runner, upload};
+
+const ATTEMPTS: u32 = 3;
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+// `remote:path/relative` with forward slashes
+fn remote_file(remote: &str, relative: &Path) -> String {
+    let mut target = remote.trim_end_matches('/').to_string();
+    for component in relative.iter() {
+        if !target.ends_with(':') {
+            target.push('/');
+        }
+        target.push_str(&component.to_string_lossy());
+    }
+    target
+}
+
+fn copy(file: &Path, target: &str) -> Result<(), error::Error> {
+    let mut last_error = String::new();
+    for attempt in 1..=ATTEMPTS {
+        debug!(
+            "rclone copyto {} {} (attempt {})",
+            file.display(),
+            target,
+            attempt
+        );
+        let mut cmd = Command::new("rclone");
+        cmd.arg("copyto").arg(file).arg(target).stdin(Stdio::null());
+        runner::show(&cmd);
+        let output = cmd.output().map_err(|_| error::Error::CommandNotFound)?;
+        if output.status.success() {
+            return Ok(());
+        }
+        // rclone logs the reason on its last line
+        last_error = String::from_utf8_lossy(&output.stderr)
+            .lines()
+            .last()
+            .unwrap_or_default()
+            .trim()
+            .to_string();
+        if attempt < ATTEMPTS {
+            warn!(
+                "rclone failed for {}, retrying: {}",
+                file.display(),
+                last_error
+            );
+            thread::sleep(RETRY_DELAY * attempt);
+        }
+    }
+    Err(error::Error::RcloneFailed(last_error))
+}
+
+/// Copy a converted item with its side files to `remote`, mirroring the
+/// layout below `target_path`
+pub fn copy_output(
+    remote: &str,
+    target_path: &Path,
+    output: &layout::Output,
+) -> Result<(), error::Error> {
+    for file in upload::output_files(output)? {
+        let relative = file
+            .strip_prefix(target_path)
+            .map_err(|_| error::Error::InvalidFileName(file.clone()))?;
+        let target = remote_file(remote, relative);
+        info!("Copying {} to {}", file.display(), target);
+        copy(&file, &target)?;
+    }
+    Ok(())
+}
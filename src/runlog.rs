@@ -0,0 +1,82 @@
+/// Per-item records of a conversion run, written to `--log-file`
+/// independently of the console output.
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+
+use chrono::Utc;
+use clap::ValueEnum;
+use serde::Serialize;
+
+use crate::error;
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ItemRecord<'a> {
+    pub timestamp: String,
+    pub item: &'a str,
+    pub item_id: Option<u64>,
+    pub result: &'a str,
+    pub duration: f64, // seconds
+    pub bytes: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl<'a> ItemRecord<'a> {
+    pub fn new(item: &'a str, result: &'a str) -> ItemRecord<'a> {
+        ItemRecord {
+            timestamp: Utc::now().to_rfc3339(),
+            item,
+            item_id: None,
+            result,
+            duration: 0.0,
+            bytes: 0,
+            error: None,
+        }
+    }
+}
+
+pub struct RunLog {
+    file: File,
+    format: LogFormat,
+}
+
+impl RunLog {
+    /// Open the log file for appending, so consecutive runs accumulate
+    pub fn open(path: &Path, format: LogFormat) -> Result<RunLog, error::Error> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(RunLog { file, format })
+    }
+
+    pub fn record(&self, record: &ItemRecord) -> Result<(), error::Error> {
+        let line = match self.format {
+            LogFormat::Json => serde_json::to_string(record)?,
+            LogFormat::Text => format!(
+                "{} {} {} {} {:.1}s {}B{}",
+                record.timestamp,
+                record.item,
+                record
+                    .item_id
+                    .map(|id| id.to_string())
+                    .unwrap_or("-".into()),
+                record.result,
+                record.duration,
+                record.bytes,
+                record
+                    .error
+                    .as_ref()
+                    .map(|e| format!(" {}", e))
+                    .unwrap_or_default()
+            ),
+        };
+        // A single write per line keeps records intact when appending
+        (&self.file).write_all(format!("{}\n", line).as_bytes())?;
+        Ok(())
+    }
+}
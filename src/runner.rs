@@ -0,0 +1,132 @@
+/// Running ffmpeg and ffprobe through a replaceable runner
+///
+/// The system runner starts the processes. Tests give a runner answering
+/// from a script instead, so probing and muxing can be checked without
+/// ffmpeg installed, and see the command lines that would have run.
+///
+/// With `--show-commands` external commands are printed to stderr before
+/// they run, in a form that can be pasted into a shell to reproduce them.
+use std::fmt;
+use std::io;
+use std::process::{Command, Output};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::*;
+
+use crate::{error, signal};
+
+static SHOW_COMMANDS: AtomicBool = AtomicBool::new(false);
+
+/// Print external commands before running them for the rest of the run
+pub fn show_commands(enabled: bool) {
+    SHOW_COMMANDS.store(enabled, Ordering::Relaxed);
+}
+
+/// Print `cmd` if `--show-commands` was given. Commands with credentials in
+/// their arguments must not be shown.
+pub fn show(cmd: &Command) {
+    if SHOW_COMMANDS.load(Ordering::Relaxed) {
+        eprintln!("{}", command_line(cmd));
+    }
+}
+
+pub trait CommandRunner: Send + Sync + fmt::Debug {
+    /// Run `cmd` to completion, capturing its output
+    fn output(&self, cmd: &mut Command) -> io::Result<Output>;
+
+    /// Run `cmd` to completion, killing it if an interrupt arrives or it
+    /// exceeds `timeout`
+    fn run(&self, cmd: &mut Command, timeout: Option<Duration>) -> Result<(), error::Error>;
+}
+
+/// Runs commands as processes
+#[derive(Debug)]
+pub struct System;
+
+impl CommandRunner for System {
+    fn output(&self, cmd: &mut Command) -> io::Result<Output> {
+        trace!("Running {}", command_line(cmd));
+        show(cmd);
+        cmd.output()
+    }
+
+    fn run(&self, cmd: &mut Command, timeout: Option<Duration>) -> Result<(), error::Error> {
+        debug!("Running {}", command_line(cmd));
+        show(cmd);
+
+        // Poll the child instead of blocking so an interrupt can kill it
+        let start = Instant::now();
+        let mut child = cmd.spawn()?;
+        loop {
+            if let Some(status) = child.try_wait()? {
+                if !status.success() {
+                    return Err(error::Error::FfmpegFailed(status.code().unwrap_or(-1)));
+                }
+                return Ok(());
+            }
+            if signal::interrupted() {
+                warn!("Interrupted, stopping ffmpeg");
+                child.kill()?;
+                child.wait()?;
+                return Err(error::Error::Interrupted);
+            }
+            if let Some(timeout) = timeout.filter(|t| start.elapsed() > *t) {
+                warn!(
+                    "ffmpeg still running after {}s, killing it",
+                    timeout.as_secs()
+                );
+                child.kill()?;
+                child.wait()?;
+                return Err(error::Error::Timeout(timeout.as_secs()));
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+    }
+}
+
+fn quote(arg: &str) -> String {
+    let plain = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_alphanumeric() || "-_./:=+,@%".contains(c));
+    if plain {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
+
+/// `cmd` as it would be typed into a POSIX shell, with its arguments quoted
+/// where needed
+pub fn command_line(cmd: &Command) -> String {
+    let mut line = String::new();
+    if let Some(dir) = cmd.get_current_dir() {
+        line.push_str(&format!("cd {} && ", quote(&dir.to_string_lossy())));
+    }
+    let program = cmd.get_program().to_string_lossy();
+    let args = cmd.get_args().map(|a| a.to_string_lossy());
+    let words: Vec<String> = std::iter::once(program)
+        .chain(args)
+        .map(|w| quote(&w))
+        .collect();
+    line.push_str(&words.join(" "));
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quotes_arguments_for_the_shell() {
+        let mut cmd = Command::new("ffmpeg");
+        cmd.current_dir("/tmp/work dir");
+        cmd.args(["-i", "/out/It's 1.mp4", "-vf", "subtitles=danmaku.ass", ""]);
+        assert_eq!(
+            command_line(&cmd),
+            "cd '/tmp/work dir' && ffmpeg -i '/out/It'\\''s 1.mp4' -vf subtitles=danmaku.ass ''"
+        );
+    }
+}
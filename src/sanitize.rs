@@ -0,0 +1,410 @@
+/// Turn titles and names from video metadata into safe path components.
+use crate::error;
+
+// Characters not allowed in file names on at least one of the supported platforms
+const ILLEGAL_CHARS: &[char] = &['/', '\\', ':', '*', '?', '"', '<', '>', '|'];
+
+// Device names Windows refuses to use as a file name, regardless of extension
+const WINDOWS_RESERVED: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+pub const DEFAULT_REPLACEMENT: char = '_';
+pub const DEFAULT_MAX_LENGTH: usize = 200;
+
+#[derive(Debug, Clone)]
+pub struct Sanitizer {
+    /// Character used in place of illegal characters
+    pub replacement: char,
+    /// Maximum length of a path component in bytes
+    pub max_length: usize,
+    /// Restrict names to ASCII, see `ascii_name`
+    pub ascii: bool,
+}
+
+// Emoji and their joiners render unpredictably in file managers and
+// are rejected by some filesystems, so they are dropped altogether.
+fn is_emoji(c: char) -> bool {
+    matches!(c as u32,
+        0x1F000..=0x1FAFF | 0x2600..=0x27BF | 0x2B00..=0x2BFF | 0xFE0E..=0xFE0F | 0x200D | 0xE0020..=0xE007F)
+}
+
+impl Sanitizer {
+    pub fn new(
+        replacement: char,
+        max_length: usize,
+        ascii: bool,
+    ) -> Result<Sanitizer, error::Error> {
+        if ILLEGAL_CHARS.contains(&replacement) || replacement.is_control() || max_length == 0 {
+            return Err(error::Error::InvalidArgument);
+        }
+        Ok(Sanitizer {
+            replacement,
+            max_length,
+            ascii,
+        })
+    }
+
+    /// Build a path component from metadata, using `fallback` when the
+    /// name cannot be represented in ASCII and ASCII names are requested.
+    pub fn name(&self, name: &str, fallback: &str) -> String {
+        if self.ascii {
+            self.component(&ascii_name(name).unwrap_or_else(|| fallback.to_string()))
+        } else {
+            self.component(name)
+        }
+    }
+
+    /// Sanitize a single path component, the result is never empty and
+    /// composed as far as `compose` goes.
+    pub fn component(&self, name: &str) -> String {
+        let mut result = String::with_capacity(name.len());
+        for c in compose(name).chars() {
+            if ILLEGAL_CHARS.contains(&c) || c.is_control() {
+                result.push(self.replacement);
+            } else if !is_emoji(c) {
+                result.push(c);
+            }
+        }
+
+        // Leading spaces are surprising and trailing spaces or dots are
+        // silently stripped by Windows, which breaks later lookups.
+        let mut result = result.trim_start().trim_end_matches([' ', '.']).to_string();
+
+        if result.len() > self.max_length {
+            let mut end = self.max_length;
+            while !result.is_char_boundary(end) {
+                end -= 1;
+            }
+            result.truncate(end);
+            result = result.trim_end_matches([' ', '.']).to_string();
+        }
+
+        let stem = result.split('.').next().unwrap_or_default();
+        if WINDOWS_RESERVED
+            .iter()
+            .any(|reserved| reserved.eq_ignore_ascii_case(stem))
+        {
+            result.insert(0, self.replacement);
+        }
+
+        if result.is_empty() {
+            result.push(self.replacement);
+        }
+        result
+    }
+}
+
+// Base letters and their precomposed forms with a combining accent
+const ACCENTS: &[(char, &str)] = &[
+    ('\u{300}', "AÀEÈIÌOÒUÙaàeèiìoòuù"),
+    ('\u{301}', "AÁCĆEÉIÍNŃOÓSŚUÚYÝZŹaácćeéiínńoósśuúyýzź"),
+    ('\u{302}', "AÂEÊIÎOÔUÛaâeêiîoôuû"),
+    ('\u{303}', "AÃNÑOÕaãnñoõ"),
+    ('\u{304}', "AĀEĒIĪOŌUŪaāeēiīoōuū"),
+    ('\u{306}', "AĂGĞaăgğ"),
+    ('\u{307}', "ZŻzż"),
+    ('\u{308}', "AÄEËIÏOÖUÜaäeëiïoöuüyÿ"),
+    ('\u{30A}', "AÅUŮaåuů"),
+    (
+        '\u{30C}',
+        "AǍCČDĎEĚIǏNŇOǑRŘSŠTŤUǓZŽaǎcčdďeěiǐnňoǒrřsštťuǔzž",
+    ),
+    ('\u{327}', "CÇSŞTŢcçsştţ"),
+    ('\u{328}', "AĄEĘaąeę"),
+];
+
+// Kana whose voiced form follows them, and those whose semi-voiced form
+// follows that
+const VOICED_KANA: &str =
+    "かきくけこさしすせそたちつてとはひふへほカキクケコサシスセソタチツテトハヒフヘホ";
+const SEMI_VOICED_KANA: &str = "はひふへほハヒフヘホ";
+
+// The precomposed form of `base` followed by the combining `mark`
+fn compose_pair(base: char, mark: char) -> Option<char> {
+    let (b, m) = (base as u32, mark as u32);
+    match mark {
+        '\u{300}'..='\u{36F}' => {
+            let (_, pairs) = ACCENTS.iter().find(|(accent, _)| *accent == mark)?;
+            let pairs: Vec<char> = pairs.chars().collect();
+            pairs
+                .chunks(2)
+                .find(|pair| pair[0] == base)
+                .map(|pair| pair[1])
+        }
+        '\u{3099}' if VOICED_KANA.contains(base) => char::from_u32(b + 1),
+        '\u{3099}' if base == 'う' => Some('ゔ'),
+        '\u{3099}' if base == 'ウ' => Some('ヴ'),
+        '\u{309A}' if SEMI_VOICED_KANA.contains(base) => char::from_u32(b + 2),
+        // Conjoining jamo: initial and vowel, then an optional final
+        '\u{1161}'..='\u{1175}' if (0x1100..=0x1112).contains(&b) => {
+            char::from_u32(0xAC00 + ((b - 0x1100) * 21 + (m - 0x1161)) * 28)
+        }
+        '\u{11A8}'..='\u{11C2}' if (0xAC00..=0xD7A3).contains(&b) && (b - 0xAC00) % 28 == 0 => {
+            char::from_u32(b + m - 0x11A7)
+        }
+        _ => None,
+    }
+}
+
+/// Compose the decomposed forms of latin letters with accents, kana with
+/// voicing marks and hangul syllables, as macOS file names and text copied
+/// from them have them, into their single NFC characters. Names are then
+/// the same whichever form the metadata came in. Other sequences are left
+/// as they are.
+pub fn compose(name: &str) -> String {
+    let mut result: Vec<char> = Vec::with_capacity(name.len());
+    for c in name.chars() {
+        let composed = result.last().and_then(|&last| compose_pair(last, c));
+        match (composed, result.last_mut()) {
+            (Some(composed), Some(last)) => *last = composed,
+            _ => result.push(c),
+        }
+    }
+    result.into_iter().collect()
+}
+
+// Map a character to its closest ASCII form, if there is an obvious one
+fn to_ascii(c: char) -> Option<&'static str> {
+    const TABLE: &[(&str, &str)] = &[
+        ("àáâãäåāăǎ", "a"),
+        ("çćč", "c"),
+        ("ďđ", "d"),
+        ("èéêëēěę", "e"),
+        ("ìíîïīǐ", "i"),
+        ("ñńň", "n"),
+        ("òóôõöøōǒ", "o"),
+        ("ŕř", "r"),
+        ("śšş", "s"),
+        ("ťţ", "t"),
+        ("ùúûüūůǔ", "u"),
+        ("ýÿ", "y"),
+        ("źżž", "z"),
+        ("ÀÁÂÃÄÅĀĂǍ", "A"),
+        ("ÇĆČ", "C"),
+        ("ÈÉÊËĒĚĘ", "E"),
+        ("ÌÍÎÏĪǏ", "I"),
+        ("ÑŃŇ", "N"),
+        ("ÒÓÔÕÖØŌǑ", "O"),
+        ("ÙÚÛÜŪŮǓ", "U"),
+        ("ŚŠŞ", "S"),
+        ("ŹŻŽ", "Z"),
+        ("ß", "ss"),
+        ("【［〔", "["),
+        ("】］〕", "]"),
+        ("「『《〈“”", "'"),
+        ("」』》〉‘’", "'"),
+        ("，、", ","),
+        ("。", "."),
+        ("·・", "-"),
+        ("～〜", "~"),
+        ("！", "!"),
+        ("（", "("),
+        ("）", ")"),
+    ];
+    TABLE
+        .iter()
+        .find(|(from, _)| from.contains(c))
+        .map(|(_, to)| *to)
+}
+
+// Hepburn romanization of the hiragana from U+3041, katakana are 0x60 after
+// them. Empty for the small tsu doubling the next consonant.
+const KANA: [&str; 86] = [
+    "a", "a", "i", "i", "u", "u", "e", "e", "o", "o", "ka", "ga", "ki", "gi", "ku", "gu", "ke",
+    "ge", "ko", "go", "sa", "za", "shi", "ji", "su", "zu", "se", "ze", "so", "zo", "ta", "da",
+    "chi", "ji", "", "tsu", "zu", "te", "de", "to", "do", "na", "ni", "nu", "ne", "no", "ha", "ba",
+    "pa", "hi", "bi", "pi", "fu", "bu", "pu", "he", "be", "pe", "ho", "bo", "po", "ma", "mi", "mu",
+    "me", "mo", "ya", "ya", "yu", "yu", "yo", "yo", "ra", "ri", "ru", "re", "ro", "wa", "wa", "i",
+    "e", "o", "n", "vu", "ka", "ke",
+];
+
+// Revised Romanization of the initial, medial and final jamo of hangul
+const HANGUL_INITIALS: [&str; 19] = [
+    "g", "kk", "n", "d", "tt", "r", "m", "b", "pp", "s", "ss", "", "j", "jj", "ch", "k", "t", "p",
+    "h",
+];
+const HANGUL_VOWELS: [&str; 21] = [
+    "a", "ae", "ya", "yae", "eo", "e", "yeo", "ye", "o", "wa", "wae", "oe", "yo", "u", "wo", "we",
+    "wi", "yu", "eu", "ui", "i",
+];
+const HANGUL_FINALS: [&str; 28] = [
+    "", "k", "k", "k", "n", "n", "n", "t", "l", "k", "m", "l", "l", "l", "p", "l", "m", "p", "p",
+    "t", "t", "ng", "t", "t", "k", "t", "p", "t",
+];
+
+// Romanization of a kana, with whether it is a small ya, yu or yo
+fn kana(c: char) -> Option<(&'static str, bool)> {
+    let code = c as u32;
+    let index = match code {
+        0x3041..=0x3096 => code - 0x3041,
+        0x30A1..=0x30F6 => code - 0x30A1,
+        _ => return None,
+    };
+    let small_y = matches!(index, 0x42 | 0x44 | 0x46);
+    Some((KANA[index as usize], small_y))
+}
+
+fn hangul(c: char) -> Option<String> {
+    let index = (c as u32).checked_sub(0xAC00).filter(|&i| i < 11172)? as usize;
+    Some(format!(
+        "{}{}{}",
+        HANGUL_INITIALS[index / 588],
+        HANGUL_VOWELS[index % 588 / 28],
+        HANGUL_FINALS[index % 28]
+    ))
+}
+
+// Append a kana to `result`: a small ya, yu or yo merges with the syllable
+// before, e.g. ki + ya is kya and shi + ya sha, and the small tsu doubles
+// the consonant after it
+fn push_kana(result: &mut String, romaji: &str, small_y: bool, double: &mut bool) {
+    if small_y && result.ends_with('i') {
+        result.pop();
+        if !(result.ends_with("sh") || result.ends_with("ch") || result.ends_with('j')) {
+            result.push('y');
+        }
+        result.push_str(&romaji[1..]);
+        return;
+    }
+    if std::mem::take(double) {
+        match romaji.as_bytes().first() {
+            Some(b'c') => result.push('t'),
+            Some(&b) if !b"aiueon".contains(&b) => result.push(b as char),
+            _ => {}
+        }
+    }
+    result.push_str(romaji);
+}
+
+/// Reduce a name to ASCII: it is composed, see `compose`, full-width forms
+/// and common CJK punctuation are normalized, accented latin letters lose
+/// their accents, and kana and hangul are romanized. CJK ideographs have no reading without a
+/// dictionary and are dropped. `None` is returned when no letters survive
+/// or most of them were dropped, so the caller can fall back to a stable
+/// identifier instead of a name telling items apart by a few letters alone.
+pub fn ascii_name(name: &str) -> Option<String> {
+    let mut result = String::with_capacity(name.len());
+    let (mut kept, mut dropped) = (0, 0);
+    let mut double = false;
+    for c in compose(name).chars() {
+        let code = c as u32;
+        if c.is_alphanumeric() {
+            kept += 1;
+        }
+        if c.is_ascii() {
+            result.push(c);
+        } else if (0xFF01..=0xFF5E).contains(&code) {
+            // Full-width ASCII variants are a fixed offset away
+            result.push(char::from_u32(code - 0xFEE0).unwrap_or(' '));
+        } else if c == '\u{3000}' {
+            result.push(' ');
+        } else if let Some(ascii) = to_ascii(c) {
+            result.push_str(ascii);
+        } else if let Some((romaji, small_y)) = kana(c) {
+            match romaji {
+                "" => double = true,
+                romaji => push_kana(&mut result, romaji, small_y, &mut double),
+            }
+        } else if let Some(romanized) = hangul(c) {
+            result.push_str(&romanized);
+        } else if c == 'ー' || ('\u{300}'..='\u{36F}').contains(&c) {
+            // Long vowels of katakana are not marked, nor accents left over
+        } else {
+            if c.is_alphanumeric() {
+                kept -= 1;
+                dropped += 1;
+            }
+            result.push(' ');
+        }
+    }
+
+    let result = result.split_whitespace().collect::<Vec<_>>().join(" ");
+    if result.chars().any(|c| c.is_ascii_alphabetic()) && dropped <= kept {
+        Some(result)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn components_are_valid_names_everywhere() {
+        let sanitizer = Sanitizer::new('_', DEFAULT_MAX_LENGTH, false).unwrap();
+        assert_eq!(sanitizer.component("a/b:c*?\u{7}"), "a_b_c___");
+        assert_eq!(sanitizer.component("🎉Party🎉"), "Party");
+        // Windows strips trailing dots and spaces
+        assert_eq!(sanitizer.component("  Live... . "), "Live");
+        assert_eq!(sanitizer.component(". ."), "_");
+        assert_eq!(sanitizer.component(""), "_");
+        // and refuses device names, with any extension
+        assert_eq!(sanitizer.component("CON"), "_CON");
+        assert_eq!(sanitizer.component("nul.mp4"), "_nul.mp4");
+        assert_eq!(sanitizer.component("Lpt9.tar.gz"), "_Lpt9.tar.gz");
+        assert_eq!(sanitizer.component("CONSOLE"), "CONSOLE");
+
+        // Lengths are bytes, cut at a character boundary
+        let short = Sanitizer::new('-', 7, false).unwrap();
+        assert_eq!(short.component("测试测试"), "测试");
+        assert_eq!(short.component("abcdef ghi"), "abcdef");
+        assert_eq!(short.component("a|b"), "a-b");
+
+        assert!(Sanitizer::new('/', 10, false).is_err());
+        assert!(Sanitizer::new('_', 0, false).is_err());
+    }
+
+    #[test]
+    fn names_fall_back_when_not_representable_in_ascii() {
+        let unicode = Sanitizer::new('_', DEFAULT_MAX_LENGTH, false).unwrap();
+        let ascii = Sanitizer::new('_', DEFAULT_MAX_LENGTH, true).unwrap();
+        assert_eq!(unicode.name("原神: 演示", "111"), "原神_ 演示");
+        assert_eq!(ascii.name("原神: 演示", "111"), "111");
+        assert_eq!(ascii.name("Café: Live", "111"), "Cafe_ Live");
+    }
+
+    #[test]
+    fn ascii_names_romanize_kana_and_hangul() {
+        let ascii = |name: &str| ascii_name(name);
+        assert_eq!(
+            ascii("Café ＡＢＣ　【MV】").as_deref(),
+            Some("Cafe ABC [MV]")
+        );
+        assert_eq!(
+            ascii("きゃりーぱみゅぱみゅ").as_deref(),
+            Some("kyaripamyupamyu")
+        );
+        assert_eq!(
+            ascii("ちょっと しゃしん").as_deref(),
+            Some("chotto shashin")
+        );
+        assert_eq!(ascii("カップ ラーメン").as_deref(), Some("kappu ramen"));
+        assert_eq!(ascii("서울 노래").as_deref(), Some("seoul norae"));
+        // Ideographs have no reading, names mostly made of them are dropped
+        assert_eq!(ascii("【4K】原神 角色演示"), None);
+        assert_eq!(ascii("Rust 教程").as_deref(), Some("Rust"));
+        assert_eq!(ascii("教程"), None);
+    }
+
+    #[test]
+    fn decomposed_names_are_composed() {
+        assert_eq!(compose("Cafe\u{301}"), "Café");
+        assert_eq!(compose("A\u{30A}ngstro\u{308}m na\u{303}o"), "Ångström não");
+        assert_eq!(compose("ka\u{30C} ha\u{300}"), "kǎ hà");
+        assert_eq!(compose("か\u{3099}ほ\u{309A}ウ\u{3099}"), "がぽヴ");
+        assert_eq!(compose("\u{1112}\u{1161}\u{11AB}\u{1100}\u{1173}"), "한그");
+        // Marks without a precomposed form stay
+        assert_eq!(compose("\u{301}q\u{301}"), "\u{301}q\u{301}");
+
+        let sanitizer = Sanitizer::new('_', DEFAULT_MAX_LENGTH, false).unwrap();
+        assert_eq!(sanitizer.component("Cafe\u{301}"), "Café");
+        assert_eq!(
+            ascii_name("Pinyin: ni\u{30C} ha\u{30C}o").as_deref(),
+            Some("Pinyin: ni hao")
+        );
+        assert_eq!(ascii_name("q\u{301}uiz").as_deref(), Some("quiz"));
+    }
+}
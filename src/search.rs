@@ -0,0 +1,189 @@
+/// Searching the cache and the archive by title, uploader or label
+///
+/// Items are matched like `--title` selects them: every word of the query
+/// must be part of the title, group title, uploader or one of the labels,
+/// compared case-insensitively without whitespace and punctuation. Only if
+/// nothing matches that way the letters of the query are looked for in
+/// order, so a misremembered title still finds something. Converted items
+/// whose cache is gone are found through the metadata next to their output.
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::i18n::tr;
+use crate::list::{print_table, Status};
+use crate::select::{item_name, normalize};
+use crate::{archive, backup, error, get_video_list, state, VideoInfo};
+
+/// An item found in the cache, the archive or both
+#[derive(Debug)]
+pub struct Match {
+    pub item: String,
+    pub info: VideoInfo,
+    pub status: Status,
+    pub tags: Vec<String>,
+    /// The output if converted, the cache directory otherwise
+    pub location: PathBuf,
+}
+
+// Whether the letters of `query` appear in `text` in order
+fn subsequence(query: &str, text: &str) -> bool {
+    let mut text = text.chars();
+    query.chars().all(|c| text.any(|t| t == c))
+}
+
+fn searched_text(m: &Match) -> String {
+    normalize(&format!(
+        "{} {} {} {}",
+        m.info.group_title,
+        m.info.title,
+        m.info.uname,
+        m.tags.join(" ")
+    ))
+}
+
+/// Keep the candidates matching `query`, best first: exact titles, then
+/// matches of every word, each by title
+pub fn filter(candidates: Vec<Match>, query: &str) -> Vec<Match> {
+    let words: Vec<String> = query
+        .split_whitespace()
+        .map(normalize)
+        .filter(|w| !w.is_empty())
+        .collect();
+    let exact = |m: &Match| normalize(&m.info.title) == normalize(query);
+    let (mut matches, rest): (Vec<Match>, Vec<Match>) = candidates.into_iter().partition(|m| {
+        let text = searched_text(m);
+        words.iter().all(|w| text.contains(w.as_str()))
+    });
+    if matches.is_empty() {
+        let letters = words.concat();
+        matches = rest
+            .into_iter()
+            .filter(|m| subsequence(&letters, &searched_text(m)))
+            .collect();
+    }
+    matches.sort_by(|a, b| {
+        exact(b)
+            .cmp(&exact(a))
+            .then_with(|| a.info.title.cmp(&b.info.title))
+    });
+    matches
+}
+
+/// Every item of the cache and the archive, once each
+fn candidates(source_path: &Path, target_path: &Path) -> Result<Vec<Match>, error::Error> {
+    let db = state::StateDb::load(target_path)?;
+    let tags = |item: &str| db.tags(item).cloned().collect::<Vec<_>>();
+    let mut candidates = Vec::new();
+    // A backup is searched through the archive only
+    if !backup::is_backup(source_path) {
+        for video in get_video_list(source_path)? {
+            let item = item_name(&video);
+            let status = Status::of(&db, &item);
+            let location = db
+                .output(&item)
+                .filter(|file| status == Status::Converted && file.is_file())
+                .unwrap_or_else(|| video.dir.clone());
+            candidates.push(Match {
+                tags: tags(&item),
+                item,
+                info: video.info,
+                status,
+                location,
+            });
+        }
+    }
+
+    // Outputs are named after their item by the state, old ones by id
+    let items: HashMap<PathBuf, &String> = db
+        .items()
+        .filter_map(|(item, _)| Some((db.output(item)?, item)))
+        .collect();
+    if target_path.is_dir() {
+        for entry in archive::scan(target_path)? {
+            if candidates.iter().any(|m| m.location == entry.file) {
+                continue;
+            }
+            let item = match items.get(&entry.file) {
+                Some(item) => item.to_string(),
+                None => entry.info.item_id.to_string(),
+            };
+            candidates.push(Match {
+                tags: tags(&item),
+                item,
+                info: entry.info,
+                status: Status::Converted,
+                location: entry.file,
+            });
+        }
+    }
+    Ok(candidates)
+}
+
+/// Print the items of the cache and the archive matching `query`
+pub fn run(source_path: &Path, target_path: &Path, query: &str) -> Result<(), error::Error> {
+    let matches = filter(candidates(source_path, target_path)?, query);
+    if matches.is_empty() {
+        println!("{}", tr!("Nothing matches '{}'", query));
+        return Ok(());
+    }
+    let rows: Vec<Vec<String>> = matches
+        .iter()
+        .map(|m| {
+            vec![
+                m.item.clone(),
+                m.status.name().to_string(),
+                m.info.title.clone(),
+                m.info.uname.clone(),
+                m.tags.join(","),
+                m.location.display().to_string(),
+            ]
+        })
+        .collect();
+    print_table(
+        &["ITEM", "STATUS", "TITLE", "UP", "TAGS", "LOCATION"],
+        &[false; 6],
+        &rows,
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(item: &str, title: &str, uname: &str, tags: &[&str]) -> Match {
+        let info = VideoInfo::parse(&format!(
+            r#"{{"uname":"{}","title":"{}","itemId":{}}}"#,
+            uname, title, item
+        ))
+        .unwrap();
+        Match {
+            item: item.to_string(),
+            info,
+            status: Status::New,
+            tags: tags.iter().map(|t| t.to_string()).collect(),
+            location: PathBuf::from(item),
+        }
+    }
+
+    fn items(matches: &[Match]) -> Vec<&str> {
+        matches.iter().map(|m| m.item.as_str()).collect()
+    }
+
+    #[test]
+    fn finds_words_in_titles_uploaders_and_labels() {
+        let candidates = || {
+            vec![
+                candidate("111", "Rust 入门 第二集", "老王", &[]),
+                candidate("222", "Rust", "小李", &["keep"]),
+                candidate("333", "Cooking at home", "小李", &["watch-later"]),
+            ]
+        };
+        assert_eq!(items(&filter(candidates(), "rust")), ["222", "111"]);
+        assert_eq!(items(&filter(candidates(), "小李 keep")), ["222"]);
+        assert_eq!(items(&filter(candidates(), "WATCH later")), ["333"]);
+        // Letters in order when no word matches
+        assert_eq!(items(&filter(candidates(), "cookng hme")), ["333"]);
+        assert!(filter(candidates(), "python").is_empty());
+    }
+}
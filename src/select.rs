@@ -0,0 +1,77 @@
+/// Finding cached items by their title instead of the opaque item id
+///
+/// An exact title wins over partial matches, comparing case-insensitively
+/// and ignoring whitespace and punctuation. Otherwise every word of the query
+/// must be part of the title, its group title or the uploader, so a few
+/// remembered words are enough.
+use std::path::Path;
+
+use crate::i18n::tr;
+use crate::{error, get_video_list, list, quality, state, CachedVideo, VideoInfo};
+
+// Lowercase without whitespace and punctuation, which CJK titles use
+// inconsistently
+pub fn normalize(s: &str) -> String {
+    s.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+fn exact(info: &VideoInfo, query: &str) -> bool {
+    let query = normalize(query);
+    [
+        &info.title,
+        &info.group_title,
+        &format!("{} {}", info.group_title, info.title),
+    ]
+    .iter()
+    .any(|t| normalize(t) == query)
+}
+
+fn partial(info: &VideoInfo, query: &str) -> bool {
+    let text = normalize(&format!(
+        "{} {} {}",
+        info.group_title, info.title, info.uname
+    ));
+    let mut words = query
+        .split_whitespace()
+        .map(normalize)
+        .filter(|w| !w.is_empty());
+    words.all(|w| text.contains(&w))
+}
+
+pub fn item_name(video: &CachedVideo) -> String {
+    crate::item_name(&video.dir)
+}
+
+/// Cache directory name of the one item matching `title`, listing the
+/// candidates if there are several
+pub fn by_title(source_path: &Path, title: &str) -> Result<String, error::Error> {
+    let videos = get_video_list(source_path)?;
+    let mut matches: Vec<CachedVideo> = Vec::new();
+    let mut rest = Vec::new();
+    for video in videos {
+        if exact(&video.info, title) {
+            matches.push(video);
+        } else {
+            rest.push(video);
+        }
+    }
+    if matches.is_empty() {
+        matches = rest
+            .into_iter()
+            .filter(|v| partial(&v.info, title))
+            .collect();
+    }
+    match matches.len() {
+        0 => Err(error::Error::TitleNotFound(title.to_string())),
+        1 => Ok(item_name(&matches[0])),
+        n => {
+            println!("{}", tr!("{} cached videos match '{}':", n, title));
+            let db = state::StateDb::default();
+            list::print(&matches, &[], false, quality::Quality::Highest, &db);
+            Err(error::Error::TitleAmbiguous(title.to_string()))
+        }
+    }
+}
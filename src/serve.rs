@@ -0,0 +1,462 @@
+/// Daemon mode: convert new cache items continuously and report progress
+/// over a small HTTP API returning JSON.
+///
+///   GET  /status          what is being converted and totals since start
+///   GET  /queue           the item being converted and the waiting items
+///   GET  /metrics         counters for Prometheus, see `metrics`
+///   POST /convert/<item>  convert the item after those requested before
+///   POST /move/<item>/<n> move a waiting item to position n, 0 is next
+///   POST /pause           finish the current item, then start no more
+///   POST /resume          continue converting waiting items
+///
+/// A worker thread rescans the cache every `interval` seconds and queues
+/// items that were neither converted nor failed before, in `--order`.
+/// Failed items only run again when requested through `/convert`.
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use log::*;
+use serde_json::{json, Value};
+
+use crate::dirs::Dirs;
+use crate::metrics::{self, Durations, TimedRunner};
+use crate::{
+    convert_video, error, ignore, ignored, item_dirs, item_job, item_name, item_path, legacy,
+    queue, signal, state, ConvertOptions, VIDEO_METADATA_FILE,
+};
+
+pub const DEFAULT_LISTEN: &str = "127.0.0.1:8787";
+
+struct Progress {
+    queue: queue::Queue,
+    current: Option<String>,
+    /// When the current item started converting
+    current_started: Option<Instant>,
+    converted: usize,
+    failed: usize,
+    /// Cache bytes of the converted items
+    bytes: u64,
+    last_scan: Option<DateTime<Utc>>,
+    last_conversion: Option<DateTime<Utc>>,
+}
+
+impl Progress {
+    fn new(order: queue::Order) -> Progress {
+        Progress {
+            queue: queue::Queue::new(order),
+            current: None,
+            current_started: None,
+            converted: 0,
+            failed: 0,
+            bytes: 0,
+            last_scan: None,
+            last_conversion: None,
+        }
+    }
+}
+
+fn is_cached(path: &Path) -> bool {
+    path.join(VIDEO_METADATA_FILE).is_file() || legacy::is_item(path)
+}
+
+// Item names are directories of the cache, or `<avid>/<page>` for pages of
+// legacy caches, and must not lead out of it
+fn valid_item(item: &str) -> bool {
+    let parts: Vec<&str> = item.split('/').collect();
+    parts.len() <= 2
+        && parts
+            .iter()
+            .all(|part| !matches!(*part, "" | "." | "..") && !part.contains(['\\', ':']))
+}
+
+// Cache items not converted yet and never failed, leaving out those excluded
+// by the ignore file, which is read again for every scan, or `exclude`
+fn pending_items(
+    source_path: &Path,
+    target_path: &Path,
+    exclude: &[String],
+) -> Result<Vec<PathBuf>, error::Error> {
+    let db = state::StateDb::load(target_path)?;
+    let ignore = ignore::Ignore::load(source_path, exclude)?;
+    let mut items = Vec::new();
+    for entry in source_path
+        .read_dir()
+        .map_err(|_| error::Error::ReadDirectoryFailed)?
+        .flatten()
+    {
+        for path in item_dirs(&entry.path()) {
+            if !is_cached(&path) || ignored(&ignore, &path) {
+                continue;
+            }
+            let name = item_name(&path);
+            let done = db.get(&name).is_some_and(|s| {
+                matches!(s.status, state::Status::Converted | state::Status::Failed)
+            });
+            if !done {
+                items.push(path);
+            }
+        }
+    }
+    items.sort();
+    Ok(items)
+}
+
+fn worker(
+    dirs: &Dirs,
+    source_path: &Path,
+    target_path: &Path,
+    interval: Duration,
+    progress: &Mutex<Progress>,
+    options: &ConvertOptions,
+) {
+    let mut last_scan: Option<Instant> = None;
+    while !signal::interrupted() {
+        if last_scan.is_none_or(|t| t.elapsed() >= interval) {
+            last_scan = Some(Instant::now());
+            match pending_items(source_path, target_path, &options.exclude) {
+                Ok(items) => {
+                    let mut progress = progress.lock().unwrap_or_else(|e| e.into_inner());
+                    for path in items {
+                        let item = item_name(&path);
+                        let running = progress.current.as_deref() == Some(&*item);
+                        if !running && !progress.queue.contains(&item) {
+                            progress.queue.push(item_job(&path, false));
+                        }
+                    }
+                    progress.last_scan = Some(Utc::now());
+                }
+                Err(e) => error!("Failed to scan {}: {}", source_path.display(), e),
+            }
+        }
+
+        let job = {
+            let mut progress = progress.lock().unwrap_or_else(|e| e.into_inner());
+            let job = progress.queue.pop();
+            progress.current = job.as_ref().map(|job| job.item.clone());
+            progress.current_started = job.as_ref().map(|_| Instant::now());
+            job
+        };
+        let Some(job) = job else {
+            thread::sleep(Duration::from_millis(500));
+            continue;
+        };
+        let item = job.item;
+
+        let result = convert_video(dirs, vec![item.clone()], options);
+        let mut progress = progress.lock().unwrap_or_else(|e| e.into_inner());
+        progress.current = None;
+        progress.current_started = None;
+        match result {
+            Ok(summary) => {
+                progress.converted += summary.converted;
+                progress.failed += summary.failed;
+                if summary.converted > 0 {
+                    progress.bytes += job.size;
+                    progress.last_conversion = Some(Utc::now());
+                }
+            }
+            Err(error::Error::Interrupted) => break,
+            Err(e) => {
+                error!("Failed to convert {}: {}", item, e);
+                progress.failed += 1;
+            }
+        }
+    }
+}
+
+fn respond_with(
+    stream: &mut TcpStream,
+    status: &str,
+    content_type: &str,
+    body: &str,
+) -> std::io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )
+}
+
+fn respond(stream: &mut TcpStream, status: &str, body: &Value) -> std::io::Result<()> {
+    respond_with(stream, status, "application/json", &body.to_string())
+}
+
+fn handle(
+    mut stream: TcpStream,
+    source_path: &Path,
+    started: &str,
+    progress: &Mutex<Progress>,
+    durations: &Durations,
+) -> std::io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+    // Headers are not needed, only drained so the client sees a clean close
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+
+    let mut parts = request.split_whitespace();
+    let method = parts.next().unwrap_or_default();
+    let path = parts.next().unwrap_or_default();
+    debug!("{} {}", method, path);
+
+    let mut progress = progress.lock().unwrap_or_else(|e| e.into_inner());
+    match (method, path) {
+        ("GET", "/status") => respond(
+            &mut stream,
+            "200 OK",
+            &json!({
+                "started": started,
+                "current": progress.current,
+                "queued": progress.queue.len(),
+                "paused": progress.queue.is_paused(),
+                "converted": progress.converted,
+                "failed": progress.failed,
+                "last_scan": progress.last_scan.map(|t| t.to_rfc3339()),
+            }),
+        ),
+        ("GET", "/metrics") => {
+            let snapshot = metrics::Snapshot {
+                converted: progress.converted,
+                failed: progress.failed,
+                bytes: progress.bytes,
+                queued: progress.queue.len(),
+                paused: progress.queue.is_paused(),
+                converting: progress.current_started.map(|t| t.elapsed().as_secs_f64()),
+                last_scan: progress.last_scan.map(|t| t.timestamp()),
+                last_conversion: progress.last_conversion.map(|t| t.timestamp()),
+            };
+            let body = metrics::render(&snapshot, durations);
+            respond_with(&mut stream, "200 OK", "text/plain; version=0.0.4", &body)
+        }
+        ("GET", "/queue") => respond(
+            &mut stream,
+            "200 OK",
+            &json!({
+                "current": progress.current,
+                "paused": progress.queue.is_paused(),
+                "queue": progress.queue.jobs().iter().map(|job| &job.item).collect::<Vec<_>>(),
+            }),
+        ),
+        ("POST", path) if path.starts_with("/convert/") => {
+            let item = &path["/convert/".len()..];
+            if !valid_item(item) || !is_cached(&item_path(source_path, item)) {
+                return respond(
+                    &mut stream,
+                    "404 Not Found",
+                    &json!({ "error": "no such item" }),
+                );
+            }
+            if !progress.queue.select(item) {
+                let path = item_path(source_path, item);
+                progress.queue.push(item_job(&path, true));
+            }
+            respond(&mut stream, "202 Accepted", &json!({ "queued": item }))
+        }
+        ("POST", path) if path.starts_with("/move/") => {
+            let target = path["/move/".len()..]
+                .rsplit_once('/')
+                .and_then(|(item, index)| Some((item, index.parse::<usize>().ok()?)));
+            let Some((item, index)) = target else {
+                return respond(
+                    &mut stream,
+                    "400 Bad Request",
+                    &json!({ "error": "expected /move/<item>/<position>" }),
+                );
+            };
+            if !progress.queue.move_to(item, index) {
+                return respond(
+                    &mut stream,
+                    "404 Not Found",
+                    &json!({ "error": "item not queued" }),
+                );
+            }
+            let position = index.min(progress.queue.len() - 1);
+            respond(
+                &mut stream,
+                "200 OK",
+                &json!({ "moved": item, "position": position }),
+            )
+        }
+        ("POST", "/pause") => {
+            progress.queue.pause();
+            respond(&mut stream, "200 OK", &json!({ "paused": true }))
+        }
+        ("POST", "/resume") => {
+            progress.queue.resume();
+            respond(&mut stream, "200 OK", &json!({ "paused": false }))
+        }
+        (_, path)
+            if ["/status", "/queue", "/metrics", "/pause", "/resume"].contains(&path)
+                || path.starts_with("/convert/")
+                || path.starts_with("/move/") =>
+        {
+            respond(
+                &mut stream,
+                "405 Method Not Allowed",
+                &json!({ "error": "method not allowed" }),
+            )
+        }
+        _ => respond(
+            &mut stream,
+            "404 Not Found",
+            &json!({ "error": "not found" }),
+        ),
+    }
+}
+
+/// Serve until interrupted, converting with `options`
+pub fn run(
+    dirs: &Dirs,
+    source_path: &Path,
+    target_path: &Path,
+    listen: &str,
+    interval: u64,
+    mut options: ConvertOptions,
+) -> Result<(), error::Error> {
+    let listener = TcpListener::bind(listen)?;
+    // Polled, so an interrupt is noticed without a pending connection
+    listener.set_nonblocking(true)?;
+    info!("Listening on http://{}", listen);
+    signal::install();
+
+    let durations = Arc::new(Durations::default());
+    options.ffmpeg.runner = Arc::new(TimedRunner {
+        inner: options.ffmpeg.runner.clone(),
+        durations: durations.clone(),
+    });
+    let options = &options;
+
+    let progress = Mutex::new(Progress::new(options.order()));
+    let started = Utc::now().to_rfc3339();
+    let interval = Duration::from_secs(interval);
+    thread::scope(|scope| {
+        scope.spawn(|| worker(dirs, source_path, target_path, interval, &progress, options));
+        while !signal::interrupted() {
+            match listener.accept() {
+                Ok((stream, _)) => {
+                    if let Err(e) = handle(stream, source_path, &started, &progress, &durations) {
+                        warn!("Request failed: {}", e);
+                    }
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                    thread::sleep(Duration::from_millis(100));
+                }
+                Err(e) => warn!("Failed to accept connection: {}", e),
+            }
+        }
+    });
+    info!("Interrupted, stopped serving");
+    Err(error::Error::Interrupted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::{Item, TempDir};
+    use std::fs;
+    use std::io::Read;
+
+    // Send `request` and return the status and body of the response
+    fn request(source_path: &Path, progress: &Mutex<Progress>, request: &str) -> (String, Value) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        write!(client, "{}\r\nHost: localhost\r\n\r\n", request).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        let durations = Durations::default();
+        handle(stream, source_path, "now", progress, &durations).unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        let status = head.lines().next().unwrap().trim_start_matches("HTTP/1.1 ");
+        (status.to_string(), serde_json::from_str(body).unwrap())
+    }
+
+    #[test]
+    fn queues_requested_items_and_legacy_pages() {
+        let dir = TempDir::new();
+        let cache = dir.path().join("cache");
+        Item::single(222, "Single").write(&cache);
+        let page = cache.join("100/1");
+        fs::create_dir_all(page.join("16")).unwrap();
+        fs::write(page.join("16/0.blv"), "flv").unwrap();
+        fs::write(page.join(legacy::ENTRY_FILE), r#"{"title":"Legacy"}"#).unwrap();
+        let progress = Mutex::new(Progress::new(queue::Order::Name));
+
+        let (status, body) = request(&cache, &progress, "POST /convert/100/1 HTTP/1.1");
+        assert_eq!(
+            (status.as_str(), body),
+            ("202 Accepted", json!({ "queued": "100/1" }))
+        );
+        let (status, _) = request(&cache, &progress, "POST /convert/222 HTTP/1.1");
+        assert_eq!(status, "202 Accepted");
+        for item in [
+            "../cache/222",
+            "100/../222",
+            "100/1/16",
+            "/etc",
+            "100/",
+            "C:/222",
+        ] {
+            let line = format!("POST /convert/{} HTTP/1.1", item);
+            let (status, _) = request(&cache, &progress, &line);
+            assert_eq!(status, "404 Not Found", "{}", item);
+        }
+
+        let (status, body) = request(&cache, &progress, "POST /move/100/1/1 HTTP/1.1");
+        assert_eq!(status, "200 OK");
+        assert_eq!(body, json!({ "moved": "100/1", "position": 1 }));
+        let (_, body) = request(&cache, &progress, "GET /queue HTTP/1.1");
+        assert_eq!(body["queue"], json!(["222", "100/1"]));
+    }
+
+    #[test]
+    fn rejects_bad_requests() {
+        let dir = TempDir::new();
+        let cache = dir.path().join("cache");
+        Item::single(222, "Single").write(&cache);
+        let progress = Mutex::new(Progress::new(queue::Order::Name));
+        request(&cache, &progress, "POST /convert/222 HTTP/1.1");
+
+        for (line, expected) in [
+            ("GET /convert/222 HTTP/1.1", "405 Method Not Allowed"),
+            ("DELETE /queue HTTP/1.1", "405 Method Not Allowed"),
+            ("POST /move/222 HTTP/1.1", "400 Bad Request"),
+            ("POST /move/222/first HTTP/1.1", "400 Bad Request"),
+            ("POST /move/333/0 HTTP/1.1", "404 Not Found"),
+            ("GET /items HTTP/1.1", "404 Not Found"),
+            ("", "404 Not Found"),
+        ] {
+            let (status, body) = request(&cache, &progress, line);
+            assert_eq!(status, expected, "{}", line);
+            assert!(body["error"].is_string(), "{}", line);
+        }
+
+        // Paused, nothing is handed out, yet requests are still queued
+        let (_, body) = request(&cache, &progress, "POST /pause HTTP/1.1");
+        assert_eq!(body, json!({ "paused": true }));
+        assert!(progress.lock().unwrap().queue.pop().is_none());
+        let (_, body) = request(&cache, &progress, "GET /status HTTP/1.1");
+        assert_eq!(
+            (body["queued"].clone(), body["paused"].clone()),
+            (json!(1), json!(true))
+        );
+        request(&cache, &progress, "POST /resume HTTP/1.1");
+        assert_eq!(
+            progress.lock().unwrap().queue.pop().map(|job| job.item),
+            Some("222".to_string())
+        );
+    }
+}
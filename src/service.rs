@@ -0,0 +1,188 @@
+/// Service definitions keeping the archive up to date without manual runs:
+/// a systemd user unit on Linux, a launchd agent on macOS.
+///
+/// The service runs this binary with the global options of the current
+/// command line, either `sync` on a schedule or `serve` continuously.
+use std::env;
+use std::fmt::Write;
+use std::fs;
+use std::path::PathBuf;
+
+use log::*;
+
+use crate::error::{self, Context};
+
+const NAME: &str = "bilibili";
+
+pub struct Service {
+    /// Program followed by its arguments
+    pub command: Vec<String>,
+    /// Run `serve` continuously instead of `sync` every `interval` seconds
+    pub serve: bool,
+    pub interval: u64,
+}
+
+// Global options given before the `install-service` subcommand
+fn global_args(subcommand: &str) -> Vec<String> {
+    env::args()
+        .skip(1)
+        .take_while(|arg| arg != subcommand)
+        .collect()
+}
+
+impl Service {
+    pub fn new(subcommand: &str, serve: bool, interval: u64) -> Result<Service, error::Error> {
+        let exe = env::current_exe()?;
+        let mut command = vec![exe.to_string_lossy().to_string()];
+        command.extend(global_args(subcommand));
+        command.push(if serve { "serve" } else { "sync" }.to_string());
+        Ok(Service {
+            command,
+            serve,
+            interval,
+        })
+    }
+
+    fn name(&self) -> String {
+        format!("{}-{}", NAME, if self.serve { "serve" } else { "sync" })
+    }
+}
+
+// systemd splits ExecStart like a shell and expands `%` specifiers and `$`
+fn systemd_quote(arg: &str) -> String {
+    let escaped = arg.replace('%', "%%").replace('$', "$$");
+    if escaped.is_empty() || escaped.contains([' ', '\t', '"', '\'', '\\', ';']) {
+        format!("\"{}\"", escaped.replace('\\', "\\\\").replace('"', "\\\""))
+    } else {
+        escaped
+    }
+}
+
+/// Unit files as `(file name, content)`
+fn systemd_units(service: &Service) -> Vec<(String, String)> {
+    let name = service.name();
+    let exec: Vec<String> = service.command.iter().map(|a| systemd_quote(a)).collect();
+    let mut unit = String::new();
+    let _ = writeln!(unit, "[Unit]");
+    let _ = writeln!(unit, "Description=Bilibili video converter ({})", name);
+    let _ = writeln!(unit);
+    let _ = writeln!(unit, "[Service]");
+    if service.serve {
+        let _ = writeln!(unit, "Type=simple");
+        let _ = writeln!(unit, "Restart=on-failure");
+    } else {
+        let _ = writeln!(unit, "Type=oneshot");
+    }
+    let _ = writeln!(unit, "ExecStart={}", exec.join(" "));
+    let _ = writeln!(unit, "Nice=10");
+    if service.serve {
+        let _ = writeln!(unit);
+        let _ = writeln!(unit, "[Install]");
+        let _ = writeln!(unit, "WantedBy=default.target");
+        return vec![(format!("{}.service", name), unit)];
+    }
+
+    let mut timer = String::new();
+    let _ = writeln!(timer, "[Unit]");
+    let _ = writeln!(timer, "Description=Run {} periodically", name);
+    let _ = writeln!(timer);
+    let _ = writeln!(timer, "[Timer]");
+    let _ = writeln!(timer, "OnBootSec=5min");
+    let _ = writeln!(timer, "OnUnitInactiveSec={}s", service.interval);
+    let _ = writeln!(timer, "Persistent=true");
+    let _ = writeln!(timer);
+    let _ = writeln!(timer, "[Install]");
+    let _ = writeln!(timer, "WantedBy=timers.target");
+    vec![
+        (format!("{}.service", name), unit),
+        (format!("{}.timer", name), timer),
+    ]
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn launchd_plist(service: &Service) -> (String, String) {
+    let label = format!("{}.{}", NAME, if service.serve { "serve" } else { "sync" });
+    let mut plist = String::new();
+    let _ = writeln!(plist, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>");
+    let _ = writeln!(
+        plist,
+        "<!DOCTYPE plist PUBLIC \"-//Apple//DTD PLIST 1.0//EN\" \"http://www.apple.com/DTDs/PropertyList-1.0.dtd\">"
+    );
+    let _ = writeln!(plist, "<plist version=\"1.0\">");
+    let _ = writeln!(plist, "<dict>");
+    let _ = writeln!(plist, "    <key>Label</key>");
+    let _ = writeln!(plist, "    <string>{}</string>", label);
+    let _ = writeln!(plist, "    <key>ProgramArguments</key>");
+    let _ = writeln!(plist, "    <array>");
+    for arg in &service.command {
+        let _ = writeln!(plist, "        <string>{}</string>", xml_escape(arg));
+    }
+    let _ = writeln!(plist, "    </array>");
+    if service.serve {
+        let _ = writeln!(plist, "    <key>KeepAlive</key>");
+        let _ = writeln!(plist, "    <true/>");
+    } else {
+        let _ = writeln!(plist, "    <key>StartInterval</key>");
+        let _ = writeln!(plist, "    <integer>{}</integer>", service.interval);
+    }
+    let _ = writeln!(plist, "    <key>RunAtLoad</key>");
+    let _ = writeln!(plist, "    <true/>");
+    let _ = writeln!(plist, "    <key>ProcessType</key>");
+    let _ = writeln!(plist, "    <string>Background</string>");
+    let _ = writeln!(plist, "</dict>");
+    let _ = writeln!(plist, "</plist>");
+    (format!("{}.plist", label), plist)
+}
+
+fn home() -> Result<PathBuf, error::Error> {
+    env::home_dir().ok_or(error::Error::HomeNotFound)
+}
+
+/// Write the service files for this platform, or only print them
+pub fn install(service: &Service, print: bool) -> Result<(), error::Error> {
+    let (dir, files, enable) = if cfg!(target_os = "macos") {
+        let dir = home()?.join("Library/LaunchAgents");
+        let (name, content) = launchd_plist(service);
+        let enable = format!("launchctl load -w {}", dir.join(&name).display());
+        (dir, vec![(name, content)], enable)
+    } else {
+        let config = match env::var("XDG_CONFIG_HOME") {
+            Ok(config) if !config.is_empty() => PathBuf::from(config),
+            _ => home()?.join(".config"),
+        };
+        let dir = config.join("systemd/user");
+        let files = systemd_units(service);
+        let unit = &files
+            .last()
+            .map(|(name, _)| name.clone())
+            .unwrap_or_default();
+        let enable = format!(
+            "systemctl --user daemon-reload && systemctl --user enable --now {}",
+            unit
+        );
+        (dir, files, enable)
+    };
+
+    if print {
+        for (name, content) in &files {
+            println!("# {}", dir.join(name).display());
+            print!("{}", content);
+            println!();
+        }
+        return Ok(());
+    }
+    fs::create_dir_all(&dir).context("create directory", &dir)?;
+    for (name, content) in &files {
+        let path = dir.join(name);
+        fs::write(&path, content).context("write", &path)?;
+        info!("Wrote {}", path.display());
+    }
+    println!("Enable it with: {}", enable);
+    Ok(())
+}
@@ -0,0 +1,237 @@
+/// Writing to outputs on network shares
+///
+/// SMB and NFS mounts fail operations now and then that succeed a moment
+/// later, e.g. with a stale handle after the server restarted, so creating
+/// directories and renaming outputs is tried a few times before giving up.
+/// A rename from a local directory onto a share crosses devices and is done
+/// by copying and deleting instead. Paths longer than Windows allows are
+/// given the `\\?\` prefix lifting the limit, and as shares compare names
+/// case-insensitively, `layout` checks for names differing only in case.
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+use log::*;
+
+/// Attempts of an operation failing transiently
+const ATTEMPTS: u32 = 4;
+
+/// Delay before the first retry, doubled for each further one
+const RETRY_DELAY: Duration = Duration::from_millis(250);
+
+/// Longest path Windows accepts without the `\\?\` prefix
+const MAX_PATH: usize = 260;
+
+/// Whether `e` is likely to go away when trying again
+pub fn transient(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::Interrupted
+            | io::ErrorKind::WouldBlock
+            | io::ErrorKind::TimedOut
+            | io::ErrorKind::ResourceBusy
+            | io::ErrorKind::StaleNetworkFileHandle
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::NetworkDown
+            | io::ErrorKind::NetworkUnreachable
+            | io::ErrorKind::HostUnreachable
+    )
+}
+
+/// Run `attempt` on `path` again after transient failures
+pub fn retrying<T>(
+    action: &str,
+    path: &Path,
+    mut attempt: impl FnMut() -> io::Result<T>,
+) -> io::Result<T> {
+    let mut delay = RETRY_DELAY;
+    for n in 1.. {
+        match attempt() {
+            Err(e) if transient(&e) && n < ATTEMPTS => {
+                warn!(
+                    "Failed to {} {}: {}, trying again",
+                    action,
+                    path.display(),
+                    e
+                );
+                thread::sleep(delay);
+                delay *= 2;
+            }
+            result => return result,
+        }
+    }
+    unreachable!()
+}
+
+/// `fs::create_dir_all` retrying transient failures
+pub fn create_dir_all(dir: &Path) -> io::Result<()> {
+    retrying("create directory", dir, || fs::create_dir_all(dir))
+}
+
+// Copy the file or directory tree `from` to `to`, syncing the files
+fn copy_tree(from: &Path, to: &Path) -> io::Result<()> {
+    if from.is_dir() {
+        fs::create_dir_all(to)?;
+        for entry in fs::read_dir(from)? {
+            let entry = entry?;
+            copy_tree(&entry.path(), &to.join(entry.file_name()))?;
+        }
+        return Ok(());
+    }
+    fs::copy(from, to)?;
+    fs::File::open(to)?.sync_all()
+}
+
+fn remove(path: &Path) -> io::Result<()> {
+    let removed = if path.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    };
+    match removed {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+// Move `from` to `to` by copying it next to `to` first, so `to` never
+// exists half copied, and deleting `from` once the copy is in place. Only
+// the copy is started over after a failure, as long as `to` does not
+// exist yet, and only the deletion once it does.
+fn move_by_copy(from: &Path, to: &Path) -> io::Result<()> {
+    let mut name = to.as_os_str().to_owned();
+    name.push(".moving");
+    let staged = PathBuf::from(name);
+    retrying("copy", from, || {
+        let copied = remove(&staged)
+            .and_then(|_| copy_tree(from, &staged))
+            .and_then(|_| fs::rename(&staged, to));
+        if copied.is_err() {
+            let _ = remove(&staged);
+        }
+        copied
+    })?;
+    retrying("remove", from, || remove(from))
+}
+
+/// `fs::rename` retrying transient failures, and copying then deleting
+/// `from` if it is on another filesystem than `to`
+pub fn rename(from: &Path, to: &Path) -> io::Result<()> {
+    match retrying("rename", from, || fs::rename(from, to)) {
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            debug!("{} is on another filesystem, copying it", from.display());
+            move_by_copy(from, to)
+        }
+        result => result,
+    }
+}
+
+// `path` with the extended-length prefix, None if it does not need one
+fn extended(path: &str) -> Option<String> {
+    if path.len() < MAX_PATH || path.starts_with(r"\\?\") {
+        return None;
+    }
+    // The prefix turns off the normalization of separators and `.`
+    let path = path.replace('/', r"\");
+    match path.strip_prefix(r"\\") {
+        Some(unc) => Some(format!(r"\\?\UNC\{}", unc)),
+        None if path.as_bytes().get(1) == Some(&b':') => Some(format!(r"\\?\{}", path)),
+        None => None,
+    }
+}
+
+/// `path` usable even if longer than Windows allows, unchanged elsewhere
+pub fn long_path(path: PathBuf) -> PathBuf {
+    if !cfg!(windows) {
+        return path;
+    }
+    match extended(&path.to_string_lossy()) {
+        Some(extended) => PathBuf::from(extended),
+        None => path,
+    }
+}
+
+/// Another entry next to `path` whose name differs only in case, which is
+/// the same file on a case-insensitive share
+pub fn case_collision(path: &Path) -> Option<PathBuf> {
+    let name = path.file_name()?.to_string_lossy().to_lowercase();
+    let own = path.file_name()?;
+    let entries = fs::read_dir(path.parent()?).ok()?;
+    entries.flatten().map(|e| e.path()).find(|other| {
+        other
+            .file_name()
+            .is_some_and(|n| n != own && n.to_string_lossy().to_lowercase() == name)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::TempDir;
+    use std::cell::Cell;
+
+    #[test]
+    fn retries_transient_failures_only() {
+        let attempts = Cell::new(0);
+        let result = retrying("write", Path::new("x"), || {
+            attempts.set(attempts.get() + 1);
+            match attempts.get() {
+                1 => Err(io::Error::from(io::ErrorKind::StaleNetworkFileHandle)),
+                2 => Err(io::Error::from(io::ErrorKind::TimedOut)),
+                _ => Ok(attempts.get()),
+            }
+        });
+        assert_eq!(result.unwrap(), 3);
+
+        attempts.set(0);
+        let result: io::Result<()> = retrying("write", Path::new("x"), || {
+            attempts.set(attempts.get() + 1);
+            Err(io::ErrorKind::NotFound.into())
+        });
+        assert!(result.is_err());
+        assert_eq!(attempts.get(), 1);
+    }
+
+    #[test]
+    fn moves_trees_by_copying() {
+        let dir = TempDir::new();
+        let from = dir.path().join("item");
+        fs::create_dir_all(from.join("sub")).unwrap();
+        fs::write(from.join("sub/video.m4s"), "video").unwrap();
+        let to = dir.path().join("moved");
+        move_by_copy(&from, &to).unwrap();
+        assert!(!from.exists());
+        assert!(!dir.path().join("moved.moving").exists());
+        assert_eq!(
+            fs::read_to_string(to.join("sub/video.m4s")).unwrap(),
+            "video"
+        );
+    }
+
+    #[test]
+    fn prefixes_long_paths_and_finds_names_differing_in_case() {
+        let long = "a".repeat(MAX_PATH);
+        assert_eq!(
+            extended(&format!("C:/out/{}", long)).unwrap(),
+            format!(r"\\?\C:\out\{}", long)
+        );
+        assert_eq!(
+            extended(&format!(r"\\nas\share\{}", long)).unwrap(),
+            format!(r"\\?\UNC\nas\share\{}", long)
+        );
+        assert_eq!(extended(r"C:\out\short.mp4"), None);
+        assert_eq!(extended(&format!("relative/{}", long)), None);
+
+        let dir = TempDir::new();
+        fs::write(dir.path().join("Song.mp4"), "").unwrap();
+        assert_eq!(
+            case_collision(&dir.path().join("song.mp4")),
+            Some(dir.path().join("Song.mp4"))
+        );
+        assert_eq!(case_collision(&dir.path().join("Song.mp4")), None);
+        assert_eq!(case_collision(&dir.path().join("other.mp4")), None);
+    }
+}
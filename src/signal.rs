@@ -0,0 +1,37 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// Set by the signal handler, polled by the batch loop and while waiting for ffmpeg
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+#[cfg(unix)]
+mod ffi {
+    use std::os::raw::c_int;
+
+    pub const SIGINT: c_int = 2;
+    pub const SIGTERM: c_int = 15;
+
+    extern "C" {
+        pub fn signal(signum: c_int, handler: extern "C" fn(c_int)) -> usize;
+    }
+}
+
+#[cfg(unix)]
+extern "C" fn on_signal(_signum: std::os::raw::c_int) {
+    // Only async-signal-safe work is allowed here
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+/// Install SIGINT/SIGTERM handlers which only record the interruption,
+/// leaving the cleanup to the code that is currently running.
+pub fn install() {
+    #[cfg(unix)]
+    unsafe {
+        ffi::signal(ffi::SIGINT, on_signal);
+        ffi::signal(ffi::SIGTERM, on_signal);
+    }
+}
+
+/// Whether an interrupt has been received
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
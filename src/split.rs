@@ -0,0 +1,181 @@
+/// Splitting outputs too large for FAT32 USB sticks and old TVs
+///
+/// With `--max-size` an output larger than the limit is cut into parts at
+/// keyframes with the segment muxer of ffmpeg, copying the streams. The
+/// segment length follows from the average bit rate, a part still over the
+/// limit, e.g. after a complex scene, makes the split start over with
+/// shorter segments. The first part keeps the name of the output so side
+/// files and the conversion state still point at it, the others are named
+/// `<name>.part2.mp4` and so on.
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use log::*;
+
+use crate::container::Container;
+use crate::error::{self, Context};
+use crate::ffmpeg::Ffmpeg;
+use crate::probe;
+
+/// Tries with shorter segments before giving up
+const ATTEMPTS: usize = 3;
+
+/// Share of the limit parts are aimed at, bit rates vary along a video
+const HEADROOM: f64 = 0.9;
+
+// Hidden names of the parts while splitting, `%d` counting from 1
+fn temp_pattern(file: &Path) -> PathBuf {
+    let name = file.file_name().unwrap_or_default().to_string_lossy();
+    file.with_file_name(format!(".{}.split%d", name))
+}
+
+// Name part `n` of `file` ends up with, counting from 1
+fn part_name(file: &Path, n: usize) -> PathBuf {
+    if n == 1 {
+        return file.to_path_buf();
+    }
+    let stem = file.file_stem().unwrap_or_default().to_string_lossy();
+    match file.extension() {
+        Some(extension) => file.with_file_name(format!(
+            "{}.part{}.{}",
+            stem,
+            n,
+            extension.to_string_lossy()
+        )),
+        None => file.with_file_name(format!("{}.part{}", stem, n)),
+    }
+}
+
+// Temp parts written by the segment muxer, in order
+fn temp_parts(file: &Path) -> Vec<PathBuf> {
+    let pattern = temp_pattern(file).to_string_lossy().to_string();
+    (1..)
+        .map(|n| PathBuf::from(pattern.replace("%d", &n.to_string())))
+        .take_while(|part| part.is_file())
+        .collect()
+}
+
+fn remove_temp_parts(file: &Path) {
+    for part in temp_parts(file) {
+        let _ = fs::remove_file(part);
+    }
+}
+
+/// Split `file`, written in `container`, into parts of at most `max` bytes
+/// if it is larger, returning the files it ended up as. With `faststart`
+/// the parts get their index at the front like the whole output had.
+pub fn split(
+    ffmpeg: &Ffmpeg,
+    file: &Path,
+    max: u64,
+    container: Container,
+    faststart: bool,
+) -> Result<Vec<PathBuf>, error::Error> {
+    let size = file.metadata().context("read metadata of", file)?.len();
+    if size <= max {
+        return Ok(vec![file.to_path_buf()]);
+    }
+    let duration = probe::duration(ffmpeg, file)?;
+    let mut segment = duration * max as f64 / size as f64 * HEADROOM;
+    for _ in 0..ATTEMPTS {
+        info!(
+            "Splitting {} into parts of {:.0} seconds",
+            file.display(),
+            segment
+        );
+        // ffmpeg -i file -map 0 -c copy -f segment -segment_time T -segment_format format
+        //        [-segment_format_options movflags=+faststart] -segment_start_number 1
+        //        -reset_timestamps 1 [-threads N] -y .file.split%d
+        let mut cmd = ffmpeg.command();
+        cmd.arg("-i").arg(file);
+        cmd.args(["-map", "0", "-c", "copy", "-f", "segment"]);
+        cmd.arg("-segment_time").arg(format!("{:.3}", segment));
+        cmd.arg("-segment_format").arg(container.format());
+        if faststart && !container.faststart_args().is_empty() {
+            cmd.args(["-segment_format_options", "movflags=+faststart"]);
+        }
+        cmd.args(["-segment_start_number", "1"]);
+        cmd.args(["-reset_timestamps", "1"]);
+        cmd.args(ffmpeg.thread_args());
+        cmd.arg("-y").arg(temp_pattern(file));
+        if let Err(e) = ffmpeg.run(cmd) {
+            remove_temp_parts(file);
+            return Err(e);
+        }
+
+        let parts = temp_parts(file);
+        let largest = parts
+            .iter()
+            .filter_map(|part| part.metadata().ok())
+            .map(|m| m.len())
+            .max()
+            .unwrap_or_default();
+        if parts.is_empty() || largest > max {
+            remove_temp_parts(file);
+            if largest == 0 {
+                break;
+            }
+            segment *= max as f64 / largest as f64 * HEADROOM;
+            continue;
+        }
+        let mut files = Vec::new();
+        for (n, part) in parts.iter().enumerate() {
+            let target = part_name(file, n + 1);
+            fs::rename(part, &target).context("rename", part)?;
+            files.push(target);
+        }
+        return Ok(files);
+    }
+    Err(error::Error::SplitFailed(file.to_path_buf(), max))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::{ScriptedRunner, TempDir};
+
+    // ffprobe finds 100 seconds, ffmpeg writes three parts of 400 bytes
+    fn answer(args: &[String]) -> Option<String> {
+        if args.iter().any(|a| a == "format=duration") {
+            return Some(r#"{"format":{"duration":"100.0"}}"#.to_string());
+        }
+        let pattern = args.last()?;
+        for n in 1..=3 {
+            fs::write(pattern.replace("%d", &n.to_string()), [0; 400]).unwrap();
+        }
+        Some(String::new())
+    }
+
+    #[test]
+    fn splits_large_outputs_into_parts() {
+        let dir = TempDir::new();
+        let file = dir.path().join("111.mp4");
+        fs::write(&file, [0; 1200]).unwrap();
+        let runner = ScriptedRunner::new(answer);
+        let mut ffmpeg = Ffmpeg::new(PathBuf::from("ffmpeg"), Vec::new());
+        ffmpeg.runner = runner.clone();
+
+        assert_eq!(
+            split(&ffmpeg, &file, 2000, Container::Mp4, true).unwrap(),
+            std::slice::from_ref(&file)
+        );
+        assert!(runner.commands().is_empty());
+
+        let parts = split(&ffmpeg, &file, 500, Container::Mp4, true).unwrap();
+        assert_eq!(
+            parts,
+            ["111.mp4", "111.part2.mp4", "111.part3.mp4"].map(|name| dir.path().join(name))
+        );
+        assert!(runner.commands()[1].contains(
+            "-segment_time 37.500 -segment_format mp4 \
+             -segment_format_options movflags=+faststart "
+        ));
+        assert_eq!(fs::read(&file).unwrap().len(), 400);
+
+        // Parts over the limit are tried again with shorter segments
+        let error = split(&ffmpeg, &file, 300, Container::Mp4, true).unwrap_err();
+        assert!(matches!(error, error::Error::SplitFailed(_, 300)));
+        assert_eq!(runner.commands().len(), 2 + 1 + ATTEMPTS);
+        assert!(temp_parts(&file).is_empty());
+    }
+}
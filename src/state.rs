@@ -0,0 +1,211 @@
+/// Conversion state database
+/// kept as a JSON file in the output directory, recording what
+/// happened to every cache item across runs.
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{self, Context};
+use crate::share;
+
+const STATE_FILE: &str = ".bilibili-state.json";
+
+/// Source of the timestamps recorded for items, in seconds since the epoch
+pub type Clock = fn() -> i64;
+
+/// The system clock
+pub fn now() -> i64 {
+    Utc::now().timestamp()
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Converting,
+    Converted,
+    Failed,
+    Interrupted,
+    /// Failed too often and moved out of the cache, see `quarantine`
+    Quarantined,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ItemState {
+    pub status: Status,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub updated: i64,
+    /// Video written by the last conversion, relative to the output directory
+    /// if it is below it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<PathBuf>,
+    /// Runs in a row the conversion failed in
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub failures: u32,
+}
+
+fn is_zero(n: &u32) -> bool {
+    *n == 0
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct StateDb {
+    #[serde(skip)]
+    path: PathBuf,
+    #[serde(skip)]
+    clock: Option<Clock>,
+    items: BTreeMap<String, ItemState>,
+    /// Labels of items, independent of their conversion state
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    tags: BTreeMap<String, BTreeSet<String>>,
+    /// Bytes per second of conversion stages, see `eta`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    throughput: BTreeMap<String, f64>,
+}
+
+impl StateDb {
+    /// Load the state database from the target directory, an empty one is
+    /// returned if it does not exist yet.
+    pub fn load(target_path: &Path) -> Result<StateDb, error::Error> {
+        let path = target_path.join(STATE_FILE);
+        let mut db: StateDb = if path.exists() {
+            let content = fs::read_to_string(&path).context("read", &path)?;
+            serde_json::from_str(&content)?
+        } else {
+            StateDb::default()
+        };
+        db.path = path;
+        Ok(db)
+    }
+
+    /// Record timestamps from `clock` instead of the system clock
+    pub fn with_clock(mut self, clock: Clock) -> StateDb {
+        self.clock = Some(clock);
+        self
+    }
+
+    /// Write the database back, going through a temp file so an interrupted
+    /// write never corrupts the previous state. The temp file is synced
+    /// first, a source is only removed once its conversion is on disk.
+    pub fn save(&self) -> Result<(), error::Error> {
+        let tmp = self.path.with_extension("json.tmp");
+        let mut file = fs::File::create(&tmp).context("write", &tmp)?;
+        file.write_all(serde_json::to_string_pretty(self)?.as_bytes())
+            .and_then(|_| file.sync_all())
+            .context("write", &tmp)?;
+        share::rename(&tmp, &self.path).context("rename", &tmp)?;
+        Ok(())
+    }
+
+    /// All recorded items with their state
+    pub fn items(&self) -> impl Iterator<Item = (&String, &ItemState)> {
+        self.items.iter()
+    }
+
+    pub fn get(&self, item: &str) -> Option<&ItemState> {
+        self.items.get(item)
+    }
+
+    /// Whether the item was converted successfully by a previous run
+    pub fn is_converted(&self, item: &str) -> bool {
+        self.get(item)
+            .is_some_and(|state| state.status == Status::Converted)
+    }
+
+    /// Replace the state of the item, keeping its recorded output and
+    /// counting failures in a row
+    pub fn set(&mut self, item: &str, status: Status, error: Option<String>) {
+        let previous = self.get(item);
+        let failed = previous.map_or(0, |p| p.failures);
+        let failures = match status {
+            Status::Failed => failed + 1,
+            // An interrupted attempt neither failed nor succeeded
+            Status::Converting | Status::Interrupted => failed,
+            Status::Converted | Status::Quarantined => 0,
+        };
+        let state = ItemState {
+            status,
+            error,
+            updated: self.clock.unwrap_or(now)(),
+            output: previous.and_then(|s| s.output.clone()),
+            failures,
+        };
+        self.items.insert(item.to_string(), state);
+    }
+
+    /// Record the video the item was converted to
+    pub fn set_output(&mut self, item: &str, file: &Path) {
+        let dir = self.path.parent().unwrap_or(Path::new(""));
+        let file = file.strip_prefix(dir).unwrap_or(file).to_path_buf();
+        if let Some(state) = self.items.get_mut(item) {
+            state.output = Some(file);
+        }
+    }
+
+    /// Absolute path of the recorded output of the item
+    pub fn output(&self, item: &str) -> Option<PathBuf> {
+        let dir = self.path.parent().unwrap_or(Path::new(""));
+        self.get(item)?.output.as_ref().map(|file| dir.join(file))
+    }
+
+    /// Add a state from elsewhere, unless the one recorded here is newer
+    pub fn merge(&mut self, item: &str, state: ItemState) -> bool {
+        if self.get(item).is_some_and(|s| s.updated >= state.updated) {
+            return false;
+        }
+        self.items.insert(item.to_string(), state);
+        true
+    }
+
+    /// All labeled items with their labels
+    pub fn labels(&self) -> impl Iterator<Item = (&String, &BTreeSet<String>)> {
+        self.tags.iter()
+    }
+
+    /// Labels of the item, sorted
+    pub fn tags(&self, item: &str) -> impl Iterator<Item = &String> {
+        self.tags.get(item).into_iter().flatten()
+    }
+
+    /// Items labeled `label`, sorted
+    pub fn tagged(&self, label: &str) -> Vec<String> {
+        self.tags
+            .iter()
+            .filter(|(_, labels)| labels.contains(label))
+            .map(|(item, _)| item.clone())
+            .collect()
+    }
+
+    /// Label the item, false if it already was
+    pub fn tag(&mut self, item: &str, label: &str) -> bool {
+        self.tags
+            .entry(item.to_string())
+            .or_default()
+            .insert(label.to_string())
+    }
+
+    /// Remove a label of the item, false if it had none
+    pub fn untag(&mut self, item: &str, label: &str) -> bool {
+        let Some(labels) = self.tags.get_mut(item) else {
+            return false;
+        };
+        let removed = labels.remove(label);
+        if labels.is_empty() {
+            self.tags.remove(item);
+        }
+        removed
+    }
+
+    /// Recorded bytes per second of `stage`
+    pub fn throughput(&self, stage: &str) -> Option<f64> {
+        self.throughput.get(stage).copied()
+    }
+
+    pub fn set_throughput(&mut self, stage: &str, bytes_per_sec: f64) {
+        self.throughput.insert(stage.to_string(), bytes_per_sec);
+    }
+}
@@ -0,0 +1,244 @@
+/// Statistics of the cache and the archive, to decide what to clean
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use chrono::DateTime;
+use serde::Serialize;
+
+use crate::archive;
+use crate::i18n::tr;
+use crate::list::print_table;
+use crate::select::item_name;
+use crate::{disk, error, output, probe, state, CachedVideo, ConvertOptions, VideoInfo};
+
+const LARGEST: usize = 10;
+
+#[derive(Serialize, Default)]
+struct Totals {
+    cached: usize,
+    cached_bytes: u64,
+    archived: usize,
+    archived_bytes: u64,
+}
+
+#[derive(Serialize)]
+struct Largest {
+    item: String,
+    uname: String,
+    title: String,
+    bytes: u64,
+    converted: bool,
+}
+
+#[derive(Serialize)]
+struct Stats {
+    #[serde(flatten)]
+    totals: Totals,
+    /// Cached items already converted
+    converted: usize,
+    conversion_ratio: f64,
+    /// Over all archived videos ffprobe could read, in bits per second
+    average_bitrate: Option<f64>,
+    by_up: BTreeMap<String, Totals>,
+    by_month: BTreeMap<String, Totals>,
+    largest: Vec<Largest>,
+}
+
+fn month(info: &VideoInfo) -> String {
+    DateTime::from_timestamp(info.pubdate, 0)
+        .map(|dt| dt.format("%Y-%m").to_string())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+fn collect(
+    videos: &[CachedVideo],
+    target_path: &Path,
+    options: &ConvertOptions,
+) -> Result<Stats, error::Error> {
+    let db = state::StateDb::load(target_path)?;
+    let mut totals = Totals::default();
+    let mut by_up: BTreeMap<String, Totals> = BTreeMap::new();
+    let mut by_month: BTreeMap<String, Totals> = BTreeMap::new();
+    let mut converted = 0;
+
+    for video in videos {
+        for group in [
+            &mut totals,
+            by_up.entry(video.info.uname.clone()).or_default(),
+            by_month.entry(month(&video.info)).or_default(),
+        ] {
+            group.cached += 1;
+            group.cached_bytes += video.disk_size;
+        }
+        if db.is_converted(&item_name(video)) {
+            converted += 1;
+        }
+    }
+
+    let mut bits = 0.0;
+    let mut seconds = 0.0;
+    let entries = if target_path.is_dir() {
+        archive::scan(target_path)?
+    } else {
+        Vec::new()
+    };
+    for entry in &entries {
+        let bytes = entry.file.metadata().map(|m| m.len()).unwrap_or_default();
+        for group in [
+            &mut totals,
+            by_up.entry(entry.info.uname.clone()).or_default(),
+            by_month.entry(month(&entry.info)).or_default(),
+        ] {
+            group.archived += 1;
+            group.archived_bytes += bytes;
+        }
+        if let Ok(duration) = probe::duration(&options.ffmpeg, &entry.file) {
+            bits += bytes as f64 * 8.0;
+            seconds += duration;
+        }
+    }
+
+    let mut largest: Vec<&CachedVideo> = videos.iter().collect();
+    largest.sort_by_key(|v| std::cmp::Reverse(v.disk_size));
+    let largest = largest
+        .into_iter()
+        .take(LARGEST)
+        .map(|v| Largest {
+            item: item_name(v),
+            uname: v.info.uname.clone(),
+            title: v.info.title.clone(),
+            bytes: v.disk_size,
+            converted: db.is_converted(&item_name(v)),
+        })
+        .collect();
+
+    Ok(Stats {
+        conversion_ratio: if videos.is_empty() {
+            0.0
+        } else {
+            converted as f64 / videos.len() as f64
+        },
+        average_bitrate: (seconds > 0.0).then(|| bits / seconds),
+        totals,
+        converted,
+        by_up,
+        by_month,
+        largest,
+    })
+}
+
+fn print_groups(key: &'static str, groups: &[(&String, &Totals)]) {
+    let rows: Vec<Vec<String>> = groups
+        .iter()
+        .map(|(name, t)| {
+            vec![
+                name.to_string(),
+                t.cached.to_string(),
+                disk::human_size(t.cached_bytes),
+                t.archived.to_string(),
+                disk::human_size(t.archived_bytes),
+            ]
+        })
+        .collect();
+    print_table(
+        &[
+            tr!(key),
+            tr!("CACHED"),
+            tr!("SIZE"),
+            tr!("ARCHIVED"),
+            tr!("SIZE"),
+        ],
+        &[false, true, true, true, true],
+        &rows,
+    );
+}
+
+fn print(stats: &Stats) {
+    let t = &stats.totals;
+    println!(
+        "{}",
+        tr!(
+            "Cache: {} items, {}",
+            t.cached,
+            disk::human_size(t.cached_bytes)
+        )
+    );
+    println!(
+        "{}",
+        tr!(
+            "Archive: {} videos, {}",
+            t.archived,
+            disk::human_size(t.archived_bytes)
+        )
+    );
+    println!(
+        "{}",
+        tr!(
+            "Converted {} of {} cached items ({}%)",
+            stats.converted,
+            t.cached,
+            format!("{:.0}", stats.conversion_ratio * 100.0)
+        )
+    );
+    if let Some(bitrate) = stats.average_bitrate {
+        println!(
+            "{}",
+            tr!(
+                "Average bitrate {} kb/s",
+                format!("{:.0}", bitrate / 1000.0)
+            )
+        );
+    }
+
+    // Uploaders taking the most space first
+    let mut by_up: Vec<(&String, &Totals)> = stats.by_up.iter().collect();
+    by_up.sort_by_key(|(_, t)| std::cmp::Reverse(t.cached_bytes + t.archived_bytes));
+    println!();
+    print_groups("UP", &by_up);
+    println!();
+    print_groups("MONTH", &stats.by_month.iter().collect::<Vec<_>>());
+
+    println!();
+    let rows: Vec<Vec<String>> = stats
+        .largest
+        .iter()
+        .map(|l| {
+            vec![
+                l.item.clone(),
+                l.uname.clone(),
+                l.title.clone(),
+                disk::human_size(l.bytes),
+                tr!(if l.converted { "yes" } else { "no" }).to_string(),
+            ]
+        })
+        .collect();
+    print_table(
+        &[
+            tr!("LARGEST"),
+            tr!("UP"),
+            tr!("TITLE"),
+            tr!("SIZE"),
+            tr!("CONVERTED"),
+        ],
+        &[false, false, false, true, false],
+        &rows,
+    );
+}
+
+/// Print statistics of the cached videos and the archive, as JSON if `json`
+pub fn show(
+    videos: &[CachedVideo],
+    target_path: &Path,
+    json: bool,
+    options: &ConvertOptions,
+) -> Result<(), error::Error> {
+    let stats = collect(videos, target_path, options)?;
+    if output::json() {
+        output::emit("stats", &stats)?;
+    } else if json {
+        println!("{}", serde_json::to_string_pretty(&stats)?);
+    } else {
+        print(&stats);
+    }
+    Ok(())
+}
@@ -0,0 +1,270 @@
+/// One-command maintenance of the archive, e.g. from cron
+///
+/// The cache is compared against the state database: new and previously
+/// failed items are converted, and sources converted by an earlier run are
+/// removed once the output recorded for them is confirmed to exist, through
+/// the journal like `--autoremove`. Sources converted in this run are kept
+/// until the next sync, so a bad output can still be redone.
+use std::path::{Path, PathBuf};
+
+use log::*;
+
+use crate::dirs::Dirs;
+use crate::i18n::tr;
+use crate::select::item_name;
+use crate::{
+    convert_video, disk, error, get_video_list, ignore, ignored, journal, output_valid, state,
+    ConvertOptions, Summary,
+};
+
+// A converted source to remove
+struct Removal {
+    item: String,
+    dir: PathBuf,
+    /// Output recorded in the state database
+    output: PathBuf,
+    size: u64,
+}
+
+// Cache items compared against the archive
+#[derive(Default)]
+struct Plan {
+    convert: Vec<String>,
+    remove: Vec<Removal>,
+    missing: Vec<String>, // converted before, but the output is gone
+    archived_only: usize, // converted items no longer in the cache
+}
+
+fn plan(
+    source_path: &Path,
+    target_path: &Path,
+    options: &ConvertOptions,
+) -> Result<Plan, error::Error> {
+    let db = state::StateDb::load(target_path)?;
+    let ignore = ignore::Ignore::load(source_path, &options.exclude)?;
+    let mut videos = get_video_list(source_path)?;
+    videos.sort_by_key(item_name);
+
+    let mut plan = Plan::default();
+    let mut cached = Vec::new();
+    for video in &videos {
+        let name = item_name(video);
+        if ignored(&ignore, &video.dir) {
+            // Still cached, so not counted as archived only
+            cached.push(name);
+            continue;
+        }
+        // States written before outputs were recorded have the layout's
+        let output = db
+            .output(&name)
+            .unwrap_or_else(|| options.layout.output(&video.info, target_path).file);
+        if !db.is_converted(&name) {
+            plan.convert.push(name.clone());
+        } else if output_valid(&output) {
+            plan.remove.push(Removal {
+                item: name.clone(),
+                dir: video.dir.clone(),
+                output,
+                size: video.disk_size,
+            });
+        } else {
+            plan.missing.push(name.clone());
+        }
+        cached.push(name);
+    }
+    plan.archived_only = db
+        .items()
+        .filter(|(item, s)| s.status == state::Status::Converted && !cached.contains(item))
+        .count();
+    Ok(plan)
+}
+
+/// Bring the archive up to date, only printing the changes if `dry_run`
+pub fn run(
+    dirs: &Dirs,
+    source_path: &Path,
+    target_path: &Path,
+    dry_run: bool,
+    options: &ConvertOptions,
+) -> Result<Summary, error::Error> {
+    let plan = plan(source_path, target_path, options)?;
+
+    if dry_run {
+        println!("{}", tr!("Would convert {} new items", plan.convert.len()));
+        for item in &plan.convert {
+            println!("  + {}", item);
+        }
+        let freed: u64 = plan.remove.iter().map(|removal| removal.size).sum();
+        println!(
+            "{}",
+            tr!(
+                "Would remove {} converted sources, freeing {}",
+                plan.remove.len(),
+                disk::human_size(freed)
+            )
+        );
+        for removal in &plan.remove {
+            println!("  - {}", removal.item);
+        }
+    }
+    if !plan.missing.is_empty() {
+        warn!(
+            "Output missing for converted items, kept their sources (use --restart convert to redo): {}",
+            plan.missing.join(", ")
+        );
+    }
+    if dry_run {
+        return Ok(Summary::default());
+    }
+
+    // Removals are journaled like those of --autoremove, so an interrupted
+    // one is finished by the next run
+    let journal = journal::Journal::new(target_path);
+    journal.recover(options.permanent)?;
+    let mut removed = 0;
+    let mut freed = 0;
+    for removal in &plan.remove {
+        info!("Removing converted source {}", removal.dir.display());
+        match journal.remove(
+            &removal.item,
+            &removal.dir,
+            &removal.output,
+            options.permanent,
+        ) {
+            Ok(_) => {
+                removed += 1;
+                freed += removal.size;
+            }
+            Err(e) => error!("Failed to remove {}: {}", removal.item, e),
+        }
+    }
+
+    let summary = if plan.convert.is_empty() {
+        Summary::default()
+    } else {
+        convert_video(dirs, plan.convert.clone(), options)?
+    };
+
+    println!("{}", tr!("Sync finished"));
+    println!(
+        "{}",
+        tr!(
+            "  converted {}, failed {}, skipped {}",
+            summary.converted,
+            summary.failed,
+            summary.skipped
+        )
+    );
+    println!(
+        "{}",
+        tr!(
+            "  removed {} sources converted earlier, freed {}",
+            removed,
+            disk::human_size(freed)
+        )
+    );
+    if !plan.missing.is_empty() {
+        println!(
+            "{}",
+            tr!("  {} converted items have no output", plan.missing.len())
+        );
+    }
+    println!(
+        "{}",
+        tr!("  {} archived items no longer cached", plan.archived_only)
+    );
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::{self, Item, StubMuxer, TempDir};
+    use std::fs;
+
+    #[test]
+    fn plan_converts_new_items_and_removes_archived_ones() {
+        let dir = TempDir::new();
+        let cache = dir.path().join("cache");
+        let target = dir.path().join("output");
+        for (id, title) in [
+            (111, "New"),
+            (222, "Archived"),
+            (333, "Lost"),
+            (444, "Failed"),
+        ] {
+            Item::single(id, title).write(&cache);
+        }
+        fs::create_dir_all(target.join("UP - Archived")).unwrap();
+        fs::write(target.join("UP - Archived/222.mp4"), "mp4").unwrap();
+        let mut db = state::StateDb::load(&target).unwrap();
+        for item in ["222", "333", "555"] {
+            db.set(item, state::Status::Converted, None);
+        }
+        db.set("444", state::Status::Failed, Some("broken".to_string()));
+        db.save().unwrap();
+        let muxer = StubMuxer::default();
+        let options = fixture::options(&[], &dir.path().join("work"), &muxer);
+
+        let plan = plan(&cache, &target, &options).unwrap();
+        assert_eq!(plan.convert, ["111", "444"]);
+        let removed: Vec<(&str, &PathBuf)> = plan
+            .remove
+            .iter()
+            .map(|removal| (removal.item.as_str(), &removal.output))
+            .collect();
+        assert_eq!(removed, [("222", &target.join("UP - Archived/222.mp4"))]);
+        assert_eq!(plan.missing, ["333"]);
+        // 555 is no longer cached
+        assert_eq!(plan.archived_only, 1);
+    }
+
+    #[test]
+    fn plan_leaves_out_ignored_items() {
+        let dir = TempDir::new();
+        let cache = dir.path().join("cache");
+        Item::single(111, "Kept raw").write(&cache);
+        Item::single(222, "Single").write(&cache);
+        Item::single(333, "Other").write(&cache);
+        fs::write(cache.join(ignore::IGNORE_FILE), "# raw\n111\n").unwrap();
+        let muxer = StubMuxer::default();
+        let args = ["--exclude", "Oth*"];
+        let options = fixture::options(&args, &dir.path().join("work"), &muxer);
+
+        let plan = plan(&cache, &dir.path().join("output"), &options).unwrap();
+        assert_eq!(plan.convert, ["222"]);
+        assert_eq!(plan.archived_only, 0);
+    }
+
+    #[test]
+    fn run_removes_sources_whose_recorded_output_exists() {
+        let dir = TempDir::new();
+        let cache = dir.path().join("cache");
+        let target = dir.path().join("output");
+        let kept = Item::single(111, "Moved").write(&cache);
+        let converted = Item::single(222, "Single").write(&cache);
+        // The layout's output of 111 exists, but not the one recorded
+        for file in ["UP - Moved/111.mp4", "elsewhere/222.mp4"] {
+            fs::create_dir_all(target.join(file).parent().unwrap()).unwrap();
+            fs::write(target.join(file), "mp4").unwrap();
+        }
+        let mut db = state::StateDb::load(&target).unwrap();
+        for (item, output) in [("111", "renamed/111.mp4"), ("222", "elsewhere/222.mp4")] {
+            db.set(item, state::Status::Converted, None);
+            db.set_output(item, &target.join(output));
+        }
+        db.save().unwrap();
+        let muxer = StubMuxer::default();
+        let options = fixture::options(&[], &dir.path().join("work"), &muxer);
+        let dirs = Dirs {
+            source: cache.clone(),
+            target: target.clone(),
+        };
+
+        run(&dirs, &cache, &target, false, &options).unwrap();
+        assert!(kept.is_dir());
+        assert!(!converted.exists());
+        let journal = fs::read_to_string(target.join(".bilibili-journal.jsonl")).unwrap();
+        assert!(journal.contains(r#"{"item":"222","step":"removed"}"#));
+    }
+}
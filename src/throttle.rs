@@ -0,0 +1,49 @@
+/// Rate limited file copies, so a long batch running in the background
+/// does not starve other applications using the same disk.
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const CHUNK_SIZE: usize = 256 * 1024;
+
+/// Copy `reader` to `writer`, at most `limit` bytes per second if given
+pub fn copy<R: Read, W: Write>(
+    reader: &mut R,
+    writer: &mut W,
+    limit: Option<u64>,
+) -> io::Result<u64> {
+    let limit = match limit {
+        Some(limit) => limit,
+        None => return io::copy(reader, writer),
+    };
+    let start = Instant::now();
+    let mut buffer = vec![0; CHUNK_SIZE];
+    let mut copied = 0;
+    loop {
+        let n = match reader.read(&mut buffer) {
+            Ok(0) => return Ok(copied),
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        writer.write_all(&buffer[..n])?;
+        copied += n as u64;
+        // Sleep off whatever the copy is ahead of the allowed rate
+        let due = Duration::from_secs_f64(copied as f64 / limit as f64);
+        if let Some(ahead) = due.checked_sub(start.elapsed()) {
+            thread::sleep(ahead);
+        }
+    }
+}
+
+/// Like `fs::copy`, at most `limit` bytes per second if given
+pub fn copy_file(source: &Path, target: &Path, limit: Option<u64>) -> io::Result<u64> {
+    if limit.is_none() {
+        return fs::copy(source, target);
+    }
+    let mut reader = fs::File::open(source)?;
+    let mut writer = fs::File::create(target)?;
+    copy(&mut reader, &mut writer, limit)
+}
@@ -0,0 +1,138 @@
+/// Preview images of converted videos
+///
+/// A contact sheet is a grid of frames sampled evenly over the whole video,
+/// the preview GIF is a short timelapse of the same. Both are written next
+/// to the output for browsing a large archive without opening each video.
+use std::path::Path;
+use std::str::FromStr;
+
+use clap::ValueEnum;
+use log::*;
+
+use crate::error;
+use crate::ffmpeg::Ffmpeg;
+use crate::layout::Output;
+use crate::probe;
+
+// Width of a single frame, the height follows the aspect ratio
+const FRAME_WIDTH: u32 = 320;
+const GIF_FRAMES: u32 = 40;
+const GIF_FPS: u32 = 4;
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Kind {
+    /// Grid of frames as `sheet.jpg`
+    Sheet,
+    /// Animated timelapse as `preview.gif`
+    Gif,
+}
+
+/// Columns and rows of a contact sheet
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Grid {
+    pub columns: u32,
+    pub rows: u32,
+}
+
+impl FromStr for Grid {
+    type Err = String;
+
+    /// Accepts `COLUMNSxROWS`, e.g. `4x4`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid grid '{}', expected e.g. 4x4", s);
+        let (columns, rows) = s
+            .trim()
+            .to_lowercase()
+            .split_once('x')
+            .map(|(c, r)| (c.parse::<u32>(), r.parse::<u32>()))
+            .ok_or_else(invalid)?;
+        match (columns, rows) {
+            (Ok(columns), Ok(rows)) if columns > 0 && rows > 0 => Ok(Grid { columns, rows }),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+// Frames per second that spreads `frames` evenly over the video
+fn sample_rate(frames: u32, duration: f64) -> String {
+    format!("{}/{:.3}", frames, duration.max(1.0))
+}
+
+fn sheet(
+    ffmpeg: &Ffmpeg,
+    video: &Path,
+    duration: f64,
+    grid: Grid,
+    output: &Path,
+) -> Result<(), error::Error> {
+    // ffmpeg -i video -vf fps=N/duration,scale=W:-1,tile=CxR -frames:v 1 -y sheet.jpg
+    let filter = format!(
+        "fps={},scale={}:-1,tile={}x{}",
+        sample_rate(grid.columns * grid.rows, duration),
+        FRAME_WIDTH,
+        grid.columns,
+        grid.rows
+    );
+    let mut cmd = ffmpeg.command();
+    cmd.arg("-i").arg(video).arg("-vf").arg(filter);
+    cmd.args(["-frames:v", "1"]).args(ffmpeg.thread_args());
+    cmd.arg("-y").arg(output);
+    ffmpeg.run(cmd)
+}
+
+fn gif(ffmpeg: &Ffmpeg, video: &Path, duration: f64, output: &Path) -> Result<(), error::Error> {
+    // ffmpeg -i video -vf fps=N/duration,scale=W:-1,setpts=N/(R*TB) -r R -frames:v N -loop 0 -y preview.gif
+    let filter = format!(
+        "fps={},scale={}:-1,setpts=N/({}*TB)",
+        sample_rate(GIF_FRAMES, duration),
+        FRAME_WIDTH,
+        GIF_FPS
+    );
+    let mut cmd = ffmpeg.command();
+    cmd.arg("-i").arg(video).arg("-vf").arg(filter);
+    cmd.arg("-r").arg(GIF_FPS.to_string());
+    cmd.arg("-frames:v").arg(GIF_FRAMES.to_string());
+    cmd.args(["-loop", "0"]).args(ffmpeg.thread_args());
+    cmd.arg("-y").arg(output);
+    ffmpeg.run(cmd)
+}
+
+/// Generate the requested previews of a finished output. Previews are a
+/// convenience, failures are logged and do not fail the item.
+pub fn generate(ffmpeg: &Ffmpeg, output: &Output, kinds: &[Kind], grid: Grid) {
+    if kinds.is_empty() {
+        return;
+    }
+    let duration = match probe::duration(ffmpeg, &output.file) {
+        Ok(duration) => duration,
+        Err(e) => {
+            warn!("Skip thumbnails of {}: {}", output.file.display(), e);
+            return;
+        }
+    };
+    for kind in kinds {
+        let result = match kind {
+            Kind::Sheet => sheet(
+                ffmpeg,
+                &output.file,
+                duration,
+                grid,
+                &output.side_file("sheet.jpg"),
+            ),
+            Kind::Gif => gif(
+                ffmpeg,
+                &output.file,
+                duration,
+                &output.side_file("preview.gif"),
+            ),
+        };
+        if let Err(e) = result {
+            warn!(
+                "Failed to generate {:?} thumbnail of {}: {}",
+                kind,
+                output.file.display(),
+                e
+            );
+        }
+    }
+}
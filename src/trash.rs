@@ -0,0 +1,175 @@
+/// Moving directories to the system trash instead of deleting them: the
+/// Finder or `~/.Trash` on macOS, the freedesktop.org trash elsewhere on
+/// Unix and the Recycle Bin on Windows, through PowerShell
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use chrono::Local;
+
+use crate::error::{self, Context};
+
+/// Move `path` to the trash of the current user
+pub fn move_to_trash(path: &Path) -> Result<(), error::Error> {
+    let path = fs::canonicalize(path).context("resolve", path)?;
+    if cfg!(target_os = "macos") {
+        macos(&path)
+    } else if cfg!(unix) {
+        freedesktop(&path)
+    } else if cfg!(windows) {
+        recycle_bin(&path)
+    } else {
+        Err(error::Error::TrashFailed(path))
+    }
+}
+
+// PowerShell moving `path` to the Recycle Bin, the path is passed in the
+// environment so it needs no quoting
+fn recycle_bin_command(path: &Path) -> Command {
+    let kind = if path.is_dir() { "Directory" } else { "File" };
+    let script = format!(
+        "Add-Type -AssemblyName Microsoft.VisualBasic; \
+         [Microsoft.VisualBasic.FileIO.FileSystem]::Delete{}(\
+         $env:BILIBILI_TRASH, 'OnlyErrorDialogs', 'SendToRecycleBin')",
+        kind
+    );
+    // Canonical paths are verbatim ones, which the shell does not take
+    let path = path.to_string_lossy();
+    let path = match path.strip_prefix(r"\\?\UNC\") {
+        Some(unc) => format!(r"\\{}", unc),
+        None => path.strip_prefix(r"\\?\").unwrap_or(&path).to_string(),
+    };
+    let mut cmd = Command::new("powershell");
+    cmd.args(["-NoProfile", "-NonInteractive", "-Command", &script])
+        .env("BILIBILI_TRASH", path);
+    cmd
+}
+
+fn recycle_bin(path: &Path) -> Result<(), error::Error> {
+    match recycle_bin_command(path).output() {
+        Ok(output) if output.status.success() && !path.exists() => Ok(()),
+        _ => Err(error::Error::TrashFailed(path.to_path_buf())),
+    }
+}
+
+fn macos(path: &Path) -> Result<(), error::Error> {
+    // Asking Finder keeps "Put Back" working and handles per-volume trashes
+    let script = format!(
+        "tell application \"Finder\" to delete POSIX file \"{}\"",
+        path.display()
+            .to_string()
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+    );
+    let finder = Command::new("osascript").arg("-e").arg(script).output();
+    if matches!(finder, Ok(ref output) if output.status.success()) {
+        return Ok(());
+    }
+    let home = env::home_dir().ok_or_else(|| error::Error::TrashFailed(path.to_path_buf()))?;
+    let trash = home.join(".Trash");
+    let target = unique_name(&trash, path)?;
+    fs::rename(path, target).map_err(|_| error::Error::TrashFailed(path.to_path_buf()))
+}
+
+// Trash directory of the freedesktop.org trash specification
+fn freedesktop_trash() -> Option<PathBuf> {
+    match env::var("XDG_DATA_HOME") {
+        Ok(data) if !data.is_empty() => Some(Path::new(&data).join("Trash")),
+        _ => env::home_dir().map(|home| home.join(".local/share/Trash")),
+    }
+}
+
+fn freedesktop(path: &Path) -> Result<(), error::Error> {
+    let failed = || error::Error::TrashFailed(path.to_path_buf());
+    let trash = freedesktop_trash().ok_or_else(failed)?;
+    let files = trash.join("files");
+    let info = trash.join("info");
+    fs::create_dir_all(&files).context("create directory", &files)?;
+    fs::create_dir_all(&info).context("create directory", &info)?;
+
+    let target = unique_name(&files, path)?;
+    let name = target
+        .file_name()
+        .ok_or_else(failed)?
+        .to_string_lossy()
+        .to_string();
+    let info_file = info.join(format!("{}.trashinfo", name));
+    fs::write(
+        &info_file,
+        format!(
+            "[Trash Info]\nPath={}\nDeletionDate={}\n",
+            url_encode(&path.to_string_lossy()),
+            Local::now().format("%Y-%m-%dT%H:%M:%S")
+        ),
+    )
+    .context("write", &info_file)?;
+
+    if fs::rename(path, &target).is_ok() {
+        return Ok(());
+    }
+    let _ = fs::remove_file(&info_file);
+    // The home trash is on another filesystem, gio knows the per-volume trash
+    match Command::new("gio").arg("trash").arg(path).output() {
+        Ok(output) if output.status.success() => Ok(()),
+        _ => Err(failed()),
+    }
+}
+
+/// A name in `dir` for `path` not used yet, appending a counter if needed
+pub fn unique_name(dir: &Path, path: &Path) -> Result<PathBuf, error::Error> {
+    let name = path
+        .file_name()
+        .ok_or_else(|| error::Error::InvalidFileName(path.to_path_buf()))?
+        .to_string_lossy()
+        .to_string();
+    let mut target = dir.join(&name);
+    let mut counter = 1;
+    while target.exists() {
+        target = dir.join(format!("{}.{}", name, counter));
+        counter += 1;
+    }
+    Ok(target)
+}
+
+// Percent-encode a path for the trashinfo file, keeping `/`
+fn url_encode(s: &str) -> String {
+    let mut encoded = String::new();
+    for b in s.bytes() {
+        if b.is_ascii_alphanumeric() || b"/-_.~".contains(&b) {
+            encoded.push(b as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", b));
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::TempDir;
+    use std::ffi::OsStr;
+
+    #[test]
+    fn recycles_with_a_plain_path() {
+        let dir = TempDir::new();
+        let cmd = recycle_bin_command(dir.path());
+        let script = cmd.get_args().last().unwrap().to_string_lossy();
+        assert!(
+            script.contains("DeleteDirectory($env:BILIBILI_TRASH"),
+            "{}",
+            script
+        );
+        let env = |path: &str| {
+            let cmd = recycle_bin_command(Path::new(path));
+            let (_, value) = cmd.get_envs().next().unwrap();
+            value.unwrap().to_os_string()
+        };
+        assert_eq!(env(r"\\?\C:\cache\111"), OsStr::new(r"C:\cache\111"));
+        assert_eq!(
+            env(r"\\?\UNC\nas\cache\111"),
+            OsStr::new(r"\\nas\cache\111")
+        );
+    }
+}
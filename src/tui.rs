@@ -0,0 +1,966 @@
+/// Interactive browser for cached videos
+///
+/// In a terminal the cache is shown full screen, one item per line:
+///   Up/Down, j/k, PgUp/PgDn, Home/End   move the cursor
+///   Space      toggle the selection of the item under the cursor
+///   /          fuzzy filter by title, group, UP or item id, ended by Enter
+///   a          select all listed items, `n` clears the selection
+///   +, -       move the item under the cursor earlier or later among the
+///              selected items, shown by their numbers `[1]`, or in the
+///              queue, shown by `(1)`
+///   c          queue the selected items for conversion
+///   x          clean (remove) the selected cache items
+///   i, Enter   inspect the item under the cursor
+///   r          rescan the cache directory
+///   q, Esc     quit
+///
+/// Queued items are converted one after another while browsing goes on, so
+/// the queue can still be reordered, and Space takes an item out of it
+/// again. The item being converted is marked `(*)`, what is still queued on
+/// quitting is converted before the program exits. While the screen is
+/// shown, logs and the progress line of the conversions are not, the header
+/// counts the converted and failed items instead.
+///
+/// Raw mode is set with `stty`, so where there is none, e.g. on Windows, or
+/// if stdin is no terminal, a line based prompt takes row numbers instead:
+///   /text      filter (`/` clears)
+///   1 3 5-7    toggle selection of the listed rows
+///   +N, -N     move the item on row N earlier or later in the order
+///   a, n, c, x, r, q and `i N` as above
+use std::io::{self, BufRead, IsTerminal, Read, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::{Mutex, MutexGuard};
+use std::thread;
+use std::time::Duration;
+
+use log::*;
+
+use crate::dirs::Dirs;
+use crate::info::print_metadata;
+use crate::list::{self, Column};
+use crate::{
+    convert_jobs, disk, error, get_video_list, item_job, item_path, progress, queue, remove_source,
+    signal, CachedVideo, ConvertOptions,
+};
+
+/// Case insensitive subsequence match, so `bjcx` finds `Bilibili 教程 CX`.
+pub fn fuzzy_match(pattern: &str, text: &str) -> bool {
+    let text = text.to_lowercase();
+    let mut chars = text.chars();
+    pattern
+        .to_lowercase()
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .all(|p| chars.any(|c| c == p))
+}
+
+fn item_name(video: &CachedVideo) -> String {
+    list::cell(video, Column::Dir)
+}
+
+fn matches(video: &CachedVideo, filter: &str) -> bool {
+    let info = &video.info;
+    let haystack = format!(
+        "{} {} {} {} {}",
+        item_name(video),
+        info.item_id,
+        info.uname,
+        info.group_title,
+        info.title
+    );
+    fuzzy_match(filter, &haystack)
+}
+
+// Parse row numbers like `1 3 5-7`, rows are 1-based
+fn parse_rows(input: &str) -> Option<Vec<usize>> {
+    let mut rows = Vec::new();
+    for part in input.split([' ', ',']).filter(|p| !p.is_empty()) {
+        if let Some((start, end)) = part.split_once('-') {
+            let start: usize = start.parse().ok()?;
+            let end: usize = end.parse().ok()?;
+            rows.extend(start..=end);
+        } else {
+            rows.push(part.parse().ok()?);
+        }
+    }
+    Some(rows)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Key {
+    Up,
+    Down,
+    PageUp,
+    PageDown,
+    Home,
+    End,
+    Enter,
+    Backspace,
+    Esc,
+    /// Ctrl-C, which raw mode delivers as a key rather than a signal
+    Interrupt,
+    Char(char),
+}
+
+// Keys in `input` as read from a terminal in raw mode, unknown escape
+// sequences are dropped
+fn parse_keys(input: &[u8]) -> Vec<Key> {
+    let text = String::from_utf8_lossy(input);
+    let mut chars = text.chars().peekable();
+    let mut keys = Vec::new();
+    while let Some(c) = chars.next() {
+        let key = match c {
+            '\x1b' if matches!(chars.peek(), Some('[' | 'O')) => {
+                chars.next();
+                let mut sequence = String::new();
+                for c in chars.by_ref() {
+                    sequence.push(c);
+                    if c.is_ascii_alphabetic() || c == '~' {
+                        break;
+                    }
+                }
+                match sequence.as_str() {
+                    "A" => Key::Up,
+                    "B" => Key::Down,
+                    "5~" => Key::PageUp,
+                    "6~" => Key::PageDown,
+                    "H" | "1~" => Key::Home,
+                    "F" | "4~" => Key::End,
+                    _ => continue,
+                }
+            }
+            '\x1b' => Key::Esc,
+            '\r' | '\n' => Key::Enter,
+            '\x7f' | '\x08' => Key::Backspace,
+            '\x03' => Key::Interrupt,
+            c if c.is_control() => continue,
+            c => Key::Char(c),
+        };
+        keys.push(key);
+    }
+    keys
+}
+
+// Fit `s` into `width` columns, cutting it short with `…`
+fn fit(s: &str, width: usize) -> String {
+    if list::display_width(s) <= width {
+        return list::pad(s, width, false);
+    }
+    let mut fitted = String::new();
+    let mut used = 0;
+    for c in s.chars() {
+        let w = list::display_width(c.encode_utf8(&mut [0; 4]));
+        if used + w + 1 > width {
+            break;
+        }
+        fitted.push(c);
+        used += w;
+    }
+    fitted.push('…');
+    list::pad(&fitted, width, false)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mode {
+    Browse,
+    /// Typing the filter
+    Filter,
+    /// Asking whether to remove the selected items
+    Confirm,
+}
+
+// What `Browser::handle` leaves to the caller, as it needs the terminal
+#[derive(Debug, PartialEq)]
+enum Action {
+    Clean,
+    Inspect(String),
+    Rescan,
+    Quit,
+}
+
+/// The conversion of the queued items, going on while the browser is used
+struct Run {
+    queue: queue::Queue,
+    /// Item being converted
+    current: Option<String>,
+    converted: usize,
+    failed: usize,
+    /// Set on quitting, the worker stops once the queue is empty
+    quit: bool,
+}
+
+impl Run {
+    fn new(order: queue::Order) -> Run {
+        Run {
+            queue: queue::Queue::new(order),
+            current: None,
+            converted: 0,
+            failed: 0,
+            quit: false,
+        }
+    }
+
+    // Whether `item` is queued or being converted
+    fn has(&self, item: &str) -> bool {
+        self.current.as_deref() == Some(item) || self.queue.contains(item)
+    }
+}
+
+fn lock(run: &Mutex<Run>) -> MutexGuard<'_, Run> {
+    run.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+// Convert the queued items one at a time, until the browser quit and the
+// queue is empty or the run is interrupted
+fn worker(dirs: &Dirs, run: &Mutex<Run>, options: &ConvertOptions) {
+    while !signal::interrupted() {
+        let job = {
+            let mut run = lock(run);
+            let job = run.queue.pop();
+            if job.is_none() && run.quit {
+                return;
+            }
+            run.current = job.as_ref().map(|job| job.item.clone());
+            job
+        };
+        let Some(job) = job else {
+            thread::sleep(Duration::from_millis(200));
+            continue;
+        };
+        let result = convert_jobs(dirs, vec![job], options);
+        let mut run = lock(run);
+        run.current = None;
+        match result {
+            Ok(summary) => {
+                run.converted += summary.converted;
+                run.failed += summary.failed;
+            }
+            Err(error::Error::Interrupted) => return,
+            Err(e) => {
+                error!("Conversion failed: {}", e);
+                run.failed += 1;
+            }
+        }
+    }
+}
+
+struct Browser<'a> {
+    source_path: &'a Path,
+    options: &'a ConvertOptions,
+    run: &'a Mutex<Run>,
+    /// Converted and failed items of the run when the cache was last scanned
+    finished: usize,
+    videos: Vec<CachedVideo>,
+    filter: String,
+    /// Selected items in the order they are queued
+    selected: Vec<String>,
+    /// Row of the cursor among the listed videos, and the first row shown
+    cursor: usize,
+    top: usize,
+    mode: Mode,
+    status: String,
+}
+
+impl Browser<'_> {
+    fn listed(&self) -> Vec<&CachedVideo> {
+        self.videos
+            .iter()
+            .filter(|v| matches(v, &self.filter))
+            .collect()
+    }
+
+    fn listed_items(&self) -> Vec<String> {
+        self.listed().into_iter().map(item_name).collect()
+    }
+
+    // Select `item`, or take it out of the selection or the queue
+    fn toggle(&mut self, item: String) {
+        if let Some(i) = self.selected.iter().position(|s| *s == item) {
+            self.selected.remove(i);
+            return;
+        }
+        let mut run = lock(self.run);
+        if run.current.as_deref() == Some(&*item) {
+            self.status = format!("{} is being converted", item);
+        } else if run.queue.remove(&item).is_some() {
+            self.status = format!("Took {} out of the queue", item);
+        } else {
+            self.selected.push(item);
+        }
+    }
+
+    // Select the listed items that are neither selected nor queued yet
+    fn select_all(&mut self, listed: Vec<String>) {
+        let run = lock(self.run);
+        for item in listed {
+            if !self.selected.contains(&item) && !run.has(&item) {
+                self.selected.push(item);
+            }
+        }
+    }
+
+    // Move a selected or queued item one place earlier or later in the order
+    fn reorder(&mut self, item: &str, earlier: bool) -> bool {
+        let to = |i: usize, len: usize| {
+            let to = if earlier {
+                i.checked_sub(1)
+            } else {
+                Some(i + 1)
+            };
+            to.filter(|to| *to < len)
+        };
+        if let Some(i) = self.selected.iter().position(|s| s == item) {
+            return match to(i, self.selected.len()) {
+                Some(to) => {
+                    self.selected.swap(i, to);
+                    true
+                }
+                None => false,
+            };
+        }
+        let mut run = lock(self.run);
+        let queued = run.queue.jobs().iter().position(|job| job.item == item);
+        match queued.and_then(|i| to(i, run.queue.len())) {
+            Some(to) => run.queue.move_to(item, to),
+            None => false,
+        }
+    }
+
+    fn rescan(&mut self) -> Result<(), error::Error> {
+        self.videos = get_video_list(self.source_path)?;
+        self.selected
+            .retain(|item| self.videos.iter().any(|v| item_name(v) == *item));
+        Ok(())
+    }
+
+    // Rescan once the run finished items, converted sources may be removed
+    fn refresh(&mut self) -> Result<(), error::Error> {
+        let finished = {
+            let run = lock(self.run);
+            run.converted + run.failed
+        };
+        if finished != self.finished {
+            self.finished = finished;
+            self.rescan()?;
+        }
+        Ok(())
+    }
+
+    fn convert(&mut self) {
+        // Selected jobs are converted in the order they are queued in
+        let jobs: Vec<queue::Job> = self
+            .selected
+            .drain(..)
+            .map(|item| item_job(&item_path(self.source_path, &item), true))
+            .collect();
+        self.status = format!("Queued {} items", jobs.len());
+        let mut run = lock(self.run);
+        for job in jobs {
+            run.queue.push(job);
+        }
+    }
+
+    // What the run is doing, e.g. `converting 111, 2 queued, 1 converted`
+    fn progress(&self) -> String {
+        let run = lock(self.run);
+        let mut parts = Vec::new();
+        if let Some(item) = &run.current {
+            parts.push(format!("converting {}", item));
+        }
+        if !run.queue.is_empty() {
+            parts.push(format!("{} queued", run.queue.len()));
+        }
+        if run.converted > 0 {
+            parts.push(format!("{} converted", run.converted));
+        }
+        if run.failed > 0 {
+            parts.push(format!("{} failed", run.failed));
+        }
+        parts.join(", ")
+    }
+
+    fn clean(&mut self) -> Result<(), error::Error> {
+        for item in &self.selected {
+            let path = item_path(self.source_path, item);
+            info!("Removing directory {}", path.display());
+            if let Err(e) = remove_source(&path, self.options.permanent) {
+                error!("Failed to remove {}: {}", path.display(), e);
+            }
+        }
+        self.selected.clear();
+        self.rescan()
+    }
+
+    // Number of `item` among the selected items, e.g. `[2]`, its place in
+    // the queue, e.g. `(1)`, `(*)` while it is converted, or `[ ]`
+    fn mark(&self, item: &str) -> String {
+        if let Some(i) = self.selected.iter().position(|s| s == item) {
+            return format!("[{}]", i + 1);
+        }
+        let run = lock(self.run);
+        if run.current.as_deref() == Some(item) {
+            return "(*)".to_string();
+        }
+        match run.queue.jobs().iter().position(|job| job.item == item) {
+            Some(i) => format!("({})", i + 1),
+            None => "[ ]".to_string(),
+        }
+    }
+
+    /// Update the state for `key`, with `page` rows shown, returning what
+    /// is left to do
+    fn handle(&mut self, key: Key, page: usize) -> Option<Action> {
+        self.status.clear();
+        if key == Key::Interrupt {
+            return Some(Action::Quit);
+        }
+        match self.mode {
+            Mode::Filter => {
+                match key {
+                    Key::Enter | Key::Esc => self.mode = Mode::Browse,
+                    Key::Backspace => {
+                        self.filter.pop();
+                    }
+                    Key::Char(c) => self.filter.push(c),
+                    _ => {}
+                }
+                self.cursor = 0;
+                return None;
+            }
+            Mode::Confirm => {
+                self.mode = Mode::Browse;
+                if key == Key::Char('y') || key == Key::Char('Y') {
+                    return Some(Action::Clean);
+                }
+                self.status = "Nothing removed".to_string();
+                return None;
+            }
+            Mode::Browse => {}
+        }
+
+        let listed = self.listed_items();
+        let last = listed.len().saturating_sub(1);
+        let current = listed.get(self.cursor).cloned();
+        match key {
+            Key::Up | Key::Char('k') => self.cursor = self.cursor.saturating_sub(1),
+            Key::Down | Key::Char('j') => self.cursor = (self.cursor + 1).min(last),
+            Key::PageUp => self.cursor = self.cursor.saturating_sub(page),
+            Key::PageDown => self.cursor = (self.cursor + page).min(last),
+            Key::Home => self.cursor = 0,
+            Key::End => self.cursor = last,
+            Key::Char(' ') => {
+                if let Some(item) = current {
+                    self.toggle(item);
+                    self.cursor = (self.cursor + 1).min(last);
+                }
+            }
+            Key::Char(c @ ('+' | '-')) => {
+                let moved = current.is_some_and(|item| self.reorder(&item, c == '+'));
+                if !moved {
+                    self.status = "Select or queue the item to move it in the order".to_string();
+                }
+            }
+            Key::Char('/') => self.mode = Mode::Filter,
+            Key::Char('a') => self.select_all(listed),
+            Key::Char('n') => self.selected.clear(),
+            Key::Char('c' | 'x') if self.selected.is_empty() => {
+                self.status = "Nothing selected".to_string();
+            }
+            Key::Char('c') => self.convert(),
+            Key::Char('x') => {
+                self.mode = Mode::Confirm;
+                self.status = format!("Remove {} cache items? [y/N]", self.selected.len());
+            }
+            Key::Char('i') | Key::Enter => return current.map(Action::Inspect),
+            Key::Char('r') => return Some(Action::Rescan),
+            Key::Char('q') | Key::Esc => return Some(Action::Quit),
+            _ => {}
+        }
+        None
+    }
+
+    /// The screen of `rows` lines of `cols` columns, scrolled to the cursor
+    fn render(&mut self, rows: usize, cols: usize) -> String {
+        let page = rows.saturating_sub(4).max(1);
+        if self.cursor < self.top {
+            self.top = self.cursor;
+        } else if self.cursor >= self.top + page {
+            self.top = self.cursor + 1 - page;
+        }
+        let listed = self.listed();
+        let shown = &listed[self.top.min(listed.len())..listed.len().min(self.top + page)];
+
+        let ids: Vec<String> = shown.iter().map(|v| v.info.item_id.to_string()).collect();
+        let sizes: Vec<String> = shown
+            .iter()
+            .map(|v| disk::human_size(v.disk_size))
+            .collect();
+        let width = |cells: &[String], header: &str| {
+            cells
+                .iter()
+                .map(|c| list::display_width(c))
+                .chain([header.len()])
+                .max()
+                .unwrap_or_default()
+        };
+        let queued = lock(self.run).queue.len();
+        let mark = format!("[{}]", self.selected.len().max(queued))
+            .len()
+            .max(3);
+        let id = width(&ids, "ID");
+        let size = width(&sizes, "DISK");
+        let up = shown
+            .iter()
+            .map(|v| list::display_width(&v.info.uname))
+            .chain([2])
+            .max()
+            .unwrap_or_default()
+            .min(16);
+        let title = cols.saturating_sub(mark + id + up + size + 8).max(5);
+        let line =
+            |mark_cell: &str, id_cell: &str, up_cell: &str, title_cell: &str, size_cell: &str| {
+                fit(
+                    &format!(
+                        "{} {}  {}  {}  {}",
+                        list::pad(mark_cell, mark, false),
+                        list::pad(id_cell, id, true),
+                        fit(up_cell, up),
+                        fit(title_cell, title),
+                        list::pad(size_cell, size, true)
+                    ),
+                    cols,
+                )
+            };
+
+        let progress = self.progress();
+        let mut screen = vec![fit(
+            &format!(
+                "bilibili: {} items, {} selected{}{}",
+                listed.len(),
+                self.selected.len(),
+                match (self.mode, self.filter.is_empty()) {
+                    (Mode::Filter, _) => format!(", filter /{}_", self.filter),
+                    (_, false) => format!(", filter /{}", self.filter),
+                    _ => String::new(),
+                },
+                if progress.is_empty() {
+                    String::new()
+                } else {
+                    format!(" | {}", progress)
+                }
+            ),
+            cols,
+        )];
+        screen.push(line("", "ID", "UP", "TITLE", "DISK"));
+        for (i, video) in shown.iter().enumerate() {
+            let text = line(
+                &self.mark(&item_name(video)),
+                &ids[i],
+                &video.info.uname,
+                &video.info.title,
+                &sizes[i],
+            );
+            if self.top + i == self.cursor {
+                screen.push(format!("\x1b[7m{}\x1b[0m", text));
+            } else {
+                screen.push(text);
+            }
+        }
+        screen.resize(rows.saturating_sub(2).max(screen.len()), String::new());
+        screen.push(fit(&self.status, cols));
+        screen.push(fit(
+            "space select  / filter  +- order  a all  c convert  x clean  i info  q quit",
+            cols,
+        ));
+        // From the top left, clearing what is left of every line
+        format!("\x1b[H{}\x1b[J", screen.join("\x1b[K\r\n"))
+    }
+}
+
+// Run `stty` on the terminal of stdin, its output if it succeeded
+fn stty(args: &[&str]) -> Option<String> {
+    let output = Command::new("stty")
+        .args(args)
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// The terminal in raw mode showing the alternate screen, restored when
+/// dropped
+struct Screen {
+    /// Settings of the terminal before, as `stty -g` prints them
+    saved: String,
+    /// Logs shown outside of the screen
+    level: LevelFilter,
+}
+
+impl Screen {
+    fn enter() -> Option<Screen> {
+        if !cfg!(unix) || !io::stdin().is_terminal() || !io::stdout().is_terminal() {
+            return None;
+        }
+        let screen = Screen {
+            saved: stty(&["-g"])?,
+            level: log::max_level(),
+        };
+        screen.resume().then_some(screen)
+    }
+
+    // Reads return after half a second without keys, so the screen follows
+    // the run
+    fn resume(&self) -> bool {
+        log::set_max_level(LevelFilter::Off);
+        progress::suppress(true);
+        print!("\x1b[?1049h\x1b[?25l");
+        let _ = io::stdout().flush();
+        stty(&["raw", "-echo", "min", "0", "time", "5"]).is_some()
+    }
+
+    /// Leave the screen for the output of a clean and the like, until
+    /// `resume`
+    fn suspend(&self) {
+        print!("\x1b[?25h\x1b[?1049l");
+        let _ = io::stdout().flush();
+        stty(&[&self.saved]);
+        log::set_max_level(self.level);
+        progress::suppress(false);
+    }
+
+    /// Lines and columns of the terminal
+    fn size(&self) -> (usize, usize) {
+        let size = stty(&["size"]).unwrap_or_default();
+        match size.split_once(' ') {
+            Some((rows, cols)) => (rows.parse().unwrap_or(24), cols.parse().unwrap_or(80)),
+            None => (24, 80),
+        }
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        self.suspend();
+    }
+}
+
+fn wait_for_enter() -> io::Result<()> {
+    print!("Press Enter to return ");
+    io::stdout().flush()?;
+    io::stdin().lock().read_line(&mut String::new())?;
+    Ok(())
+}
+
+fn run_screen(browser: &mut Browser, screen: &Screen) -> Result<(), error::Error> {
+    let mut input = [0; 64];
+    loop {
+        browser.refresh()?;
+        let (rows, cols) = screen.size();
+        print!("{}", browser.render(rows, cols));
+        io::stdout().flush()?;
+        let read = io::stdin().lock().read(&mut input)?;
+        for key in parse_keys(&input[..read]) {
+            let page = rows.saturating_sub(4).max(1);
+            let Some(action) = browser.handle(key, page) else {
+                continue;
+            };
+            match action {
+                Action::Quit => return Ok(()),
+                Action::Rescan => browser.rescan()?,
+                Action::Clean | Action::Inspect(_) => {
+                    screen.suspend();
+                    match action {
+                        Action::Clean => browser.clean()?,
+                        Action::Inspect(item) => {
+                            if let Some(video) =
+                                browser.videos.iter().find(|v| item_name(v) == item)
+                            {
+                                print_metadata(video);
+                            }
+                        }
+                        _ => {}
+                    }
+                    wait_for_enter()?;
+                    screen.resume();
+                }
+            }
+            // Keys typed ahead of an action are not meant for what follows
+            break;
+        }
+    }
+}
+
+fn show(browser: &Browser, videos: &[&CachedVideo]) {
+    let headers = ["", "#", "ID", "UP", "TITLE", "DISK"];
+    let right_aligned = [false, true, true, false, false, true];
+    let rows: Vec<Vec<String>> = videos
+        .iter()
+        .enumerate()
+        .map(|(i, video)| {
+            vec![
+                browser.mark(&item_name(video)),
+                (i + 1).to_string(),
+                video.info.item_id.to_string(),
+                video.info.uname.clone(),
+                video.info.title.clone(),
+                disk::human_size(video.disk_size),
+            ]
+        })
+        .collect();
+    list::print_table(&headers, &right_aligned, &rows);
+}
+
+fn confirm(prompt: &str) -> io::Result<bool> {
+    print!("{} [y/N] ", prompt);
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+    Ok(answer.trim().eq_ignore_ascii_case("y"))
+}
+
+fn run_lines(browser: &mut Browser) -> Result<(), error::Error> {
+    let stdin = io::stdin();
+    loop {
+        browser.refresh()?;
+        let listed = browser.listed_items();
+        show(browser, &browser.listed());
+        let progress = browser.progress();
+        print!(
+            "[{} selected{}{}] /filter, rows, +N, -N, a, n, c, x, i N, r, q > ",
+            browser.selected.len(),
+            if browser.filter.is_empty() {
+                String::new()
+            } else {
+                format!(", filter '{}'", browser.filter)
+            },
+            if progress.is_empty() {
+                String::new()
+            } else {
+                format!(", {}", progress)
+            }
+        );
+        io::stdout().flush()?;
+
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+        let line = line.trim();
+        let row = |number: &str| {
+            number
+                .trim()
+                .parse::<usize>()
+                .ok()
+                .filter(|row| *row >= 1 && *row <= listed.len())
+                .map(|row| listed[row - 1].clone())
+        };
+
+        match line {
+            "" => {}
+            "q" => return Ok(()),
+            "a" => browser.select_all(listed),
+            "n" => browser.selected.clear(),
+            "r" => browser.rescan()?,
+            "c" | "x" if browser.selected.is_empty() => println!("Nothing selected"),
+            "c" => browser.convert(),
+            "x" => {
+                if confirm(&format!("Remove {} cache items?", browser.selected.len()))? {
+                    browser.clean()?;
+                }
+            }
+            _ if line.starts_with('/') => browser.filter = line[1..].trim().to_string(),
+            _ if line.starts_with("i ") => match row(&line[2..]) {
+                Some(item) => {
+                    if let Some(video) = browser.videos.iter().find(|v| item_name(v) == item) {
+                        print_metadata(video);
+                    }
+                }
+                None => println!("No such row"),
+            },
+            _ if line.starts_with(['+', '-']) => match row(&line[1..]) {
+                Some(item) => {
+                    if !browser.reorder(&item, line.starts_with('+')) {
+                        println!("Select or queue the item to move it in the order");
+                    }
+                }
+                None => println!("No such row"),
+            },
+            _ => match parse_rows(line) {
+                Some(rows) => {
+                    for row in rows {
+                        if row == 0 || row > listed.len() {
+                            println!("No such row: {}", row);
+                            continue;
+                        }
+                        browser.toggle(listed[row - 1].clone());
+                    }
+                }
+                None => println!("Unknown command: {}", line),
+            },
+        }
+        if !browser.status.is_empty() {
+            println!("{}", browser.status);
+            browser.status.clear();
+        }
+    }
+}
+
+pub fn run(dirs: &Dirs, source_path: &Path, options: &ConvertOptions) -> Result<(), error::Error> {
+    let videos = get_video_list(source_path)?;
+    let run = Mutex::new(Run::new(options.order()));
+    signal::install();
+    thread::scope(|scope| {
+        scope.spawn(|| worker(dirs, &run, options));
+        let mut browser = Browser {
+            source_path,
+            options,
+            run: &run,
+            finished: 0,
+            videos,
+            filter: String::new(),
+            selected: Vec::new(),
+            cursor: 0,
+            top: 0,
+            mode: Mode::Browse,
+            status: String::new(),
+        };
+        let result = match Screen::enter() {
+            Some(screen) => run_screen(&mut browser, &screen),
+            None => run_lines(&mut browser),
+        };
+
+        // The worker finishes the queue before the scope ends
+        let mut run = lock(&run);
+        run.quit = true;
+        let left = run.queue.len() + usize::from(run.current.is_some());
+        if left > 0 {
+            println!("Converting {} more items, interrupt to stop", left);
+        }
+        result
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::{self, Item, StubMuxer, TempDir};
+
+    #[test]
+    fn parses_row_numbers_and_ranges() {
+        assert_eq!(parse_rows("1 3 5-7"), Some(vec![1, 3, 5, 6, 7]));
+        assert_eq!(parse_rows("2,4  9"), Some(vec![2, 4, 9]));
+        assert_eq!(parse_rows(""), Some(vec![]));
+        assert_eq!(parse_rows("3-"), None);
+        assert_eq!(parse_rows("a"), None);
+    }
+
+    #[test]
+    fn parses_keys_and_escape_sequences() {
+        assert_eq!(
+            parse_keys(b"j \x1b[A\x1b[6~\x1bOH\x1b[Z\x1b\r\x7f\x03"),
+            [
+                Key::Char('j'),
+                Key::Char(' '),
+                Key::Up,
+                Key::PageDown,
+                Key::Home,
+                Key::Esc,
+                Key::Enter,
+                Key::Backspace,
+                Key::Interrupt,
+            ]
+        );
+        assert_eq!(parse_keys("番".as_bytes()), [Key::Char('番')]);
+    }
+
+    #[test]
+    fn selects_reorders_and_filters_with_keys() {
+        let dir = TempDir::new();
+        let cache = dir.path().join("cache");
+        for (id, title) in [(111, "First"), (222, "Second"), (333, "Third")] {
+            Item::single(id, title).write(&cache);
+        }
+        let muxer = StubMuxer::default();
+        let options = fixture::options(&[], &dir.path().join("work"), &muxer);
+        let run = Mutex::new(Run::new(queue::Order::Name));
+        let mut videos = get_video_list(&cache).unwrap();
+        videos.sort_by_key(item_name);
+        let mut browser = Browser {
+            source_path: &cache,
+            options: &options,
+            run: &run,
+            finished: 0,
+            videos,
+            filter: String::new(),
+            selected: Vec::new(),
+            cursor: 0,
+            top: 0,
+            mode: Mode::Browse,
+            status: String::new(),
+        };
+        let keys = |browser: &mut Browser, keys: &str| {
+            parse_keys(keys.as_bytes())
+                .into_iter()
+                .filter_map(|key| browser.handle(key, 10))
+                .collect::<Vec<_>>()
+        };
+
+        // Space moves on to the next row, `+` moves 333 before 111
+        assert!(keys(&mut browser, " j ").is_empty());
+        assert_eq!(browser.selected, ["111", "333"]);
+        assert!(keys(&mut browser, "+").is_empty());
+        assert_eq!(browser.selected, ["333", "111"]);
+        assert_eq!(browser.mark("111"), "[2]");
+        assert_eq!(browser.mark("222"), "[ ]");
+
+        assert!(keys(&mut browser, "/sec\rx").is_empty());
+        assert_eq!(browser.listed_items(), ["222"]);
+        assert_eq!(browser.status, "Remove 2 cache items? [y/N]");
+        assert!(keys(&mut browser, "n").is_empty());
+        assert_eq!(browser.status, "Nothing removed");
+        assert_eq!(
+            keys(&mut browser, "a\n"),
+            [Action::Inspect("222".to_string())]
+        );
+        assert_eq!(browser.selected, ["333", "111", "222"]);
+        let screen = browser.render(8, 60);
+        assert!(screen.contains("1 items, 3 selected, filter /sec"));
+        assert!(screen.contains("[3] 222"));
+
+        // Queued items stay in the queue's order, which `+` and `-` change
+        // like the selection's, and Space takes them out again
+        assert!(keys(&mut browser, "c").is_empty());
+        assert_eq!(browser.status, "Queued 3 items");
+        assert!(browser.selected.is_empty());
+        assert!(keys(&mut browser, "+").is_empty());
+        let queued = |run: &Mutex<Run>| {
+            let run = lock(run);
+            let jobs = run.queue.jobs().iter().map(|job| job.item.clone());
+            jobs.collect::<Vec<_>>()
+        };
+        assert_eq!(queued(&run), ["333", "222", "111"]);
+        assert_eq!(browser.mark("222"), "(2)");
+        {
+            let mut run = lock(&run);
+            run.current = run.queue.pop().map(|job| job.item);
+        }
+        assert_eq!(browser.mark("333"), "(*)");
+        assert!(browser.render(8, 80).contains("| converting 333, 2 queued"));
+        assert!(keys(&mut browser, " ").is_empty());
+        assert_eq!(browser.status, "Took 222 out of the queue");
+        assert_eq!(queued(&run), ["111"]);
+
+        // After the run converted an item, `a` leaves out what is queued
+        assert!(keys(&mut browser, "/\x7f\x7f\x7f\r").is_empty());
+        lock(&run).converted += 1;
+        browser.refresh().unwrap();
+        assert_eq!(browser.finished, 1);
+        assert_eq!(keys(&mut browser, "aq"), [Action::Quit]);
+        assert_eq!(browser.selected, ["222"]);
+    }
+}
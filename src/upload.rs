@@ -0,0 +1,491 @@
+/// Uploading converted videos to remote storage
+///
+/// Transfers go through the usual command line clients, so their existing
+/// configuration and credentials are used as is:
+///   sftp://[user@]host[:port]/path   sftp in batch mode, needs key authentication
+///   webdav://host/path, webdavs://   curl, credentials from the URL or ~/.netrc
+///   s3://bucket/prefix               the AWS CLI
+/// Every file is verified by comparing its remote size after the transfer,
+/// local files are only removed once that matched.
+use std::fmt;
+use std::fs;
+use std::io::Write;
+use std::path::{Component, Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use std::str::FromStr;
+
+use log::*;
+
+use crate::error::{self, Context};
+use crate::layout;
+use crate::runner;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Destination {
+    Sftp {
+        host: String,
+        port: Option<u16>,
+        path: String,
+    },
+    /// Base URL of a WebDAV collection
+    WebDav {
+        url: String,
+    },
+    S3 {
+        bucket: String,
+        prefix: String,
+    },
+}
+
+impl FromStr for Destination {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (scheme, rest) = s.split_once("://").ok_or_else(|| {
+            format!(
+                "invalid destination '{}', expected e.g. sftp://host/path",
+                s
+            )
+        })?;
+        let (authority, path) = match rest.split_once('/') {
+            Some((authority, path)) => (authority, path.trim_end_matches('/')),
+            None => (rest, ""),
+        };
+        if authority.is_empty() {
+            return Err(format!("missing host in '{}'", s));
+        }
+        match scheme {
+            "sftp" => {
+                let (host, port) = match authority.rsplit_once(':') {
+                    Some((host, port)) => (
+                        host,
+                        Some(
+                            port.parse()
+                                .map_err(|_| format!("invalid port in '{}'", s))?,
+                        ),
+                    ),
+                    None => (authority, None),
+                };
+                Ok(Destination::Sftp {
+                    host: host.to_string(),
+                    port,
+                    path: path.to_string(),
+                })
+            }
+            "webdav" | "http" => Ok(Destination::WebDav {
+                url: format!("http://{}/{}", authority, path),
+            }),
+            "webdavs" | "https" => Ok(Destination::WebDav {
+                url: format!("https://{}/{}", authority, path),
+            }),
+            "s3" => Ok(Destination::S3 {
+                bucket: authority.to_string(),
+                prefix: path.to_string(),
+            }),
+            _ => Err(format!("unsupported destination scheme '{}'", scheme)),
+        }
+    }
+}
+
+impl fmt::Display for Destination {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Destination::Sftp { host, port, path } => match port {
+                Some(port) => write!(f, "sftp://{}:{}/{}", host, port, path),
+                None => write!(f, "sftp://{}/{}", host, path),
+            },
+            Destination::WebDav { url } => f.write_str(url),
+            Destination::S3 { bucket, prefix } => write!(f, "s3://{}/{}", bucket, prefix),
+        }
+    }
+}
+
+// Remote path of `relative` below `base`, always with forward slashes
+fn remote_path(base: &str, relative: &Path) -> String {
+    let mut path = base.trim_end_matches('/').to_string();
+    for component in relative.components() {
+        if let Component::Normal(name) = component {
+            if !path.is_empty() {
+                path.push('/');
+            }
+            path.push_str(&name.to_string_lossy());
+        }
+    }
+    path
+}
+
+// Percent-encode a URL path, keeping the separators
+fn encode_path(path: &str) -> String {
+    let mut encoded = String::with_capacity(path.len());
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+// Parent directories of a remote path, outermost first
+fn parents(remote: &str) -> Vec<&str> {
+    remote
+        .match_indices('/')
+        .map(|(i, _)| &remote[..i])
+        .filter(|dir| !dir.is_empty())
+        .collect()
+}
+
+fn webdav_url(base: &str, remote: &str) -> String {
+    format!("{}/{}", base.trim_end_matches('/'), encode_path(remote))
+}
+
+fn run(mut cmd: Command, input: Option<&str>) -> Result<Output, error::Error> {
+    debug!("Running {:?}", cmd);
+    cmd.stdin(if input.is_some() {
+        Stdio::piped()
+    } else {
+        Stdio::null()
+    })
+    .stdout(Stdio::piped())
+    .stderr(Stdio::piped());
+    runner::show(&cmd);
+    let mut child = cmd.spawn().map_err(|_| error::Error::CommandNotFound)?;
+    if let (Some(input), Some(mut stdin)) = (input, child.stdin.take()) {
+        stdin.write_all(input.as_bytes())?;
+    }
+    Ok(child.wait_with_output()?)
+}
+
+fn check(output: Output, what: &str) -> Result<Output, error::Error> {
+    if output.status.success() {
+        Ok(output)
+    } else {
+        Err(error::Error::UploadFailed(format!(
+            "{}: {}",
+            what,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )))
+    }
+}
+
+fn sftp_command(host: &str, port: Option<u16>) -> Command {
+    let mut cmd = Command::new("sftp");
+    cmd.args(["-q", "-b", "-"]);
+    if let Some(port) = port {
+        cmd.arg("-P").arg(port.to_string());
+    }
+    cmd.arg(host);
+    cmd
+}
+
+// sftp batch files quote arguments with double quotes
+fn sftp_quote(path: &str) -> String {
+    format!("\"{}\"", path.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn curl() -> Command {
+    let mut cmd = Command::new("curl");
+    cmd.args(["-sS", "--netrc-optional"]);
+    cmd
+}
+
+impl Destination {
+    fn put(&self, local: &Path, relative: &Path) -> Result<(), error::Error> {
+        let what = format!("upload of {}", local.display());
+        match self {
+            Destination::Sftp { host, port, path } => {
+                // Leading `-` lets sftp carry on when a directory already exists
+                let remote = remote_path(path, relative);
+                let mut batch = String::new();
+                for dir in parents(&remote) {
+                    batch.push_str(&format!("-mkdir {}\n", sftp_quote(dir)));
+                }
+                batch.push_str(&format!(
+                    "put {} {}\n",
+                    sftp_quote(&local.to_string_lossy()),
+                    sftp_quote(&remote)
+                ));
+                check(run(sftp_command(host, *port), Some(&batch))?, &what)?;
+            }
+            Destination::WebDav { url } => {
+                // Collections have to exist before files can be put into them,
+                // failures are left to the upload itself to report
+                let remote = remote_path("", relative);
+                for dir in parents(&remote) {
+                    let mut cmd = curl();
+                    cmd.args(["-X", "MKCOL"])
+                        .arg(format!("{}/", webdav_url(url, dir)));
+                    let _ = run(cmd, None);
+                }
+                let mut cmd = curl();
+                cmd.arg("-f")
+                    .arg("-T")
+                    .arg(local)
+                    .arg(webdav_url(url, &remote));
+                check(run(cmd, None)?, &what)?;
+            }
+            Destination::S3 { bucket, prefix } => {
+                let mut cmd = Command::new("aws");
+                cmd.args(["s3", "cp", "--only-show-errors"])
+                    .arg(local)
+                    .arg(format!("s3://{}/{}", bucket, remote_path(prefix, relative)));
+                check(run(cmd, None)?, &what)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Size of an uploaded file, `None` if it cannot be determined
+    fn remote_size(&self, relative: &Path) -> Result<Option<u64>, error::Error> {
+        let what = format!("verification of {}", relative.display());
+        let size = match self {
+            Destination::Sftp { host, port, path } => {
+                let remote = remote_path(path, relative);
+                let batch = format!("ls -ln {}\n", sftp_quote(&remote));
+                let output = check(run(sftp_command(host, *port), Some(&batch))?, &what)?;
+                // -rw-r--r--    1 1000     1000     12345 Jan  1 00:00 name
+                String::from_utf8_lossy(&output.stdout)
+                    .lines()
+                    .filter(|line| !line.starts_with("sftp>"))
+                    .find_map(|line| line.split_whitespace().nth(4)?.parse().ok())
+            }
+            Destination::WebDav { url } => {
+                let mut cmd = curl();
+                cmd.args(["-f", "-I"])
+                    .arg(webdav_url(url, &remote_path("", relative)));
+                let output = check(run(cmd, None)?, &what)?;
+                String::from_utf8_lossy(&output.stdout)
+                    .lines()
+                    .find_map(|line| {
+                        let (name, value) = line.split_once(':')?;
+                        if name.trim().eq_ignore_ascii_case("content-length") {
+                            value.trim().parse().ok()
+                        } else {
+                            None
+                        }
+                    })
+            }
+            Destination::S3 { bucket, prefix } => {
+                let mut cmd = Command::new("aws");
+                cmd.args(["s3api", "head-object", "--bucket", bucket, "--key"])
+                    .arg(remote_path(prefix, relative))
+                    .args(["--query", "ContentLength", "--output", "text"]);
+                let output = check(run(cmd, None)?, &what)?;
+                String::from_utf8_lossy(&output.stdout).trim().parse().ok()
+            }
+        };
+        Ok(size)
+    }
+
+    /// Upload `local` to `relative` below the destination and verify its size
+    pub fn upload(&self, local: &Path, relative: &Path) -> Result<(), error::Error> {
+        info!("Uploading {} to {}", local.display(), self);
+        self.put(local, relative)?;
+        let size = local.metadata().context("read metadata of", local)?.len();
+        match self.remote_size(relative)? {
+            Some(remote) if remote == size => Ok(()),
+            Some(remote) => Err(error::Error::UploadFailed(format!(
+                "{} has {} bytes remotely instead of {}",
+                relative.display(),
+                remote,
+                size
+            ))),
+            None => Err(error::Error::UploadFailed(format!(
+                "unable to verify size of {}",
+                relative.display()
+            ))),
+        }
+    }
+}
+
+/// Upload files below `target_path`, keeping their relative paths, and
+/// remove each one locally after a verified transfer if `remove` is set.
+pub fn files(
+    destination: &Destination,
+    target_path: &Path,
+    files: &[PathBuf],
+    remove: bool,
+) -> Result<(), error::Error> {
+    for file in files {
+        let relative = file
+            .strip_prefix(target_path)
+            .map_err(|_| error::Error::InvalidFileName(file.clone()))?;
+        destination.upload(file, relative)?;
+        if remove {
+            debug!("Removing uploaded {}", file.display());
+            fs::remove_file(file).context("remove", file)?;
+        }
+    }
+    Ok(())
+}
+
+// The video and its side files, see `layout::Output::side_file`
+pub fn output_files(output: &layout::Output) -> Result<Vec<PathBuf>, error::Error> {
+    let prefix = format!(
+        "{}.",
+        output
+            .file
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy()
+    );
+    let mut files = Vec::new();
+    for entry in output
+        .dir
+        .read_dir()
+        .context("read directory", &output.dir)?
+    {
+        let path = entry.context("read directory", &output.dir)?.path();
+        let name = path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string();
+        if path.is_file() && !name.starts_with('.') && (output.own_dir || name.starts_with(&prefix))
+        {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Upload a converted item with its side files
+pub fn output(
+    destination: &Destination,
+    target_path: &Path,
+    output: &layout::Output,
+    remove: bool,
+) -> Result<(), error::Error> {
+    files(destination, target_path, &output_files(output)?, remove)?;
+    if remove && output.own_dir {
+        let _ = fs::remove_dir(&output.dir);
+    }
+    Ok(())
+}
+
+/// Every file below `path`, skipping hidden ones like the state database
+pub fn collect(path: &Path, files: &mut Vec<PathBuf>) -> Result<(), error::Error> {
+    if path.is_file() {
+        files.push(path.to_path_buf());
+        return Ok(());
+    }
+    let mut entries: Vec<PathBuf> = path
+        .read_dir()
+        .context("read directory", path)?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|p| {
+            !p.file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .starts_with('.')
+        })
+        .collect();
+    entries.sort();
+    for entry in entries {
+        collect(&entry, files)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::TempDir;
+    use std::collections::HashMap;
+    use std::io::{BufRead, BufReader, Read};
+    use std::net::TcpListener;
+    use std::thread;
+
+    // A WebDAV server on loopback keeping the size of every file put, which
+    // reports a byte less for `short` files and forgets `lost` ones
+    fn webdav() -> Destination {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("webdav://{}/videos", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            let mut sizes: HashMap<String, usize> = HashMap::new();
+            for mut stream in listener.incoming().flatten() {
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request = String::new();
+                let _ = reader.read_line(&mut request);
+                let mut parts = request.split_whitespace();
+                let method = parts.next().unwrap_or_default().to_string();
+                let path = parts.next().unwrap_or_default().to_string();
+                let (mut length, mut expect) = (0, false);
+                let mut header = String::new();
+                while reader.read_line(&mut header).unwrap_or_default() > 2 {
+                    let (name, value) = header.split_once(':').unwrap_or_default();
+                    if name.eq_ignore_ascii_case("content-length") {
+                        length = value.trim().parse().unwrap_or_default();
+                    }
+                    expect |= name.eq_ignore_ascii_case("expect");
+                    header.clear();
+                }
+                if expect {
+                    let _ = write!(stream, "HTTP/1.1 100 Continue\r\n\r\n");
+                }
+                let _ = reader.read_exact(&mut vec![0; length]);
+                let (status, length) = match method.as_str() {
+                    "PUT" if !path.contains("lost") => {
+                        sizes.insert(path, length);
+                        ("201 Created", 0)
+                    }
+                    "HEAD" => match sizes.get(&path) {
+                        Some(size) if path.contains("short") => ("200 OK", size - 1),
+                        Some(size) => ("200 OK", *size),
+                        None => ("404 Not Found", 0),
+                    },
+                    _ => ("201 Created", 0),
+                };
+                let _ = write!(
+                    stream,
+                    "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    status, length
+                );
+            }
+        });
+        url.parse().unwrap()
+    }
+
+    #[test]
+    fn removes_only_files_verified_remotely() {
+        let dir = TempDir::new();
+        let target = dir.path().join("output");
+        fs::create_dir_all(target.join("UP")).unwrap();
+        let file = |name: &str| {
+            let path = target.join("UP").join(name);
+            fs::write(&path, "muxed").unwrap();
+            path
+        };
+        let destination = webdav();
+
+        let uploaded = [file("222.mp4")];
+        files(&destination, &target, &uploaded, true).unwrap();
+        assert!(!uploaded[0].exists());
+
+        for (name, expected) in [
+            (
+                "short.mp4",
+                "UP/short.mp4 has 4 bytes remotely instead of 5",
+            ),
+            ("lost.mp4", "verification of UP/lost.mp4: "),
+        ] {
+            let kept = [file(name)];
+            match files(&destination, &target, &kept, true) {
+                Err(error::Error::UploadFailed(e)) => assert!(e.starts_with(expected), "{}", e),
+                result => panic!("{} uploaded: {:?}", name, result),
+            }
+            assert!(kept[0].exists(), "{}", name);
+        }
+
+        // Files are uploaded by their path below the output directory
+        let outside = dir.path().join("outside.mp4");
+        fs::write(&outside, "muxed").unwrap();
+        assert!(matches!(
+            files(&destination, &target, &[outside], true),
+            Err(error::Error::InvalidFileName(_))
+        ));
+    }
+}
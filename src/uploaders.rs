@@ -0,0 +1,140 @@
+/// Settings of single uploaders, overriding the command line
+///
+/// `uploaders.json` in the config directory, or the file given with
+/// `--uploaders`, maps uploader names to the settings their videos get in
+/// every run, e.g. lectures re-encoded into their own directory and a
+/// channel kept out of conversions:
+///
+/// ```json
+/// {
+///   "Lecture UP": { "profile": "h265", "subdir": "Lectures" },
+///   "Music UP": { "organize": "flat", "name_template": "{title}" },
+///   "Raw UP": { "skip": true }
+/// }
+/// ```
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::error::{self, Context};
+use crate::layout::Organize;
+use crate::profile::Profile;
+
+const FILE_NAME: &str = "uploaders.json";
+
+/// What differs for the videos of one uploader
+#[derive(Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Overrides {
+    pub organize: Option<Organize>,
+    /// File name template of the flat layout, see `layout::render`, only
+    /// used with `"organize": "flat"` or `--organize flat`
+    pub name_template: Option<String>,
+    pub profile: Option<Profile>,
+    /// Directory below the output directory the videos go to
+    pub subdir: Option<PathBuf>,
+    /// Never convert the videos
+    #[serde(default)]
+    pub skip: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct Uploaders(BTreeMap<String, Overrides>);
+
+impl Uploaders {
+    fn parse(content: &str, path: &Path) -> Result<Uploaders, error::Error> {
+        let invalid = |message: String| error::Error::ConfigInvalid(path.to_path_buf(), message);
+        let uploaders: BTreeMap<String, Overrides> =
+            serde_json::from_str(content).map_err(|e| invalid(e.to_string()))?;
+        for (uname, overrides) in &uploaders {
+            // Outputs must stay inside the output directory
+            let outside = overrides.subdir.as_ref().is_some_and(|subdir| {
+                subdir
+                    .components()
+                    .any(|c| !matches!(c, Component::Normal(_)))
+            });
+            if outside {
+                return Err(invalid(format!(
+                    "the subdir of {} must be a relative path without ..",
+                    uname
+                )));
+            }
+        }
+        Ok(Uploaders(uploaders))
+    }
+
+    /// Settings from `path`, or from `uploaders.json` in the config
+    /// directory if it exists
+    pub fn load(path: Option<&Path>) -> Result<Uploaders, error::Error> {
+        let path = match path {
+            Some(path) => path.to_path_buf(),
+            None => match crate::auth::config_dir().map(|dir| dir.join(FILE_NAME)) {
+                Some(path) if path.is_file() => path,
+                _ => return Ok(Uploaders::default()),
+            },
+        };
+        let content = fs::read_to_string(&path).context("read", &path)?;
+        Uploaders::parse(&content, &path)
+    }
+
+    /// Overrides of the uploader `uname`, if any
+    pub fn get(&self, uname: &str) -> Option<&Overrides> {
+        self.0.get(uname)
+    }
+
+    /// Whether the videos of `uname` are never converted
+    pub fn skips(&self, uname: &str) -> bool {
+        self.get(uname).is_some_and(|overrides| overrides.skip)
+    }
+
+    /// Profiles some uploader is converted with
+    pub fn profiles(&self) -> impl Iterator<Item = Profile> + '_ {
+        self.0.values().filter_map(|overrides| overrides.profile)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_overrides_per_uploader() {
+        let path = Path::new("uploaders.json");
+        let uploaders = Uploaders::parse(
+            r#"{
+                "Lecture UP": { "profile": "h265", "subdir": "Lectures/2024" },
+                "Music UP": { "organize": "flat", "name_template": "{title}" },
+                "Raw UP": { "skip": true }
+            }"#,
+            path,
+        )
+        .unwrap();
+        let lectures = uploaders.get("Lecture UP").unwrap();
+        assert_eq!(lectures.profile, Some(Profile::H265));
+        assert_eq!(lectures.subdir, Some(PathBuf::from("Lectures/2024")));
+        assert_eq!(
+            uploaders.get("Music UP").unwrap().organize,
+            Some(Organize::Flat)
+        );
+        assert!(uploaders.skips("Raw UP"));
+        assert!(uploaders.get("Other UP").is_none());
+        assert_eq!(uploaders.profiles().collect::<Vec<_>>(), [Profile::H265]);
+
+        for invalid in [
+            r#"{ "UP": { "subdir": "../elsewhere" } }"#,
+            r#"{ "UP": { "subdir": "/srv" } }"#,
+            r#"{ "UP": { "profil": "h264" } }"#,
+        ] {
+            assert!(
+                matches!(
+                    Uploaders::parse(invalid, path),
+                    Err(error::Error::ConfigInvalid(..))
+                ),
+                "{}",
+                invalid
+            );
+        }
+    }
+}
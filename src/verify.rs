@@ -0,0 +1,270 @@
+/// Checking the outputs of converted items
+///
+/// Every output recorded as converted must exist, not be empty and hold
+/// streams ffprobe can read. With `--deep` its streams are also compared
+/// with the cached media it was made from, as far as that is still cached:
+/// the duration has to match within a tolerance, and the resolution and
+/// audio channels must be the same. A different codec is only noted, as
+/// `--profile` re-encodes on purpose.
+use std::path::{Path, PathBuf};
+
+use serde_json::json;
+
+use crate::i18n::tr;
+use crate::probe::{self, Stream};
+use crate::{
+    error, get_files_by_extension, get_metadata, item_path, legacy, output, quality, state,
+    ConvertOptions, Summary,
+};
+
+/// Streams and duration of a source or output
+#[derive(Debug, Default)]
+struct Media {
+    streams: Vec<Stream>,
+    duration: Option<f64>,
+}
+
+impl Media {
+    fn stream(&self, kind: &str) -> Option<&Stream> {
+        self.streams.iter().find(|s| s.codec_type == kind)
+    }
+}
+
+fn resolution(stream: &Stream) -> Option<(u32, u32)> {
+    stream.width.zip(stream.height)
+}
+
+/// Differences of `output` from `source`, as problems and notes
+fn compare(source: &Media, output: &Media, tolerance: f64) -> (Vec<String>, Vec<String>) {
+    let (mut problems, mut notes) = (Vec::new(), Vec::new());
+    if let (Some(source), Some(output)) = (source.duration, output.duration) {
+        if (source - output).abs() > tolerance {
+            problems.push(tr!(
+                "duration {}s, the source has {}s",
+                format!("{:.1}", output),
+                format!("{:.1}", source)
+            ));
+        }
+    }
+    for kind in ["video", "audio"] {
+        let Some(expected) = source.stream(kind) else {
+            continue;
+        };
+        let Some(actual) = output.stream(kind) else {
+            problems.push(tr!("no {} stream", kind));
+            continue;
+        };
+        if expected.codec_name != actual.codec_name {
+            notes.push(tr!("{} re-encoded: {}", kind, actual.describe()));
+        }
+        if resolution(expected) != resolution(actual) || expected.channels != actual.channels {
+            problems.push(tr!(
+                "{}, the source has {}",
+                actual.describe(),
+                expected.describe()
+            ));
+        }
+    }
+    (problems, notes)
+}
+
+// The cached media the item in `dir` is converted from and its duration:
+// the selected streams of DASH media, or all legacy segments one after another
+fn probe_source(dir: &Path, options: &ConvertOptions) -> Result<Media, error::Error> {
+    let ffmpeg = &options.ffmpeg;
+    if legacy::is_item(dir) {
+        let segments = legacy::segments(dir)?;
+        let mut duration = 0.0;
+        for segment in &segments {
+            duration += probe::duration(ffmpeg, segment)?;
+        }
+        let streams = match segments.first() {
+            Some(first) => probe::output_streams(ffmpeg, first)?,
+            None => Vec::new(),
+        };
+        return Ok(Media {
+            streams,
+            duration: Some(duration),
+        });
+    }
+    let media = get_files_by_extension(dir, "m4s")?;
+    let selection = quality::select(ffmpeg, media, options.quality)?;
+    let mut source = Media::default();
+    for file in &selection.files {
+        source.streams.extend(probe::streams(ffmpeg, file)?);
+        let duration = probe::media_duration(ffmpeg, file)?;
+        source.duration = Some(source.duration.unwrap_or_default().max(duration));
+    }
+    Ok(source)
+}
+
+fn probe_output(file: &Path, options: &ConvertOptions) -> Result<Media, error::Error> {
+    Ok(Media {
+        streams: probe::output_streams(&options.ffmpeg, file)?,
+        duration: probe::duration(&options.ffmpeg, file).ok(),
+    })
+}
+
+// Problems and notes of the output of `item`
+fn check(
+    item: &str,
+    file: &Path,
+    source_path: &Path,
+    deep: bool,
+    tolerance: f64,
+    options: &ConvertOptions,
+) -> (Vec<String>, Vec<String>) {
+    match file.metadata() {
+        Ok(m) if m.len() > 0 => {}
+        Ok(_) => return (vec![tr!("empty output").to_string()], vec![]),
+        Err(_) => return (vec![tr!("output missing").to_string()], vec![]),
+    }
+    let output = match probe_output(file, options) {
+        Ok(output) if output.streams.is_empty() => {
+            return (vec![tr!("no streams").to_string()], vec![])
+        }
+        Ok(output) => output,
+        Err(e) => return (vec![tr!("unreadable: {}", e)], vec![]),
+    };
+    if !deep {
+        return (vec![], vec![]);
+    }
+    let dir = item_path(source_path, item);
+    if !dir.is_dir() {
+        return (
+            vec![],
+            vec![tr!("not cached anymore, not compared").to_string()],
+        );
+    }
+    match probe_source(&dir, options) {
+        Ok(source) => compare(&source, &output, tolerance),
+        Err(e) => (vec![], vec![tr!("source unreadable, not compared: {}", e)]),
+    }
+}
+
+/// Check the outputs of the converted items in `items`, or of all of them,
+/// comparing them with their sources if `deep`. Items with problems count
+/// as failed.
+pub fn run(
+    source_path: &Path,
+    target_path: &Path,
+    items: &[String],
+    deep: bool,
+    tolerance: f64,
+    options: &ConvertOptions,
+) -> Result<Summary, error::Error> {
+    let db = state::StateDb::load(target_path)?;
+    let converted: Vec<&String> = db
+        .items()
+        .filter(|(item, s)| {
+            s.status == state::Status::Converted && (items.is_empty() || items.contains(item))
+        })
+        .map(|(item, _)| item)
+        .collect();
+    for item in items {
+        if !converted.contains(&item) {
+            eprintln!("{}", tr!("{} is not converted", item));
+        }
+    }
+
+    let mut summary = Summary::default();
+    for item in converted {
+        // Outputs of old versions were not recorded
+        let file: Option<PathBuf> = db.output(item).or_else(|| {
+            let info = get_metadata(&item_path(source_path, item)).ok()?;
+            Some(options.layout.output(&info, target_path).file)
+        });
+        let (problems, notes) = match &file {
+            Some(file) => check(item, file, source_path, deep, tolerance, options),
+            None => (vec![tr!("output unknown").to_string()], vec![]),
+        };
+        output::emit(
+            "verify",
+            &json!({ "item": item, "output": file, "problems": problems, "notes": notes }),
+        )?;
+        if problems.is_empty() {
+            summary.converted += 1;
+        } else {
+            summary.failed += 1;
+        }
+        if output::json() {
+            continue;
+        }
+        let status = if problems.is_empty() {
+            tr!("ok")
+        } else {
+            tr!("FAILED")
+        };
+        let details: Vec<String> = problems.into_iter().chain(notes).collect();
+        if details.is_empty() {
+            println!("{}  {}", status, item);
+        } else {
+            println!("{}  {}  {}", status, item, details.join("; "));
+        }
+    }
+    if !output::json() {
+        println!(
+            "{}",
+            tr!(
+                "{} outputs verified, {} with problems",
+                summary.converted + summary.failed,
+                summary.failed
+            )
+        );
+    }
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stream(kind: &str, codec: &str, size: Option<(u32, u32)>, channels: Option<u32>) -> Stream {
+        Stream {
+            index: 0,
+            codec_type: kind.to_string(),
+            codec_name: Some(codec.to_string()),
+            width: size.map(|s| s.0),
+            height: size.map(|s| s.1),
+            bit_rate: None,
+            channels,
+            duration: None,
+        }
+    }
+
+    #[test]
+    fn compares_streams_with_the_source() {
+        let source = Media {
+            streams: vec![
+                stream("video", "hevc", Some((1920, 1080)), None),
+                stream("audio", "aac", None, Some(2)),
+            ],
+            duration: Some(300.0),
+        };
+        let copied = Media {
+            streams: source.streams.clone(),
+            duration: Some(300.5),
+        };
+        assert_eq!(compare(&source, &copied, 1.0), (vec![], vec![]));
+
+        let reencoded = Media {
+            streams: vec![stream("video", "h264", Some((1920, 1080)), None)],
+            duration: Some(120.0),
+        };
+        let (problems, notes) = compare(&source, &reencoded, 1.0);
+        assert_eq!(
+            problems,
+            ["duration 120.0s, the source has 300.0s", "no audio stream"]
+        );
+        assert_eq!(notes, ["video re-encoded: video h264 1920x1080"]);
+
+        let scaled = Media {
+            streams: vec![
+                stream("video", "hevc", Some((1280, 720)), None),
+                stream("audio", "aac", None, Some(6)),
+            ],
+            duration: Some(300.0),
+        };
+        assert_eq!(compare(&source, &scaled, 1.0).0.len(), 2);
+    }
+}
@@ -0,0 +1,303 @@
+/// The `.videoInfo` metadata the client writes next to each cached video
+///
+/// Client versions differ in which fields they write and how, so everything
+/// but the item id is optional, numbers may come as strings, and unknown
+/// fields are ignored.
+use std::fmt::{self, Display};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use chrono::DateTime;
+use serde::de::{Deserializer, Error};
+use serde::{Deserialize, Serialize};
+
+use crate::chapters;
+
+/// Where the cover art of a video is
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum Cover {
+    #[default]
+    None,
+    /// Downloaded by the client
+    Path(PathBuf),
+    /// Not downloaded, e.g. by clients that only store the URL
+    Url(String),
+}
+
+impl From<Option<String>> for Cover {
+    fn from(value: Option<String>) -> Self {
+        let value = value.unwrap_or_default();
+        let value = value.trim();
+        if value.is_empty() {
+            Cover::None
+        } else if value.starts_with("http://")
+            || value.starts_with("https://")
+            || value.starts_with("//")
+        {
+            Cover::Url(value.to_string())
+        } else {
+            Cover::Path(PathBuf::from(value))
+        }
+    }
+}
+
+impl From<Cover> for String {
+    fn from(cover: Cover) -> Self {
+        match cover {
+            Cover::None => String::new(),
+            Cover::Path(path) => path.to_string_lossy().to_string(),
+            Cover::Url(url) => url,
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Cover {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(Cover::from(Option::<String>::deserialize(deserializer)?))
+    }
+}
+
+impl Serialize for Cover {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&String::from(self.clone()))
+    }
+}
+
+impl Cover {
+    /// File name of the cover, without any URL query
+    pub fn file_name(&self) -> Option<String> {
+        match self {
+            Cover::None => None,
+            Cover::Path(path) => path.file_name().map(|n| n.to_string_lossy().to_string()),
+            Cover::Url(url) => url
+                .split(['?', '#'])
+                .next()
+                .and_then(|url| url.rsplit('/').next())
+                .filter(|name| !name.is_empty())
+                .map(String::from),
+        }
+    }
+}
+
+impl Display for Cover {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Cover::None => f.write_str("none"),
+            Cover::Path(path) => write!(f, "{}", path.display()),
+            Cover::Url(url) => f.write_str(url),
+        }
+    }
+}
+
+// A number, also accepted as a numeric string
+fn number<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de> + FromStr + Default,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Lenient<T> {
+        Number(T),
+        Text(String),
+        Null(()),
+    }
+    match Lenient::deserialize(deserializer)? {
+        Lenient::Number(n) => Ok(n),
+        Lenient::Text(s) if s.trim().is_empty() => Ok(T::default()),
+        Lenient::Text(s) => s
+            .trim()
+            .parse()
+            .map_err(|_| D::Error::custom(format!("invalid number '{}'", s))),
+        Lenient::Null(_) => Ok(T::default()),
+    }
+}
+
+// A string, or null for an empty one
+fn text<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    Ok(Option::<String>::deserialize(deserializer)?.unwrap_or_default())
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct VideoInfo {
+    #[serde(default, deserialize_with = "text")]
+    pub uname: String,
+    #[serde(default, deserialize_with = "text")]
+    pub title: String,
+    /// Multiple items can be in the same group, the title of single videos
+    #[serde(default, rename = "groupTitle", deserialize_with = "text")]
+    pub group_title: String,
+    #[serde(default, deserialize_with = "number")]
+    pub pubdate: i64,
+    #[serde(default, rename = "updateTime", deserialize_with = "number")]
+    pub update_time: i64,
+    #[serde(default, rename = "totalSize", deserialize_with = "number")]
+    pub total_size: u64,
+    #[serde(rename = "itemId", deserialize_with = "number")]
+    pub item_id: u64,
+    #[serde(default, rename = "coverPath")]
+    pub cover_path: Cover,
+    #[serde(default, rename = "groupCoverPath")]
+    pub group_cover_path: Cover,
+    /// Where the client downloaded the cover from
+    #[serde(default, rename = "coverUrl", alias = "cover")]
+    pub cover_url: Option<String>,
+    #[serde(default, rename = "groupCoverUrl", alias = "groupCover")]
+    pub group_cover_url: Option<String>,
+    /// Index of the item in its group, starting at 1
+    #[serde(default, deserialize_with = "number")]
+    pub p: u32,
+    #[serde(default, rename = "viewPoints", alias = "chapters")]
+    pub view_points: Vec<chapters::Chapter>,
+    /// Id of the group, the av id of the video the item is a part of, 0 if
+    /// the client does not write it
+    #[serde(
+        default,
+        rename = "groupId",
+        alias = "aid",
+        deserialize_with = "number"
+    )]
+    pub group_id: u64,
+    /// Id of the uploader, 0 if unknown
+    #[serde(default, alias = "mid", deserialize_with = "number")]
+    pub uid: u64,
+}
+
+// `id`, or a hash of `name` where the metadata has no id. FNV-1a is used as
+// the names must stay the same across runs and versions.
+fn key(id: u64, name: &str) -> String {
+    if id > 0 {
+        return id.to_string();
+    }
+    let hash = name.bytes().fold(0xcbf2_9ce4_8422_2325, |hash: u64, b| {
+        (hash ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3)
+    });
+    format!("{:016x}", hash)
+}
+
+impl VideoInfo {
+    /// Parse metadata, filling in what older clients leave out
+    pub fn parse(content: &str) -> Result<VideoInfo, serde_json::Error> {
+        let mut info: VideoInfo = serde_json::from_str(content)?;
+        if info.group_title.is_empty() {
+            info.group_title = info.title.clone();
+        }
+        if info.p == 0 {
+            info.p = 1;
+        }
+        Ok(info)
+    }
+
+    /// Stands in for the group title where it can not be used, the same
+    /// for every part of the group
+    pub fn group_key(&self) -> String {
+        key(self.group_id, &self.group_title)
+    }
+
+    /// Stands in for the uploader name where it can not be used, the same
+    /// for every video of the uploader
+    pub fn uploader_key(&self) -> String {
+        key(self.uid, &self.uname)
+    }
+}
+
+impl Display for VideoInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let dt = DateTime::from_timestamp(self.pubdate, 0)
+            .map(|dt| dt.to_string())
+            .unwrap_or_else(|| self.pubdate.to_string());
+        let message = format!(
+            "[{}] {} - {}, Page<{}>, UP<{}>, Size<{}>, Updated<{}>",
+            self.item_id, self.group_title, self.title, self.p, self.uname, self.total_size, dt
+        );
+        f.write_str(message.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CURRENT: &str = r#"{
+        "uname": "UP", "title": "Part", "groupTitle": "Group",
+        "pubdate": 1700000000, "updateTime": 1700000100, "totalSize": 36,
+        "itemId": 111, "coverPath": "/cache/111/cover.jpg",
+        "groupCoverPath": "/cache/111/group.jpg", "p": 2
+    }"#;
+
+    #[test]
+    fn parses_current_client() {
+        let info = VideoInfo::parse(CURRENT).unwrap();
+        assert_eq!(info.uname, "UP");
+        assert_eq!(info.group_title, "Group");
+        assert_eq!(info.item_id, 111);
+        assert_eq!(info.p, 2);
+        assert_eq!(
+            info.cover_path,
+            Cover::Path(PathBuf::from("/cache/111/cover.jpg"))
+        );
+        assert!(info.view_points.is_empty());
+    }
+
+    #[test]
+    fn ignores_unknown_fields() {
+        let content = CURRENT.replacen('{', r#"{"danmakuCount": 5, "quality": {"id": 80},"#, 1);
+        assert_eq!(VideoInfo::parse(&content).unwrap().item_id, 111);
+    }
+
+    #[test]
+    fn tolerates_missing_fields() {
+        let info = VideoInfo::parse(r#"{"itemId": 5, "title": "Only"}"#).unwrap();
+        assert_eq!(info.group_title, "Only");
+        assert_eq!(info.p, 1);
+        assert_eq!(info.pubdate, 0);
+        assert_eq!(info.cover_path, Cover::None);
+        assert_eq!(info.cover_url, None);
+    }
+
+    #[test]
+    fn requires_item_id() {
+        assert!(VideoInfo::parse(r#"{"title": "No id"}"#).is_err());
+    }
+
+    #[test]
+    fn accepts_numbers_as_strings_and_nulls() {
+        let info = VideoInfo::parse(
+            r#"{"itemId": "42", "pubdate": "1700000000", "totalSize": null, "uname": null, "p": ""}"#,
+        )
+        .unwrap();
+        assert_eq!(info.item_id, 42);
+        assert_eq!(info.pubdate, 1700000000);
+        assert_eq!(info.total_size, 0);
+        assert_eq!(info.uname, "");
+        assert!(VideoInfo::parse(r#"{"itemId": "abc"}"#).is_err());
+    }
+
+    #[test]
+    fn cover_kinds() {
+        let cover = |s: &str| Cover::from(Some(s.to_string()));
+        assert_eq!(cover(""), Cover::None);
+        assert_eq!(
+            cover("//i0.hdslb.com/a.jpg"),
+            Cover::Url("//i0.hdslb.com/a.jpg".into())
+        );
+        assert_eq!(
+            cover("https://x/b.png?w=1").file_name().as_deref(),
+            Some("b.png")
+        );
+        assert_eq!(
+            cover("C:\\cache\\cover.jpg"),
+            Cover::Path(PathBuf::from("C:\\cache\\cover.jpg"))
+        );
+    }
+
+    #[test]
+    fn round_trips() {
+        let info = VideoInfo::parse(CURRENT).unwrap();
+        let again = VideoInfo::parse(&serde_json::to_string(&info).unwrap()).unwrap();
+        assert_eq!(again.cover_path, info.cover_path);
+        assert_eq!(again.group_cover_path, info.group_cover_path);
+        assert_eq!(again.update_time, info.update_time);
+        assert_eq!(again.title, info.title);
+    }
+}